@@ -0,0 +1,32 @@
+//! The reads of a connection as the server takes them apart, then parses each packet
+//!
+//! The first byte of the input sets the size of the reads the rest is cut into.
+
+#![no_main]
+
+use std::str::FromStr;
+
+use libfuzzer_sys::fuzz_target;
+use rschat::{
+    packet::PacketType,
+    server::inbound::{Inbound, PacketBuffer},
+};
+
+const MAX_PACKET_SIZE: usize = 256;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&read_size, stream)) = data.split_first() else {
+        return;
+    };
+    let mut buffer = PacketBuffer::new(MAX_PACKET_SIZE);
+    for read in stream.chunks(usize::from(read_size).max(1)) {
+        for completed in buffer.push(read) {
+            match completed {
+                Inbound::Packet(text) => {
+                    let _ = PacketType::from_str(&text);
+                }
+                Inbound::TooLarge(size) => assert!(size > MAX_PACKET_SIZE),
+            }
+        }
+    }
+});
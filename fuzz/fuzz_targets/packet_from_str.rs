@@ -0,0 +1,18 @@
+//! Whatever a client sends is either a packet or an error, and a packet parsed keeps its type
+
+#![no_main]
+
+use std::str::FromStr;
+
+use libfuzzer_sys::fuzz_target;
+use rschat::packet::PacketType;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    if PacketType::from_str(text).is_ok() {
+        let value: serde_json::Value = serde_json::from_str(text).unwrap();
+        assert!(value["type"].is_string());
+    }
+});
@@ -0,0 +1,206 @@
+//! Command line interface: `rschat <server|client> [options]`
+
+pub const DEFAULT_PORT_NUM: &str = "8080";
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+
+const USAGE: &str = "\
+Usage: rschat <command> [options]
+
+Commands:
+  server    run the chat server
+  client    run the chat client
+  verify-transcript <file>...
+            check the chain of the compliance transcript files, oldest first
+
+Options:
+  -h, --help       print help
+  -V, --version    print version
+
+Run 'rschat <command> --help' for the options of each command.";
+
+const SERVER_USAGE: &str = "\
+Usage: rschat server [options]
+
+Options:
+  -p, --port <port>      port to listen on (default: 8080)
+  -c, --config <path>    config file (default: rschat_server.json if exists)
+      --db-url <url>     MySQL url, overrides the config file
+      --irc-port <port>  serve IRC clients on <port>, overrides the config file
+  -h, --help             print help";
+
+const CLIENT_USAGE: &str = "\
+Usage: rschat client [options]
+
+Options:
+      --host <host>      server host (default: the one of client.json, 127.0.0.1
+                         without it)
+  -p, --port <port>      server port (default: the one of client.json, 8080
+                         without it)
+  -u, --user <id>        log in as <id> after connecting
+      --auto-login       log in with the credentials saved by '/login --save'
+      --tls              connect over TLS
+      --max-messages <n> messages kept in the message section (default: 5000)
+      --away-after <duration>
+                         turn away after being idle for this long, 'off'
+                         never does (default: 10m)
+      --spell-check <dictionary>
+                         underline the words of the input missing from the
+                         dictionary, a file of one word per line
+      --lite             save bandwidth on metered or slow connections: shorter
+                         histories, no nicknames and fewer pings
+  -h, --help             print help";
+
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    pub port: String,
+    pub config: Option<String>,
+    pub db_url: Option<String>,
+    pub irc_port: Option<u16>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Server to connect to, the one of the client configuration if not given
+    pub host: Option<String>,
+    pub port: Option<String>,
+    pub user: Option<String>,
+    pub auto_login: bool,
+    pub tls: bool,
+
+    /// Messages kept in the message section, the oldest are dropped beyond it
+    pub max_messages: usize,
+
+    /// Idle time before the presence turns away, never if `None`
+    pub away_after: Option<std::time::Duration>,
+
+    /// Dictionary the input is spell checked against, not checked if unset
+    pub spell_check: Option<String>,
+
+    /// Ask the server for less traffic and ping it less often
+    pub lite: bool,
+}
+
+pub enum Cli {
+    Server(ServerOptions),
+    Client(ClientOptions),
+
+    /// Check the chain of the transcript files, oldest first
+    VerifyTranscript(Vec<String>),
+
+    /// Print the text and exit
+    Print(String),
+}
+
+/// Split `--flag=value` into the flag and its inline value
+fn split_flag(arg: &str) -> (&str, Option<&str>) {
+    match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => (flag, Some(value)),
+        _ => (arg, None),
+    }
+}
+
+/// Value of `flag` either inlined or from the next argument
+fn flag_value(
+    flag: &str,
+    inline: Option<&str>,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<String, String> {
+    match inline {
+        Some(v) => Ok(v.to_owned()),
+        None => args
+            .next()
+            .ok_or(format!("option '{}' requires a value", flag)),
+    }
+}
+
+fn parse_server(mut args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    let mut opts = ServerOptions {
+        port: DEFAULT_PORT_NUM.to_owned(),
+        config: None,
+        db_url: None,
+        irc_port: None,
+    };
+    while let Some(arg) = args.next() {
+        let (flag, inline) = split_flag(&arg);
+        match flag {
+            "-p" | "--port" => opts.port = flag_value(flag, inline, &mut args)?,
+            "-c" | "--config" => opts.config = Some(flag_value(flag, inline, &mut args)?),
+            "--db-url" => opts.db_url = Some(flag_value(flag, inline, &mut args)?),
+            "--irc-port" => {
+                let port = flag_value(flag, inline, &mut args)?;
+                opts.irc_port = Some(
+                    port.parse()
+                        .map_err(|_| format!("invalid port for '{}': '{}'", flag, port))?,
+                );
+            }
+            "-h" | "--help" => return Ok(Cli::Print(SERVER_USAGE.to_owned())),
+            unknown => return Err(format!("unknown option for 'server': '{}'", unknown)),
+        }
+    }
+    Ok(Cli::Server(opts))
+}
+
+fn parse_client(mut args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    let mut opts = ClientOptions {
+        host: None,
+        port: None,
+        user: None,
+        auto_login: false,
+        tls: false,
+        max_messages: crate::client::message_channel::DEFAULT_CAPACITY,
+        away_after: Some(crate::client::away::DEFAULT_AWAY_AFTER),
+        spell_check: None,
+        lite: false,
+    };
+    while let Some(arg) = args.next() {
+        let (flag, inline) = split_flag(&arg);
+        match flag {
+            "--host" => opts.host = Some(flag_value(flag, inline, &mut args)?),
+            "-p" | "--port" => opts.port = Some(flag_value(flag, inline, &mut args)?),
+            "-u" | "--user" => opts.user = Some(flag_value(flag, inline, &mut args)?),
+            "--auto-login" => opts.auto_login = true,
+            "--tls" => opts.tls = true,
+            "--lite" => opts.lite = true,
+            "--max-messages" => {
+                let n = flag_value(flag, inline, &mut args)?;
+                opts.max_messages = n
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or(format!("invalid number for '{}': '{}'", flag, n))?;
+            }
+            "--away-after" => {
+                let d = flag_value(flag, inline, &mut args)?;
+                opts.away_after = match d.as_str() {
+                    "off" => None,
+                    d => Some(
+                        crate::client::util::parse_duration(d)
+                            .ok_or(format!("invalid duration for '{}': '{}'", flag, d))?,
+                    ),
+                };
+            }
+            "--spell-check" => opts.spell_check = Some(flag_value(flag, inline, &mut args)?),
+            "-h" | "--help" => return Ok(Cli::Print(CLIENT_USAGE.to_owned())),
+            unknown => return Err(format!("unknown option for 'client': '{}'", unknown)),
+        }
+    }
+    Ok(Cli::Client(opts))
+}
+
+/// Parse command line arguments, excluding the program name
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    match args.next().as_deref() {
+        Some("server") => parse_server(args),
+        Some("client") => parse_client(args),
+        Some("verify-transcript") => {
+            let files: Vec<String> = args.collect();
+            if files.is_empty() {
+                return Err("'verify-transcript' requires the transcript files".to_owned());
+            }
+            Ok(Cli::VerifyTranscript(files))
+        }
+        Some("-V" | "--version") => Ok(Cli::Print(format!("rschat {}", env!("CARGO_PKG_VERSION")))),
+        Some("-h" | "--help") | None => Ok(Cli::Print(USAGE.to_owned())),
+        Some(unknown) => Err(format!("unknown command: '{}'\n\n{}", unknown, USAGE)),
+    }
+}
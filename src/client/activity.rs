@@ -0,0 +1,167 @@
+//! Protocol events of the session, kept apart from the chat and shown in the activity pane
+//!
+//! Connection changes, channel switches, responses and errors are recorded here, the message
+//! section only shows what the users write.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use super::{redraw::RedrawFlag, util};
+use crate::packet::{
+    ChannelClosed, ChannelInfo, GotoRes, InvalidPacket, LimitExceeded, PacketError, ServerBusy,
+};
+
+/// Number of events kept, the oldest are dropped beyond it
+pub const NUM_MAX_EVENTS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Unix timestamp in seconds
+    pub time: u64,
+    pub level: Level,
+    pub text: String,
+}
+
+/// Thread safe log of the events, shared by the background tasks and the pane
+#[derive(Default, Clone)]
+pub struct ActivityLog {
+    events: Arc<Mutex<VecDeque<Event>>>,
+
+    /// Raised on every new event
+    pub redraw: RedrawFlag,
+}
+
+impl ActivityLog {
+    pub fn info(&self, text: impl Into<String>) {
+        self.record(Level::Info, text.into());
+    }
+
+    pub fn error(&self, text: impl Into<String>) {
+        self.record(Level::Error, text.into());
+    }
+
+    pub fn record(&self, level: Level, text: String) {
+        let mut events = self.events.lock().unwrap();
+        events.push_back(Event {
+            time: util::unix_time(),
+            level,
+            text,
+        });
+        if events.len() > NUM_MAX_EVENTS {
+            events.pop_front();
+        }
+        self.redraw.raise();
+    }
+
+    /// Copy of the latest `n` events, oldest first
+    pub fn latest(&self, n: usize) -> Vec<Event> {
+        let events = self.events.lock().unwrap();
+        events
+            .iter()
+            .skip(events.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Event of the packet `msg_str` of the server, `None` for the chat content and the probes
+pub fn describe(msg_str: &str) -> Option<(Level, String)> {
+    if let Some(res) = util::parse_packet::<GotoRes>(msg_str) {
+        return Some(match res.result {
+            Ok(channel) => (Level::Info, format!("Joined #{}", channel)),
+            Err(e) => (Level::Error, format!("Switching channels failed: {}", e)),
+        });
+    }
+    if let Some(info) = util::parse_packet::<ChannelInfo>(msg_str) {
+        let mode = if info.announce_only {
+            "announcements only"
+        } else {
+            "open"
+        };
+        return Some((
+            Level::Info,
+            format!("Settings of #{}: {}", info.channel_name, mode),
+        ));
+    }
+    if let Some(closed) = util::parse_packet::<ChannelClosed>(msg_str) {
+        return Some((
+            Level::Error,
+            format!(
+                "#{} was closed ({}), moved to #{}",
+                closed.channel_name, closed.reason, closed.moved_to
+            ),
+        ));
+    }
+    if let Some(exceeded) = util::parse_packet::<LimitExceeded>(msg_str) {
+        return Some((
+            Level::Error,
+            format!(
+                "The {} was rejected: {} > {} bytes",
+                exceeded.what, exceeded.size, exceeded.limit
+            ),
+        ));
+    }
+    if let Some(invalid) = util::parse_packet::<InvalidPacket>(msg_str) {
+        return Some((
+            Level::Error,
+            format!("A packet was dropped by the server: {}", invalid.error),
+        ));
+    }
+    if let Some(busy) = util::parse_packet::<ServerBusy>(msg_str) {
+        return Some((Level::Error, format!("Server busy: {}", busy.reason)));
+    }
+
+    // the other responses only tell whether the request succeeded
+    let packet: serde_json::Value = serde_json::from_str(msg_str).ok()?;
+    let name = packet.get("type")?.as_str()?;
+    if !name.ends_with("Res") || name == "HelloRes" {
+        return None;
+    }
+    let what = match packet.get("item").and_then(|item| item.as_str()) {
+        Some(item) => format!("{} '{}'", name, item),
+        None => name.to_owned(),
+    };
+    match packet.get("result").and_then(|result| result.get("Err")) {
+        Some(e) => {
+            let e: PacketError = serde_json::from_value(e.clone()).ok()?;
+            Some((Level::Error, format!("{} failed: {}", what, e)))
+        }
+        None => Some((Level::Info, format!("{} succeeded", what))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_protocol_events_are_described() {
+        let goto = r#"{"type":"GotoRes","result":{"Ok":"dev"}}"#;
+        assert_eq!(
+            describe(goto),
+            Some((Level::Info, "Joined #dev".to_owned()))
+        );
+
+        let fetch = r#"{"type":"FetchRes","item":"retracted",
+            "result":{"Err":{"code":"permission_denied","message":"moderators only"}}}"#;
+        assert_eq!(
+            describe(fetch),
+            Some((
+                Level::Error,
+                "FetchRes 'retracted' failed: moderators only".to_owned()
+            ))
+        );
+
+        let chat = r#"{"type":"Message","id":"alice","msg":"hi","is_system":false}"#;
+        assert_eq!(describe(chat), None);
+        assert_eq!(describe(r#"{"type":"Pong","timestamp":1}"#), None);
+    }
+}
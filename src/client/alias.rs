@@ -0,0 +1,227 @@
+//! Aliases of command lines, expanded before the command parser sees the line
+//!
+//! An alias names one or more commands separated by `;`, e.g. `/alias gl "/goto lobby"`. `$1` to
+//! `$9` in the definition are replaced by the words typed after the alias and `$*` by all of them,
+//! words given to an alias without any of these are appended to its last command. Aliases may use
+//! other aliases, but built-in commands can't be redefined.
+//!
+//! The definitions are kept in `aliases.json` of the configuration directory, a map of the names
+//! to the definitions that can be edited by hand as well.
+
+use std::{collections::BTreeMap, fs};
+
+use super::{command::CommandSpec, credentials};
+
+const ALIASES_FILE: &str = "aliases.json";
+
+/// Depth of the aliases used by aliases, beyond it an alias is taken as using itself
+const MAX_DEPTH: usize = 8;
+
+/// Commands a single line may expand to
+const MAX_COMMANDS: usize = 32;
+
+#[derive(Default)]
+pub struct Aliases {
+    /// Definition by the name of the alias
+    defs: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    /// Aliases saved in the configuration directory, none if there is no file
+    pub fn load() -> Self {
+        let defs = credentials::config_dir()
+            .and_then(|dir| fs::read_to_string(dir.join(ALIASES_FILE)).ok())
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self { defs }
+    }
+
+    /// Definitions as (name, definition), in the order of the names
+    pub fn list(&self) -> Vec<(String, String)> {
+        self.defs
+            .iter()
+            .map(|(name, def)| (name.clone(), def.clone()))
+            .collect()
+    }
+
+    /// Define `name` as `definition`, quotes around it are dropped, returns the replaced one
+    pub fn define(&mut self, name: &str, definition: &str) -> Result<Option<String>, String> {
+        let name = name.trim_start_matches('/');
+        let definition = definition.trim();
+        let definition = definition
+            .strip_prefix('"')
+            .and_then(|d| d.strip_suffix('"'))
+            .unwrap_or(definition)
+            .trim();
+
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ';') {
+            return Err(format!("'{}' is not a valid alias name", name));
+        }
+        if CommandSpec::find(name).is_some() {
+            return Err(format!("'/{}' is a built-in command", name));
+        }
+        if let Some(part) = definition
+            .split(';')
+            .map(str::trim)
+            .find(|part| !part.starts_with('/'))
+        {
+            return Err(format!(
+                "'{}' is not a command, every part of an alias starts with '/'",
+                part
+            ));
+        }
+
+        let replaced = self.defs.insert(name.to_owned(), definition.to_owned());
+        self.save()?;
+        Ok(replaced)
+    }
+
+    /// Returns false if there is no alias `name`
+    pub fn remove(&mut self, name: &str) -> Result<bool, String> {
+        let removed = self.defs.remove(name.trim_start_matches('/')).is_some();
+        self.save()?;
+        Ok(removed)
+    }
+
+    /// Command lines the alias of `line` stands for, `None` if it doesn't start with an alias
+    pub fn expand(&self, line: &str) -> Option<Result<Vec<String>, String>> {
+        let name = line.strip_prefix('/')?.split_whitespace().next()?;
+        if CommandSpec::find(name).is_some() || !self.defs.contains_key(name) {
+            return None;
+        }
+
+        let mut lines = Vec::new();
+        Some(self.expand_into(line, 0, &mut lines).map(|_| lines))
+    }
+
+    fn expand_into(&self, line: &str, depth: usize, lines: &mut Vec<String>) -> Result<(), String> {
+        let mut words = line.trim_start_matches('/').split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let definition = match self.defs.get(name) {
+            Some(def) if CommandSpec::find(name).is_none() => def,
+            // built-in commands and the ones of the scripts
+            _ => {
+                if lines.len() == MAX_COMMANDS {
+                    return Err(format!(
+                        "An alias can't run more than {} commands",
+                        MAX_COMMANDS
+                    ));
+                }
+                lines.push(line.trim().to_owned());
+                return Ok(());
+            }
+        };
+        if depth == MAX_DEPTH {
+            return Err(format!(
+                "Alias '{}' expands too deep, does it use itself?",
+                name
+            ));
+        }
+
+        let args: Vec<&str> = words.collect();
+        let parts: Vec<&str> = definition.split(';').map(str::trim).collect();
+        let has_placeholders = (1..=9)
+            .map(|n| format!("${}", n))
+            .chain(["$*".to_owned()])
+            .any(|placeholder| definition.contains(&placeholder));
+        for (idx, part) in parts.iter().enumerate() {
+            let mut command = substitute(part, &args);
+            if !has_placeholders && !args.is_empty() && idx == parts.len() - 1 {
+                command = format!("{} {}", command, args.join(" "));
+            }
+            self.expand_into(&command, depth + 1, lines)?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let dir = credentials::config_dir()
+            .ok_or_else(|| "no configuration directory, HOME is not set".to_owned())?;
+        fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+        let path = dir.join(ALIASES_FILE);
+        let json = serde_json::to_string_pretty(&self.defs).unwrap();
+        fs::write(&path, json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// `part` with `$1` to `$9` replaced by `args`, `$*` by all of them and `$$` by `$`
+fn substitute(part: &str, args: &[&str]) -> String {
+    let mut out = String::with_capacity(part.len());
+    let mut chars = part.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        match chars.peek().copied() {
+            Some('*') => out.push_str(&args.join(" ")),
+            Some('$') => out.push('$'),
+            Some(n @ '1'..='9') => {
+                let idx = n as usize - '1' as usize;
+                out.push_str(args.get(idx).copied().unwrap_or_default());
+            }
+            _ => {
+                out.push('$');
+                continue;
+            }
+        }
+        chars.next();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(defs: &[(&str, &str)]) -> Aliases {
+        Aliases {
+            defs: defs
+                .iter()
+                .map(|(name, def)| (name.to_string(), def.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn aliases_expand_to_command_lines() {
+        let aliases = aliases(&[
+            ("gl", "/goto lobby"),
+            ("g", "/goto"),
+            ("hi", "/goto $1; /msg $2 hello from $1$$"),
+            ("both", "/gl; /hi dev $*"),
+            ("loop", "/loop"),
+        ]);
+
+        assert_eq!(
+            aliases.expand("/gl"),
+            Some(Ok(vec!["/goto lobby".to_owned()]))
+        );
+        assert_eq!(
+            aliases.expand("/g dev"),
+            Some(Ok(vec!["/goto dev".to_owned()]))
+        );
+        assert_eq!(
+            aliases.expand("/hi dev bob"),
+            Some(Ok(vec![
+                "/goto dev".to_owned(),
+                "/msg bob hello from dev$".to_owned()
+            ]))
+        );
+        assert_eq!(
+            aliases.expand("/both alice"),
+            Some(Ok(vec![
+                "/goto lobby".to_owned(),
+                "/goto dev".to_owned(),
+                "/msg alice hello from dev$".to_owned()
+            ]))
+        );
+        assert!(matches!(aliases.expand("/loop"), Some(Err(_))));
+
+        // not aliases
+        assert_eq!(aliases.expand("/goto lobby"), None);
+        assert_eq!(aliases.expand("/unknown"), None);
+        assert_eq!(aliases.expand("gl"), None);
+    }
+}
@@ -1,15 +1,40 @@
-use std::str::FromStr;
+use std::collections::BTreeSet;
 
 use tokio::sync::{broadcast, mpsc};
 
 use super::{
+    activity::ActivityLog,
+    alias::Aliases,
+    away::AutoAway,
     command::*,
+    config::ClientConfig,
+    credentials::{self, Credentials},
+    export,
+    ignore_list::IgnoreList,
     input_controller::*,
-    message_channel::MessageChannel,
+    message_channel::{Kind, MessageChannel, RenderOptions},
+    message_view::MessageView,
+    notification::Notifications,
     popup::{self, login::LoginPopupManager, register::RegisterPopupManager},
-    session, util,
+    recovery::Recovery,
+    script::{ScriptAction, ScriptHost},
+    session::{self, UserListQuery},
+    spell::{self, SpellChecker},
+    status::{ConnectionState, ConnectionStatus},
+    thread::ThreadView,
+    util,
 };
-use crate::{crypto::hash, db, packet::*};
+use crate::{
+    crypto::{auth, hash},
+    db,
+    packet::*,
+};
+
+/// Number of users to show per page of `/fetch list`
+const USER_LIST_PAGE_SIZE: usize = 20;
+
+/// Shortest password of a new account, the server only ever sees a verifier of it
+const MIN_PASSWORD_LEN: usize = 4;
 
 #[derive(PartialEq)]
 pub enum HandleCommandStatus {
@@ -20,9 +45,22 @@ pub enum HandleCommandStatus {
     Continue,
 }
 
+#[derive(Clone)]
 pub enum CommandAction {
     Login,
+    AutoLogin,
     Register,
+    Upgrade,
+    /// Join the channel chosen in a popup
+    Goto,
+    /// Send a confirmed or completed channel request, `"action"` on `"channel_name"`
+    ManageChannel,
+    /// Send an account request, `"action"` confirmed with `"password"`
+    Account,
+    /// Replace the misspelled word at `"start".."end"` of the input with the `"choice"`
+    Correct,
+    /// Bring the layout of the recovery file back, the `Recovery` is the args
+    Restore,
 }
 
 /// App holds the state of the application
@@ -31,8 +69,48 @@ pub struct App {
     pub messages: MessageChannel,
     pub outgoing_tx: mpsc::Sender<String>,
     pub incoming_tx: broadcast::Sender<String>,
+
+    /// Subscription for packets the server pushes without being requested
+    pub pushed_rx: broadcast::Receiver<String>,
     pub state: session::State,
-    pub popup: Option<Box<dyn popup::PopupManager>>,
+
+    /// Open popups, the last one is on top and takes the input
+    pub popups: Vec<Box<dyn popup::PopupManager>>,
+    pub render_options: RenderOptions,
+
+    /// Kinds of messages left out of the message section, see `/filter`
+    pub hidden_kinds: BTreeSet<Kind>,
+    pub notifications: Notifications,
+    pub view: MessageView,
+
+    /// Sequence number of the message the input replies to, picked in the message section
+    pub reply_to: Option<u64>,
+
+    /// Thread shown next to the messages, see `/thread`
+    pub thread: Option<ThreadView>,
+    pub connection: ConnectionStatus,
+
+    /// Only the title of the pinned section is shown, toggled by F2
+    pub pins_collapsed: bool,
+
+    /// Protocol events, shown next to the messages while `activity_open`, toggled by F3
+    pub activity: ActivityLog,
+    pub activity_open: bool,
+
+    /// Underlines the misspelled words of the input, if a dictionary is given
+    pub spell: Option<SpellChecker>,
+    pub away: AutoAway,
+    pub scripts: ScriptHost,
+    pub aliases: Aliases,
+
+    /// Id the login popup is filled in with, from the client configuration
+    pub display_name: Option<String>,
+
+    /// Go back to the channel of the last session, from the client configuration
+    pub rejoin_last_channel: bool,
+
+    /// Layout as of the latest checkpoint, it's written again only once changed
+    checkpoint: Option<Recovery>,
 }
 
 impl App {
@@ -43,23 +121,205 @@ impl App {
     ) -> Self {
         Self {
             main_input: InputController::default(),
-            messages: MessageChannel::default(),
+            messages: MessageChannel::new(IgnoreList::load()),
             outgoing_tx,
+            pushed_rx: incoming_tx.subscribe(),
             incoming_tx,
             state,
-            popup: None,
+            popups: Vec::new(),
+            render_options: RenderOptions::default(),
+            hidden_kinds: BTreeSet::new(),
+            notifications: Notifications::default(),
+            view: MessageView::default(),
+            reply_to: None,
+            thread: None,
+            connection: ConnectionStatus::default(),
+            pins_collapsed: false,
+            activity: ActivityLog::default(),
+            activity_open: false,
+            spell: None,
+            away: AutoAway::new(None),
+            scripts: ScriptHost::new(),
+            aliases: Aliases::load(),
+            display_name: None,
+            rejoin_last_channel: false,
+            checkpoint: None,
         }
     }
 
+    /// Current layout of the session, as written to the recovery file
+    pub fn layout(&self) -> Recovery {
+        Recovery {
+            id: self.state.id.clone(),
+            channel: self.state.channel.clone(),
+            draft: self.main_input.buf.clone(),
+            reply_to: self.reply_to,
+            thread: self.thread.as_ref().map(|thread| thread.root),
+            activity_open: self.activity_open,
+            pins_collapsed: self.pins_collapsed,
+            hidden_kinds: self
+                .hidden_kinds
+                .iter()
+                .map(|kind| kind.as_str().to_owned())
+                .collect(),
+            scroll: self.view.scroll(),
+            unread: self
+                .notifications
+                .unread
+                .iter()
+                .map(|(channel, unread)| (channel.clone(), *unread))
+                .collect(),
+            mentioned: self.notifications.mentioned.clone(),
+        }
+    }
+
+    /// Write the layout to the recovery file if it changed since the last checkpoint
+    pub fn checkpoint(&mut self) {
+        let layout = self.layout();
+        if self.checkpoint.as_ref() == Some(&layout) {
+            return;
+        }
+        if let Err(e) = layout.save() {
+            self.activity
+                .error(format!("Failed to checkpoint the session: {}", e));
+        }
+        self.checkpoint = Some(layout);
+    }
+
+    /// Bring back the layout of a session that was killed or crashed
+    async fn restore(&mut self, recovery: Recovery) {
+        if recovery.channel != self.state.channel {
+            self.goto(recovery.channel.clone()).await;
+        }
+        // the other channel couldn't be joined, what's left is about it
+        if recovery.channel == self.state.channel {
+            if self.main_input.buf.is_empty() {
+                self.main_input.insert_str(&recovery.draft, true);
+            }
+            self.reply_to = recovery.reply_to;
+            if let Some(root) = recovery.thread {
+                self.run_command(&format!("/thread {}", root)).await;
+            }
+            self.view.set_scroll(recovery.scroll);
+        }
+        self.activity_open = recovery.activity_open;
+        self.pins_collapsed = recovery.pins_collapsed;
+        self.hidden_kinds.extend(
+            recovery
+                .hidden_kinds
+                .iter()
+                .filter_map(|kind| Kind::from_name(kind)),
+        );
+        // what the server counted since is newer
+        for (channel, unread) in recovery.unread {
+            self.notifications.unread.entry(channel).or_insert(unread);
+        }
+        self.notifications.mentioned.extend(recovery.mentioned);
+        self.messages
+            .push_sys_msg("The previous session is restored".to_owned());
+    }
+
+    /// The user has seen the current channel, the server keeps how far for the next login
+    pub async fn mark_read(&mut self) {
+        self.notifications.mark_read(&self.state.channel);
+        if self.state.is_guest {
+            return;
+        }
+        if let Some(seq) = self.messages.mark_read(&self.state.channel) {
+            let req = ReadMarkerReq {
+                channel_name: self.state.channel.clone(),
+                seq,
+            };
+            _ = self.outgoing_tx.send(req.as_json_string()).await;
+        }
+    }
+
+    /// Put `popup` on top of the open ones, it takes the keys until closed
+    pub fn open_popup(&mut self, popup: impl popup::PopupManager + 'static) {
+        self.main_input.normal_mode();
+        self.popups.push(Box::new(popup));
+    }
+
+    /// Close the popup on top, the one below takes the keys again
+    pub fn close_popup(&mut self) {
+        self.popups.pop();
+    }
+
     /// Send message to the outgoing channel
-    pub async fn send_message(&self) {
+    ///
+    /// It's shown right away as being sent, til the server echoes it in the order of the channel.
+    pub async fn send_message(&mut self) {
+        let msg = self.main_input.buf.clone();
+        let reply_to = self.reply_to.take();
+        let idx = self
+            .messages
+            .push_outgoing(self.state.id.clone(), msg.clone(), reply_to);
+        if !self.send_text(msg, reply_to).await {
+            self.messages.fail_outgoing(idx);
+        }
+    }
+
+    /// Send the messages of the current channel that failed, once connected again
+    pub async fn retry_failed(&mut self) {
+        if self.connection.state() != ConnectionState::Connected {
+            self.messages.push_sys_err(
+                "Not connected, the messages can be sent again once the connection is back"
+                    .to_owned(),
+            );
+            return;
+        }
+        let failed = self.messages.retry_failed(&self.state.channel);
+        if failed.is_empty() {
+            self.messages
+                .push_sys_msg("No message of the channel failed to be sent".to_owned());
+        }
+        for (idx, text, reply_to) in failed {
+            if !self.send_text(text, reply_to).await {
+                self.messages.fail_outgoing(idx);
+            }
+        }
+    }
+
+    /// Send `msg` to the current channel, replying to the message `reply_to` if some, false if
+    /// the connection is gone
+    async fn send_text(&self, msg: String, reply_to: Option<u64>) -> bool {
+        let msg_bytes = Message {
+            id: self.state.id.clone(),
+            msg,
+            is_system: false,
+            to: None,
+            seq: None,
+            retracted: false,
+            display_name: None,
+            reply_to,
+            thread: None,
+        }
+        .as_json_string();
+        self.outgoing_tx.send(msg_bytes).await.is_ok()
+    }
+
+    /// Send a direct message to `to`
+    pub async fn send_direct_message(&mut self, to: String, msg: String) {
         let msg_bytes = Message {
             id: self.state.id.clone(),
-            msg: self.main_input.buf.clone(),
+            msg: msg.clone(),
             is_system: false,
+            to: Some(to.clone()),
+            seq: None,
+            retracted: false,
+            display_name: None,
+            reply_to: None,
+            thread: None,
         }
         .as_json_string();
-        _ = self.outgoing_tx.send(msg_bytes).await;
+        match self.outgoing_tx.send(msg_bytes).await {
+            Ok(_) => self
+                .messages
+                .push(Kind::Direct, format!("[DM] you -> {}", to), msg),
+            Err(e) => self
+                .messages
+                .push_sys_err(format!("Channel send failed, try again: '{}'", e)),
+        }
     }
 
     pub async fn run_action(&mut self, action: &CommandAction, args: Option<serde_json::Value>) {
@@ -68,7 +328,23 @@ impl App {
                 let args = args.unwrap();
                 let id = args["id"].as_str().unwrap();
                 let password = args["password"].as_str().unwrap();
-                self.login(id, password).await;
+                let hashed = hash::sha256_password(password);
+                if self.login(id, &hashed).await {
+                    if let Some(passphrase) = args["passphrase"].as_str() {
+                        self.save_credentials(id, &hashed, passphrase);
+                    }
+                }
+            }
+            CommandAction::AutoLogin => {
+                let args = args.unwrap();
+                match credentials::load(args["passphrase"].as_str().unwrap()) {
+                    Ok(saved) => {
+                        self.login(&saved.id, &saved.password).await;
+                    }
+                    Err(e) => self
+                        .messages
+                        .push_sys_err(format!("Failed to read saved credentials: {}", e)),
+                }
             }
             CommandAction::Register => {
                 let args = args.unwrap();
@@ -80,20 +356,144 @@ impl App {
                 )
                 .await;
             }
+            CommandAction::Upgrade => {
+                let args = args.unwrap();
+                self.upgrade(
+                    args["id"].as_str().unwrap(),
+                    args["password"].as_str().unwrap(),
+                    args["bio"].as_str(),
+                    args["location"].as_str(),
+                )
+                .await;
+            }
+            CommandAction::Goto => {
+                let args = args.unwrap();
+                let password = args["password"].as_str().map(hash::sha256_password);
+                self.goto_with(args["choice"].as_str().unwrap().to_owned(), password)
+                    .await;
+            }
+            CommandAction::ManageChannel => {
+                let args = args.unwrap();
+                let Ok(action) = serde_json::from_value(args["action"].clone()) else {
+                    return;
+                };
+                let channel_name = args["channel_name"].as_str().unwrap().trim();
+                if channel_name.is_empty() {
+                    self.messages
+                        .push_sys_err("A channel name is required".to_owned());
+                    return;
+                }
+                let password = args["password"].as_str().map(hash::sha256_password);
+                self.manage_channel(action, channel_name.to_owned(), password)
+                    .await;
+            }
+            CommandAction::Account => {
+                let args = args.unwrap();
+                let Ok(action) = serde_json::from_value(args["action"].clone()) else {
+                    return;
+                };
+                let password = hash::sha256_password(args["password"].as_str().unwrap());
+                self.account(action, password).await;
+            }
+            CommandAction::Correct => {
+                let args = args.unwrap();
+                let (Some(start), Some(end), Some(choice)) = (
+                    args["start"].as_u64(),
+                    args["end"].as_u64(),
+                    args["choice"].as_str(),
+                ) else {
+                    return;
+                };
+                self.main_input
+                    .replace_range(start as usize..end as usize, choice);
+                // the popup left the editing, typing goes on after the word
+                self.main_input.editing_mode();
+            }
+            CommandAction::Restore => {
+                let Some(Ok(recovery)) = args.map(serde_json::from_value) else {
+                    return;
+                };
+                self.restore(recovery).await;
+            }
         };
     }
 
-    pub async fn login(&mut self, id: &str, password: &str) {
+    /// Answer to a challenge for `id`, proving the hashed `password` is known without sending it
+    async fn prove(&mut self, id: &str, password: &str) -> Result<String, String> {
+        let nonce = auth::nonce();
+        let res_rx = self.incoming_tx.subscribe();
+        self.outgoing_tx
+            .send(
+                ChallengeReq {
+                    id: id.to_owned(),
+                    nonce: nonce.clone(),
+                }
+                .as_json_string(),
+            )
+            .await
+            .map_err(|e| format!("Channel send failed, try again: '{}'", e))?;
+        let challenge = util::consume_til::<ChallengeRes>(res_rx)
+            .await
+            .result
+            .map_err(|e| e.to_string())?;
+        challenge.prove(id, &nonce, password)
+    }
+
+    /// Send the account request `action`, an export is saved to the working directory
+    async fn account(&mut self, action: AccountAction, password: String) {
+        let id = self.state.id.clone();
+        let proof = match self.prove(&id, &password).await {
+            Ok(proof) => proof,
+            Err(e) => {
+                self.messages.push_sys_err(e);
+                return;
+            }
+        };
+        let res_rx = self.incoming_tx.subscribe();
+        _ = self
+            .outgoing_tx
+            .send(AccountReq { action, proof }.as_json_string())
+            .await;
+        let res = util::consume_til::<AccountRes>(res_rx).await;
+        match (action, res.result) {
+            (AccountAction::Export, Ok(bundle)) => {
+                match export::write_account_bundle(&self.state.id, &bundle) {
+                    Ok(path) => self
+                        .messages
+                        .push_sys_msg(format!("Your data is exported to '{}'", path.display())),
+                    Err(e) => self.messages.push_sys_err(e),
+                }
+            }
+            (AccountAction::Erase, Ok(summary)) => {
+                self.connection.set_resume_token(None, &self.state.channel);
+                self.messages.push_sys_msg(format!(
+                    "Your account is erased, {} messages are no longer yours. Disconnecting...",
+                    summary["anonymized_messages"]
+                ));
+            }
+            (_, Err(e)) => self.messages.push_sys_err(e.to_string()),
+        }
+    }
+
+    /// Log in with the hashed `password`, returns true on success
+    pub async fn login(&mut self, id: &str, password: &str) -> bool {
         if !self.state.is_guest {
             self.messages
                 .push_sys_err("You are already logged in".to_owned());
-            return;
+            return false;
         }
 
+        let proof = match self.prove(id, password).await {
+            Ok(proof) => proof,
+            Err(e) => {
+                self.messages.push_sys_err(format!("Failure: '{}'", e));
+                return false;
+            }
+        };
         let login_info = db::user::Login {
             guest: false,
             id: Some(id.to_owned()),
-            password: Some(hash::sha256_password(password)),
+            proof: Some(proof),
         };
 
         // id backup
@@ -105,22 +505,58 @@ impl App {
         {
             self.messages
                 .push_sys_err(format!("Channel send failed, try again: '{}'", e));
-            return;
+            return false;
         }
 
         // block til Login response
-        match util::consume_til::<LoginRes>(self.incoming_tx.subscribe())
-            .await
-            .result
-        {
+        let res = util::consume_til::<LoginRes>(self.incoming_tx.subscribe()).await;
+        match res.result {
             Ok(_) => {
                 // Succeded to login, you are no longer a guest
                 self.state.id = id_clone;
                 self.state.is_guest = false;
+                self.state.role = res.role;
+                self.connection
+                    .set_resume_token(res.token, &self.state.channel);
                 self.messages.push_sys_msg("Success!".to_owned());
+                let actions = self.scripts.on_connect(&self.state.id);
+                self.apply_script_actions(actions);
+                if let Some(channel) = res.last_channel {
+                    self.rejoin(channel).await;
+                }
+                true
+            }
+            Err(e) => {
+                self.messages.push_sys_err(format!("Failure: '{}'", e));
+                // ask for the password again, the id was probably right
+                if e.code == ErrorCode::WrongCredentials {
+                    self.open_popup(LoginPopupManager::with_id(&id_clone));
+                }
+                false
             }
-            Err(s) => self.messages.push_sys_err(format!("Failure: '{}'", s)),
+        }
+    }
+
+    fn save_credentials(&mut self, id: &str, password: &str, passphrase: &str) {
+        if passphrase.is_empty() {
+            self.messages
+                .push_sys_err("Credentials are not saved, the passphrase is empty".to_owned());
+            return;
+        }
+
+        let saved = Credentials {
+            id: id.to_owned(),
+            password: password.to_owned(),
         };
+        match credentials::save(&saved, passphrase) {
+            Ok(path) => self.messages.push_sys_msg(format!(
+                "Credentials are saved to '{}', use --auto-login to log in with them",
+                path.display()
+            )),
+            Err(e) => self
+                .messages
+                .push_sys_err(format!("Failed to save credentials: {}", e)),
+        }
     }
 
     pub async fn register(
@@ -134,11 +570,17 @@ impl App {
             self.messages
                 .push_sys_err("ID or Password is empty".to_owned());
             return;
+        } else if password.chars().count() < MIN_PASSWORD_LEN {
+            self.messages.push_sys_err(format!(
+                "too short password! (password >= {})",
+                MIN_PASSWORD_LEN
+            ));
+            return;
         }
 
         let user = db::user::User {
             id: id.to_owned(),
-            password: hash::sha256_password(password),
+            verifier: auth::Verifier::new(&hash::sha256_password(password)),
             bio: bio.map(String::from),
             location: location.map(String::from),
         };
@@ -151,105 +593,1272 @@ impl App {
 
         // block til Register response
         self.messages.push_sys_msg(
-            match util::consume_til::<RegisterRes>(self.incoming_tx.subscribe())
-                .await
-                .result
-            {
-                Ok(_) => "Success!".to_owned(),
-                Err(s) => format!("Failure: {}", s),
+            match util::consume_til::<RegisterRes>(self.incoming_tx.subscribe()).await {
+                RegisterRes {
+                    result: Ok(_),
+                    pending: true,
+                } => "Registered! An admin has to approve the account before it can log in, \
+                     you'll be told here"
+                    .to_owned(),
+                RegisterRes { result: Ok(_), .. } => "Success!".to_owned(),
+                RegisterRes { result: Err(s), .. } => format!("Failure: {}", s),
             },
         );
     }
 
+    /// Register a new account and continue the session as it
+    pub async fn upgrade(
+        &mut self,
+        id: &str,
+        password: &str,
+        bio: Option<&str>,
+        location: Option<&str>,
+    ) {
+        if id.is_empty() || password.is_empty() {
+            self.messages
+                .push_sys_err("ID or Password is empty".to_owned());
+            return;
+        } else if password.chars().count() < MIN_PASSWORD_LEN {
+            self.messages.push_sys_err(format!(
+                "too short password! (password >= {})",
+                MIN_PASSWORD_LEN
+            ));
+            return;
+        }
+
+        let user = db::user::User {
+            id: id.to_owned(),
+            verifier: auth::Verifier::new(&hash::sha256_password(password)),
+            bio: bio.map(String::from),
+            location: location.map(String::from),
+        };
+
+        let res_rx = self.incoming_tx.subscribe();
+        if let Err(e) = self
+            .outgoing_tx
+            .send(UpgradeReq { user }.as_json_string())
+            .await
+        {
+            self.messages
+                .push_sys_err(format!("Channel send failed, retry later: {}", e));
+            return;
+        }
+
+        // block til Upgrade response
+        match util::consume_til::<UpgradeRes>(res_rx).await.result {
+            Ok(new_id) => {
+                self.messages.push_sys_msg(format!(
+                    "Success! You are now '{}' in the channel '{}'",
+                    new_id, self.state.channel
+                ));
+                self.state.id = new_id;
+                self.state.is_guest = false;
+            }
+            Err(e) => self.messages.push_sys_err(format!("Failure: {}", e)),
+        }
+    }
+
+    /// Apply packets pushed by the server that change the state of the session
+    ///
+    /// Returns true if any packet was handled, the screen may have to be redrawn.
+    pub fn handle_pushed_packets(&mut self) -> bool {
+        let mut handled = false;
+        loop {
+            let msg = match self.pushed_rx.try_recv() {
+                Ok(msg) => msg,
+                Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            };
+            handled = true;
+
+            if let Some(msg) = util::parse_packet::<Message>(&msg) {
+                if !msg.is_system && self.messages.ignored.contains(&msg.id) {
+                    continue;
+                }
+                self.notifications
+                    .on_message(&msg, &self.state.id, &self.state.channel);
+                self.run_message_scripts(&msg);
+            } else if let Some(invite) = util::parse_packet::<Invite>(&msg) {
+                if self.messages.ignored.contains(&invite.from) {
+                    continue;
+                }
+                self.messages.push_sys_msg(format!(
+                    "'{}' invited you to the channel '{}', type /accept to join",
+                    invite.from, invite.channel_name
+                ));
+                self.state.pending_invite = Some(invite.channel_name);
+            } else if let Some(mention) = util::parse_packet::<Mention>(&msg) {
+                if self.messages.ignored.contains(&mention.message.id) {
+                    continue;
+                }
+                self.messages.push_mention(&mention);
+                self.notifications.on_mention(&mention, &self.state.id);
+                let actions = self.scripts.on_mention(
+                    &mention.message.id,
+                    &mention.message.msg,
+                    &mention.channel_name,
+                );
+                self.apply_script_actions(actions);
+                self.state.last_mention = Some(mention.channel_name);
+            } else if let Some(info) = util::parse_packet::<ChannelInfo>(&msg) {
+                self.state.channel_info = Some(info);
+            } else if let Some(read) = util::parse_packet::<ReadMarkers>(&msg) {
+                // read on another terminal, or not read at all since
+                for (channel, unread) in read.unread {
+                    self.notifications.set_unread(&channel, unread);
+                }
+                self.messages.set_read_markers(read.markers);
+            } else if let Some(closed) = util::parse_packet::<ChannelClosed>(&msg) {
+                self.messages.push_sys_err(format!(
+                    "{}, you've been moved to the channel: '{}'",
+                    closed.reason, closed.moved_to
+                ));
+                self.messages.set_channel(&closed.moved_to);
+                self.connection.set_resume_channel(&closed.moved_to);
+                self.state.channel = closed.moved_to;
+                self.reply_to = None;
+                self.thread = None;
+            } else if let Some(res) = util::parse_packet::<ResumeRes>(&msg) {
+                match res.result {
+                    Ok(id) => {
+                        // the chat goes on as if the connection was never lost
+                        self.activity
+                            .info(format!("The session of '{}' is resumed", id));
+                        self.connection
+                            .set_resume_token(res.token, &self.state.channel);
+                        self.state.id = id;
+                        self.state.role = res.role;
+                    }
+                    Err(e) => {
+                        self.connection.set_resume_token(None, &self.state.channel);
+                        self.messages
+                            .push_sys_err(format!("The session can't be resumed: {}", e));
+                    }
+                }
+            } else if let Some(res) = util::parse_packet::<PresenceRes>(&msg) {
+                match res.result {
+                    Ok(presence) => {
+                        self.messages
+                            .push_sys_msg(format!("Your presence is '{}'", presence.as_str()));
+                        self.notifications.dnd = presence == Presence::Dnd;
+                        self.state.presence = presence;
+                    }
+                    Err(e) => self
+                        .messages
+                        .push_sys_err(format!("Failed to change the presence: {}", e)),
+                }
+            }
+        }
+        handled
+    }
+
+    /// Hand a message of someone else to the scripts, your own would let them answer themselves
+    fn run_message_scripts(&mut self, msg: &Message) {
+        if msg.is_system || msg.id == self.state.id {
+            return;
+        }
+        let channel = match msg.to {
+            Some(_) => String::new(),
+            None => self.state.channel.clone(),
+        };
+        let mut actions = self.scripts.on_message(&msg.id, &msg.msg, &channel);
+        if Notifications::mentions(&msg.msg, &self.state.id) {
+            actions.extend(self.scripts.on_mention(&msg.id, &msg.msg, &channel));
+        }
+        self.apply_script_actions(actions);
+    }
+
+    /// Do what the scripts asked for, their messages go to the current channel
+    pub fn apply_script_actions(&mut self, actions: Vec<ScriptAction>) {
+        for action in actions {
+            match action {
+                ScriptAction::Send(_) if self.state.is_read_only() => self
+                    .messages
+                    .push_sys_err("A script can't post, the channel is read-only".to_owned()),
+                ScriptAction::Send(text) => {
+                    let msg = Message {
+                        id: self.state.id.clone(),
+                        msg: text,
+                        is_system: false,
+                        to: None,
+                        seq: None,
+                        retracted: false,
+                        display_name: None,
+                        reply_to: None,
+                        thread: None,
+                    };
+                    if self.outgoing_tx.try_send(msg.as_json_string()).is_err() {
+                        self.messages
+                            .push_sys_err("A message of a script couldn't be sent".to_owned());
+                    }
+                }
+                ScriptAction::Print(text) => self.messages.push_sys_msg(text),
+                ScriptAction::Error(text) => self.messages.push_sys_err(text),
+            }
+        }
+    }
+
+    /// Switch to the channel `channel_name`
+    pub async fn goto(&mut self, channel_name: String) {
+        self.goto_with(channel_name, None).await;
+    }
+
+    /// Switch to the channel `channel_name` with the hashed `password` it asks for, the password
+    /// is prompted for if the channel asks and none was given
+    async fn goto_with(&mut self, channel_name: String, password: Option<String>) {
+        let res_rx = self.incoming_tx.subscribe();
+        let asked = password.is_some();
+        _ = self
+            .outgoing_tx
+            .send(
+                GotoReq {
+                    channel_name: channel_name.clone(),
+                    password,
+                }
+                .as_json_string(),
+            )
+            .await;
+        let res = util::consume_til::<GotoRes>(res_rx).await;
+        match res.result {
+            Err(e) if e.code == ErrorCode::WrongCredentials && !asked => {
+                self.open_popup(
+                    popup::prompt::PromptPopupManager::new(
+                        &format!("Password of the channel '{}'", channel_name),
+                        "password",
+                        CommandAction::Goto,
+                    )
+                    .masked()
+                    .with_args(serde_json::json!({ "choice": channel_name })),
+                );
+            }
+            Ok(name) => {
+                // goto succeeded, change channel
+                self.messages.push_sys_msg(format!(
+                    "You've succesfully switched to the channel: '{}'",
+                    &name
+                ));
+                if let Some(topic) = &res.topic {
+                    self.messages.push_topic(&name, topic);
+                }
+                self.messages.set_channel(&name);
+                self.connection.set_resume_channel(&name);
+                // the server only keeps it for the members, and a missing file is left to the
+                // onboarding of the next launch
+                if self.state.is_guest && self.rejoin_last_channel {
+                    if let Some(mut config) = ClientConfig::load() {
+                        config.last_channel = Some(name.clone());
+                        if let Err(e) = config.save() {
+                            self.activity
+                                .error(format!("Failed to save the last channel: {}", e));
+                        }
+                    }
+                }
+                self.state.channel = name;
+                self.reply_to = None;
+                self.thread = None;
+            }
+            Err(e) => self
+                .messages
+                .push_sys_err(format!("failed to join channel: '{}'", e)),
+        }
+    }
+
+    /// Go back to `channel`, where the last session was
+    pub async fn rejoin(&mut self, channel: String) {
+        if !self.rejoin_last_channel || channel == self.state.channel {
+            return;
+        }
+        self.messages.push_sys_msg(format!(
+            "Rejoining '{}' where you left off, 'rejoin_last_channel' of client.json turns it off",
+            channel
+        ));
+        self.goto(channel).await;
+    }
+
+    /// Refresh the cached names of the channels, used to resolve and complete channel names
+    async fn refresh_channel_list(&mut self) {
+        let req = FetchReq {
+            item: FetchItem::Channels,
+        };
+        if let Some(res) = self.fetch(req).await {
+            self.state.channel_list =
+                serde_json::from_value(res["channels"].clone()).unwrap_or_default();
+        }
+    }
+
+    /// Open a popup listing the channels with their users and topics, the one picked is joined
+    async fn browse_channels(&mut self) {
+        let req = FetchReq {
+            item: FetchItem::Channels,
+        };
+        let Some(res) = self.fetch(req).await else {
+            return;
+        };
+        self.state.channel_list =
+            serde_json::from_value(res["channels"].clone()).unwrap_or_default();
+
+        let details = res["details"].as_array().cloned().unwrap_or_default();
+        if details.is_empty() {
+            self.messages
+                .push_sys_err("There are no channels to join".to_owned());
+            return;
+        }
+        let mut choices = Vec::new();
+        let mut labels = Vec::new();
+        for channel in &details {
+            let name = channel["channel"].as_str().unwrap_or_default();
+            let mut label = format!(
+                "#{} ({} users, {} guests)",
+                name,
+                channel["num_user"].as_u64().unwrap_or_default(),
+                channel["num_guest"].as_u64().unwrap_or_default()
+            );
+            if name == self.state.channel {
+                label.push_str(" *");
+            }
+            if let Some(topic) = channel["topic"].as_str() {
+                label.push_str(" - ");
+                label.push_str(topic);
+            }
+            choices.push(name.to_owned());
+            labels.push(label);
+        }
+        let current = choices
+            .iter()
+            .position(|name| *name == self.state.channel)
+            .unwrap_or_default();
+        self.open_popup(
+            popup::select::SelectPopupManager::new(
+                &format!("Channels ({})", choices.len()),
+                choices,
+                CommandAction::Goto,
+            )
+            .with_labels(labels)
+            .with_selected(current),
+        );
+    }
+
+    /// Switch to the channel best matching `query`, asking the user if several match alike
+    pub async fn goto_matching(&mut self, query: String) {
+        self.refresh_channel_list().await;
+        let matches = util::fuzzy_match(&query, &self.state.channel_list);
+        match matches[..] {
+            // the server tells why it can't be joined
+            [] => self.goto(query).await,
+            [name] => self.goto(name.to_owned()).await,
+            _ => {
+                let choices = matches.into_iter().map(str::to_owned).collect();
+                self.open_popup(popup::select::SelectPopupManager::new(
+                    &format!("Channels matching '{}'", query),
+                    choices,
+                    CommandAction::Goto,
+                ));
+            }
+        }
+    }
+
+    /// Turn away once idle for long enough, members only
+    pub async fn check_idle(&mut self) {
+        if !self.state.is_guest && self.away.due(self.state.presence) {
+            self.request_presence(Presence::Away).await;
+        }
+    }
+
+    /// A key was pressed, back online if the idle time made the user away
+    pub async fn on_activity(&mut self) {
+        if self.away.touch() && self.state.presence == Presence::Away {
+            self.request_presence(Presence::Online).await;
+        }
+    }
+
+    async fn request_presence(&mut self, presence: Presence) {
+        _ = self
+            .outgoing_tx
+            .send(PresenceReq { presence }.as_json_string())
+            .await;
+    }
+
+    /// Offer the closest spellings of the word at the cursor in a popup
+    pub async fn suggest_spelling(&mut self) {
+        let Some(checker) = &self.spell else {
+            self.messages.push_sys_err(
+                "Spell checking is off, start with '--spell-check <dictionary>'".to_owned(),
+            );
+            return;
+        };
+        let buf = &self.main_input.buf;
+        let Some(range) = spell::word_at(buf, self.main_input.cursor_pos) else {
+            return;
+        };
+        let word = buf[range.clone()].to_owned();
+        match checker.suggest(&word).await {
+            None => self
+                .messages
+                .push_sys_err("The dictionary isn't loaded yet".to_owned()),
+            Some(choices) if choices.is_empty() => self
+                .messages
+                .push_sys_msg(format!("No suggestions for '{}'", word)),
+            Some(choices) => self.open_popup(
+                popup::select::SelectPopupManager::new(
+                    &format!("Spelling of '{}'", word),
+                    choices,
+                    CommandAction::Correct,
+                )
+                .with_args(serde_json::json!({ "start": range.start, "end": range.end })),
+            ),
+        }
+    }
+
+    /// Retract the latest message sent to the current channel, the server tells whether it's
+    /// still recent enough
+    pub async fn retract_last(&mut self) {
+        let Some(seq) = self.messages.last_of(&self.state.channel, &self.state.id) else {
+            self.messages
+                .push_sys_err("You have no message here to retract".to_owned());
+            return;
+        };
+        _ = self
+            .outgoing_tx
+            .send(RetractReq { seq }.as_json_string())
+            .await;
+    }
+
+    /// Complete the channel name under the cursor from the cached channel list
+    pub async fn complete_channel(&mut self) {
+        let buf = self.main_input.buf.clone();
+        let words: Vec<&str> = buf.split(' ').collect();
+        let takes_channel = matches!(
+            words[..],
+            ["/goto" | "/mute" | "/unmute", _]
+                | ["/channel", "delete" | "archive", _]
+                | ["/invite", _, _]
+        );
+        if !takes_channel || self.main_input.cursor_pos != buf.len() {
+            return;
+        }
+        if self.state.channel_list.is_empty() {
+            self.refresh_channel_list().await;
+        }
+
+        let partial = words.last().copied().unwrap_or_default();
+        let candidates: Vec<&str> = self
+            .state
+            .channel_list
+            .iter()
+            .map(String::as_str)
+            .filter(|name| name.starts_with(partial))
+            .collect();
+        let completion = match candidates[..] {
+            [] => return,
+            [name] => format!("{} ", name),
+            _ => {
+                self.messages
+                    .push_sys_msg(format!("Channels: {}", candidates.join(", ")));
+                util::common_prefix(&candidates)
+            }
+        };
+        self.main_input
+            .insert_str(&completion[partial.len()..], false);
+    }
+
+    /// Send a channel request and print the result, `password` is the hashed one of a new channel
+    async fn manage_channel(
+        &mut self,
+        action: ChannelAction,
+        channel_name: String,
+        password: Option<String>,
+    ) {
+        _ = self
+            .outgoing_tx
+            .send(
+                ChannelReq {
+                    action,
+                    channel_name,
+                    password,
+                }
+                .as_json_string(),
+            )
+            .await;
+        match util::consume_til::<ChannelRes>(self.incoming_tx.subscribe())
+            .await
+            .result
+        {
+            Ok(msg) => self.messages.push_sys_msg(msg),
+            Err(e) => self.messages.push_sys_err(e),
+        }
+    }
+
+    /// Send `req` and wait for the result, errors are printed
+    async fn fetch(&mut self, req: FetchReq) -> Option<serde_json::Value> {
+        let incoming_rx = self.incoming_tx.subscribe();
+        if let Err(e) = self.outgoing_tx.send(req.as_json_string()).await {
+            self.messages
+                .push_sys_err(format!("Channel send failed, try again: '{}'", e));
+            return None;
+        }
+
+        // block til Fetch response
+        match util::consume_til::<FetchRes>(incoming_rx).await.result {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.messages.push_sys_err(e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Print the statistics of every channel as a table
+    fn print_stats(&mut self, stats: &serde_json::Value) {
+        let mut table = vec![format!(
+            "{:<16} {:>6} {:>6} {:>5} {:>5}  {:<10}  {}",
+            "channel", "1h", "24h", "users", "peak", "created", "top speakers"
+        )];
+        for channel in stats["channels"].as_array().into_iter().flatten() {
+            let speakers = channel["top_speakers"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|s| format!("{} ({})", s["id"].as_str().unwrap_or_default(), s["count"]))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let created = util::format_time(channel["created_at"].as_u64().unwrap_or_default());
+            table.push(format!(
+                "{:<16} {:>6} {:>6} {:>5} {:>5}  {:<10}  {}",
+                channel["channel"].as_str().unwrap_or_default(),
+                channel["last_hour"],
+                channel["last_day"],
+                channel["users"],
+                channel["peak_users"],
+                created.split(' ').next().unwrap_or_default(),
+                speakers
+            ));
+        }
+        let load = &stats["load"];
+        if load.is_object() {
+            table.push(format!(
+                "server load: {}/{} sessions, {} connections turned away",
+                load["sessions"], load["max_sessions"], load["shed"]
+            ));
+        }
+        let traffic = &stats["traffic"];
+        if traffic.is_object() {
+            table.push(format!(
+                "traffic: {} bytes in, {} bytes out, {} messages",
+                traffic["bytes_in"], traffic["bytes_out"], traffic["messages_in"]
+            ));
+        }
+        let dropped = &stats["dead_letters"];
+        if dropped.is_object() {
+            let count = |reason: &str| dropped[reason].as_u64().unwrap_or_default();
+            table.push(format!(
+                "dropped packets: {} closed, {} full, {} lagged",
+                count("closed"),
+                count("full"),
+                count("lagged")
+            ));
+        }
+        self.messages.push_sys_msg(table.join("\n"));
+    }
+
+    fn print_traffic(&mut self, report: &serde_json::Value) {
+        let row = |name: &str, totals: &serde_json::Value| {
+            format!(
+                "{:<24} {:>10} {:>10} {:>8} {:>8}",
+                name,
+                totals["bytes_in"],
+                totals["bytes_out"],
+                totals["packets_in"],
+                totals["messages_in"]
+            )
+        };
+        let header = format!(
+            "{:<24} {:>10} {:>10} {:>8} {:>8}",
+            "", "bytes in", "bytes out", "packets", "messages"
+        );
+
+        let mut table = vec![format!("open connections\n{}", header)];
+        for conn in report["connections"].as_array().into_iter().flatten() {
+            let id = conn["id"]
+                .as_str()
+                .filter(|id| !id.is_empty())
+                .unwrap_or("-");
+            let name = format!("{} {}", id, conn["addr"].as_str().unwrap_or_default());
+            table.push(row(&name, &conn["totals"]));
+        }
+        table.push(format!("busiest accounts\n{}", header));
+        for account in report["accounts"].as_array().into_iter().flatten() {
+            table.push(row(
+                account["id"].as_str().unwrap_or_default(),
+                &account["totals"],
+            ));
+        }
+        table.push(row("total", &report["totals"]));
+        self.messages.push_sys_msg(table.join("\n"));
+    }
+
+    fn print_dead_letters(&mut self, report: &serde_json::Value) {
+        let mut table = vec![format!("{:<12} {:<8} {:>8}", "queue", "reason", "packets")];
+        for count in report["counts"].as_array().into_iter().flatten() {
+            table.push(format!(
+                "{:<12} {:<8} {:>8}",
+                count["queue"].as_str().unwrap_or_default(),
+                count["reason"].as_str().unwrap_or_default(),
+                count["count"]
+            ));
+        }
+        table.push("latest drops".to_owned());
+        for sample in report["samples"].as_array().into_iter().flatten() {
+            let at = util::format_time(sample["at"].as_u64().unwrap_or_default());
+            table.push(format!(
+                "{} {:<12} {:<8} {} x{}",
+                at,
+                sample["queue"].as_str().unwrap_or_default(),
+                sample["reason"].as_str().unwrap_or_default(),
+                sample["what"].as_str().unwrap_or_default(),
+                sample["count"]
+            ));
+        }
+        self.messages.push_sys_msg(table.join("\n"));
+    }
+
+    /// Print a page of the user list and keep the query for `/fetch next` and `/fetch prev`
+    fn print_user_page(&mut self, mut query: UserListQuery, page: &serde_json::Value) {
+        let users: Vec<&str> = page["user_list"]
+            .as_array()
+            .map(|users| users.iter().filter_map(|u| u.as_str()).collect())
+            .unwrap_or_default();
+        query.total = page["total"].as_u64().unwrap_or_default() as usize;
+
+        let matching = match &query.filter {
+            Some(filter) => format!(" matching '{}'", filter),
+            None => String::new(),
+        };
+        if users.is_empty() {
+            self.messages
+                .push_sys_msg(format!("No users{} in the channel", matching));
+        } else {
+            self.messages.push_sys_msg(format!(
+                "Users {}-{} of {}{} ({} members, {} guests): {}",
+                query.offset + 1,
+                query.offset + users.len(),
+                query.total,
+                matching,
+                page["num_user"],
+                page["num_guest"],
+                users.join(", ")
+            ));
+            let mut hints = Vec::new();
+            if query.offset > 0 {
+                hints.push("'/fetch prev'");
+            }
+            if query.offset + users.len() < query.total {
+                hints.push("'/fetch next'");
+            }
+            if !hints.is_empty() {
+                self.messages
+                    .push_sys_msg(format!("More users: {}", hints.join(", ")));
+            }
+        }
+        self.state.user_list_query = Some(query);
+    }
+
+    /// Run the command line of the input, one command after another if it's an alias
     pub async fn handle_command(&mut self) -> HandleCommandStatus {
-        match Command::from_str(&self.main_input.buf) {
-            Ok(Command::Help) => Command::help(),
+        let line = self.main_input.buf.clone();
+        let lines = match self.aliases.expand(&line) {
+            Some(Ok(lines)) => lines,
+            Some(Err(e)) => {
+                self.messages.push_sys_err(e);
+                return HandleCommandStatus::Continue;
+            }
+            None => vec![line],
+        };
+        for line in lines {
+            if self.run_command(&line).await == HandleCommandStatus::Exit {
+                return HandleCommandStatus::Exit;
+            }
+        }
+        HandleCommandStatus::Continue
+    }
+
+    async fn run_command(&mut self, line: &str) -> HandleCommandStatus {
+        match Command::parse(line, self.state.is_guest) {
+            Ok(Command::Help) => self.open_popup(popup::help::HelpPopupManager::new()),
             Ok(Command::Get(item)) => match &item[..] {
                 "info" | "name" => self
                     .messages
                     .push_sys_msg(format!("Your ID: '{}'", self.state.id)),
+                "version" => {
+                    let server = match &self.state.server {
+                        Some(s) => format!("'{}' (protocol v{})", s.software, s.version),
+                        None => "unknown".to_owned(),
+                    };
+                    self.messages.push_sys_msg(format!(
+                        "Client: '{}' (protocol v{}), Server: {}",
+                        software_version(),
+                        PROTOCOL_VERSION,
+                        server
+                    ));
+                }
+                "server" => match &self.state.server {
+                    Some(res) => {
+                        for line in session::describe_server(res, self.connection.is_lite()) {
+                            self.messages.push_sys_msg(line);
+                        }
+                    }
+                    None => self
+                        .messages
+                        .push_sys_err("Not connected to a server yet".to_owned()),
+                },
                 _ => self
                     .messages
                     .push_sys_err(format!("Unknown item for 'get' command: '{}'", item)),
             },
             Ok(Command::Register) => {
-                self.main_input.normal_mode();
-                self.popup = Some(Box::new(RegisterPopupManager::new()));
+                self.open_popup(RegisterPopupManager::new());
             }
-            Ok(Command::Login()) => {
-                self.main_input.normal_mode();
-                self.popup = Some(Box::new(LoginPopupManager::new()));
+            Ok(Command::Upgrade) => self.open_popup(RegisterPopupManager::for_upgrade()),
+            Ok(Command::Login(save)) => {
+                if save {
+                    self.open_popup(LoginPopupManager::with_save());
+                } else if let Some(name) = &self.display_name {
+                    self.open_popup(LoginPopupManager::with_id(name));
+                } else {
+                    self.open_popup(LoginPopupManager::new());
+                }
             }
-            Ok(Command::Fetch(fetch)) => {
-                let item_str = match fetch {
-                    Fetch::UserList => "list",
-                    _ => {
+            Ok(Command::Forget) => match credentials::forget() {
+                Ok(true) => self
+                    .messages
+                    .push_sys_msg("Saved credentials are wiped".to_owned()),
+                Ok(false) => self
+                    .messages
+                    .push_sys_msg("There are no saved credentials".to_owned()),
+                Err(e) => self
+                    .messages
+                    .push_sys_err(format!("Failed to wipe credentials: {}", e)),
+            },
+            Ok(Command::Fetch(Fetch::Stats)) => {
+                let fetch_req = FetchReq {
+                    item: FetchItem::Stats,
+                };
+                if let Some(stats) = self.fetch(fetch_req).await {
+                    self.print_stats(&stats);
+                }
+            }
+            Ok(Command::Fetch(Fetch::Traffic)) => {
+                let req = FetchReq {
+                    item: FetchItem::Traffic,
+                };
+                if let Some(report) = self.fetch(req).await {
+                    self.print_traffic(&report);
+                }
+            }
+            Ok(Command::Fetch(Fetch::DeadLetters)) => {
+                let req = FetchReq {
+                    item: FetchItem::DeadLetters,
+                };
+                if let Some(report) = self.fetch(req).await {
+                    self.print_dead_letters(&report);
+                }
+            }
+            Ok(Command::Fetch(Fetch::Retracted)) => {
+                let req = FetchReq {
+                    item: FetchItem::Retracted,
+                };
+                if let Some(res) = self.fetch(req).await {
+                    let messages: Vec<Message> =
+                        serde_json::from_value(res["messages"].clone()).unwrap_or_default();
+                    self.messages.push_sys_msg(if messages.is_empty() {
+                        "No messages were retracted recently in this channel".to_owned()
+                    } else {
+                        messages
+                            .iter()
+                            .map(|msg| {
+                                format!(
+                                    "Retracted #{} {}: {}",
+                                    msg.seq.unwrap_or_default(),
+                                    msg.id,
+                                    msg.msg
+                                )
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    });
+                }
+            }
+            Ok(Command::Fetch(Fetch::Moderation)) => {
+                let req = FetchReq {
+                    item: FetchItem::Moderation,
+                };
+                if let Some(res) = self.fetch(req).await {
+                    let mut lines: Vec<String> = res["banned"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|id| id.as_str())
+                        .map(|id| format!("Banned {}", id))
+                        .collect();
+                    for mute in res["muted"].as_array().into_iter().flatten() {
+                        let id = mute["id"].as_str().unwrap_or_default();
+                        lines.push(match mute["until"].as_u64() {
+                            Some(until) => {
+                                format!("Muted {} until {}", id, util::format_time(until))
+                            }
+                            None => format!("Muted {} until unmuted", id),
+                        });
+                    }
+                    self.messages.push_sys_msg(if lines.is_empty() {
+                        "Nobody is banned or muted in this channel".to_owned()
+                    } else {
+                        lines.join("\n")
+                    });
+                }
+            }
+            Ok(Command::Fetch(
+                fetch @ (Fetch::UserList(_) | Fetch::NextPage | Fetch::PrevPage),
+            )) => {
+                let query = match (fetch, self.state.user_list_query.take()) {
+                    (Fetch::UserList(filter), _) => UserListQuery {
+                        filter,
+                        ..Default::default()
+                    },
+                    (Fetch::NextPage, Some(query)) => {
+                        let offset = query.offset + USER_LIST_PAGE_SIZE;
+                        if offset >= query.total {
+                            self.messages
+                                .push_sys_err("This is the last page".to_owned());
+                            self.state.user_list_query = Some(query);
+                            return HandleCommandStatus::Continue;
+                        }
+                        UserListQuery { offset, ..query }
+                    }
+                    (Fetch::PrevPage, Some(query)) => {
+                        if query.offset == 0 {
+                            self.messages
+                                .push_sys_err("This is the first page".to_owned());
+                            self.state.user_list_query = Some(query);
+                            return HandleCommandStatus::Continue;
+                        }
+                        UserListQuery {
+                            offset: query.offset.saturating_sub(USER_LIST_PAGE_SIZE),
+                            ..query
+                        }
+                    }
+                    (Fetch::NextPage | Fetch::PrevPage, None) => {
                         self.messages
-                            .push_sys_err("Unhandled fetch item".to_owned());
+                            .push_sys_err("Fetch the user list first: '/fetch list'".to_owned());
+                        return HandleCommandStatus::Continue;
+                    }
+                    // the other items have their own arms
+                    (_, query) => {
+                        self.state.user_list_query = query;
                         return HandleCommandStatus::Continue;
                     }
                 };
 
-                if let Err(e) = self
+                let fetch_req = FetchReq {
+                    item: FetchItem::UserList {
+                        offset: query.offset,
+                        limit: Some(USER_LIST_PAGE_SIZE),
+                        filter: query.filter.clone(),
+                    },
+                };
+                if let Some(page) = self.fetch(fetch_req).await {
+                    self.print_user_page(query, &page);
+                }
+            }
+            Ok(Command::Goto(query)) => self.goto_matching(query).await,
+            Ok(Command::Invite(to, channel_name)) => {
+                let invite = Invite {
+                    from: String::new(),
+                    to,
+                    channel_name: channel_name.unwrap_or_else(|| self.state.channel.clone()),
+                };
+                _ = self.outgoing_tx.send(invite.as_json_string()).await;
+            }
+            Ok(Command::Channels) => self.browse_channels().await,
+            Ok(Command::Jump) => match self.state.last_mention.take() {
+                Some(channel_name) if channel_name != self.state.channel => {
+                    self.goto(channel_name).await
+                }
+                Some(_) => self
+                    .messages
+                    .push_sys_msg("You are already in the channel".to_owned()),
+                None => self
+                    .messages
+                    .push_sys_err("You haven't been mentioned elsewhere".to_owned()),
+            },
+            Ok(Command::Remind(after, text)) => {
+                self.messages.push_sys_msg(format!(
+                    "You'll be reminded at {} UTC",
+                    util::format_time(util::unix_time() + after.as_secs())
+                ));
+                let mut messages = self.messages.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(after).await;
+                    messages.push_sys_msg(format!("Reminder: {}", text));
+                });
+            }
+            Ok(Command::Schedule(at, msg)) => {
+                let res_rx = self.incoming_tx.subscribe();
+                _ = self
                     .outgoing_tx
-                    .send(
-                        FetchReq {
-                            item: item_str.to_owned(),
-                        }
-                        .as_json_string(),
+                    .send(ScheduleReq { at, msg }.as_json_string())
+                    .await;
+                let res = util::consume_til::<ScheduleRes>(res_rx).await;
+                match res.result {
+                    Ok(id) => self.messages.push_sys_msg(format!(
+                        "Message #{} is scheduled for {} UTC",
+                        id,
+                        util::format_time(res.at)
+                    )),
+                    Err(e) => self
+                        .messages
+                        .push_sys_err(format!("Failed to schedule: {}", e)),
+                }
+            }
+            // the response is handled along with the pushed packets
+            Ok(Command::Presence(presence)) => self.request_presence(presence).await,
+            Ok(Command::Accept) => match self.state.pending_invite.take() {
+                Some(channel_name) => self.goto(channel_name).await,
+                None => self
+                    .messages
+                    .push_sys_err("You have no pending invitation".to_owned()),
+            },
+            Ok(Command::Render(option, enabled)) => match &option[..] {
+                "markdown" => {
+                    self.render_options.markdown = enabled;
+                    self.messages.push_sys_msg(format!(
+                        "Markdown rendering is {}",
+                        if enabled { "on" } else { "off" }
+                    ));
+                }
+                _ => self
+                    .messages
+                    .push_sys_err(format!("Unknown render option: '{}'", option)),
+            },
+            Ok(Command::Filter(Some((kind, shown)))) => {
+                if shown {
+                    self.hidden_kinds.remove(&kind);
+                } else {
+                    self.hidden_kinds.insert(kind);
+                }
+                self.messages.push_sys_msg(format!(
+                    "Messages of the kind '{}' are {}",
+                    kind.as_str(),
+                    if shown { "shown" } else { "hidden" }
+                ));
+            }
+            Ok(Command::Filter(None)) => {
+                let hidden: Vec<&str> = self.hidden_kinds.iter().map(Kind::as_str).collect();
+                self.messages.push_sys_msg(if hidden.is_empty() {
+                    "Every kind of message is shown".to_owned()
+                } else {
+                    format!(
+                        "Hidden: {}, the kinds are {}",
+                        hidden.join(", "),
+                        Kind::ALL.map(|kind| kind.as_str()).join(", ")
                     )
-                    .await
-                {
-                    self.messages
-                        .push_sys_err(format!("Channel send failed, try again: '{}'", e));
-                    return HandleCommandStatus::Continue;
-                }
-
-                // block til Login response
-                let fetch_res = util::consume_til::<FetchRes>(self.incoming_tx.subscribe()).await;
-                match fetch_res.item.as_str() {
-                    "list" => match fetch_res.result {
-                        Ok(v) => self
-                            .messages
-                            .push_sys_msg(serde_json::to_string_pretty(&v).unwrap()),
-                        Err(e) => self.messages.push_sys_err(e),
-                    },
-                    unknown => self
+                });
+            }
+            Ok(Command::Theme(theme)) => {
+                self.render_options.theme = theme;
+                // the next launches start with it too
+                let mut config = ClientConfig::load().unwrap_or_default();
+                config.theme = theme;
+                match config.save() {
+                    Ok(_) => self
                         .messages
-                        .push_sys_err(format!("unknown item: '{}'", unknown)),
+                        .push_sys_msg(format!("The theme is {}", theme.as_str())),
+                    Err(e) => self.messages.push_sys_err(format!(
+                        "The theme is {}, failed to save it: {}",
+                        theme.as_str(),
+                        e
+                    )),
                 }
             }
-            Ok(Command::Goto(channel_name)) => {
+            Ok(Command::Channel(ChannelAction::Create, None)) => {
+                self.open_popup(
+                    popup::prompt::PromptPopupManager::new(
+                        "Name of the new channel",
+                        "channel_name",
+                        CommandAction::ManageChannel,
+                    )
+                    .with_args(serde_json::json!({ "action": ChannelAction::Create })),
+                );
+            }
+            // can't be undone, ask first
+            Ok(Command::Channel(
+                action @ (ChannelAction::Delete | ChannelAction::Archive),
+                channel_name,
+            )) => {
+                let channel_name = channel_name.unwrap_or(self.state.channel.clone());
+                let question = format!(
+                    "{} the channel '{}'? This can't be undone.",
+                    if matches!(action, ChannelAction::Delete) {
+                        "Delete"
+                    } else {
+                        "Archive"
+                    },
+                    channel_name
+                );
+                self.open_popup(popup::confirm::ConfirmPopupManager::new(
+                    &question,
+                    CommandAction::ManageChannel,
+                    Some(serde_json::json!({ "action": action, "channel_name": channel_name })),
+                ));
+            }
+            Ok(Command::ProtectedChannel(channel_name)) => {
+                self.open_popup(
+                    popup::prompt::PromptPopupManager::new(
+                        &format!("Password of the new channel '{}'", channel_name),
+                        "password",
+                        CommandAction::ManageChannel,
+                    )
+                    .masked()
+                    .with_args(serde_json::json!({
+                        "action": ChannelAction::Create,
+                        "channel_name": channel_name,
+                    })),
+                );
+            }
+            Ok(Command::Channel(action, channel_name)) => {
+                let channel_name = channel_name.unwrap_or(self.state.channel.clone());
+                self.manage_channel(action, channel_name, None).await;
+            }
+            Ok(Command::Msg(to, msg)) => self.send_direct_message(to, msg).await,
+            Ok(Command::Notify(setting)) => {
+                self.notifications.apply(setting);
+                self.messages.push_sys_msg(format!(
+                    "Notifications: {:?}, bell: {}, flash: {}",
+                    self.notifications.trigger, self.notifications.bell, self.notifications.flash
+                ));
+            }
+            Ok(Command::Export(path, json)) => {
+                let entries = self.messages.channel_entries(&self.state.channel);
+                let path = path.unwrap_or(export::default_path(&self.state.channel, json));
+                self.messages.push_sys_msg(format!(
+                    "Exporting {} messages of '{}'...",
+                    entries.len(),
+                    self.state.channel
+                ));
+
+                let messages = self.messages.clone();
+                let total = entries.len();
+                match export::write_log(&path, &entries, json, |n| {
+                    messages.push(
+                        Kind::SystemInfo,
+                        "System".to_owned(),
+                        format!("Exported {}/{}", n, total),
+                    )
+                }) {
+                    Ok(abs_path) => self.messages.push_sys_msg(format!(
+                        "Exported {} messages to '{}'",
+                        total,
+                        abs_path.display()
+                    )),
+                    Err(e) => self.messages.push_sys_err(e),
+                }
+            }
+            Ok(Command::Mute(channel)) => {
+                self.messages
+                    .push_sys_msg(format!("Channel '{}' is muted", channel));
+                self.notifications.muted.insert(channel);
+            }
+            Ok(Command::Unmute(channel)) => {
+                self.messages
+                    .push_sys_msg(format!("Channel '{}' is unmuted", channel));
+                self.notifications.muted.remove(&channel);
+            }
+            Ok(Command::Ping) => {
+                let timestamp = util::unix_time_millis();
+                self.connection.request_ping(timestamp);
                 _ = self
                     .outgoing_tx
-                    .send(GotoReq { channel_name }.as_json_string())
+                    .send(Ping { timestamp }.as_json_string())
                     .await;
-                match util::consume_til::<GotoRes>(self.incoming_tx.subscribe())
-                    .await
-                    .result
-                {
-                    Ok(name) => {
-                        // goto succeeded, change channel
-                        self.messages.push_sys_msg(format!(
-                            "You've succesfully switched to the channel: '{}'",
-                            &name
+            }
+            Ok(Command::React(seq, emoji)) => {
+                let req = ReactionReq { seq, emoji };
+                _ = self.outgoing_tx.send(req.as_json_string()).await;
+            }
+            Ok(Command::Pin(seq, pinned)) => {
+                let req = PinReq { seq, pinned };
+                _ = self.outgoing_tx.send(req.as_json_string()).await;
+            }
+            Ok(Command::Pins) => {
+                let req = FetchReq {
+                    item: FetchItem::Pins,
+                };
+                if let Some(res) = self.fetch(req).await {
+                    let pins: Vec<Message> =
+                        serde_json::from_value(res["pins"].clone()).unwrap_or_default();
+                    self.messages.push_sys_msg(if pins.is_empty() {
+                        "No messages are pinned in this channel".to_owned()
+                    } else {
+                        pins.iter()
+                            .map(|msg| {
+                                format!(
+                                    "Pinned #{} {}: {}",
+                                    msg.seq.unwrap_or_default(),
+                                    msg.id,
+                                    msg.msg
+                                )
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    });
+                    self.messages.set_pins(&self.state.channel, pins);
+                    self.pins_collapsed = false;
+                }
+            }
+            Ok(Command::Thread(None)) => self.thread = None,
+            Ok(Command::Thread(Some(seq))) => {
+                let req = FetchReq {
+                    item: FetchItem::Thread { seq },
+                };
+                if let Some(res) = self.fetch(req).await {
+                    let root = res["root"].as_u64().unwrap_or(seq);
+                    let messages: Vec<Message> =
+                        serde_json::from_value(res["messages"].clone()).unwrap_or_default();
+                    if messages.is_empty() {
+                        self.messages.push_sys_err(format!(
+                            "Message #{} is not in the recent history of the channel",
+                            seq
                         ));
-                        self.state.channel = name;
+                    } else {
+                        self.thread = Some(ThreadView::new(&self.state.channel, root, messages));
                     }
+                }
+            }
+            Ok(Command::Ignore(Some(user))) => match self.messages.ignored.add(&user) {
+                Ok(true) => self
+                    .messages
+                    .push_sys_msg(format!("Messages from '{}' are ignored", user)),
+                Ok(false) => self
+                    .messages
+                    .push_sys_err(format!("'{}' is already ignored", user)),
+                Err(e) => self
+                    .messages
+                    .push_sys_err(format!("Failed to save the ignore list: {}", e)),
+            },
+            Ok(Command::Ignore(None)) => {
+                let users = self.messages.ignored.users();
+                self.messages.push_sys_msg(if users.is_empty() {
+                    "You are not ignoring anyone".to_owned()
+                } else {
+                    format!("Ignored users: {}", users.join(", "))
+                });
+            }
+            Ok(Command::Unignore(user)) => match self.messages.ignored.remove(&user) {
+                Ok(true) => self
+                    .messages
+                    .push_sys_msg(format!("'{}' is no longer ignored", user)),
+                Ok(false) => self
+                    .messages
+                    .push_sys_err(format!("'{}' is not ignored", user)),
+                Err(e) => self
+                    .messages
+                    .push_sys_err(format!("Failed to save the ignore list: {}", e)),
+            },
+            Ok(Command::Admin(action)) => {
+                let res_rx = self.incoming_tx.subscribe();
+                _ = self
+                    .outgoing_tx
+                    .send(AdminReq { action }.as_json_string())
+                    .await;
+                match util::consume_til::<AdminRes>(res_rx).await.result {
+                    Ok(msg) => self.messages.push_sys_msg(msg),
+                    Err(e) => self.messages.push_sys_err(e.to_string()),
+                }
+            }
+            Ok(Command::Account(action)) => {
+                let title = match action {
+                    AccountAction::Export => "Password to export your data",
+                    AccountAction::Erase => "Password to erase your account, it can't be undone",
+                };
+                self.open_popup(
+                    popup::prompt::PromptPopupManager::new(
+                        title,
+                        "password",
+                        CommandAction::Account,
+                    )
+                    .with_args(serde_json::json!({ "action": action }))
+                    .masked(),
+                );
+            }
+            Ok(Command::ReloadScripts) => {
+                let mut actions = self.scripts.reload();
+                let loaded = self.scripts.list().len();
+                actions.push(ScriptAction::Print(format!(
+                    "{} scripts are loaded",
+                    loaded
+                )));
+                actions.extend(self.scripts.on_connect(&self.state.id));
+                self.apply_script_actions(actions);
+            }
+            Ok(Command::Scripts) => {
+                let scripts = self.scripts.list();
+                if scripts.is_empty() {
+                    let dir = ScriptHost::dir()
+                        .map(|dir| dir.display().to_string())
+                        .unwrap_or_default();
+                    self.messages
+                        .push_sys_msg(format!("No scripts are loaded from '{}'", dir));
+                }
+                for (name, commands) in scripts {
+                    self.messages.push_sys_msg(match commands.is_empty() {
+                        true => format!("Script '{}'", name),
+                        false => format!("Script '{}': {}", name, commands.join(", ")),
+                    });
+                }
+            }
+            Ok(Command::Alias(Some((name, commands)))) => {
+                match self.aliases.define(&name, &commands) {
+                    Ok(replaced) => self.messages.push_sys_msg(match replaced {
+                        Some(old) => format!("'/{}' was '{}', it's redefined", name, old),
+                        None => format!("'/{}' is defined", name),
+                    }),
                     Err(e) => self
                         .messages
-                        .push_sys_err(format!("failed to join channel: '{}'", e)),
+                        .push_sys_err(format!("Failed to define the alias: {}", e)),
+                }
+            }
+            Ok(Command::Alias(None)) => {
+                let aliases = self.aliases.list();
+                if aliases.is_empty() {
+                    self.messages
+                        .push_sys_msg("No aliases, see '/alias <name> <commands>'".to_owned());
+                }
+                for (name, def) in aliases {
+                    self.messages.push_sys_msg(format!("/{}: {}", name, def));
                 }
             }
+            Ok(Command::Unalias(name)) => match self.aliases.remove(&name) {
+                Ok(true) => self
+                    .messages
+                    .push_sys_msg(format!("'/{}' is forgotten", name)),
+                Ok(false) => self
+                    .messages
+                    .push_sys_err(format!("There is no alias '{}'", name)),
+                Err(e) => self
+                    .messages
+                    .push_sys_err(format!("Failed to save the aliases: {}", e)),
+            },
+            // built-in commands come first, the scripts can't take their names
+            Err(ParseCommandError::UnknownCommand(name, _)) if self.scripts.has_command(&name) => {
+                let line = line.trim_end();
+                let args = line[1 + name.len()..].trim_start().to_owned();
+                let actions = self.scripts.command(&name, &args);
+                self.apply_script_actions(actions);
+            }
             Ok(Command::Exit) => {
                 _ = self.outgoing_tx.send(Exit {}.as_json_string()).await;
+                Recovery::remove();
                 return HandleCommandStatus::Exit;
             }
-            // Not a command
-            Err(ParseCommandError::UnknownCommand(cmd)) => self
-                .messages
-                .push_sys_err(format!("Unknown command: {}", cmd)),
-            Err(e) => self.messages.push_sys_err(format!("{:?}", e)),
+            Err(e) => self.messages.push_sys_err(e.to_string()),
         }
         HandleCommandStatus::Continue
     }
@@ -0,0 +1,46 @@
+//! Automatic away presence after a while without keyboard input
+
+use std::time::{Duration, Instant};
+
+use crate::packet::Presence;
+
+/// Idle time after which the presence turns away, unless given
+pub const DEFAULT_AWAY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+pub struct AutoAway {
+    /// Idle time before going away, never if `None`
+    after: Option<Duration>,
+    last_activity: Instant,
+
+    /// The away presence was set by this, and not by the user
+    away: bool,
+}
+
+impl AutoAway {
+    pub fn new(after: Option<Duration>) -> Self {
+        Self {
+            after,
+            last_activity: Instant::now(),
+            away: false,
+        }
+    }
+
+    /// A key was pressed, true if the presence is to be restored
+    pub fn touch(&mut self) -> bool {
+        self.last_activity = Instant::now();
+        std::mem::take(&mut self.away)
+    }
+
+    /// True once the user has been idle long enough to be marked away
+    ///
+    /// Only an online presence is changed, dnd and invisible are the choice of the user.
+    pub fn due(&mut self, presence: Presence) -> bool {
+        let due = !self.away
+            && presence == Presence::Online
+            && self
+                .after
+                .is_some_and(|after| self.last_activity.elapsed() >= after);
+        self.away |= due;
+        due
+    }
+}
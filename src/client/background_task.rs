@@ -1,64 +1,321 @@
+use std::time::Duration;
+
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
     sync::{broadcast, mpsc},
 };
 
-use super::message_channel::MessageChannel;
+use super::{
+    activity::{self, ActivityLog},
+    message_channel::{Kind, MessageChannel},
+    reorder::{self, ReorderBuffer},
+    status::{ConnectionState, ConnectionStatus},
+    util,
+};
 use crate::packet::*;
 
-/// receive formatted packets from `rd` and enqueue them to `incoming_tx` channel
-pub async fn produce_incomings(
-    mut rd: ReadHalf<TcpStream>,
+/// Attempts to resume the session once the connection is lost, a second apart and doubling
+const MAX_RESUME_ATTEMPTS: u32 = 6;
+
+/// Oldest protocol version of a server resuming sessions
+const RESUME_VERSION: u32 = 5;
+
+/// Carry the packets of `outgoing_rx` to the server at `addr` and enqueue the frames it sends to
+/// `incoming_tx`, over `stream` and the connections replacing it
+///
+/// A lost connection is replaced by one resuming the session if `status` has a token for it, e.g.
+/// through a load balancer picking another server. `status` turns to disconnected once the
+/// connection is lost for good. The changes are recorded in `activity`.
+pub async fn run_connection(
+    addr: String,
+    mut stream: TcpStream,
+    mut outgoing_rx: mpsc::Receiver<String>,
     incoming_tx: broadcast::Sender<String>,
+    status: ConnectionStatus,
+    activity: ActivityLog,
 ) {
+    loop {
+        let (mut rd, mut wr) = tokio::io::split(stream);
+        tokio::select! {
+            _ = read_frames(&mut rd, &incoming_tx) => (),
+            closed = write_outgoings(&mut wr, &mut outgoing_rx) => if closed {
+                return;
+            },
+        }
+
+        let Some(resume) = status.resume() else {
+            break;
+        };
+        status.set_state(ConnectionState::Reconnecting);
+        activity.error(format!(
+            "Lost the connection to {}, resuming the session",
+            addr
+        ));
+        match reconnect(&addr, &status.hello(), &resume, &incoming_tx).await {
+            Some(resumed) => {
+                stream = resumed;
+                status.set_state(ConnectionState::Connected);
+                activity.info(format!("Reconnected to {}", addr));
+            }
+            None => break,
+        }
+    }
+    status.set_state(ConnectionState::Disconnected);
+    activity.error(format!("Disconnected from {}", addr));
+}
+
+/// Write the packets of `outgoing_rx` til a write fails, true once there's nothing left to send
+async fn write_outgoings<W: AsyncWrite + Unpin>(
+    wr: &mut W,
+    outgoing_rx: &mut mpsc::Receiver<String>,
+) -> bool {
+    while let Some(msg) = outgoing_rx.recv().await {
+        if wr.write_all(msg.as_bytes()).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Connect to `addr` again and resume the session, `None` if it can't be resumed
+async fn reconnect(
+    addr: &str,
+    hello: &Hello,
+    resume: &ResumeSession,
+    incoming_tx: &broadcast::Sender<String>,
+) -> Option<TcpStream> {
+    let mut backoff = Duration::from_secs(1);
+    for _ in 0..MAX_RESUME_ATTEMPTS {
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        let Ok(mut stream) = TcpStream::connect(addr).await else {
+            continue;
+        };
+        match resume_on(&mut stream, hello, resume, incoming_tx).await {
+            Some(true) => return Some(stream),
+            Some(false) => return None,
+            // lost again before the server answered
+            None => continue,
+        }
+    }
+    None
+}
+
+/// Resume the session on `stream`, false if the server refuses, `None` if the connection is lost
+///
+/// The frames of the server are enqueued as usual, the `ResumeRes` included, except for the
+/// response to the handshake.
+async fn resume_on(
+    stream: &mut TcpStream,
+    hello: &Hello,
+    resume: &ResumeSession,
+    incoming_tx: &broadcast::Sender<String>,
+) -> Option<bool> {
+    let hello = hello.as_json_string();
+    stream.write_all(hello.as_bytes()).await.ok()?;
+    let hello_res = loop {
+        if let Some(res) = util::parse_packet::<HelloRes>(&read_frame(stream).await?) {
+            break res;
+        }
+    };
+    if hello_res.result.is_err() || hello_res.version < RESUME_VERSION {
+        return Some(false);
+    }
+
+    stream
+        .write_all(resume.as_json_string().as_bytes())
+        .await
+        .ok()?;
+    loop {
+        let frame = read_frame(stream).await?;
+        let res = util::parse_packet::<ResumeRes>(&frame);
+        enqueue(incoming_tx, frame);
+        if let Some(res) = res {
+            return Some(res.result.is_ok());
+        }
+    }
+}
+
+/// Read frames til EOF
+async fn read_frames<R: AsyncRead + Unpin>(rd: &mut R, incoming_tx: &broadcast::Sender<String>) {
+    while let Some(msg_str) = read_frame(rd).await {
+        enqueue(incoming_tx, msg_str);
+    }
+}
+
+/// Enqueue the frame `msg_str`, the messages of a batch one by one as if they arrived apart
+fn enqueue(incoming_tx: &broadcast::Sender<String>, msg_str: String) {
+    match util::parse_packet::<MessageBatch>(&msg_str) {
+        Some(batch) => {
+            for msg in batch.messages {
+                _ = incoming_tx.send(msg.as_json_string());
+            }
+        }
+        None => _ = incoming_tx.send(msg_str),
+    }
+}
+
+/// Read the next frame, `None` at EOF
+async fn read_frame<R: AsyncRead + Unpin>(rd: &mut R) -> Option<String> {
     loop {
         // Size header
         let size_msg = match rd.read_u32().await {
-            Ok(0) | Err(_) => panic!("[System] EOF"),
+            Ok(0) | Err(_) => return None,
             Ok(size) => size,
         };
 
+        // Skip frames too large to be sane without allocating them
+        if size_msg > MAX_FRAME_SIZE {
+            let mut frame = rd.take(size_msg as u64);
+            tokio::io::copy(&mut frame, &mut tokio::io::sink())
+                .await
+                .ok()?;
+            continue;
+        }
+
         // Message body
         let mut buf = vec![0; size_msg as usize];
         let n = match rd.read_exact(buf.as_mut_slice()).await {
-            Ok(0) | Err(_) => panic!("[System] EOF"),
+            Ok(0) | Err(_) => return None,
             Ok(size) => size,
         };
 
-        let msg_str = String::from_utf8(buf[0..n].to_vec()).unwrap();
-        _ = incoming_tx.send(msg_str);
+        return Some(String::from_utf8(buf[0..n].to_vec()).unwrap());
+    }
+}
+
+/// Show `msg` in the message section
+fn show_message(out_queue: &MessageChannel, msg: Message) {
+    if !msg.is_system && out_queue.ignored.contains(&msg.id) {
+        return;
     }
+    let id = if msg.is_system {
+        "System".to_owned()
+    } else if msg.to.is_some() {
+        format!("[DM] {}", msg.id)
+    } else {
+        msg.id.clone()
+    };
+    out_queue.push_message(id, msg);
 }
 
 /// handle message packets
+///
+/// Messages of the channel are shown in the order of their sequence numbers, so every client
+/// sees the same order.
 pub async fn print_message_packets(
     mut incoming_rx: broadcast::Receiver<String>,
     out_queue: MessageChannel,
+    status: ConnectionStatus,
 ) {
+    let mut reorder = ReorderBuffer::default();
+    let mut snapshots = util::SnapshotStreams::new();
     loop {
-        let Ok(msg_str) = incoming_rx.recv().await else {
+        let received = if reorder.is_waiting() {
+            match tokio::time::timeout(reorder::MAX_WAIT, incoming_rx.recv()).await {
+                Ok(received) => received,
+                Err(_) => {
+                    for msg in reorder.release_expired() {
+                        show_message(&out_queue, msg);
+                    }
+                    continue;
+                }
+            }
+        } else {
+            incoming_rx.recv().await
+        };
+        for msg in reorder.release_expired() {
+            show_message(&out_queue, msg);
+        }
+        let Ok(msg_str) = received else {
             continue;
         };
 
-        if let Ok(msg) = serde_json::from_str::<Message>(msg_str.as_str()) {
+        if let Some(pong) = util::parse_packet::<Pong>(msg_str.as_str()) {
+            let rtt =
+                Duration::from_millis(util::unix_time_millis().saturating_sub(pong.timestamp));
+            status.record_latency(rtt);
+            if status.take_requested_pong(pong.timestamp) {
+                let average = status.latency().unwrap_or(rtt);
+                out_queue.push(
+                    Kind::SystemInfo,
+                    "System".to_owned(),
+                    format!(
+                        "Pong: {}ms (average {}ms)",
+                        rtt.as_millis(),
+                        average.as_millis()
+                    ),
+                );
+            }
+        } else if let Some(snapshot) = util::parse_snapshot(&mut snapshots, msg_str.as_str()) {
+            for msg in reorder.on_snapshot(&snapshot) {
+                show_message(&out_queue, msg);
+            }
+            out_queue.replay(snapshot);
+        } else if let Some(update) = util::parse_packet::<ReactionUpdate>(msg_str.as_str()) {
+            out_queue.set_reactions(&update.channel_name, update.seq, update.reactions);
+        } else if let Some(update) = util::parse_packet::<RetractUpdate>(msg_str.as_str()) {
+            out_queue.retract(&update.channel_name, update.seq);
+        } else if let Some(update) = util::parse_packet::<PinUpdate>(msg_str.as_str()) {
+            out_queue.push(
+                Kind::SystemInfo,
+                "System".to_owned(),
+                format!(
+                    "'{}' {} message #{}",
+                    update.by,
+                    if update.pinned { "pinned" } else { "unpinned" },
+                    update.message.seq.unwrap_or_default()
+                ),
+            );
+            out_queue.update_pin(update);
+        } else if let Some(msg) = util::parse_packet::<Message>(msg_str.as_str()) {
+            for msg in reorder.push(msg) {
+                show_message(&out_queue, msg);
+            }
+        } else if let Some(exceeded) = util::parse_packet::<LimitExceeded>(msg_str.as_str()) {
             out_queue.push(
-                if msg.is_system {
-                    "System".to_owned()
-                } else {
-                    msg.id
-                },
-                msg.msg,
+                Kind::SystemError,
+                "SystemError".to_owned(),
+                format!(
+                    "The {} is too large ({} > {} bytes)",
+                    exceeded.what, exceeded.size, exceeded.limit
+                ),
             );
         }
     }
 }
 
-pub async fn consume_outgoings(
-    mut write_stream: WriteHalf<TcpStream>,
-    mut outgoing_rx: mpsc::Receiver<String>,
-) {
-    while let Some(msg) = outgoing_rx.recv().await {
-        _ = write_stream.write_all(msg.as_bytes()).await;
+/// Record the protocol events among the packets of `incoming_rx` in `activity`
+pub async fn log_activity(mut incoming_rx: broadcast::Receiver<String>, activity: ActivityLog) {
+    loop {
+        match incoming_rx.recv().await {
+            Ok(msg_str) => {
+                if let Some((level, text)) = activity::describe(&msg_str) {
+                    activity.record(level, text);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                activity.error(format!(
+                    "{} packets were skipped, the client is lagging",
+                    skipped
+                ));
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Ping the server every `interval` so the latency shown in the status bar stays fresh
+pub async fn ping_periodically(outgoing_tx: mpsc::Sender<String>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let ping = Ping {
+            timestamp: util::unix_time_millis(),
+        };
+        if outgoing_tx.send(ping.as_json_string()).await.is_err() {
+            break;
+        }
     }
 }
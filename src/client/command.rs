@@ -1,83 +1,1269 @@
-use std::str::FromStr;
+use std::{fmt, time::Duration};
+
+use super::config::Theme;
+use super::message_channel::Kind;
+use super::notification::{NotifySetting, Trigger};
+use super::util;
+use crate::{
+    db::user::Role,
+    packet::{AccountAction, AdminAction, ChannelAction, HistoryPolicy, Presence},
+};
 
 // Request specific type of information from server
 pub enum Fetch {
-    UserList,
-    None,
+    /// First page of the users, optionally containing the substring
+    UserList(Option<String>),
+    /// Next page of the latest user list
+    NextPage,
+    /// Previous page of the latest user list
+    PrevPage,
+    /// Activity of every channel
+    Stats,
+    /// Originals of the messages retracted in the current channel, for moderators
+    Retracted,
+    /// Users banned and muted in the current channel, for moderators
+    Moderation,
+    /// Traffic of the open connections and the busiest accounts, for admins
+    Traffic,
+    /// Packets the server failed to deliver, for admins
+    DeadLetters,
 }
 
 pub enum Command {
     Help,
+    /// Remind yourself of the text after the duration
+    Remind(Duration, String),
+    /// Post the text to the current channel at the unix time
+    Schedule(u64, String),
     Get(String),
     Register,
-    Login(),
+    /// Register and become the new account without leaving the channel
+    Upgrade,
+    /// Log in, saving the credentials for `--auto-login` if true
+    Login(bool),
+    /// Wipe the saved credentials
+    Forget,
     Fetch(Fetch),
     Goto(String),
+    /// Browse the channels in a popup and go to the one picked
+    Channels,
+    /// Go to the channel of the latest mention
+    Jump,
+    Render(String, bool),
+    /// Switch to the theme and save it as the default
+    Theme(Theme),
+    /// Show or hide the messages of the kind, `None` to list what's hidden
+    Filter(Option<(Kind, bool)>),
+    /// Channel management, `None` for the current channel
+    Channel(ChannelAction, Option<String>),
+    /// Create the channel asking everyone joining for a password, prompted for
+    ProtectedChannel(String),
+    Msg(String, String),
+    Notify(NotifySetting),
+    Export(Option<String>, bool),
+    Mute(String),
+    Unmute(String),
+    /// Ignore messages from the user, `None` to list ignored users
+    Ignore(Option<String>),
+    Unignore(String),
+    Ping,
+    /// Invite the user to the channel, `None` for the current channel
+    Invite(String, Option<String>),
+    /// Join the channel of the latest invitation
+    Accept,
+    /// Toggle a reaction on the message with the sequence number
+    React(u64, String),
+    /// Pin or unpin the message with the sequence number
+    Pin(u64, bool),
+    /// List the pinned messages of the current channel
+    Pins,
+    /// Open the thread of the message with the sequence number, `None` to close it
+    Thread(Option<u64>),
+    /// Change how others see you, kept by the server for members
+    Presence(Presence),
+    /// Server-wide operation, the server decides if your role allows it
+    Admin(AdminAction),
+    /// Export or erase the data the server keeps about you
+    Account(AccountAction),
+    /// Load the user scripts again
+    ReloadScripts,
+    /// List the loaded user scripts and their commands
+    Scripts,
+    /// Define the alias as the command lines, `None` to list the aliases
+    Alias(Option<(String, String)>),
+    Unalias(String),
     Exit,
 }
 
+/// Group of related commands in the help
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Account,
+    Channels,
+    Messages,
+    Notifications,
+    Client,
+    Admin,
+}
+
+impl Category {
+    pub const ALL: [Category; 6] = [
+        Category::Account,
+        Category::Channels,
+        Category::Messages,
+        Category::Notifications,
+        Category::Client,
+        Category::Admin,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Category::Account => "Account",
+            Category::Channels => "Channels",
+            Category::Messages => "Messages",
+            Category::Notifications => "Notifications",
+            Category::Client => "Client",
+            Category::Admin => "Administration",
+        }
+    }
+}
+
+/// Who may run a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Auth {
+    Anyone,
+    /// Only guests, e.g. to log in
+    Guest,
+    /// Only logged in members
+    Member,
+}
+
+/// An argument of a command form
+pub enum Arg {
+    /// The word itself, e.g. a subcommand
+    Literal(&'static str),
+    /// One of the words, named
+    Choice(&'static str, &'static [&'static str]),
+    /// Any word, named
+    Word(&'static str),
+    /// A word that may be left out, always the last positional one
+    Optional(&'static str),
+    /// `--flag` anywhere among the arguments
+    Flag(&'static str),
+    /// Unsigned number
+    Number(&'static str),
+    /// Duration such as `1h30m`
+    Duration(&'static str),
+    /// `HH:MM` in UTC or a duration from now, as a unix time
+    Time(&'static str),
+    /// Sequence number of a message, `#` is optional
+    MessageId(&'static str),
+    /// The rest of the line as is, always the last one
+    Text(&'static str),
+}
+
+impl Arg {
+    fn usage(&self) -> String {
+        match self {
+            Arg::Literal(word) => word.to_string(),
+            Arg::Choice(_, choices) => format!("[required:{}]", choices.join("|")),
+            Arg::Optional(name) => format!("<optional:{}>", name),
+            Arg::Flag(flag) => format!("<optional:{}>", flag),
+            Arg::Word(name)
+            | Arg::Number(name)
+            | Arg::Duration(name)
+            | Arg::Time(name)
+            | Arg::MessageId(name)
+            | Arg::Text(name) => format!("[required:{}]", name),
+        }
+    }
+}
+
+/// A parsed argument
+enum Value {
+    Word(String),
+    Number(u64),
+    Duration(Duration),
+    Flag(bool),
+    Absent,
+}
+
+/// Parsed arguments of a form, taken in the order of its spec, literals excluded
+pub struct Args(std::vec::IntoIter<Value>);
+
+impl Args {
+    fn word(&mut self) -> String {
+        match self.0.next() {
+            Some(Value::Word(word)) => word,
+            _ => String::new(),
+        }
+    }
+
+    fn optional(&mut self) -> Option<String> {
+        match self.0.next() {
+            Some(Value::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn number(&mut self) -> u64 {
+        match self.0.next() {
+            Some(Value::Number(n)) => n,
+            _ => 0,
+        }
+    }
+
+    fn duration(&mut self) -> Duration {
+        match self.0.next() {
+            Some(Value::Duration(d)) => d,
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn flag(&mut self) -> bool {
+        matches!(self.0.next(), Some(Value::Flag(true)))
+    }
+}
+
+/// One way to call a command
+pub struct Form {
+    pub args: &'static [Arg],
+    pub help: &'static str,
+    build: fn(&mut Args) -> Command,
+}
+
+impl Form {
+    /// Arguments as shown in the help, e.g. `[required:user] <optional:channel>`
+    pub fn usage(&self) -> String {
+        self.args
+            .iter()
+            .map(Arg::usage)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Match `words` of the line `line` against the form
+    fn parse(&self, line: &str, words: &[(usize, &str)]) -> Result<Command, FormError> {
+        // flags go anywhere, take them out first
+        let is_flag = |word: &str| {
+            self.args
+                .iter()
+                .any(|arg| matches!(arg, Arg::Flag(flag) if *flag == word))
+        };
+        let positional: Vec<(usize, &str)> = words
+            .iter()
+            .copied()
+            .filter(|(_, word)| !is_flag(word))
+            .collect();
+
+        let mut values = Vec::new();
+        let mut idx = 0;
+        for arg in self.args {
+            let word = positional.get(idx).map(|(_, word)| *word);
+            match arg {
+                Arg::Flag(flag) => {
+                    values.push(Value::Flag(words.iter().any(|(_, word)| word == flag)));
+                    continue;
+                }
+                Arg::Optional(_) => {
+                    values.push(match word {
+                        Some(word) => Value::Word(word.to_owned()),
+                        None => Value::Absent,
+                    });
+                }
+                Arg::Text(_) => {
+                    let Some((start, _)) = positional.get(idx) else {
+                        return Err(FormError::Shape);
+                    };
+                    values.push(Value::Word(line[*start..].trim().to_owned()));
+                    idx = positional.len();
+                    continue;
+                }
+                _ => {
+                    let Some(word) = word else {
+                        return Err(FormError::Shape);
+                    };
+                    // literals are matched, not kept
+                    if let Some(value) = Self::parse_word(arg, word)? {
+                        values.push(value);
+                    }
+                }
+            }
+            idx += 1;
+        }
+        if idx < positional.len() {
+            return Err(FormError::Shape);
+        }
+        Ok((self.build)(&mut Args(values.into_iter())))
+    }
+
+    fn parse_word(arg: &Arg, word: &str) -> Result<Option<Value>, FormError> {
+        let value = match arg {
+            Arg::Literal(literal) if *literal == word => return Ok(None),
+            Arg::Literal(_) => return Err(FormError::Shape),
+            Arg::Choice(name, choices) => {
+                if !choices.contains(&word) {
+                    return Err(FormError::Type(format!(
+                        "'{}' is not a valid {}, expected {}",
+                        word,
+                        name,
+                        choices.join("|")
+                    )));
+                }
+                Value::Word(word.to_owned())
+            }
+            Arg::Number(name) => Value::Number(word.parse().map_err(|_| {
+                FormError::Type(format!("'{}' is not a valid number of {}", word, name))
+            })?),
+            Arg::Duration(name) => {
+                Value::Duration(util::parse_duration(word).ok_or(FormError::Type(format!(
+                    "'{}' is not a valid {}, e.g. 10m or 1h30m",
+                    word, name
+                )))?)
+            }
+            Arg::Time(name) => Value::Number(
+                util::parse_schedule_time(word, util::unix_time()).ok_or(FormError::Type(
+                    format!("'{}' is not a valid {}, e.g. 18:30 or 2h", word, name),
+                ))?,
+            ),
+            Arg::MessageId(name) => Value::Number(
+                word.trim_start_matches('#')
+                    .parse()
+                    .map_err(|_| FormError::Type(format!("'{}' is not a valid {}", word, name)))?,
+            ),
+            _ => Value::Word(word.to_owned()),
+        };
+        Ok(Some(value))
+    }
+}
+
+enum FormError {
+    /// The arguments don't have the shape of the form
+    Shape,
+    /// An argument has the wrong type
+    Type(String),
+}
+
+/// What the parser and the help know of a command
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub category: Category,
+    pub auth: Auth,
+
+    /// Ways to call the command, tried in order
+    pub forms: &'static [Form],
+}
+
+/// Every command, in the order of the help
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "register",
+        aliases: &["reg"],
+        category: Category::Account,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[],
+            help: "register a new member",
+            build: |_| Command::Register,
+        }],
+    },
+    CommandSpec {
+        name: "upgrade",
+        aliases: &[],
+        category: Category::Account,
+        auth: Auth::Guest,
+        forms: &[Form {
+            args: &[],
+            help: "register and continue as the new member, for guests",
+            build: |_| Command::Upgrade,
+        }],
+    },
+    CommandSpec {
+        name: "login",
+        aliases: &[],
+        category: Category::Account,
+        auth: Auth::Guest,
+        forms: &[Form {
+            args: &[Arg::Flag("--save")],
+            help: "log in, --save keeps encrypted credentials",
+            build: |args| Command::Login(args.flag()),
+        }],
+    },
+    CommandSpec {
+        name: "logout",
+        aliases: &[],
+        category: Category::Account,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Literal("--forget")],
+            help: "wipe the saved credentials",
+            build: |_| Command::Forget,
+        }],
+    },
+    CommandSpec {
+        name: "account",
+        aliases: &[],
+        category: Category::Account,
+        auth: Auth::Member,
+        forms: &[
+            Form {
+                args: &[Arg::Literal("export")],
+                help: "save what the server keeps about you, asks for the password",
+                build: |_| Command::Account(AccountAction::Export),
+            },
+            Form {
+                args: &[Arg::Literal("erase")],
+                help: "delete your account for good, asks for the password",
+                build: |_| Command::Account(AccountAction::Erase),
+            },
+        ],
+    },
+    CommandSpec {
+        name: "presence",
+        aliases: &[],
+        category: Category::Account,
+        auth: Auth::Member,
+        forms: &[Form {
+            args: &[Arg::Choice(
+                "presence",
+                &["online", "dnd", "invisible", "away"],
+            )],
+            help: "dnd silences notifications, away is set after a while without input",
+            build: |args| Command::Presence(args.word().parse().unwrap_or_default()),
+        }],
+    },
+    CommandSpec {
+        name: "goto",
+        aliases: &[],
+        category: Category::Channels,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Text("channel")],
+            help: "goto channel, a partial name is matched (Tab completes)",
+            build: |args| Command::Goto(args.word()),
+        }],
+    },
+    CommandSpec {
+        name: "channels",
+        aliases: &[],
+        category: Category::Channels,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[],
+            help: "browse the channels with their users and topics, Enter goes to one",
+            build: |_| Command::Channels,
+        }],
+    },
+    CommandSpec {
+        name: "jump",
+        aliases: &[],
+        category: Category::Channels,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[],
+            help: "goto the channel you were mentioned in last",
+            build: |_| Command::Jump,
+        }],
+    },
+    CommandSpec {
+        name: "channel",
+        aliases: &[],
+        category: Category::Channels,
+        auth: Auth::Anyone,
+        forms: &[
+            Form {
+                args: &[
+                    Arg::Literal("create"),
+                    Arg::Word("channel"),
+                    Arg::Flag("--password"),
+                ],
+                help: "create a channel, --password asks everyone joining for one",
+                build: |args| {
+                    let channel_name = args.word();
+                    if args.flag() {
+                        Command::ProtectedChannel(channel_name)
+                    } else {
+                        Command::Channel(ChannelAction::Create, Some(channel_name))
+                    }
+                },
+            },
+            Form {
+                args: &[
+                    Arg::Choice("action", &["create", "delete", "archive"]),
+                    Arg::Optional("channel"),
+                ],
+                help: "manage channels, asks first",
+                build: |args| {
+                    let action = match &args.word()[..] {
+                        "create" => ChannelAction::Create,
+                        "delete" => ChannelAction::Delete,
+                        _ => ChannelAction::Archive,
+                    };
+                    Command::Channel(action, args.optional())
+                },
+            },
+            Form {
+                args: &[
+                    Arg::Literal("set"),
+                    Arg::Literal("slowmode"),
+                    Arg::Number("seconds"),
+                ],
+                help: "limit how often users post, 0 to stop",
+                build: |args| Command::Channel(ChannelAction::SetSlowMode(args.number()), None),
+            },
+            Form {
+                args: &[
+                    Arg::Literal("set"),
+                    Arg::Literal("announce"),
+                    Arg::Choice("state", &["on", "off"]),
+                ],
+                help: "only moderators post",
+                build: |args| {
+                    Command::Channel(ChannelAction::SetAnnounceOnly(args.word() == "on"), None)
+                },
+            },
+            Form {
+                args: &[
+                    Arg::Literal("set"),
+                    Arg::Literal("history"),
+                    Arg::Choice("policy", &["persist", "ephemeral"]),
+                ],
+                help: "keep recent messages or never store them",
+                build: |args| {
+                    let policy = match &args.word()[..] {
+                        "ephemeral" => HistoryPolicy::Ephemeral,
+                        _ => HistoryPolicy::Persist,
+                    };
+                    Command::Channel(ChannelAction::SetHistory(policy), None)
+                },
+            },
+            Form {
+                args: &[
+                    Arg::Literal("set"),
+                    Arg::Literal("history"),
+                    Arg::Literal("keep"),
+                    Arg::Duration("duration"),
+                ],
+                help: "keep messages only for a while, e.g. 1h",
+                build: |args| {
+                    let policy = HistoryPolicy::Retain(args.duration().as_secs());
+                    Command::Channel(ChannelAction::SetHistory(policy), None)
+                },
+            },
+            Form {
+                args: &[
+                    Arg::Literal("set"),
+                    Arg::Literal("keep"),
+                    Arg::Choice("state", &["on", "off"]),
+                ],
+                help: "keep the channel even when empty, admins only",
+                build: |args| {
+                    Command::Channel(ChannelAction::SetGcExempt(args.word() == "on"), None)
+                },
+            },
+            Form {
+                args: &[
+                    Arg::Literal("set"),
+                    Arg::Literal("topic"),
+                    Arg::Literal("clear"),
+                ],
+                help: "clear the topic",
+                build: |_| Command::Channel(ChannelAction::SetTopic(None), None),
+            },
+            Form {
+                args: &[
+                    Arg::Literal("set"),
+                    Arg::Literal("topic"),
+                    Arg::Text("topic"),
+                ],
+                help: "tell everyone joining what the channel is about",
+                build: |args| Command::Channel(ChannelAction::SetTopic(Some(args.word())), None),
+            },
+            Form {
+                args: &[Arg::Literal("mod"), Arg::Word("user")],
+                help: "make the user a moderator",
+                build: |args| Command::Channel(ChannelAction::AddModerator(args.word()), None),
+            },
+            Form {
+                args: &[Arg::Literal("ban"), Arg::Word("user")],
+                help: "move the user out of the channel and keep them out",
+                build: |args| Command::Channel(ChannelAction::Ban(args.word()), None),
+            },
+            Form {
+                args: &[Arg::Literal("unban"), Arg::Word("user")],
+                help: "let a banned user back in",
+                build: |args| Command::Channel(ChannelAction::Unban(args.word()), None),
+            },
+            Form {
+                args: &[
+                    Arg::Literal("mute"),
+                    Arg::Word("user"),
+                    Arg::Duration("duration"),
+                ],
+                help: "keep the user from posting for a while, e.g. 10m",
+                build: |args| {
+                    let user = args.word();
+                    let secs = args.duration().as_secs();
+                    Command::Channel(ChannelAction::Mute(user, Some(secs)), None)
+                },
+            },
+            Form {
+                args: &[Arg::Literal("mute"), Arg::Word("user")],
+                help: "keep the user from posting until unmuted",
+                build: |args| Command::Channel(ChannelAction::Mute(args.word(), None), None),
+            },
+            Form {
+                args: &[Arg::Literal("unmute"), Arg::Word("user")],
+                help: "let a muted user post again",
+                build: |args| Command::Channel(ChannelAction::Unmute(args.word()), None),
+            },
+            Form {
+                args: &[Arg::Literal("system"), Arg::Word("channel")],
+                help: "create a system channel, admins only",
+                build: |args| Command::Channel(ChannelAction::CreateSystem, Some(args.word())),
+            },
+        ],
+    },
+    CommandSpec {
+        name: "nick",
+        aliases: &[],
+        category: Category::Channels,
+        auth: Auth::Member,
+        forms: &[
+            Form {
+                args: &[
+                    Arg::Literal("--channel"),
+                    Arg::Word("channel"),
+                    Arg::Optional("nickname"),
+                ],
+                help: "go by a nickname in the channel, your id again if left out",
+                build: |args| {
+                    let channel = args.word();
+                    Command::Channel(ChannelAction::SetNickname(args.optional()), Some(channel))
+                },
+            },
+            Form {
+                args: &[Arg::Optional("nickname")],
+                help: "go by a nickname in the current channel",
+                build: |args| Command::Channel(ChannelAction::SetNickname(args.optional()), None),
+            },
+        ],
+    },
+    CommandSpec {
+        name: "invite",
+        aliases: &[],
+        category: Category::Channels,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Word("user"), Arg::Optional("channel")],
+            help: "invite a user to a channel",
+            build: |args| Command::Invite(args.word(), args.optional()),
+        }],
+    },
+    CommandSpec {
+        name: "accept",
+        aliases: &[],
+        category: Category::Channels,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[],
+            help: "join the channel you've been invited to",
+            build: |_| Command::Accept,
+        }],
+    },
+    CommandSpec {
+        name: "fetch",
+        aliases: &[],
+        category: Category::Channels,
+        auth: Auth::Anyone,
+        forms: &[
+            Form {
+                args: &[Arg::Literal("list"), Arg::Optional("filter")],
+                help: "list users of the channel",
+                build: |args| Command::Fetch(Fetch::UserList(args.optional())),
+            },
+            Form {
+                args: &[Arg::Choice("page", &["next", "prev"])],
+                help: "turn the page of the user list",
+                build: |args| {
+                    Command::Fetch(if args.word() == "next" {
+                        Fetch::NextPage
+                    } else {
+                        Fetch::PrevPage
+                    })
+                },
+            },
+            Form {
+                args: &[Arg::Literal("stats")],
+                help: "activity of every channel",
+                build: |_| Command::Fetch(Fetch::Stats),
+            },
+            Form {
+                args: &[Arg::Literal("retracted")],
+                help: "originals of the retracted messages, moderators only",
+                build: |_| Command::Fetch(Fetch::Retracted),
+            },
+            Form {
+                args: &[Arg::Literal("moderation")],
+                help: "banned and muted users of the channel, moderators only",
+                build: |_| Command::Fetch(Fetch::Moderation),
+            },
+            Form {
+                args: &[Arg::Literal("traffic")],
+                help: "bytes and messages of the connections and the accounts, admins only",
+                build: |_| Command::Fetch(Fetch::Traffic),
+            },
+            Form {
+                args: &[Arg::Literal("dead_letters")],
+                help: "packets the server failed to deliver and why, admins only",
+                build: |_| Command::Fetch(Fetch::DeadLetters),
+            },
+        ],
+    },
+    CommandSpec {
+        name: "msg",
+        aliases: &["dm"],
+        category: Category::Messages,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Word("user"), Arg::Text("message")],
+            help: "send a direct message",
+            build: |args| Command::Msg(args.word(), args.word()),
+        }],
+    },
+    CommandSpec {
+        name: "react",
+        aliases: &[],
+        category: Category::Messages,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::MessageId("message_id"), Arg::Word("emoji|:shortcode:")],
+            help: "react to a message",
+            build: |args| Command::React(args.number(), args.word()),
+        }],
+    },
+    CommandSpec {
+        name: "pin",
+        aliases: &[],
+        category: Category::Messages,
+        auth: Auth::Anyone,
+        forms: &[
+            Form {
+                args: &[],
+                help: "list the pinned messages",
+                build: |_| Command::Pins,
+            },
+            Form {
+                args: &[Arg::MessageId("message_id")],
+                help: "pin a message, moderators only",
+                build: |args| Command::Pin(args.number(), true),
+            },
+        ],
+    },
+    CommandSpec {
+        name: "unpin",
+        aliases: &[],
+        category: Category::Messages,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::MessageId("message_id")],
+            help: "unpin a message, moderators only",
+            build: |args| Command::Pin(args.number(), false),
+        }],
+    },
+    CommandSpec {
+        name: "thread",
+        aliases: &[],
+        category: Category::Messages,
+        auth: Auth::Anyone,
+        forms: &[
+            Form {
+                args: &[],
+                help: "close the thread view",
+                build: |_| Command::Thread(None),
+            },
+            Form {
+                args: &[Arg::MessageId("message_id")],
+                help: "open the thread of a message next to the messages",
+                build: |args| Command::Thread(Some(args.number())),
+            },
+        ],
+    },
+    CommandSpec {
+        name: "remind",
+        aliases: &[],
+        category: Category::Messages,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Duration("duration"), Arg::Text("text")],
+            help: "remind yourself, e.g. 10m",
+            build: |args| Command::Remind(args.duration(), args.word()),
+        }],
+    },
+    CommandSpec {
+        name: "schedule",
+        aliases: &[],
+        category: Category::Messages,
+        auth: Auth::Member,
+        forms: &[Form {
+            args: &[Arg::Time("HH:MM|duration"), Arg::Text("text")],
+            help: "post later, UTC",
+            build: |args| Command::Schedule(args.number(), args.word()),
+        }],
+    },
+    CommandSpec {
+        name: "export",
+        aliases: &[],
+        category: Category::Messages,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Optional("path"), Arg::Flag("--json")],
+            help: "save messages of the channel",
+            build: |args| {
+                let path = args.optional();
+                // the extension decides the format too
+                let json = args.flag() || path.as_ref().is_some_and(|p| p.ends_with(".json"));
+                Command::Export(path, json)
+            },
+        }],
+    },
+    CommandSpec {
+        name: "ignore",
+        aliases: &[],
+        category: Category::Messages,
+        auth: Auth::Anyone,
+        forms: &[
+            Form {
+                args: &[Arg::Literal("list")],
+                help: "show ignored users",
+                build: |_| Command::Ignore(None),
+            },
+            Form {
+                args: &[Arg::Word("user")],
+                help: "hide messages from the user",
+                build: |args| Command::Ignore(Some(args.word())),
+            },
+        ],
+    },
+    CommandSpec {
+        name: "unignore",
+        aliases: &[],
+        category: Category::Messages,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Word("user")],
+            help: "show messages from the user again",
+            build: |args| Command::Unignore(args.word()),
+        }],
+    },
+    CommandSpec {
+        name: "notify",
+        aliases: &[],
+        category: Category::Notifications,
+        auth: Auth::Anyone,
+        forms: &[
+            Form {
+                args: &[Arg::Choice("trigger", &["all", "mentions", "dms", "off"])],
+                help: "when to notify",
+                build: |args| {
+                    Command::Notify(NotifySetting::Trigger(match &args.word()[..] {
+                        "all" => Trigger::All,
+                        "mentions" => Trigger::Mentions,
+                        "dms" => Trigger::DirectMessages,
+                        _ => Trigger::Off,
+                    }))
+                },
+            },
+            Form {
+                args: &[
+                    Arg::Choice("alert", &["bell", "flash"]),
+                    Arg::Choice("state", &["on", "off"]),
+                ],
+                help: "how to notify",
+                build: |args| {
+                    let bell = args.word() == "bell";
+                    let on = args.word() == "on";
+                    Command::Notify(if bell {
+                        NotifySetting::Bell(on)
+                    } else {
+                        NotifySetting::Flash(on)
+                    })
+                },
+            },
+        ],
+    },
+    CommandSpec {
+        name: "mute",
+        aliases: &[],
+        category: Category::Notifications,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Text("channel")],
+            help: "never notify for the channel",
+            build: |args| Command::Mute(args.word()),
+        }],
+    },
+    CommandSpec {
+        name: "unmute",
+        aliases: &[],
+        category: Category::Notifications,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Text("channel")],
+            help: "notify for the channel again",
+            build: |args| Command::Unmute(args.word()),
+        }],
+    },
+    CommandSpec {
+        name: "help",
+        aliases: &["h"],
+        category: Category::Client,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[],
+            help: "show this help, F1 too",
+            build: |_| Command::Help,
+        }],
+    },
+    CommandSpec {
+        name: "get",
+        aliases: &[],
+        category: Category::Client,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Text("key")],
+            help: "get information: info, version or server",
+            build: |args| Command::Get(args.word()),
+        }],
+    },
+    CommandSpec {
+        name: "ping",
+        aliases: &[],
+        category: Category::Client,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[],
+            help: "measure the round-trip time to the server",
+            build: |_| Command::Ping,
+        }],
+    },
+    CommandSpec {
+        name: "script",
+        aliases: &["scripts"],
+        category: Category::Client,
+        auth: Auth::Anyone,
+        forms: &[
+            Form {
+                args: &[Arg::Literal("reload")],
+                help: "load the scripts of the config directory again",
+                build: |_| Command::ReloadScripts,
+            },
+            Form {
+                args: &[],
+                help: "list the loaded scripts and their commands",
+                build: |_| Command::Scripts,
+            },
+        ],
+    },
+    CommandSpec {
+        name: "alias",
+        aliases: &[],
+        category: Category::Client,
+        auth: Auth::Anyone,
+        forms: &[
+            Form {
+                args: &[Arg::Literal("list")],
+                help: "show the aliases",
+                build: |_| Command::Alias(None),
+            },
+            Form {
+                args: &[Arg::Word("name"), Arg::Text("commands")],
+                help: "run the commands separated by ';' as /name, $1..$9 and $* take its words",
+                build: |args| Command::Alias(Some((args.word(), args.word()))),
+            },
+        ],
+    },
+    CommandSpec {
+        name: "unalias",
+        aliases: &[],
+        category: Category::Client,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Word("name")],
+            help: "forget the alias",
+            build: |args| Command::Unalias(args.word()),
+        }],
+    },
+    CommandSpec {
+        name: "render",
+        aliases: &[],
+        category: Category::Client,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Word("markdown"), Arg::Choice("state", &["on", "off"])],
+            help: "toggle rendering options",
+            build: |args| Command::Render(args.word(), args.word() == "on"),
+        }],
+    },
+    CommandSpec {
+        name: "theme",
+        aliases: &[],
+        category: Category::Client,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[Arg::Choice("theme", &["dark", "light", "accessible"])],
+            help: "switch the theme, accessible avoids telling things apart by color",
+            build: |args| Command::Theme(Theme::from_name(&args.word()).unwrap_or_default()),
+        }],
+    },
+    CommandSpec {
+        name: "filter",
+        aliases: &[],
+        category: Category::Client,
+        auth: Auth::Anyone,
+        forms: &[
+            Form {
+                args: &[],
+                help: "list the kinds of messages hidden from the message section",
+                build: |_| Command::Filter(None),
+            },
+            Form {
+                args: &[
+                    Arg::Choice("kind", &["chat", "info", "errors", "presence", "dm"]),
+                    Arg::Choice("state", &["on", "off"]),
+                ],
+                help: "show or hide the messages of the kind, e.g. '/filter presence off'",
+                build: |args| {
+                    let kind = Kind::from_name(&args.word()).unwrap_or(Kind::Chat);
+                    Command::Filter(Some((kind, args.word() == "on")))
+                },
+            },
+        ],
+    },
+    CommandSpec {
+        name: "exit",
+        aliases: &[],
+        category: Category::Client,
+        auth: Auth::Anyone,
+        forms: &[Form {
+            args: &[],
+            help: "exit from chat",
+            build: |_| Command::Exit,
+        }],
+    },
+    CommandSpec {
+        name: "admin",
+        aliases: &[],
+        category: Category::Admin,
+        auth: Auth::Member,
+        forms: &[
+            Form {
+                args: &[Arg::Literal("kick"), Arg::Word("user")],
+                help: "disconnect the user, moderators too",
+                build: |args| Command::Admin(AdminAction::Kick(args.word())),
+            },
+            Form {
+                args: &[Arg::Literal("ban"), Arg::Word("user")],
+                help: "keep the member from logging in, moderators too",
+                build: |args| Command::Admin(AdminAction::Ban(args.word())),
+            },
+            Form {
+                args: &[Arg::Literal("unban"), Arg::Word("user")],
+                help: "let the member log in again, moderators too",
+                build: |args| Command::Admin(AdminAction::Unban(args.word())),
+            },
+            Form {
+                args: &[Arg::Literal("broadcast"), Arg::Text("message")],
+                help: "post a notice to every channel",
+                build: |args| Command::Admin(AdminAction::Broadcast(args.word())),
+            },
+            Form {
+                args: &[
+                    Arg::Literal("role"),
+                    Arg::Word("user"),
+                    Arg::Choice("role", &["admin", "moderator", "user"]),
+                ],
+                help: "change the server-wide role of the member",
+                build: |args| {
+                    let user = args.word();
+                    let role = args.word().parse().unwrap_or(Role::User);
+                    Command::Admin(AdminAction::SetRole(user, role))
+                },
+            },
+            Form {
+                args: &[Arg::Literal("approve"), Arg::Word("user")],
+                help: "let the pending account log in",
+                build: |args| Command::Admin(AdminAction::Approve(args.word())),
+            },
+            Form {
+                args: &[Arg::Literal("reject"), Arg::Word("user")],
+                help: "turn the pending registration down",
+                build: |args| Command::Admin(AdminAction::Reject(args.word())),
+            },
+            Form {
+                args: &[Arg::Literal("pending")],
+                help: "list the registrations waiting for approval",
+                build: |_| Command::Admin(AdminAction::Pending),
+            },
+            Form {
+                args: &[Arg::Literal("motd"), Arg::Literal("clear")],
+                help: "stop sending a message of the day",
+                build: |_| Command::Admin(AdminAction::SetMotd(None)),
+            },
+            Form {
+                args: &[Arg::Literal("motd"), Arg::Text("message")],
+                help: "send the message to everyone logging in from now on",
+                build: |args| Command::Admin(AdminAction::SetMotd(Some(args.word()))),
+            },
+        ],
+    },
+];
+
+/// Keys of the main screen as (keys, description), for the help
+pub const KEYBINDINGS: &[(&str, &str)] = &[
+    ("i", "start editing"),
+    ("Esc", "stop editing"),
+    ("Enter", "send the message or run the command"),
+    ("Alt+Enter", "compose multiple lines, again to send"),
+    ("Tab", "complete the channel name of a command"),
+    ("Ctrl+V", "paste from the system clipboard"),
+    (
+        "Up / Down",
+        "pick a message of the channel, while not editing",
+    ),
+    ("Enter on a message", "reply to the picked message"),
+    ("F1", "show this help"),
+    ("F2", "show or hide the pinned messages"),
+    ("F3", "show or hide the activity pane"),
+    ("F4", "suggest spellings of the word at the cursor"),
+    ("F5", "send the messages that failed again"),
+    (
+        "Ctrl+R",
+        "retract your last message shortly after sending it",
+    ),
+    ("Mouse wheel", "scroll the messages"),
+    ("Mouse drag", "copy the selected messages"),
+];
+
+impl CommandSpec {
+    /// Command named `name` or one of its aliases
+    pub fn find(name: &str) -> Option<&'static CommandSpec> {
+        COMMANDS
+            .iter()
+            .find(|spec| spec.name == name || spec.aliases.contains(&name))
+    }
+
+    /// Closest command to the mistyped `name`, if any is close enough
+    fn suggest(name: &str) -> Option<&'static str> {
+        COMMANDS
+            .iter()
+            .flat_map(|spec| std::iter::once(&spec.name).chain(spec.aliases))
+            .map(|candidate| (util::edit_distance(name, candidate), *candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| CommandSpec::find(candidate).map_or(candidate, |s| s.name))
+    }
+
+    fn usage_error(&self) -> ParseCommandError {
+        let usages: Vec<String> = self
+            .forms
+            .iter()
+            .map(|form| {
+                format!("/{} {}", self.name, form.usage())
+                    .trim_end()
+                    .to_owned()
+            })
+            .collect();
+        ParseCommandError::InvalidArgument(format!("Usage: {}", usages.join(", ")))
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseCommandError {
     NotCommand,
     InvalidArgument(String),
-    UnknownCommand(String),
+    /// The command and the closest known one
+    UnknownCommand(String, Option<&'static str>),
+    /// The command isn't for the current kind of user
+    NotAllowed(String),
 }
 
-impl FromStr for Command {
-    type Err = ParseCommandError;
+impl fmt::Display for ParseCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseCommandError::NotCommand => write!(f, "Not a command"),
+            ParseCommandError::InvalidArgument(msg) | ParseCommandError::NotAllowed(msg) => {
+                write!(f, "{}", msg)
+            }
+            ParseCommandError::UnknownCommand(cmd, Some(suggestion)) => {
+                write!(
+                    f,
+                    "Unknown command: /{}, did you mean /{}?",
+                    cmd, suggestion
+                )
+            }
+            ParseCommandError::UnknownCommand(cmd, None) => {
+                write!(f, "Unknown command: /{}, see /help", cmd)
+            }
+        }
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Command {
+    /// Parse the command line `s` of a guest if `is_guest`, or of a member
+    pub fn parse(s: &str, is_guest: bool) -> Result<Self, ParseCommandError> {
         if !s.starts_with('/') {
             return Err(ParseCommandError::NotCommand);
         }
 
         let cmdline = s.trim_end();
-        let command = if let Some(idx) = cmdline.find(' ') {
-            &cmdline[1..idx]
-        } else {
-            &cmdline[1..]
-        };
+        // words after the command with their offsets in the line
+        let mut words: Vec<(usize, &str)> = cmdline
+            .split(' ')
+            .scan(0, |offset, word| {
+                let start = *offset;
+                *offset += word.len() + 1;
+                Some((start, word))
+            })
+            .filter(|(_, word)| !word.is_empty())
+            .collect();
+        let command = &words.remove(0).1[1..];
 
-        match command {
-            "exit" => Ok(Command::Exit),
-            "help" | "h" => Ok(Command::Help),
-            "register" | "reg" => Ok(Command::Register),
-            "login" => Ok(Command::Login()),
-            "get" => {
-                if let Some(idx) = cmdline.find(' ') {
-                    let item = String::from(cmdline[idx + 1..].trim());
-                    Ok(Command::Get(item))
-                } else {
-                    Err(ParseCommandError::InvalidArgument(
-                        "Command 'get' requires an argument: '[key]'".to_owned(),
-                    ))
-                }
+        let Some(spec) = CommandSpec::find(command) else {
+            return Err(ParseCommandError::UnknownCommand(
+                command.to_owned(),
+                CommandSpec::suggest(command),
+            ));
+        };
+        match (spec.auth, is_guest) {
+            (Auth::Guest, false) => {
+                return Err(ParseCommandError::NotAllowed(format!(
+                    "Command '{}' is only for guests",
+                    spec.name
+                )))
             }
-            "fetch" => Ok(Command::Fetch(
-                match cmdline.find(' ').map(|idx| cmdline[idx + 1..].trim()) {
-                    Some("list") => Fetch::UserList,
-                    _ => Fetch::None,
-                },
-            )),
-            "goto" => match cmdline.find(' ') {
-                Some(idx) => Ok(Command::Goto(String::from(cmdline[idx + 1..].trim()))),
-                None => Err(ParseCommandError::InvalidArgument(
-                    "[#SystemError] Command 'goto' requires an argument: [channel_name]".to_owned(),
-                )),
-            },
-            unknown => Err(ParseCommandError::UnknownCommand(unknown.to_owned())),
+            (Auth::Member, true) => {
+                return Err(ParseCommandError::NotAllowed(format!(
+                    "Command '{}' is only for members, '/register' or '/upgrade' first",
+                    spec.name
+                )))
+            }
+            _ => (),
         }
-    }
-}
 
-impl Command {
-    pub fn help() {
-        println!(" | ----- Help -----");
-        println!(" | /help: help message");
-        println!(" | /register: register a new member");
-        println!(" | /login <optional:id>: log in");
-        println!(" | /get [required:key]: get information");
-        println!(" | /goto [required:channel]: goto channel");
-        println!(" | /exit: exit from chat");
+        let mut type_errors = Vec::new();
+        for form in spec.forms {
+            match form.parse(cmdline, &words) {
+                Ok(command) => return Ok(command),
+                Err(FormError::Type(e)) => type_errors.push(e),
+                Err(FormError::Shape) => (),
+            }
+        }
+        // a wrong argument is only certain if there's no other way to read the line
+        match (type_errors.pop(), type_errors.is_empty()) {
+            (Some(e), true) => Err(ParseCommandError::InvalidArgument(e)),
+            _ => Err(spec.usage_error()),
+        }
     }
 }
@@ -0,0 +1,162 @@
+//! Client configuration, `client.json` of the configuration directory
+//!
+//! It's written by the onboarding wizard on the first launch and may be edited by hand later,
+//! `--host` and `--port` override the server it names.
+
+use std::{fs, path::PathBuf};
+
+use ratatui::style::{Color, Modifier, Style};
+use serde::{Deserialize, Serialize};
+
+use super::credentials;
+use crate::cli;
+
+const CONFIG_FILE: &str = "client.json";
+
+/// Colors of the message section, the input box and the status bar
+///
+/// The accessible theme leaves the colors to the terminal and tells things apart with the
+/// modifiers and the text instead, e.g. on monochrome terminals and for colorblind users.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+    Accessible,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Dark, Theme::Light, Theme::Accessible];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+            Theme::Accessible => "accessible",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Theme> {
+        Theme::ALL.into_iter().find(|theme| theme.as_str() == name)
+    }
+
+    /// Text standing out, e.g. the input being edited and the mentions
+    pub fn highlight(&self) -> Style {
+        match self {
+            Theme::Dark => Style::default().fg(Color::Yellow),
+            Theme::Light => Style::default().fg(Color::Blue),
+            Theme::Accessible => {
+                Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+            }
+        }
+    }
+
+    /// Text in the background, e.g. the retracted messages and the reactions
+    pub fn muted(&self) -> Style {
+        match self {
+            Theme::Dark => Style::default().fg(Color::DarkGray),
+            Theme::Light => Style::default().fg(Color::Gray),
+            Theme::Accessible => Style::default().add_modifier(Modifier::ITALIC),
+        }
+    }
+
+    /// Errors and the messages that failed
+    pub fn error(&self) -> Style {
+        match self {
+            Theme::Dark | Theme::Light => Style::default().fg(Color::LightRed),
+            Theme::Accessible => Style::default().add_modifier(Modifier::BOLD),
+        }
+    }
+
+    /// The status bar
+    pub fn bar(&self) -> Style {
+        match self {
+            Theme::Dark => Style::default().bg(Color::DarkGray),
+            Theme::Light => Style::default().bg(Color::Gray),
+            Theme::Accessible => Style::default().add_modifier(Modifier::REVERSED),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ClientConfig {
+    pub host: String,
+    pub port: String,
+
+    /// Id offered by the login popup
+    pub display_name: Option<String>,
+    pub theme: Theme,
+
+    /// Layout of the chat messages, e.g. `"{time} {id}: {msg}"`, see `message_channel::format`
+    pub message_format: Option<String>,
+
+    /// Go back to the channel of the last session after connecting or logging in
+    pub rejoin_last_channel: bool,
+
+    /// Channel the last guest session was in, the server keeps it for the members
+    pub last_channel: Option<String>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            host: cli::DEFAULT_HOST.to_owned(),
+            port: cli::DEFAULT_PORT_NUM.to_owned(),
+            display_name: None,
+            theme: Theme::default(),
+            message_format: None,
+            rejoin_last_channel: true,
+            last_channel: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Configuration saved in the configuration directory, `None` if there is no file yet
+    ///
+    /// A file that can't be parsed is taken as the defaults rather than a first launch.
+    pub fn load() -> Option<Self> {
+        let s = fs::read_to_string(path().ok()?).ok()?;
+        Some(serde_json::from_str(&s).unwrap_or_default())
+    }
+
+    pub fn save(&self) -> Result<PathBuf, String> {
+        let path = path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let json = serde_json::to_string_pretty(self).unwrap();
+        fs::write(&path, json).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(path)
+    }
+}
+
+fn path() -> Result<PathBuf, String> {
+    credentials::config_dir()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .ok_or_else(|| "no configuration directory, HOME is not set".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_settings_are_the_defaults() {
+        let config: ClientConfig =
+            serde_json::from_str(r#"{"host": "chat.example.com", "theme": "light"}"#).unwrap();
+        assert_eq!(
+            config,
+            ClientConfig {
+                host: "chat.example.com".to_owned(),
+                theme: Theme::Light,
+                ..ClientConfig::default()
+            }
+        );
+        assert!(serde_json::from_str::<ClientConfig>(r#"{"theme": "solarized"}"#).is_err());
+        let config: ClientConfig = serde_json::from_str(r#"{"theme": "accessible"}"#).unwrap();
+        assert_eq!(Some(config.theme), Theme::from_name("accessible"));
+    }
+}
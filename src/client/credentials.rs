@@ -0,0 +1,85 @@
+use std::{fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::vault;
+
+const CREDENTIALS_FILE: &str = "credentials";
+
+/// Login information saved for auto-login
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Credentials {
+    pub id: String,
+
+    /// Password hashed the way it is sent to the server, never the plain one
+    pub password: String,
+}
+
+/// Client configuration directory, `$XDG_CONFIG_HOME/rschat` or `~/.config/rschat`
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("rschat"))
+}
+
+fn path() -> Result<PathBuf, String> {
+    config_dir()
+        .map(|dir| dir.join(CREDENTIALS_FILE))
+        .ok_or_else(|| "no configuration directory, HOME is not set".to_owned())
+}
+
+/// True if credentials have been saved
+pub fn exists() -> bool {
+    path().is_ok_and(|p| p.exists())
+}
+
+/// Encrypt `credentials` with `passphrase` and write them to the configuration directory
+pub fn save(credentials: &Credentials, passphrase: &str) -> Result<PathBuf, String> {
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+
+    let plaintext = serde_json::to_vec(credentials).map_err(|e| e.to_string())?;
+    write_private(&path, vault::seal(passphrase, &plaintext).as_bytes())
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/// Read the saved credentials and decrypt them with `passphrase`
+pub fn load(passphrase: &str) -> Result<Credentials, String> {
+    let path = path()?;
+    let sealed = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let plaintext = vault::open(passphrase, &sealed)?;
+    serde_json::from_slice(&plaintext).map_err(|_| "corrupted credentials".to_owned())
+}
+
+/// Wipe the saved credentials, returns false if there were none
+pub fn forget() -> Result<bool, String> {
+    let path = path()?;
+    match fs::remove_file(&path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+/// Write `contents` to `path` readable by the owner only
+#[cfg(unix)]
+fn write_private(path: &std::path::Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &std::path::Path, contents: &[u8]) -> std::io::Result<()> {
+    fs::write(path, contents)
+}
@@ -0,0 +1,77 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{message_channel::Entry, util};
+
+/// Number of messages written between progress reports
+const PROGRESS_STEP: usize = 1000;
+
+/// Default export path in the working directory
+pub fn default_path(channel: &str, json: bool) -> String {
+    format!(
+        "rschat-{}-{}.{}",
+        channel,
+        util::unix_time(),
+        if json { "json" } else { "log" }
+    )
+}
+
+/// Write `entries` to `path` as a JSON array or as plaintext lines
+///
+/// `progress` is called with the number of written messages every `PROGRESS_STEP` messages.
+/// Returns the absolute path of the written file.
+pub fn write_log(
+    path: &str,
+    entries: &[Entry],
+    json: bool,
+    mut progress: impl FnMut(usize),
+) -> Result<PathBuf, String> {
+    let file = File::create(path).map_err(|e| format!("failed to create '{}': {}", path, e))?;
+    let mut writer = BufWriter::new(file);
+    let write_err = |e: std::io::Error| format!("failed to write '{}': {}", path, e);
+
+    if json {
+        serde_json::to_writer_pretty(&mut writer, entries)
+            .map_err(|e| format!("failed to write '{}': {}", path, e))?;
+        progress(entries.len());
+    } else {
+        for (i, entry) in entries.iter().enumerate() {
+            // continuation lines of multi-line messages are indented under the message
+            writeln!(
+                writer,
+                "[{}] {}: {}",
+                util::format_time(entry.time),
+                entry.id,
+                entry.msg.replace('\n', "\n    ")
+            )
+            .map_err(write_err)?;
+            if (i + 1) % PROGRESS_STEP == 0 {
+                progress(i + 1);
+            }
+        }
+    }
+    writer.flush().map_err(write_err)?;
+
+    Path::new(path)
+        .canonicalize()
+        .map_err(|e| format!("failed to resolve '{}': {}", path, e))
+}
+
+/// Write the data the server keeps about the member `id` to the working directory, returns the
+/// absolute path of the written file
+pub fn write_account_bundle(id: &str, bundle: &serde_json::Value) -> Result<PathBuf, String> {
+    let path = format!("rschat-account-{}-{}.json", id, util::unix_time());
+    let file = File::create(&path).map_err(|e| format!("failed to create '{}': {}", path, e))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, bundle)
+        .map_err(|e| format!("failed to write '{}': {}", path, e))?;
+    writer
+        .flush()
+        .map_err(|e| format!("failed to write '{}': {}", path, e))?;
+    Path::new(&path)
+        .canonicalize()
+        .map_err(|e| format!("failed to resolve '{}': {}", path, e))
+}
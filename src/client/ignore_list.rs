@@ -0,0 +1,60 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    sync::{Arc, Mutex},
+};
+
+use super::credentials;
+
+const IGNORE_LIST_FILE: &str = "ignored.json";
+
+/// Users whose messages and DMs are never shown, kept in the configuration directory
+#[derive(Default, Clone)]
+pub struct IgnoreList {
+    users: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl IgnoreList {
+    /// Ignore list saved in the configuration directory, empty if there is none
+    pub fn load() -> Self {
+        let users = credentials::config_dir()
+            .and_then(|dir| fs::read_to_string(dir.join(IGNORE_LIST_FILE)).ok())
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self {
+            users: Arc::new(Mutex::new(users)),
+        }
+    }
+
+    pub fn contains(&self, user: &str) -> bool {
+        self.users.lock().unwrap().contains(user)
+    }
+
+    /// Returns false if `user` was already ignored
+    pub fn add(&self, user: &str) -> Result<bool, String> {
+        let added = self.users.lock().unwrap().insert(user.to_owned());
+        self.save()?;
+        Ok(added)
+    }
+
+    /// Returns false if `user` was not ignored
+    pub fn remove(&self, user: &str) -> Result<bool, String> {
+        let removed = self.users.lock().unwrap().remove(user);
+        self.save()?;
+        Ok(removed)
+    }
+
+    pub fn users(&self) -> Vec<String> {
+        self.users.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let dir = credentials::config_dir()
+            .ok_or_else(|| "no configuration directory, HOME is not set".to_owned())?;
+        fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+        let path = dir.join(IGNORE_LIST_FILE);
+        let json = serde_json::to_string_pretty(&*self.users.lock().unwrap()).unwrap();
+        fs::write(&path, json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
@@ -1,3 +1,5 @@
+use super::util;
+
 #[derive(PartialEq)]
 pub enum InputMode {
     Normal,
@@ -13,6 +15,9 @@ pub struct InputController {
 
     /// current input mode (Normal, Editing)
     pub input_mode: InputMode,
+
+    /// True if the input box is expanded into a multi-line editor
+    pub compose_mode: bool,
 }
 
 impl Default for InputController {
@@ -21,6 +26,7 @@ impl Default for InputController {
             buf: String::new(),
             cursor_pos: 0,
             input_mode: InputMode::Editing,
+            compose_mode: false,
         }
     }
 }
@@ -40,28 +46,83 @@ impl InputController {
         self.input_mode = InputMode::Editing;
     }
 
+    /// Toggle multi-line compose mode
+    pub fn toggle_compose_mode(&mut self) {
+        self.compose_mode = !self.compose_mode;
+    }
+
     pub fn clamp_cursor(&self, new_cursor_pos: usize) -> usize {
         new_cursor_pos.clamp(0, self.buf.len())
     }
 
+    // `cursor_pos` is a byte offset, it moves over whole characters
     pub fn move_cursor_left(&mut self) {
-        let cursor_moved_left = self.cursor_pos.saturating_sub(1);
-        self.cursor_pos = self.clamp_cursor(cursor_moved_left);
+        if let Some(c) = self.buf[..self.cursor_pos].chars().next_back() {
+            self.cursor_pos -= c.len_utf8();
+        }
     }
 
     pub fn move_cursor_right(&mut self) {
-        let cursor_moved_right = self.cursor_pos.saturating_add(1);
-        self.cursor_pos = self.clamp_cursor(cursor_moved_right);
+        if let Some(c) = self.buf[self.cursor_pos..].chars().next() {
+            self.cursor_pos += c.len_utf8();
+        }
     }
 
     pub fn enter_char(&mut self, ch: char) {
         self.buf.insert(self.cursor_pos, ch);
-        self.move_cursor_right();
+        self.cursor_pos += ch.len_utf8();
+    }
+
+    /// Insert a line break at the cursor, only meaningful in compose mode
+    pub fn enter_newline(&mut self) {
+        self.enter_char('\n');
+    }
+
+    /// Insert pasted `text` at the cursor
+    ///
+    /// Control characters are stripped, and line breaks are joined with a space unless
+    /// `multiline` is set.
+    pub fn insert_str(&mut self, text: &str, multiline: bool) {
+        let mut sanitized = util::sanitize_pasted(text);
+        if !multiline {
+            sanitized = sanitized.replace('\n', " ");
+        }
+        self.buf.insert_str(self.cursor_pos, &sanitized);
+        self.cursor_pos = self.clamp_cursor(self.cursor_pos + sanitized.len());
     }
 
+    /// Replace the `range` of the content with `text`, the cursor is put right after it
+    ///
+    /// Nothing changes if `range` isn't in the content.
+    pub fn replace_range(&mut self, range: std::ops::Range<usize>, text: &str) {
+        if self.buf.get(range.clone()).is_none() {
+            return;
+        }
+        self.buf.replace_range(range.clone(), text);
+        self.cursor_pos = self.clamp_cursor(range.start + text.len());
+    }
+
+    /// Delete the character before the cursor
     pub fn delete_char(&mut self) {
-        self.buf.pop();
-        self.move_cursor_left();
+        if let Some(c) = self.buf[..self.cursor_pos].chars().next_back() {
+            self.cursor_pos -= c.len_utf8();
+            self.buf.remove(self.cursor_pos);
+        }
+    }
+
+    /// Column of the cursor on a single line, wide characters take two columns
+    pub fn cursor_col(&self) -> u16 {
+        util::display_width(&self.buf[..self.cursor_pos]) as u16
+    }
+
+    /// The content hidden behind one asterisk per character, e.g. for passwords
+    pub fn masked(&self) -> String {
+        "*".repeat(self.buf.chars().count())
+    }
+
+    /// Column of the cursor when the content is shown `masked`
+    pub fn masked_cursor_col(&self) -> u16 {
+        self.buf[..self.cursor_pos].chars().count() as u16
     }
 
     pub fn reset_cursor_pos(&mut self) {
@@ -72,4 +133,83 @@ impl InputController {
         self.buf.clear();
         self.reset_cursor_pos();
     }
+
+    /// Number of rows the content occupies when soft wrapped to `width`
+    pub fn wrapped_height(&self, width: usize) -> usize {
+        util::wrap_text(&self.buf, width).len()
+    }
+
+    /// (column, row) of the cursor when the content is soft wrapped to `width` columns
+    pub fn wrapped_cursor_pos(&self, width: usize) -> (u16, u16) {
+        let before_cursor = &self.buf[..self.cursor_pos];
+        let rows = util::wrap_text(before_cursor, width);
+        let last_row = rows.last().map(|r| util::display_width(r)).unwrap_or(0);
+
+        // the cursor jumps to the next row right after a row gets filled up
+        if width > 0 && last_row == width {
+            (0, rows.len() as u16)
+        } else {
+            (last_row as u16, rows.len().saturating_sub(1) as u16)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(text: &str) -> InputController {
+        let mut input = InputController::default();
+        text.chars().for_each(|c| input.enter_char(c));
+        input
+    }
+
+    #[test]
+    fn cursor_moves_over_whole_characters() {
+        let mut input = typed("a한b");
+        assert_eq!(input.cursor_pos, input.buf.len());
+        assert_eq!(input.cursor_col(), 4);
+
+        input.move_cursor_left();
+        input.move_cursor_left();
+        assert_eq!(input.cursor_pos, 1);
+        assert_eq!(input.cursor_col(), 1);
+
+        input.move_cursor_right();
+        assert_eq!(input.cursor_col(), 3);
+        input.enter_char('日');
+        assert_eq!(input.buf, "a한日b");
+        assert_eq!(input.cursor_col(), 5);
+    }
+
+    #[test]
+    fn delete_the_character_before_the_cursor() {
+        let mut input = typed("가나다");
+        input.move_cursor_left();
+        input.delete_char();
+        assert_eq!(input.buf, "가다");
+        assert_eq!(input.cursor_col(), 2);
+
+        input.move_cursor_left();
+        input.delete_char();
+        assert_eq!(input.buf, "가다");
+    }
+
+    #[test]
+    fn masked_cursor_counts_characters() {
+        let input = typed("비밀pw");
+        assert_eq!(input.masked(), "****");
+        assert_eq!(input.masked_cursor_col(), 4);
+    }
+
+    #[test]
+    fn wrapped_cursor_with_wide_characters() {
+        // "한국" fills the first row of 4 columns
+        let input = typed("한국");
+        assert_eq!(input.wrapped_cursor_pos(4), (0, 1));
+
+        let input = typed("a한국");
+        assert_eq!(input.wrapped_height(4), 2);
+        assert_eq!(input.wrapped_cursor_pos(4), (2, 1));
+    }
 }
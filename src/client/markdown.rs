@@ -0,0 +1,106 @@
+use ratatui::style::{Color, Modifier, Style};
+
+/// A line of characters with their own styles
+pub type StyledLine = Vec<(char, Style)>;
+
+fn code_style() -> Style {
+    Style::default().fg(Color::LightYellow).bg(Color::DarkGray)
+}
+
+/// Find a closing `marker` in `chars` starting from `from`, returns the index of the marker
+fn find_closing(
+    chars: &[char],
+    from: usize,
+    marker: &[char],
+    word_boundary: bool,
+) -> Option<usize> {
+    let mut i = from;
+    while i + marker.len() <= chars.len() {
+        if &chars[i..i + marker.len()] == marker
+            && i > from
+            && (!word_boundary
+                || chars
+                    .get(i + marker.len())
+                    .is_none_or(|c| !c.is_alphanumeric()))
+        {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Parse inline markups (`code`, **bold**, *italic*, _italic_) of a single line
+fn parse_inline(line: &str) -> StyledLine {
+    let chars: Vec<char> = line.chars().collect();
+    let mut styled = StyledLine::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let at_word_start = i == 0 || !chars[i - 1].is_alphanumeric();
+
+        // inline code has the highest priority, nothing is parsed inside of it
+        if chars[i] == '`' {
+            if let Some(end) = find_closing(&chars, i + 1, &['`'], false) {
+                styled.extend(chars[i + 1..end].iter().map(|c| (*c, code_style())));
+                i = end + 1;
+                continue;
+            }
+        }
+
+        let (marker, modifier): (&[char], Modifier) = match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => (&['*', '*'], Modifier::BOLD),
+            '*' | '_' if at_word_start => (&chars[i..i + 1], Modifier::ITALIC),
+            c => {
+                styled.push((c, Style::default()));
+                i += 1;
+                continue;
+            }
+        };
+
+        match find_closing(&chars, i + marker.len(), marker, true) {
+            Some(end) => {
+                let inner: String = chars[i + marker.len()..end].iter().collect();
+                styled.extend(
+                    parse_inline(&inner)
+                        .into_iter()
+                        .map(|(c, s)| (c, s.add_modifier(modifier))),
+                );
+                i = end + marker.len();
+            }
+            // a lone marker is just a character
+            None => {
+                styled.push((chars[i], Style::default()));
+                i += 1;
+            }
+        }
+    }
+    styled
+}
+
+/// Parse the minimal markdown subset of `text` into styled lines
+///
+/// Fenced code block markers (```) are removed and the lines in between are rendered verbatim.
+pub fn parse(text: &str) -> Vec<StyledLine> {
+    let mut lines = Vec::new();
+    let mut in_code_block = false;
+    for line in text.split('\n') {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+
+        lines.push(if in_code_block {
+            line.chars().map(|c| (c, code_style())).collect()
+        } else {
+            parse_inline(line)
+        });
+    }
+    lines
+}
+
+/// Styled lines of `text` without parsing any markup
+pub fn raw(text: &str) -> Vec<StyledLine> {
+    text.split('\n')
+        .map(|line| line.chars().map(|c| (c, Style::default())).collect())
+        .collect()
+}
@@ -1,49 +1,1286 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+};
 
 use ratatui::{
-    style::{Color, Style},
-    text::{Line, Span},
+    style::{Color, Modifier, Style},
+    text::Text,
     widgets::ListItem,
 };
 
+use serde::Serialize;
+
+use super::{config::Theme, ignore_list::IgnoreList, markdown, redraw::RedrawFlag, util};
+use crate::packet::{JoinSnapshot, Mention, Message, PinUpdate};
+
+/// Number of messages kept unless configured otherwise, the oldest are dropped beyond it
+pub const DEFAULT_CAPACITY: usize = 5000;
+
+/// Layout of the chat messages unless configured otherwise
+///
+/// `{time}` is the time of the day the message arrived at, `{badge}` the sequence number as
+/// `[#seq] ` and `{seq}` the bare number, both empty without one, `{id}` the sender along with
+/// the nickname, `{channel}` the channel and `{msg}` the message itself.
+pub const DEFAULT_FORMAT: &str = "{badge}{id}: {msg}";
+
+/// Seconds a message of the user may wait for the server to echo it before it's taken as failed
+const DELIVERY_TIMEOUT_SECS: u64 = 10;
+
+/// User preferences on how messages are rendered
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    /// Render the markdown subset of chat messages, raw text otherwise
+    pub markdown: bool,
+    pub theme: Theme,
+
+    /// Layout of the chat messages, `DEFAULT_FORMAT` if `None`
+    pub format: Option<String>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            markdown: true,
+            theme: Theme::default(),
+            format: None,
+        }
+    }
+}
+
+/// How far a message of the user got on its way to the channel
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Delivery {
+    /// Handed to the connection, the server hasn't echoed it yet
+    Sending,
+    /// Echoed by the server, the channel has it
+    Sent,
+    /// Never made it to the channel, it can be sent again
+    Failed,
+}
+
+/// What an entry is about, the message section is filtered and styled by it
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// Messages of the channels
+    Chat,
+    /// Notices of the server and the client, e.g. the responses of the commands
+    SystemInfo,
+    SystemError,
+    /// Members joining and leaving the channel
+    Presence,
+    /// Direct messages, sent or received
+    Direct,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 5] = [
+        Kind::Chat,
+        Kind::SystemInfo,
+        Kind::SystemError,
+        Kind::Presence,
+        Kind::Direct,
+    ];
+
+    /// Name of the kind for `/filter`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Chat => "chat",
+            Kind::SystemInfo => "info",
+            Kind::SystemError => "errors",
+            Kind::Presence => "presence",
+            Kind::Direct => "dm",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Kind> {
+        Kind::ALL.into_iter().find(|kind| kind.as_str() == name)
+    }
+
+    /// Kind of `msg` of the server
+    fn of(msg: &Message) -> Kind {
+        match msg {
+            // the joins and leaves carry the id of the member
+            Message {
+                is_system: true,
+                id,
+                ..
+            } if id != "System" => Kind::Presence,
+            Message {
+                is_system: true, ..
+            } => Kind::SystemInfo,
+            Message { to: Some(_), .. } => Kind::Direct,
+            _ => Kind::Chat,
+        }
+    }
+}
+
+/// A message kept in the message section
+#[derive(Serialize, Debug, Clone)]
+pub struct Entry {
+    pub kind: Kind,
+    pub id: String,
+    pub msg: String,
+
+    /// Channel the client was in when the message arrived
+    pub channel: String,
+
+    /// Unix timestamp in seconds
+    pub time: u64,
+
+    /// Sequence number assigned by the server, reactions refer to it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+
+    /// Number of reactions per emoji
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, usize>,
+
+    /// Taken back by the sender, only a placeholder is shown
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub retracted: bool,
+
+    /// Nickname of the sender in the channel, shown along with the id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+
+    /// Delivery of a message the user sent, `None` for the others
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery: Option<Delivery>,
+
+    /// Sequence number of the message of the channel this one replies to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<u64>,
+
+    /// Sequence number of the first message of the thread of a reply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread: Option<u64>,
+
+    /// Replies seen in the thread this message started, counted under it
+    #[serde(skip_serializing_if = "is_zero")]
+    pub replies: usize,
+
+    /// Oldest message of the channel not read yet on the last login, a divider goes above it
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub first_unread: bool,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// Styled form of an entry, valid as long as the width, the options and the quote are the same
+struct Rendered {
+    width: usize,
+    options: RenderOptions,
+    quote: Option<String>,
+    text: Text<'static>,
+}
+
+/// An entry along with its styling, styled on the first render
+struct Slot {
+    entry: Entry,
+    rendered: Option<Rendered>,
+}
+
+/// Ring buffer of the entries, indices keep counting up as the oldest are dropped
+struct History {
+    slots: VecDeque<Slot>,
+
+    /// Number of entries dropped so far, the index of the oldest kept entry
+    dropped: usize,
+    capacity: usize,
+
+    /// Indices of the messages of the user that aren't sent yet, the oldest first
+    undelivered: Vec<usize>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            slots: VecDeque::new(),
+            dropped: 0,
+            capacity: DEFAULT_CAPACITY,
+            undelivered: Vec::new(),
+        }
+    }
+}
+
+impl History {
+    fn push(&mut self, entry: Entry) {
+        if let Some(root) = entry.thread {
+            self.count_reply(&entry.channel.clone(), root);
+        }
+        self.slots.push_back(Slot {
+            entry,
+            rendered: None,
+        });
+        self.shrink();
+    }
+
+    /// Drop the oldest entries beyond the capacity
+    fn shrink(&mut self) {
+        while self.slots.len() > self.capacity {
+            self.slots.pop_front();
+            self.dropped += 1;
+        }
+        let dropped = self.dropped;
+        self.undelivered.retain(|idx| *idx >= dropped);
+    }
+
+    /// Index the next entry will be pushed at
+    fn next_index(&self) -> usize {
+        self.dropped + self.slots.len()
+    }
+
+    /// Mark the oldest undelivered message of `channel` matching the echo `msg` of `id` as sent,
+    /// false if there's none
+    ///
+    /// The server normalizes the whitespace of the messages, the words are compared.
+    fn deliver(&mut self, channel: &str, id: &str, msg: &Message) -> bool {
+        let found = self.undelivered.iter().copied().find(|idx| {
+            self.get(*idx).is_some_and(|slot| {
+                slot.entry.id == id
+                    && slot.entry.channel == channel
+                    && slot
+                        .entry
+                        .msg
+                        .split_whitespace()
+                        .eq(msg.msg.split_whitespace())
+            })
+        });
+        let Some(idx) = found else {
+            return false;
+        };
+        if let Some(slot) = self.get_mut(idx) {
+            slot.entry.msg = msg.msg.clone();
+            slot.entry.seq = msg.seq;
+            slot.entry.display_name = msg.display_name.clone();
+            slot.entry.reply_to = msg.reply_to;
+            slot.entry.thread = msg.thread;
+        }
+        if let Some(root) = msg.thread {
+            self.count_reply(channel, root);
+        }
+        self.set_delivery(idx, Delivery::Sent);
+        true
+    }
+
+    /// Count a reply under the message `root` of `channel` that started the thread
+    fn count_reply(&mut self, channel: &str, root: u64) {
+        if let Some(slot) = self
+            .slots
+            .iter_mut()
+            .rev()
+            .find(|slot| slot.entry.channel == channel && slot.entry.seq == Some(root))
+        {
+            slot.entry.replies += 1;
+            slot.rendered = None;
+        }
+    }
+
+    /// Put the divider of the new messages of `channel` above the first message after `marker`
+    fn place_divider(&mut self, channel: &str, marker: u64) {
+        let mut placed = false;
+        for slot in self.slots.iter_mut() {
+            let entry = &slot.entry;
+            if entry.channel != channel {
+                continue;
+            }
+            let first_unread =
+                !placed && entry.kind == Kind::Chat && entry.seq.is_some_and(|seq| seq > marker);
+            placed |= first_unread;
+            if entry.first_unread != first_unread {
+                slot.entry.first_unread = first_unread;
+                slot.rendered = None;
+            }
+        }
+    }
+
+    /// Change the delivery of the message `idx`, it's styled again
+    fn set_delivery(&mut self, idx: usize, delivery: Delivery) {
+        if let Some(slot) = self.get_mut(idx) {
+            slot.entry.delivery = Some(delivery);
+            slot.rendered = None;
+        }
+        if delivery == Delivery::Sent {
+            self.undelivered.retain(|i| *i != idx);
+        }
+    }
+
+    /// Sender and first line of the message `seq` of `channel`, quoted above its replies
+    fn quote(&self, channel: &str, seq: u64) -> String {
+        match self
+            .entries()
+            .rev()
+            .find(|e| e.channel == channel && e.seq == Some(seq))
+        {
+            Some(e) if e.retracted => format!("#{} {}: message retracted", seq, e.id),
+            Some(e) => format!(
+                "#{} {}: {}",
+                seq,
+                e.id,
+                e.msg.lines().next().unwrap_or_default()
+            ),
+            // dropped already, or never seen
+            None => format!("#{}", seq),
+        }
+    }
+
+    fn entries(&self) -> impl DoubleEndedIterator<Item = &Entry> {
+        self.slots.iter().map(|slot| &slot.entry)
+    }
+
+    fn get(&self, idx: usize) -> Option<&Slot> {
+        self.slots.get(idx.checked_sub(self.dropped)?)
+    }
+
+    fn get_mut(&mut self, idx: usize) -> Option<&mut Slot> {
+        let dropped = self.dropped;
+        self.slots.get_mut(idx.checked_sub(dropped)?)
+    }
+}
+
 /// Thread safe queue for styled messages to be displayed on the message section
 #[derive(Default, Clone)]
 pub struct MessageChannel {
-    pub messages: Arc<Mutex<Vec<(String, String)>>>,
+    history: Arc<Mutex<History>>,
+
+    /// Channel new messages are recorded for
+    channel: Arc<Mutex<String>>,
+
+    /// Messages from these users are dropped before they are recorded
+    pub ignored: IgnoreList,
+
+    /// Pinned messages of the channel joined last, in the order of their sequence numbers
+    pins: Arc<Mutex<Vec<Entry>>>,
+
+    /// Sequence number of the latest message read, by channel, synced with the server for members
+    read_markers: Arc<Mutex<HashMap<String, u64>>>,
+
+    /// Raised on every change of the messages
+    pub redraw: RedrawFlag,
 }
 
 impl MessageChannel {
-    pub fn push(&self, id: String, msg: String) {
-        self.messages.lock().unwrap().push((id, msg));
+    pub fn new(ignored: IgnoreList) -> Self {
+        Self {
+            ignored,
+            ..Default::default()
+        }
     }
 
-    pub fn push_sys_msg(&mut self, msg: String) {
-        self.push("System".to_owned(), msg);
+    /// Keep at most `capacity` messages, at least one
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut history = self.history.lock().unwrap();
+        history.capacity = capacity.max(1);
+        history.shrink();
     }
 
-    pub fn push_sys_err(&mut self, msg: String) {
-        self.push("SystemError".to_owned(), msg);
+    /// Indices of the messages kept but those of the `hidden` kinds, oldest first
+    ///
+    /// The indices stay valid until the messages are dropped.
+    pub fn shown(&self, hidden: &BTreeSet<Kind>) -> Vec<usize> {
+        let history = self.history.lock().unwrap();
+        history
+            .entries()
+            .enumerate()
+            .filter(|(_, e)| !hidden.contains(&e.kind))
+            .map(|(pos, _)| history.dropped + pos)
+            .collect()
+    }
+
+    pub fn push(&self, kind: Kind, id: String, msg: String) {
+        self.push_entry(Entry {
+            kind,
+            id,
+            msg,
+            channel: String::new(),
+            time: util::unix_time(),
+            seq: None,
+            reactions: BTreeMap::new(),
+            retracted: false,
+            display_name: None,
+            delivery: None,
+            reply_to: None,
+            thread: None,
+            replies: 0,
+            first_unread: false,
+        });
     }
 
-    pub fn collect_list_item(&self) -> Vec<ListItem> {
-        self.messages
+    /// Push `msg` of the server shown as sent by `id`, with its sequence number and nickname
+    ///
+    /// The echo of a message the user sent takes the place of the message instead.
+    pub fn push_message(&self, id: String, msg: Message) {
+        if !msg.is_system && msg.to.is_none() && self.deliver(&id, &msg) {
+            return;
+        }
+        self.push_entry(Entry {
+            kind: Kind::of(&msg),
+            id,
+            msg: msg.msg,
+            channel: String::new(),
+            time: util::unix_time(),
+            seq: msg.seq,
+            reactions: BTreeMap::new(),
+            retracted: msg.retracted,
+            display_name: msg.display_name,
+            delivery: None,
+            reply_to: msg.reply_to,
+            thread: msg.thread,
+            replies: 0,
+            first_unread: false,
+        });
+    }
+
+    /// Push the message `msg` the user `id` is sending to the current channel, it waits for the
+    /// echo of the server
+    ///
+    /// It's pushed before it's sent so the echo can't come first, returns its index.
+    pub fn push_outgoing(&self, id: String, msg: String, reply_to: Option<u64>) -> usize {
+        let mut history = self.history.lock().unwrap();
+        let idx = history.next_index();
+        history.push(Entry {
+            kind: Kind::Chat,
+            id,
+            msg,
+            channel: self.channel.lock().unwrap().clone(),
+            time: util::unix_time(),
+            seq: None,
+            reactions: BTreeMap::new(),
+            retracted: false,
+            display_name: None,
+            delivery: Some(Delivery::Sending),
+            reply_to,
+            thread: None,
+            replies: 0,
+            first_unread: false,
+        });
+        if history.get(idx).is_some() {
+            history.undelivered.push(idx);
+        }
+        self.redraw.raise();
+        idx
+    }
+
+    /// Mark the message `idx` that couldn't be sent as failed
+    pub fn fail_outgoing(&self, idx: usize) {
+        self.history
             .lock()
             .unwrap()
+            .set_delivery(idx, Delivery::Failed);
+        self.redraw.raise();
+    }
+
+    /// Take the echo `msg` of `id` as the delivery of a message the user sent, false if it's not
+    fn deliver(&self, id: &str, msg: &Message) -> bool {
+        let channel = self.channel.lock().unwrap().clone();
+        let delivered = self.history.lock().unwrap().deliver(&channel, id, msg);
+        if delivered {
+            self.redraw.raise();
+        }
+        delivered
+    }
+
+    /// Mark the messages waiting for their echo for too long as failed, as of `now`
+    pub fn expire_outgoing(&self, now: u64) {
+        let mut history = self.history.lock().unwrap();
+        let expired: Vec<usize> = history
+            .undelivered
             .iter()
-            .map(|(id, msg)| {
-                // construct a list of the styled items
-                ListItem::new(match &id[..] {
-                    "System" => Line::from(Span::styled(
-                        format!("[System]: {}", msg),
-                        Style::default().fg(Color::LightBlue),
-                    )),
-                    "SystemError" => Line::from(Span::styled(
-                        format!("[SystemError]: {}", msg),
-                        Style::default().fg(Color::LightRed),
-                    )),
-                    _ => Line::from(Span::raw(format!("{}: {}", id, msg))),
+            .copied()
+            .filter(|idx| {
+                history.get(*idx).is_some_and(|slot| {
+                    slot.entry.delivery == Some(Delivery::Sending)
+                        && slot.entry.time + DELIVERY_TIMEOUT_SECS <= now
                 })
             })
+            .collect();
+        for idx in &expired {
+            history.set_delivery(*idx, Delivery::Failed);
+        }
+        if !expired.is_empty() {
+            self.redraw.raise();
+        }
+    }
+
+    /// Failed messages of `channel` to send again along with their indices and the messages they
+    /// reply to, they're waiting for their echo from now on
+    pub fn retry_failed(&self, channel: &str) -> Vec<(usize, String, Option<u64>)> {
+        let mut history = self.history.lock().unwrap();
+        let now = util::unix_time();
+        let mut texts = Vec::new();
+        for idx in history.undelivered.clone() {
+            let Some(slot) = history.get_mut(idx) else {
+                continue;
+            };
+            if slot.entry.channel != channel || slot.entry.delivery != Some(Delivery::Failed) {
+                continue;
+            }
+            slot.entry.time = now;
+            texts.push((idx, slot.entry.msg.clone(), slot.entry.reply_to));
+            history.set_delivery(idx, Delivery::Sending);
+        }
+        if !texts.is_empty() {
+            self.redraw.raise();
+        }
+        texts
+    }
+
+    /// Record `entry` in the current channel
+    fn push_entry(&self, mut entry: Entry) {
+        entry.channel = self.channel.lock().unwrap().clone();
+        self.history.lock().unwrap().push(entry);
+        self.redraw.raise();
+    }
+
+    /// Record the history of a channel just joined, messages already recorded are skipped
+    pub fn replay(&self, snapshot: JoinSnapshot) {
+        self.set_pins(&snapshot.channel_name, snapshot.pins);
+        let mut history = self.history.lock().unwrap();
+        let known: HashSet<u64> = history
+            .entries()
+            .filter(|e| e.channel == snapshot.channel_name)
+            .filter_map(|e| e.seq)
+            .collect();
+
+        let time = util::unix_time();
+        for msg in snapshot.messages {
+            let Some(seq) = msg.seq else {
+                continue;
+            };
+            if known.contains(&seq) || (!msg.is_system && self.ignored.contains(&msg.id)) {
+                continue;
+            }
+            // the echo of a message the user sent may have been missed
+            if !msg.is_system && history.deliver(&snapshot.channel_name, &msg.id, &msg) {
+                continue;
+            }
+            history.push(Entry {
+                kind: Kind::of(&msg),
+                id: if msg.is_system {
+                    "System".to_owned()
+                } else {
+                    msg.id
+                },
+                msg: msg.msg,
+                channel: snapshot.channel_name.clone(),
+                time,
+                seq: Some(seq),
+                reactions: snapshot.reactions.get(&seq).cloned().unwrap_or_default(),
+                retracted: msg.retracted,
+                display_name: msg.display_name,
+                delivery: None,
+                reply_to: msg.reply_to,
+                thread: msg.thread,
+                replies: 0,
+                first_unread: false,
+            });
+        }
+        if let Some(marker) = self
+            .read_markers
+            .lock()
+            .unwrap()
+            .get(&snapshot.channel_name)
+        {
+            history.place_divider(&snapshot.channel_name, *marker);
+        }
+        self.redraw.raise();
+    }
+
+    /// Take the read markers of the server, the new messages of the channels get their divider
+    pub fn set_read_markers(&self, markers: BTreeMap<String, u64>) {
+        let mut history = self.history.lock().unwrap();
+        let mut read_markers = self.read_markers.lock().unwrap();
+        for (channel, marker) in markers {
+            history.place_divider(&channel, marker);
+            let known = read_markers.entry(channel).or_default();
+            *known = (*known).max(marker);
+        }
+        self.redraw.raise();
+    }
+
+    /// Move the read marker of `channel` to its latest message, returns it if the marker moved
+    pub fn mark_read(&self, channel: &str) -> Option<u64> {
+        let latest = self
+            .history
+            .lock()
+            .unwrap()
+            .entries()
+            .rev()
+            .filter(|e| e.channel == channel)
+            .find_map(|e| e.seq)?;
+        let mut read_markers = self.read_markers.lock().unwrap();
+        if read_markers
+            .get(channel)
+            .is_some_and(|marker| *marker >= latest)
+        {
+            return None;
+        }
+        read_markers.insert(channel.to_owned(), latest);
+        Some(latest)
+    }
+
+    /// Replace the reactions of the message `seq` in `channel`
+    pub fn set_reactions(&self, channel: &str, seq: u64, reactions: BTreeMap<String, usize>) {
+        if let Some(slot) = self
+            .history
+            .lock()
+            .unwrap()
+            .slots
+            .iter_mut()
+            .rev()
+            .find(|slot| slot.entry.channel == channel && slot.entry.seq == Some(seq))
+        {
+            slot.entry.reactions = reactions;
+            slot.rendered = None;
+            self.redraw.raise();
+        }
+    }
+
+    /// Replace the message `seq` in `channel` with the placeholder of a retracted message
+    pub fn retract(&self, channel: &str, seq: u64) {
+        if let Some(slot) = self
+            .history
+            .lock()
+            .unwrap()
+            .slots
+            .iter_mut()
+            .rev()
+            .find(|slot| slot.entry.channel == channel && slot.entry.seq == Some(seq))
+        {
+            slot.entry.msg.clear();
+            slot.entry.reactions.clear();
+            slot.entry.retracted = true;
+            slot.rendered = None;
+        }
+        self.pins
+            .lock()
+            .unwrap()
+            .retain(|e| !(e.channel == channel && e.seq == Some(seq)));
+        self.redraw.raise();
+    }
+
+    /// Sequence number of the latest message of `id` in `channel` that isn't retracted yet
+    pub fn last_of(&self, channel: &str, id: &str) -> Option<u64> {
+        self.history
+            .lock()
+            .unwrap()
+            .entries()
+            .rev()
+            .find(|e| e.channel == channel && e.id == id && e.seq.is_some())
+            .filter(|e| !e.retracted)
+            .and_then(|e| e.seq)
+    }
+
+    /// Replace the pinned messages with `pins` of `channel`
+    pub fn set_pins(&self, channel: &str, pins: Vec<Message>) {
+        *self.pins.lock().unwrap() = pins
+            .into_iter()
+            .map(|msg| Entry {
+                kind: Kind::Chat,
+                id: msg.id,
+                msg: msg.msg,
+                channel: channel.to_owned(),
+                time: util::unix_time(),
+                seq: msg.seq,
+                reactions: BTreeMap::new(),
+                retracted: false,
+                display_name: msg.display_name,
+                delivery: None,
+                reply_to: None,
+                thread: None,
+                replies: 0,
+                first_unread: false,
+            })
+            .collect();
+        self.redraw.raise();
+    }
+
+    /// Apply a pin or unpin of a message, ignored if it's not of the channel joined last
+    pub fn update_pin(&self, update: PinUpdate) {
+        let mut pins = self.pins.lock().unwrap();
+        if pins
+            .first()
+            .is_some_and(|e| e.channel != update.channel_name)
+        {
+            pins.clear();
+        }
+        pins.retain(|e| e.seq != update.message.seq);
+        if update.pinned {
+            pins.push(Entry {
+                kind: Kind::Chat,
+                id: update.message.id,
+                msg: update.message.msg,
+                channel: update.channel_name,
+                time: util::unix_time(),
+                seq: update.message.seq,
+                reactions: BTreeMap::new(),
+                retracted: false,
+                display_name: update.message.display_name,
+                delivery: None,
+                reply_to: None,
+                thread: None,
+                replies: 0,
+                first_unread: false,
+            });
+            pins.sort_by_key(|e| e.seq);
+        }
+        self.redraw.raise();
+    }
+
+    /// Copy of the pinned messages of `channel`
+    pub fn pinned(&self, channel: &str) -> Vec<Entry> {
+        self.pins
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.channel == channel)
+            .cloned()
             .collect()
     }
+
+    /// Record messages pushed from now on as messages of `channel`
+    pub fn set_channel(&self, channel: &str) {
+        *self.channel.lock().unwrap() = channel.to_owned();
+    }
+
+    /// Copy of the messages recorded in `channel`
+    pub fn channel_entries(&self, channel: &str) -> Vec<Entry> {
+        self.history
+            .lock()
+            .unwrap()
+            .entries()
+            .filter(|e| e.channel == channel)
+            .cloned()
+            .collect()
+    }
+
+    /// Text of the messages in `range` of indices but those of the `hidden` kinds, one message
+    /// per line
+    pub fn entries_text(&self, range: RangeInclusive<usize>, hidden: &BTreeSet<Kind>) -> String {
+        let history = self.history.lock().unwrap();
+        history
+            .entries()
+            .enumerate()
+            .filter(|(pos, e)| {
+                range.contains(&(history.dropped + pos)) && !hidden.contains(&e.kind)
+            })
+            .map(|(_, e)| format!("{}: {}", e.id, e.msg))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn push_sys_msg(&mut self, msg: String) {
+        self.push(Kind::SystemInfo, "System".to_owned(), msg);
+    }
+
+    pub fn push_sys_err(&mut self, msg: String) {
+        self.push(Kind::SystemError, "SystemError".to_owned(), msg);
+    }
+
+    /// Highlighted summary of a mention in another channel
+    pub fn push_mention(&mut self, mention: &Mention) {
+        self.push(
+            Kind::SystemInfo,
+            "Mention".to_owned(),
+            format!(
+                "#{} {}: {}",
+                mention.channel_name, mention.message.id, mention.message.msg
+            ),
+        );
+    }
+
+    /// Banner of the message of the day of the server
+    pub fn push_motd(&mut self, motd: &str) {
+        self.push(Kind::SystemInfo, "MOTD".to_owned(), motd.to_owned());
+    }
+
+    /// Banner of the topic of the channel just joined
+    pub fn push_topic(&mut self, channel_name: &str, topic: &str) {
+        self.push(
+            Kind::SystemInfo,
+            "Topic".to_owned(),
+            format!("#{}: {}", channel_name, topic),
+        );
+    }
+
+    /// Sequence number of the message `idx` if it can be replied to
+    pub fn reply_target(&self, idx: usize) -> Option<u64> {
+        let history = self.history.lock().unwrap();
+        let entry = &history.get(idx)?.entry;
+        match entry.kind {
+            Kind::Chat if !entry.retracted => entry.seq,
+            _ => None,
+        }
+    }
+
+    /// Sender and first line of the message `seq` of `channel`, e.g. `#4 alice: hello`
+    pub fn quote(&self, channel: &str, seq: u64) -> String {
+        self.history.lock().unwrap().quote(channel, seq)
+    }
+
+    /// Styled list item of the message `idx`, multi-line messages are soft wrapped to `width`
+    ///
+    /// The styling is cached, so only new or changed messages are styled again.
+    pub fn list_item(
+        &self,
+        idx: usize,
+        width: usize,
+        options: &RenderOptions,
+    ) -> Option<ListItem<'static>> {
+        let mut history = self.history.lock().unwrap();
+        let entry = &history.get(idx)?.entry;
+        let quote = entry.reply_to.map(|seq| history.quote(&entry.channel, seq));
+        let slot = history.get_mut(idx)?;
+        let cached = slot
+            .rendered
+            .as_ref()
+            .is_some_and(|r| r.width == width && r.options == *options && r.quote == quote);
+        if !cached {
+            let text = render(&slot.entry, width, options, quote.as_deref());
+            slot.rendered = Some(Rendered {
+                width,
+                options: options.clone(),
+                quote,
+                text,
+            });
+        }
+        slot.rendered
+            .as_ref()
+            .map(|rendered| ListItem::new(rendered.text.clone()))
+    }
+}
+
+/// Text of `format` before and after `{msg}` with the other placeholders of `entry` replaced
+///
+/// The message goes at the end if `format` has no `{msg}`.
+fn format(format: &str, entry: &Entry, sender: &str) -> (String, String) {
+    let time = util::format_time(entry.time);
+    let (seq, badge) = match entry.seq {
+        Some(seq) => (seq.to_string(), format!("[#{}] ", seq)),
+        None => (String::new(), String::new()),
+    };
+    let fill = |part: &str| {
+        part.replace("{time}", &time[11..16])
+            .replace("{badge}", &badge)
+            .replace("{seq}", &seq)
+            .replace("{channel}", &entry.channel)
+            .replace("{id}", sender)
+    };
+    let (before, after) = format.split_once("{msg}").unwrap_or((format, ""));
+    (fill(before), fill(after))
+}
+
+/// Style `entry` and soft wrap it to `width`, a reply with the `quote` of its message above it
+fn render(
+    entry: &Entry,
+    width: usize,
+    options: &RenderOptions,
+    quote: Option<&str>,
+) -> Text<'static> {
+    let Entry {
+        kind,
+        id,
+        msg,
+        seq,
+        reactions,
+        retracted,
+        display_name,
+        delivery,
+        replies,
+        ..
+    } = entry;
+
+    // the nickname goes first, the id stays visible to tell who it is
+    let sender = match display_name {
+        Some(nick) => format!("{} ({})", nick, id),
+        None => id.clone(),
+    };
+
+    // the layout of the chat messages, the text around the message
+    let layout = format(
+        options.format.as_deref().unwrap_or(DEFAULT_FORMAT),
+        entry,
+        &sender,
+    );
+    let suffix = match kind {
+        Kind::Chat | Kind::Direct => layout.1.as_str(),
+        _ => "",
+    };
+
+    // the accessible theme tells the system lines apart by their prefix rather than the color
+    let accessible = options.theme == Theme::Accessible;
+
+    // construct a list of the styled items
+    let system_prefix = if accessible { "[SYS] " } else { "[System]: " };
+    let (prefix, mut lines, style) = match (kind, &id[..]) {
+        // the comings and goings stay in the background
+        (Kind::Presence, _) => (
+            system_prefix.to_owned(),
+            markdown::raw(msg),
+            options.theme.muted(),
+        ),
+        (Kind::SystemError, _) => (
+            if accessible {
+                "[ERR] "
+            } else {
+                "[SystemError]: "
+            }
+            .to_owned(),
+            markdown::raw(msg),
+            options.theme.error(),
+        ),
+        (Kind::SystemInfo, "Mention") => (
+            "[Mention]: ".to_owned(),
+            markdown::raw(msg),
+            options.theme.highlight(),
+        ),
+        (Kind::SystemInfo, "MOTD") => (
+            "[MOTD]: ".to_owned(),
+            markdown::raw(msg),
+            Style::default()
+                .fg(Color::LightGreen)
+                .add_modifier(Modifier::BOLD),
+        ),
+        (Kind::SystemInfo, "Topic") => (
+            "[Topic]: ".to_owned(),
+            markdown::raw(msg),
+            Style::default()
+                .fg(Color::LightCyan)
+                .add_modifier(Modifier::BOLD),
+        ),
+        (Kind::SystemInfo, _) => (
+            system_prefix.to_owned(),
+            markdown::raw(msg),
+            Style::default().fg(Color::LightBlue),
+        ),
+        _ if *retracted => (
+            layout.0.clone(),
+            markdown::raw("message retracted"),
+            options.theme.muted(),
+        ),
+        (Kind::Chat | Kind::Direct, _) => (
+            layout.0.clone(),
+            if options.markdown {
+                markdown::parse(msg)
+            } else {
+                markdown::raw(msg)
+            },
+            match kind {
+                Kind::Direct => Style::default().fg(Color::LightMagenta),
+                _ => Style::default(),
+            },
+        ),
+    };
+
+    // the prefix goes in front of the first line
+    let prefix = prefix.chars().map(|c| (c, Style::default()));
+    match lines.first_mut() {
+        Some(first) => {
+            first.splice(0..0, prefix);
+        }
+        None => lines.push(prefix.collect()),
+    }
+    if let Some(last) = lines.last_mut() {
+        last.extend(suffix.chars().map(|c| (c, Style::default())));
+    }
+
+    // where a message of the user is on its way, after the text
+    let marker = match delivery {
+        Some(Delivery::Sending) => Some((" (sending)", options.theme.muted())),
+        Some(Delivery::Failed) => Some((" (not sent, F5 to retry)", options.theme.error())),
+        Some(Delivery::Sent) | None => None,
+    };
+    if let (Some((marker, style)), Some(last)) = (marker, lines.last_mut()) {
+        last.extend(marker.chars().map(|c| (c, style)));
+    }
+
+    // the message replied to, a single line above the reply
+    if let Some(quote) = quote {
+        let quote = util::truncate(&format!("┌ {}", quote), width.max(1));
+        lines.insert(
+            0,
+            quote.chars().map(|c| (c, options.theme.muted())).collect(),
+        );
+    }
+
+    // where the reading stopped on the last login
+    if entry.first_unread {
+        let label = " new messages ";
+        let side = width.saturating_sub(label.chars().count()) / 2;
+        let divider = format!("{}{}{}", "─".repeat(side), label, "─".repeat(side));
+        lines.insert(
+            0,
+            divider
+                .chars()
+                .map(|c| (c, options.theme.highlight()))
+                .collect(),
+        );
+    }
+
+    // compact summary of the reactions under the message
+    if !reactions.is_empty() {
+        let summary = reactions
+            .iter()
+            .map(|(emoji, count)| format!("{} {}", emoji, count))
+            .collect::<Vec<_>>()
+            .join("  ");
+        lines.push(
+            format!("  {}", summary)
+                .chars()
+                .map(|c| (c, options.theme.muted()))
+                .collect(),
+        );
+    }
+    // activity of the thread the message started
+    if let (Some(seq), 1..) = (seq, replies) {
+        let counter = match replies {
+            1 => format!("  1 reply, /thread {} to read it", seq),
+            n => format!("  {} replies, /thread {} to read them", n, seq),
+        };
+        lines.push(
+            counter
+                .chars()
+                .map(|c| (c, options.theme.muted()))
+                .collect(),
+        );
+    }
+    Text::from(util::wrap_styled(lines, width, style))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(id: &str, msg: &str, seq: u64) -> Message {
+        Message {
+            id: id.to_owned(),
+            msg: msg.to_owned(),
+            is_system: false,
+            to: None,
+            seq: Some(seq),
+            retracted: false,
+            display_name: None,
+            reply_to: None,
+            thread: None,
+        }
+    }
+
+    fn deliveries(messages: &MessageChannel) -> Vec<(Option<u64>, Option<Delivery>)> {
+        messages
+            .channel_entries("public")
+            .iter()
+            .map(|e| (e.seq, e.delivery))
+            .collect()
+    }
+
+    #[test]
+    fn echoes_deliver_the_messages_sent() {
+        let messages = MessageChannel::default();
+        messages.set_channel("public");
+        messages.push_outgoing("alice".to_owned(), "hello  there ".to_owned(), None);
+        messages.push_outgoing("alice".to_owned(), "lost".to_owned(), None);
+        let offline = messages.push_outgoing("alice".to_owned(), "offline".to_owned(), None);
+        messages.fail_outgoing(offline);
+
+        // normalized by the server, it's the same message
+        messages.push_message("alice".to_owned(), echo("alice", "hello there", 7));
+        messages.push_message("bob".to_owned(), echo("bob", "lost", 8));
+        assert_eq!(
+            deliveries(&messages),
+            vec![
+                (Some(7), Some(Delivery::Sent)),
+                (None, Some(Delivery::Sending)),
+                (None, Some(Delivery::Failed)),
+                (Some(8), None),
+            ]
+        );
+
+        messages.expire_outgoing(util::unix_time() + DELIVERY_TIMEOUT_SECS);
+        assert_eq!(deliveries(&messages)[1], (None, Some(Delivery::Failed)));
+        assert!(messages.retry_failed("elsewhere").is_empty());
+        let retried: Vec<String> = messages
+            .retry_failed("public")
+            .into_iter()
+            .map(|(_, text, _)| text)
+            .collect();
+        assert_eq!(retried, vec!["lost", "offline"]);
+        assert_eq!(deliveries(&messages)[2], (None, Some(Delivery::Sending)));
+
+        messages.push_message("alice".to_owned(), echo("alice", "offline", 9));
+        assert_eq!(deliveries(&messages)[2], (Some(9), Some(Delivery::Sent)));
+        assert_eq!(messages.channel_entries("public").len(), 4);
+    }
+
+    #[test]
+    fn accessible_theme_prefixes_the_system_lines() {
+        let entry = |kind, id: &str, delivery| Entry {
+            kind,
+            id: id.to_owned(),
+            msg: "gone".to_owned(),
+            channel: String::new(),
+            time: 0,
+            seq: None,
+            reactions: BTreeMap::new(),
+            retracted: false,
+            display_name: None,
+            delivery,
+            reply_to: None,
+            thread: None,
+            replies: 0,
+            first_unread: false,
+        };
+        let text = |entry: &Entry, theme| {
+            let options = RenderOptions {
+                theme,
+                ..RenderOptions::default()
+            };
+            let line = &render(entry, 80, &options, None).lines[0];
+            line.spans
+                .iter()
+                .map(|span| span.content.as_ref())
+                .collect::<String>()
+        };
+
+        let error = entry(Kind::SystemError, "SystemError", None);
+        assert_eq!(text(&error, Theme::Dark), "[SystemError]: gone");
+        assert_eq!(text(&error, Theme::Accessible), "[ERR] gone");
+        assert_eq!(
+            text(&entry(Kind::SystemInfo, "System", None), Theme::Accessible),
+            "[SYS] gone"
+        );
+        // the failure is spelled out, not only colored
+        assert_eq!(
+            text(
+                &entry(Kind::Chat, "alice", Some(Delivery::Failed)),
+                Theme::Accessible
+            ),
+            "alice: gone (not sent, F5 to retry)"
+        );
+    }
+
+    #[test]
+    fn hidden_kinds_are_left_out() {
+        let mut messages = MessageChannel::default();
+        messages.push_message("alice".to_owned(), echo("alice", "hi", 1));
+        messages.push_message("System".to_owned(), Message::connection("bob"));
+        messages.push_sys_err("lost".to_owned());
+        let mut dm = echo("carol", "psst", 2);
+        dm.to = Some("alice".to_owned());
+        messages.push_message("[DM] carol".to_owned(), dm);
+
+        let kinds: Vec<Kind> = messages
+            .channel_entries("")
+            .iter()
+            .map(|e| e.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![Kind::Chat, Kind::Presence, Kind::SystemError, Kind::Direct]
+        );
+        assert_eq!(messages.shown(&BTreeSet::new()), vec![0, 1, 2, 3]);
+        let hidden = BTreeSet::from([Kind::Presence, Kind::SystemError]);
+        assert_eq!(messages.shown(&hidden), vec![0, 3]);
+        assert_eq!(
+            messages.entries_text(0..=3, &hidden),
+            "alice: hi\n[DM] carol: psst"
+        );
+    }
+
+    #[test]
+    fn chat_messages_follow_the_format() {
+        let mut entry = Entry {
+            kind: Kind::Chat,
+            id: "alice".to_owned(),
+            msg: "hi".to_owned(),
+            channel: "public".to_owned(),
+            time: 3600 * 13 + 60 * 5,
+            seq: Some(4),
+            reactions: BTreeMap::new(),
+            retracted: false,
+            display_name: None,
+            delivery: None,
+            reply_to: None,
+            thread: None,
+            replies: 0,
+            first_unread: false,
+        };
+        let text = |entry: &Entry, format: Option<&str>| {
+            let options = RenderOptions {
+                format: format.map(str::to_owned),
+                ..RenderOptions::default()
+            };
+            let line = &render(entry, 80, &options, None).lines[0];
+            line.spans
+                .iter()
+                .map(|span| span.content.as_ref())
+                .collect::<String>()
+        };
+
+        assert_eq!(text(&entry, None), "[#4] alice: hi");
+        let verbose = Some("{time} #{channel} {id}: {msg} ({seq})");
+        assert_eq!(text(&entry, verbose), "13:05 #public alice: hi (4)");
+        // without a sequence number the badge is left out
+        entry.seq = None;
+        assert_eq!(text(&entry, Some("{badge}<{id}> {msg}")), "<alice> hi");
+        assert_eq!(text(&entry, Some("{id} >")), "alice >hi");
+    }
+
+    #[test]
+    fn replies_quote_their_message() {
+        let messages = MessageChannel::default();
+        messages.push_message("alice".to_owned(), echo("alice", "hi\nall", 1));
+        let mut reply = echo("bob", "hello", 2);
+        reply.reply_to = Some(1);
+        messages.push_message("bob".to_owned(), reply);
+        let mut lost = echo("bob", "what?", 3);
+        lost.reply_to = Some(99);
+        messages.push_message("bob".to_owned(), lost);
+
+        assert_eq!(messages.reply_target(0), Some(1));
+        let lines = |idx: usize| {
+            let entry = &messages.channel_entries("")[idx];
+            let quote = entry.reply_to.map(|seq| messages.quote("", seq));
+            let text = render(entry, 80, &RenderOptions::default(), quote.as_deref());
+            text.lines
+                .iter()
+                .map(|line| {
+                    line.spans
+                        .iter()
+                        .map(|span| span.content.as_ref())
+                        .collect::<String>()
+                })
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(lines(1), vec!["┌ #1 alice: hi", "[#2] bob: hello"]);
+        // the message replied to isn't there anymore
+        assert_eq!(lines(2), vec!["┌ #99", "[#3] bob: what?"]);
+    }
+
+    #[test]
+    fn divider_goes_above_the_first_unread_message() {
+        let messages = MessageChannel::default();
+        for seq in 1..=4 {
+            messages.push_message("alice".to_owned(), echo("alice", "hi", seq));
+        }
+        messages.set_read_markers(BTreeMap::from([("".to_owned(), 2)]));
+        let unread: Vec<bool> = messages
+            .channel_entries("")
+            .iter()
+            .map(|e| e.first_unread)
+            .collect();
+        assert_eq!(unread, vec![false, false, true, false]);
+
+        // read up to the latest message, once
+        assert_eq!(messages.mark_read(""), Some(4));
+        assert_eq!(messages.mark_read(""), None);
+        // a marker of another terminal behind the local one doesn't move it back
+        messages.set_read_markers(BTreeMap::from([("".to_owned(), 3)]));
+        assert_eq!(messages.mark_read(""), None);
+    }
 }
@@ -0,0 +1,151 @@
+use std::ops::RangeInclusive;
+
+use ratatui::{layout::Rect, widgets::ListItem};
+
+/// Number of messages a mouse wheel step scrolls
+const SCROLL_STEP: usize = 3;
+
+/// Scroll position and mouse selection of the message section
+#[derive(Default)]
+pub struct MessageView {
+    /// Number of messages hidden below the view, 0 follows the newest message
+    scroll: usize,
+
+    /// Message where the selection started and where it ends now
+    selection: Option<(usize, usize)>,
+
+    /// Message picked with the arrows of the normal mode, e.g. to reply to it
+    cursor: Option<usize>,
+
+    /// Areas of the last drawn frame, mouse events are hit-tested against them
+    pub messages_area: Rect,
+    pub input_area: Rect,
+
+    /// Index of the message drawn on each row of the message section
+    rows: Vec<usize>,
+}
+
+impl MessageView {
+    pub fn scroll_up(&mut self) {
+        self.scroll += SCROLL_STEP;
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_sub(SCROLL_STEP);
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    /// Hide `scroll` messages below the view, it's kept within the messages at the next layout
+    pub fn set_scroll(&mut self, scroll: usize) {
+        self.scroll = scroll;
+    }
+
+    /// Items of the messages fitting in `area`, from the oldest
+    ///
+    /// `indices` are the indices of the messages shown, oldest first, and `item` makes the item
+    /// of a message, it's called only for the messages walked over from the newest one shown.
+    pub fn layout<'a>(
+        &mut self,
+        area: Rect,
+        indices: &[usize],
+        mut item: impl FnMut(usize) -> Option<ListItem<'a>>,
+    ) -> Vec<ListItem<'a>> {
+        self.messages_area = area;
+        let height = area.height.saturating_sub(2) as usize;
+
+        // scrolling can't go beyond the oldest message
+        self.scroll = self.scroll.min(indices.len().saturating_sub(1));
+        let end = indices.len() - self.scroll.min(indices.len());
+
+        // walk back from the last visible message until the section is full
+        let mut visible = Vec::new();
+        let mut used = 0;
+        for &idx in indices[..end].iter().rev() {
+            let Some(item) = item(idx) else {
+                break;
+            };
+            if used + item.height() > height {
+                // a single message taller than the section is still shown
+                if visible.is_empty() {
+                    visible.push((idx, item));
+                }
+                break;
+            }
+            used += item.height();
+            visible.push((idx, item));
+        }
+        visible.reverse();
+
+        self.rows.clear();
+        for (idx, item) in &visible {
+            self.rows.extend(std::iter::repeat_n(*idx, item.height()));
+        }
+        visible.into_iter().map(|(_, item)| item).collect()
+    }
+
+    /// Index of the message drawn at the terminal row `y`
+    pub fn message_at(&self, y: u16) -> Option<usize> {
+        // skip the border of the block
+        let row = y.checked_sub(self.messages_area.y + 1)?;
+        self.rows.get(row as usize).copied()
+    }
+
+    pub fn start_selection(&mut self, idx: usize) {
+        self.selection = Some((idx, idx));
+    }
+
+    pub fn extend_selection(&mut self, idx: usize) {
+        if let Some((_, end)) = &mut self.selection {
+            *end = idx;
+        }
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection = None;
+    }
+
+    /// Selected messages, from the older one
+    pub fn selection(&self) -> Option<RangeInclusive<usize>> {
+        let (from, to) = self.selection?;
+        Some(from.min(to)..=from.max(to))
+    }
+
+    pub fn cursor(&self) -> Option<usize> {
+        self.cursor
+    }
+
+    pub fn clear_cursor(&mut self) {
+        self.cursor = None;
+    }
+
+    /// Move the cursor to the previous or the next message of `shown` that is `pickable`
+    ///
+    /// It starts from the newest message and goes away past it, the view scrolls along.
+    pub fn move_cursor(&mut self, shown: &[usize], pickable: impl Fn(usize) -> bool, up: bool) {
+        let current = self
+            .cursor
+            .and_then(|idx| shown.iter().position(|&i| i == idx));
+        let mut candidates: Box<dyn Iterator<Item = usize>> = match (current, up) {
+            (Some(pos), true) => Box::new((0..pos).rev()),
+            (Some(pos), false) => Box::new(pos + 1..shown.len()),
+            (None, true) => Box::new((0..shown.len()).rev()),
+            (None, false) => return,
+        };
+        match candidates.find(|&pos| pickable(shown[pos])) {
+            Some(pos) => {
+                self.cursor = Some(shown[pos]);
+                self.scroll = shown.len() - 1 - pos;
+            }
+            // nothing older to go to stays, past the newest one leaves
+            None if up => (),
+            None => self.cursor = None,
+        }
+    }
+
+    pub fn contains(area: Rect, x: u16, y: u16) -> bool {
+        area.x <= x && x < area.x + area.width && area.y <= y && y < area.y + area.height
+    }
+}
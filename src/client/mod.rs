@@ -3,22 +3,62 @@ use tokio::{
     sync::{broadcast, mpsc},
 };
 
-use crate::{db, packet::*};
+use crate::{cli::ClientOptions, db, packet::*};
 
+pub mod activity;
+pub mod alias;
 pub mod app;
+pub mod away;
 pub mod background_task;
 pub mod command;
+pub mod config;
+pub mod credentials;
+pub mod export;
+pub mod ignore_list;
 pub mod input_controller;
+pub mod markdown;
 pub mod message_channel;
+pub mod message_view;
+pub mod notification;
 pub mod popup;
+pub mod recovery;
+pub mod redraw;
+pub mod reorder;
+pub mod script;
 pub mod session;
+pub mod spell;
+pub mod status;
+pub mod thread;
 pub mod tui;
 pub mod util;
 
-pub async fn run_client(port: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // Establish a connection and split into two unidirectional streams
-    let (rd, wr) = match TcpStream::connect(format!("0.0.0.0:{}", port)).await {
-        Ok(s) => tokio::io::split(s),
+pub async fn run_client(opts: &ClientOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if opts.tls {
+        return Err("TLS is not supported by this build of rschat".into());
+    }
+
+    // The first launch asks for the settings, unless the server is given
+    let mut saved = None;
+    let config = match config::ClientConfig::load() {
+        Some(config) => config,
+        None if opts.host.is_none() && opts.port.is_none() => {
+            let Some(config) = tui::run_onboarding()? else {
+                return Ok(());
+            };
+            saved = Some(config.save());
+            config
+        }
+        None => config::ClientConfig::default(),
+    };
+
+    // Establish a connection, replaced by the next one resuming the session if it's lost
+    let addr = format!(
+        "{}:{}",
+        opts.host.as_ref().unwrap_or(&config.host),
+        opts.port.as_ref().unwrap_or(&config.port)
+    );
+    let stream = match TcpStream::connect(&addr).await {
+        Ok(s) => s,
         Err(e) => panic!("'{}'", e),
     };
 
@@ -28,14 +68,49 @@ pub async fn run_client(port: &str) -> Result<(), Box<dyn std::error::Error>> {
     // Channel for messages received
     let (incoming_tx, _) = broadcast::channel::<String>(32);
 
-    // Task for comsuming the outgoing channel
-    tokio::task::spawn(background_task::consume_outgoings(wr, outgoing_rx));
+    // The history of the default channel arrives before the message section is set up
+    let mut history_rx = incoming_tx.subscribe();
 
-    // Task for reading TcpStream and enqueueing the messages to the channel
-    tokio::task::spawn(background_task::produce_incomings(rd, incoming_tx.clone()));
+    // A busy server turns the connection away before anything is sent
+    let busy_rx = incoming_tx.subscribe();
+
+    // Task writing the outgoing channel and enqueueing the messages received
+    let connection = status::ConnectionStatus::default();
+    connection.set_lite(opts.lite);
+    let activity = activity::ActivityLog::default();
+    tokio::task::spawn(background_task::run_connection(
+        addr.clone(),
+        stream,
+        outgoing_rx,
+        incoming_tx.clone(),
+        connection.clone(),
+        activity.clone(),
+    ));
+
+    // Protocol version negotiation
+    let hello_res = {
+        // subscribe before sending so the response can't slip through
+        let res_rx = incoming_tx.subscribe();
+        outgoing_tx
+            .send(connection.hello().as_json_string())
+            .await?;
+        let res = tokio::select! {
+            res = util::consume_til::<HelloRes>(res_rx) => res,
+            busy = util::consume_til::<ServerBusy>(busy_rx) => {
+                return Err(format!("server refused the connection: {}", busy.reason).into());
+            }
+        };
+        if let Err(e) = &res.result {
+            return Err(format!("server '{}' refused the connection: {}", res.software, e).into());
+        }
+        check_protocol_version(res.version)
+            .map_err(|e| format!("server '{}' is not supported: {}", res.software, e))?;
+        res
+    };
 
     // Handshaking server for retrieveing temporary ID
     let id = {
+        let res_rx = incoming_tx.subscribe();
         outgoing_tx
             .send(
                 LoginReq {
@@ -45,18 +120,87 @@ pub async fn run_client(port: &str) -> Result<(), Box<dyn std::error::Error>> {
                 .as_json_string(),
             )
             .await?;
-        match util::consume_til::<LoginRes>(incoming_tx.subscribe())
-            .await
-            .result
-        {
+        match util::consume_til::<LoginRes>(res_rx).await.result {
             Ok(r) => r,
             Err(s) => panic!("{}", s),
         }
     };
 
-    let state = session::State::new_guest(id.as_str());
+    let connected = format!(
+        "Connected to {} ({}, protocol version {}) as {}",
+        addr, hello_res.software, hello_res.version, id
+    );
+    let mut state = session::State::new_guest(id.as_str());
+    state.server = Some(hello_res);
+
+    let mut app = app::App::new(outgoing_tx.clone(), incoming_tx.clone(), state);
+    app.messages.set_capacity(opts.max_messages);
+    app.render_options.theme = config.theme;
+    app.render_options.format = config.message_format;
+    app.display_name = config.display_name;
+    app.rejoin_last_channel = config.rejoin_last_channel;
+    app.away = away::AutoAway::new(opts.away_after);
+    app.spell = opts
+        .spell_check
+        .as_deref()
+        .map(|path| spell::SpellChecker::start(path, app.messages.clone()));
+    app.messages.set_channel(&app.state.channel);
+    app.connection = connection;
+    app.activity = activity;
+    app.activity.info(connected);
+    match saved {
+        Some(Ok(path)) => app
+            .messages
+            .push_sys_msg(format!("Settings saved to {}", path.display())),
+        Some(Err(e)) => app
+            .messages
+            .push_sys_err(format!("Failed to save the settings: {}", e)),
+        None => (),
+    }
+
+    // the scripts see the session from the start
+    let mut actions = app.scripts.reload();
+    actions.extend(app.scripts.on_connect(&app.state.id));
+    app.apply_script_actions(actions);
+    let mut snapshots = util::SnapshotStreams::new();
+    while let Ok(msg) = history_rx.try_recv() {
+        if let Some(snapshot) = util::parse_snapshot(&mut snapshots, &msg) {
+            app.messages.replay(snapshot);
+        } else if let Some(motd) = util::parse_packet::<Motd>(&msg) {
+            // sent right before the response to the login, only once
+            app.messages.push_motd(&motd.text);
+        }
+    }
+
+    // guests go back to where they were, members once they log in
+    if let Some(channel) = config.last_channel {
+        app.rejoin(channel).await;
+    }
 
-    let app = app::App::new(outgoing_tx.clone(), incoming_tx.clone(), state);
+    // a session that didn't exit cleanly, the prompts to log in come first
+    if let Some(recovery) = recovery::Recovery::load() {
+        let question = format!(
+            "The last session didn't exit cleanly. Restore it? ({})",
+            recovery.summary()
+        );
+        app.open_popup(popup::confirm::ConfirmPopupManager::new(
+            &question,
+            app::CommandAction::Restore,
+            serde_json::to_value(&recovery).ok(),
+        ));
+    }
+
+    // Ask for the password right away if the user to log in as is given
+    if let Some(user) = &opts.user {
+        app.open_popup(popup::login::LoginPopupManager::with_id(user));
+    } else if opts.auto_login {
+        if credentials::exists() {
+            app.open_popup(popup::unlock::UnlockPopupManager::new());
+        } else {
+            app.messages
+                .push_sys_err("No saved credentials, use '/login --save' first".to_owned());
+        }
+    }
     tui::set_tui(app).await?;
     Ok(())
 }
@@ -0,0 +1,154 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::Write,
+};
+
+use crossterm::{execute, terminal::SetTitle};
+
+use crate::packet::{Mention, Message};
+
+/// Title of the terminal window when there's nothing to notify
+const DEFAULT_TITLE: &str = "rschat";
+
+/// Which incoming messages trigger a notification
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    All,
+    Mentions,
+    DirectMessages,
+    Off,
+}
+
+/// A change of the notification preferences requested by the user
+pub enum NotifySetting {
+    Trigger(Trigger),
+    Bell(bool),
+    Flash(bool),
+}
+
+/// Notification preferences and unread counters of the client
+pub struct Notifications {
+    pub trigger: Trigger,
+
+    /// Ring the terminal bell
+    pub bell: bool,
+
+    /// Flash the title bar with the number of unread messages
+    pub flash: bool,
+
+    /// Channels that never ring
+    pub muted: HashSet<String>,
+
+    /// Number of unread messages per channel
+    pub unread: HashMap<String, usize>,
+
+    /// Other channels you've been mentioned in since you last visited them
+    pub mentioned: BTreeSet<String>,
+
+    /// Do not disturb, messages are still counted but never ring
+    pub dnd: bool,
+
+    /// True if the title bar is currently showing a notification
+    flashing: bool,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self {
+            trigger: Trigger::Mentions,
+            bell: true,
+            flash: true,
+            muted: HashSet::new(),
+            unread: HashMap::new(),
+            mentioned: BTreeSet::new(),
+            dnd: false,
+            flashing: false,
+        }
+    }
+}
+
+impl Notifications {
+    pub fn apply(&mut self, setting: NotifySetting) {
+        match setting {
+            NotifySetting::Trigger(trigger) => self.trigger = trigger,
+            NotifySetting::Bell(enabled) => self.bell = enabled,
+            NotifySetting::Flash(enabled) => self.flash = enabled,
+        }
+    }
+
+    /// True if `msg` mentions `id` either as `@id` or as a whole word
+    pub fn mentions(msg: &str, id: &str) -> bool {
+        msg.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .any(|word| word == id)
+    }
+
+    /// Count `msg` received in `channel` as unread and notify if it matches the trigger
+    pub fn on_message(&mut self, msg: &Message, self_id: &str, channel: &str) {
+        if msg.is_system || msg.id == self_id {
+            return;
+        }
+
+        let unread = self.unread.entry(channel.to_owned()).or_default();
+        *unread += 1;
+        let unread = *unread;
+
+        // muted channels still accumulate unread counts but never ring
+        if self.dnd || self.muted.contains(channel) {
+            return;
+        }
+
+        let triggered = match self.trigger {
+            Trigger::All => true,
+            Trigger::Mentions => msg.to.is_some() || Self::mentions(&msg.msg, self_id),
+            Trigger::DirectMessages => msg.to.is_some(),
+            Trigger::Off => false,
+        };
+        if !triggered {
+            return;
+        }
+
+        let mut stdout = std::io::stdout();
+        if self.bell {
+            _ = stdout.write_all(b"\x07");
+            _ = stdout.flush();
+        }
+        if self.flash {
+            self.flashing = true;
+            _ = execute!(stdout, SetTitle(format!("({}) {}", unread, DEFAULT_TITLE)));
+        }
+    }
+
+    /// Count the mention in another channel as unread and mark the channel
+    pub fn on_mention(&mut self, mention: &Mention, self_id: &str) {
+        self.on_message(&mention.message, self_id, &mention.channel_name);
+        self.mentioned.insert(mention.channel_name.clone());
+    }
+
+    /// Take `unread` as the count of `channel`, e.g. the messages left unread on another terminal
+    pub fn set_unread(&mut self, channel: &str, unread: usize) {
+        if unread == 0 {
+            self.unread.remove(channel);
+        } else {
+            self.unread.insert(channel.to_owned(), unread);
+        }
+    }
+
+    /// The user is active in `channel`, clear its unread count and the title bar
+    pub fn mark_read(&mut self, channel: &str) {
+        self.unread.remove(channel);
+        self.mentioned.remove(channel);
+        if self.flashing {
+            self.flashing = false;
+            _ = execute!(std::io::stdout(), SetTitle(DEFAULT_TITLE));
+        }
+    }
+
+    pub fn unread_count(&self, channel: &str) -> usize {
+        self.unread.get(channel).copied().unwrap_or(0)
+    }
+
+    /// Unread messages over every channel
+    pub fn total_unread(&self) -> usize {
+        self.unread.values().sum()
+    }
+}
@@ -0,0 +1,106 @@
+use crossterm::event::KeyCode;
+use ratatui::{prelude::*, widgets::*};
+
+use super::*;
+use crate::client::util;
+
+/// Asks a yes or no question, `action` runs with `args` only on yes
+pub struct ConfirmPopupManager {
+    question: String,
+    action: app::CommandAction,
+    args: Option<serde_json::Value>,
+
+    // true if "Yes" is focused, "No" is focused first so Enter alone doesn't confirm
+    yes: bool,
+}
+
+impl ConfirmPopupManager {
+    pub fn new(
+        question: &str,
+        action: app::CommandAction,
+        args: Option<serde_json::Value>,
+    ) -> Self {
+        Self {
+            question: question.to_owned(),
+            action,
+            args,
+            yes: false,
+        }
+    }
+
+    fn confirm(&self) -> PostKeyCaptureAction {
+        PostKeyCaptureAction::CloseAndRunAction(self.action.clone(), self.args.clone())
+    }
+}
+
+impl PopupManager for ConfirmPopupManager {
+    fn ui(&self, f: &mut Frame) {
+        // the question wraps within the borders
+        let inner_width = centered_rect_lines(50, 1, f.size())
+            .width
+            .saturating_sub(2)
+            .max(1);
+        let question_lines = (util::display_width(&self.question) as u16).div_ceil(inner_width);
+
+        // instruction line, the borders, the question, a blank line and the buttons
+        let popup_area = centered_rect_lines(50, question_lines + 5, f.size());
+
+        // clear out the background
+        f.render_widget(Clear, popup_area);
+
+        // instruction
+        f.render_widget(
+            Paragraph::new({
+                let mut line = Line::from(vec![
+                    "y/n".bold(),
+                    " to answer |".into(),
+                    " Left/Right".bold(),
+                    " to move |".into(),
+                    " Enter".bold(),
+                    " to choose".into(),
+                ]);
+                line.patch_style(Style::default().add_modifier(Modifier::RAPID_BLINK));
+                line
+            }),
+            rows(popup_area, 0, 1),
+        );
+
+        let focused = Style::default().fg(Color::Yellow).bold().reversed();
+        let (yes_style, no_style) = if self.yes {
+            (focused, Style::default())
+        } else {
+            (Style::default(), focused)
+        };
+        f.render_widget(
+            Paragraph::new(vec![
+                Line::from(self.question.as_str()),
+                Line::from(""),
+                Line::from(vec![
+                    Span::styled(" Yes ", yes_style),
+                    "   ".into(),
+                    Span::styled(" No ", no_style),
+                ])
+                .alignment(Alignment::Center),
+            ])
+            .wrap(Wrap { trim: true })
+            .block(Block::default().borders(Borders::ALL).title("Confirm")),
+            rows(popup_area, 1, popup_area.height.saturating_sub(1)),
+        );
+    }
+
+    fn hook_key_event(&mut self, key_event: &KeyEvent) -> PostKeyCaptureAction {
+        match key_event.code {
+            KeyCode::Char('y' | 'Y') => self.confirm(),
+            KeyCode::Enter if self.yes => self.confirm(),
+            KeyCode::Left | KeyCode::Right | KeyCode::Tab => {
+                self.yes = !self.yes;
+                PostKeyCaptureAction::Break
+            }
+            // Cancellation
+            KeyCode::Char('n' | 'N') | KeyCode::Enter | KeyCode::Esc => {
+                PostKeyCaptureAction::ClosePopup
+            }
+            _ => PostKeyCaptureAction::Break,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::cell::Cell;
+
+use crossterm::event::KeyCode;
+use ratatui::{prelude::*, widgets::*};
+
+use super::*;
+use crate::client::command::{Auth, Category, COMMANDS, KEYBINDINGS};
+
+/// Scrollable list of the commands by category and the keybindings
+pub struct HelpPopupManager {
+    lines: Vec<Line<'static>>,
+
+    // index of the first visible line
+    scroll: usize,
+
+    // furthest scroll keeping the last page full, known once drawn
+    max_scroll: Cell<usize>,
+}
+
+impl HelpPopupManager {
+    pub fn new() -> Self {
+        Self {
+            lines: Self::content(),
+            scroll: 0,
+            max_scroll: Cell::new(0),
+        }
+    }
+
+    fn content() -> Vec<Line<'static>> {
+        let mut lines = Vec::new();
+        for category in Category::ALL {
+            lines.push(Line::from(category.title().bold().underlined()));
+            for spec in COMMANDS.iter().filter(|spec| spec.category == category) {
+                for form in spec.forms {
+                    let usage = format!("/{} {}", spec.name, form.usage());
+                    lines.push(Line::from(vec![
+                        format!("  {}", usage.trim_end()).fg(Color::Yellow),
+                        format!(": {}", form.help).into(),
+                    ]));
+                }
+                let mut notes: Vec<String> = spec
+                    .aliases
+                    .iter()
+                    .map(|a| format!("also /{}", a))
+                    .collect();
+                match spec.auth {
+                    Auth::Guest => notes.push("guests only".to_owned()),
+                    Auth::Member => notes.push("members only".to_owned()),
+                    Auth::Anyone => (),
+                }
+                if !notes.is_empty() {
+                    lines.push(Line::from(format!("    {}", notes.join(", ")).dim()));
+                }
+            }
+            lines.push(Line::from(""));
+        }
+
+        lines.push(Line::from("Keys".bold().underlined()));
+        for (keys, description) in KEYBINDINGS {
+            lines.push(Line::from(vec![
+                format!("  {}", keys).fg(Color::Yellow),
+                format!(": {}", description).into(),
+            ]));
+        }
+        lines
+    }
+}
+
+impl Default for HelpPopupManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PopupManager for HelpPopupManager {
+    fn ui(&self, f: &mut Frame) {
+        let popup_area = centered_rect(80, 80, f.size());
+
+        // clear out the background
+        f.render_widget(Clear, popup_area);
+
+        // instruction
+        f.render_widget(
+            Paragraph::new({
+                let mut line = Line::from(vec![
+                    "Esc".bold(),
+                    " to close |".into(),
+                    " Up/Down/PageUp/PageDown".bold(),
+                    " to scroll".into(),
+                ]);
+                line.patch_style(Style::default().add_modifier(Modifier::RAPID_BLINK));
+                line
+            }),
+            rows(popup_area, 0, 1),
+        );
+
+        // the instruction line and the borders take 3 lines
+        let visible = popup_area.height.saturating_sub(3) as usize;
+        self.max_scroll
+            .set(self.lines.len().saturating_sub(visible));
+        f.render_widget(
+            Paragraph::new(self.lines.clone())
+                .scroll((self.scroll.min(self.max_scroll.get()) as u16, 0))
+                .block(Block::default().borders(Borders::ALL).title("Help")),
+            rows(popup_area, 1, popup_area.height.saturating_sub(1)),
+        );
+    }
+
+    fn hook_key_event(&mut self, key_event: &KeyEvent) -> PostKeyCaptureAction {
+        let max_scroll = self.max_scroll.get();
+        self.scroll = self.scroll.min(max_scroll);
+        match key_event.code {
+            KeyCode::Up | KeyCode::Char('k') => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll = (self.scroll + 1).min(max_scroll),
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::PageDown => self.scroll = (self.scroll + 10).min(max_scroll),
+            KeyCode::Home => self.scroll = 0,
+            // Cancellation
+            KeyCode::Esc | KeyCode::F(1) | KeyCode::Char('q') => {
+                return PostKeyCaptureAction::ClosePopup
+            }
+            _ => (),
+        }
+        PostKeyCaptureAction::Break
+    }
+}
@@ -1,15 +1,22 @@
-use crossterm::event::KeyCode;
+use crossterm::event::{KeyCode, KeyModifiers};
 use ratatui::{prelude::*, widgets::*};
 
 use super::*;
-use crate::client::input_controller::InputController;
+use crate::client::{input_controller::InputController, util};
 
 pub struct LoginPopupManager {
     id_input: InputController,
     password_input: InputController,
 
-    // currently focus id field
-    focus_id_field: bool,
+    /// Passphrase the credentials are saved with, only shown by `/login --save`
+    passphrase_input: Option<InputController>,
+
+    // index of the currently focus field
+    focus_idx: usize,
+
+    /// The password fields are shown as typed, toggled with Ctrl+R
+    reveal: bool,
+    caps_lock: bool,
 }
 
 impl LoginPopupManager {
@@ -17,56 +24,78 @@ impl LoginPopupManager {
         Self {
             id_input: InputController::default(),
             password_input: InputController::default(),
-            focus_id_field: true,
+            passphrase_input: None,
+            focus_idx: 0usize,
+            reveal: false,
+            caps_lock: false,
         }
     }
 
-    fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
-        let center_y = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Percentage((100 - percent_y) / 2),
-                Constraint::Percentage(percent_y),
-                Constraint::Percentage((100 - percent_y) / 2),
-            ])
-            .split(r);
-        Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints([
-                Constraint::Percentage((100 - percent_x) / 2),
-                Constraint::Percentage(percent_x),
-                Constraint::Percentage((100 - percent_x) / 2),
-            ])
-            .split(center_y[1])[1]
+    /// Login popup that also asks for a passphrase to save the credentials with
+    pub fn with_save() -> Self {
+        let mut popup = Self::new();
+        popup.passphrase_input = Some(InputController::default());
+        popup
+    }
+
+    /// Login popup with the ID field filled in, the password field is focused
+    pub fn with_id(id: &str) -> Self {
+        let mut popup = Self::new();
+        popup.id_input.insert_str(id, false);
+        popup.focus_idx = 1;
+        popup
     }
 
     /// return reference to the currently focused input controller
     fn focused_input(&self) -> &InputController {
-        if self.focus_id_field {
-            &self.id_input
-        } else {
-            &self.password_input
+        match (self.focus_idx, &self.passphrase_input) {
+            (1, _) => &self.password_input,
+            (2, Some(passphrase_input)) => passphrase_input,
+            _ => &self.id_input,
         }
     }
 
     /// return mutable reference to the currently focused input controller
     fn focused_input_mut(&mut self) -> &mut InputController {
-        if self.focus_id_field {
-            &mut self.id_input
+        match (self.focus_idx, &mut self.passphrase_input) {
+            (1, _) => &mut self.password_input,
+            (2, Some(passphrase_input)) => passphrase_input,
+            _ => &mut self.id_input,
+        }
+    }
+
+    fn num_fields(&self) -> usize {
+        if self.passphrase_input.is_some() {
+            3
         } else {
-            &mut self.password_input
+            2
         }
     }
+
+    fn field_style(&self, idx: usize) -> Style {
+        Style::default().fg(if self.focus_idx == idx {
+            Color::Yellow
+        } else {
+            Color::default()
+        })
+    }
+}
+
+impl Default for LoginPopupManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PopupManager for LoginPopupManager {
     fn ui(&self, f: &mut Frame) {
-        let popup_area = LoginPopupManager::centered_rect(50, 11, f.size());
+        // instruction line, the fields and the hint under them
+        let popup_area = centered_rect_lines(50, 2 + 3 * self.num_fields() as u16, f.size());
 
         // clear out the background
         f.render_widget(Clear, popup_area);
 
-        let (x, y, width) = (popup_area.x, popup_area.y, popup_area.width);
+        let (x, y) = (popup_area.x, popup_area.y);
 
         // instruction
         f.render_widget(
@@ -82,45 +111,69 @@ impl PopupManager for LoginPopupManager {
                 line.patch_style(Style::default().add_modifier(Modifier::RAPID_BLINK));
                 line
             }),
-            Rect::new(x, y, width, 1),
+            rows(popup_area, 0, 1),
         );
 
         // ID input box
         f.render_widget(
             Paragraph::new(self.id_input.buf.as_str())
-                .style(Style::default().fg(if self.focus_id_field {
-                    Color::Yellow
-                } else {
-                    Color::default()
-                }))
+                .style(self.field_style(0))
                 .block(Block::default().borders(Borders::ALL).title("ID")),
-            Rect::new(x, y + 1, width, 3),
+            rows(popup_area, 1, 3),
         );
 
         // Password input box
+        let (password, password_cursor) = secret_field(&self.password_input, self.reveal);
         f.render_widget(
-            Paragraph::new("*".repeat(self.password_input.buf.len()))
-                .style(Style::default().fg(if self.focus_id_field {
-                    Color::default()
-                } else {
-                    Color::Yellow
-                }))
+            Paragraph::new(password)
+                .style(self.field_style(1))
                 .block(Block::default().borders(Borders::ALL).title("Password")),
-            Rect::new(x, y + 4, width, 3),
+            rows(popup_area, 4, 3),
         );
 
-        // cursor position depends on its focusing input field
-        f.set_cursor(
-            x + self.focused_input().cursor_pos as u16 + 1,
-            y + if self.focus_id_field { 1 } else { 4 } + 1,
+        // Passphrase input box
+        let mut passphrase_cursor = 0;
+        if let Some(passphrase_input) = &self.passphrase_input {
+            let passphrase;
+            (passphrase, passphrase_cursor) = secret_field(passphrase_input, self.reveal);
+            f.render_widget(
+                Paragraph::new(passphrase).style(self.field_style(2)).block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title("Passphrase to save the credentials with"),
+                ),
+                rows(popup_area, 7, 3),
+            );
+        }
+
+        f.render_widget(
+            password_hint(self.caps_lock, self.reveal),
+            rows(popup_area, 1 + 3 * self.num_fields() as u16, 1),
         );
+
+        // cursor position depends on its focusing input field
+        let cursor = match self.focus_idx {
+            0 => self.id_input.cursor_col(),
+            1 => password_cursor,
+            _ => passphrase_cursor,
+        };
+        f.set_cursor(x + cursor + 1, y + 1 + self.focus_idx as u16 * 3 + 1);
     }
 
     fn hook_key_event(&mut self, key_event: &KeyEvent) -> PostKeyCaptureAction {
+        if is_reveal_key(key_event) {
+            self.reveal = !self.reveal;
+            return PostKeyCaptureAction::Break;
+        }
         match key_event.code {
             // Switch focus
             KeyCode::Tab => {
-                self.focus_id_field = !self.focus_id_field;
+                self.focus_idx = (self.focus_idx + 1) % self.num_fields();
+                PostKeyCaptureAction::Break
+            }
+            // the password is asked for rather than sent empty
+            KeyCode::Enter if self.focus_idx == 0 && self.password_input.buf.is_empty() => {
+                self.focus_idx = 1;
                 PostKeyCaptureAction::Break
             }
             // Enter key entered,
@@ -134,15 +187,29 @@ impl PopupManager for LoginPopupManager {
                     Some(serde_json::json!({
                         "id": id,
                         "password": password,
+                        "passphrase": self.passphrase_input.as_ref().map(|i| i.buf.clone()),
                     })),
                 )
             }
+            // Paste from the system clipboard
+            KeyCode::Char('v') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                if let Some(text) = util::read_clipboard() {
+                    self.focused_input_mut().insert_str(&text, false);
+                }
+                PostKeyCaptureAction::Break
+            }
             KeyCode::Char(ch) => {
                 self.focused_input_mut().enter_char(ch);
+                if self.focus_idx > 0 {
+                    self.caps_lock = caps_lock_on(key_event, &self.focused_input().buf);
+                }
                 PostKeyCaptureAction::Break
             }
             KeyCode::Backspace => {
                 self.focused_input_mut().delete_char();
+                if self.focus_idx > 0 {
+                    self.caps_lock = caps_lock_on(key_event, &self.focused_input().buf);
+                }
                 PostKeyCaptureAction::Break
             }
             KeyCode::Left => {
@@ -158,4 +225,10 @@ impl PopupManager for LoginPopupManager {
             _ => PostKeyCaptureAction::Break,
         }
     }
+
+    fn hook_paste_event(&mut self, text: &str) -> PostKeyCaptureAction {
+        // popup fields are single-line
+        self.focused_input_mut().insert_str(text, false);
+        PostKeyCaptureAction::Break
+    }
 }
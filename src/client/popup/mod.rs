@@ -1,10 +1,16 @@
+pub mod confirm;
+pub mod help;
 pub mod login;
+pub mod onboarding;
+pub mod prompt;
 pub mod register;
+pub mod select;
+pub mod unlock;
 
-use crossterm::event::KeyEvent;
-use ratatui::prelude::*;
+use crossterm::event::{KeyCode, KeyEvent, KeyEventState, KeyModifiers};
+use ratatui::{prelude::*, widgets::Paragraph};
 
-use crate::client::app;
+use crate::client::{app, input_controller::InputController};
 
 pub enum PostKeyCaptureAction {
     CloseAndRunAction(app::CommandAction, Option<serde_json::Value>),
@@ -21,4 +27,127 @@ pub trait PopupManager {
     fn hook_key_event(&mut self, _: &KeyEvent) -> PostKeyCaptureAction {
         PostKeyCaptureAction::Fallthrough
     }
+
+    // Implement this method if your popup should capture pasted text
+    fn hook_paste_event(&mut self, _: &str) -> PostKeyCaptureAction {
+        PostKeyCaptureAction::Fallthrough
+    }
+}
+
+/// Area of `percent_x` by `percent_y` of `r` in its center
+pub fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
+    let center_y = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ])
+        .split(r);
+    center_x(percent_x, center_y[1])
+}
+
+/// Area of `percent_x` of `r` by `height` lines in its center
+pub fn centered_rect_lines(percent_x: u16, height: u16, r: Rect) -> Rect {
+    let height = height.min(r.height);
+    let center_y = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length((r.height - height) / 2),
+            Constraint::Length(height),
+            Constraint::Min(0),
+        ])
+        .split(r);
+    center_x(percent_x, center_y[1])
+}
+
+/// Rows of `area` from `offset` down, at most `height` of them
+///
+/// The rows are clipped to `area` so a popup never draws out of its area on a small terminal,
+/// they're empty once `offset` is past its bottom.
+pub fn rows(area: Rect, offset: u16, height: u16) -> Rect {
+    let offset = offset.min(area.height);
+    Rect::new(
+        area.x,
+        area.y + offset,
+        area.width,
+        height.min(area.height - offset),
+    )
+}
+
+/// Ctrl+R shows the password fields as they're typed, and hides them again
+pub fn is_reveal_key(key_event: &KeyEvent) -> bool {
+    key_event.code == KeyCode::Char('r') && key_event.modifiers.contains(KeyModifiers::CONTROL)
+}
+
+/// Caps lock is on, as the terminal reports it, or as the letters of `password` suggest
+///
+/// Most terminals don't report the lock keys, a password of upper case letters only is taken as
+/// typed with caps lock on then.
+pub fn caps_lock_on(key_event: &KeyEvent, password: &str) -> bool {
+    if key_event.state.contains(KeyEventState::CAPS_LOCK) {
+        return true;
+    }
+    let mut letters = password.chars().filter(|ch| ch.is_alphabetic()).peekable();
+    letters.peek().is_some() && letters.all(char::is_uppercase)
+}
+
+/// Content of a password field and the column of its cursor, masked unless `reveal`
+pub fn secret_field(input: &InputController, reveal: bool) -> (String, u16) {
+    if reveal {
+        (input.buf.clone(), input.cursor_col())
+    } else {
+        (input.masked(), input.masked_cursor_col())
+    }
+}
+
+/// Line under the password fields, the caps lock warning or how to reveal the password
+pub fn password_hint(caps_lock: bool, reveal: bool) -> Paragraph<'static> {
+    let line = if caps_lock {
+        Line::styled("Caps lock is on", Style::default().fg(Color::LightRed))
+    } else {
+        Line::from(vec![
+            "Ctrl+R".bold(),
+            if reveal {
+                " to hide the password".into()
+            } else {
+                " to show the password".into()
+            },
+        ])
+    };
+    Paragraph::new(line)
+}
+
+fn center_x(percent_x: u16, r: Rect) -> Rect {
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ])
+        .split(r)[1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upper_case_passwords_warn_of_caps_lock() {
+        let key = KeyEvent::new(KeyCode::Char('D'), KeyModifiers::SHIFT);
+        assert!(caps_lock_on(&key, "HUNTER2"));
+        assert!(!caps_lock_on(&key, "Hunter2"));
+        // nothing to tell from
+        assert!(!caps_lock_on(&key, "1234"));
+        assert!(!caps_lock_on(&key, ""));
+
+        let reported = KeyEvent::new_with_kind_and_state(
+            KeyCode::Char('d'),
+            KeyModifiers::NONE,
+            crossterm::event::KeyEventKind::Press,
+            KeyEventState::CAPS_LOCK,
+        );
+        assert!(caps_lock_on(&reported, "hunter2"));
+    }
 }
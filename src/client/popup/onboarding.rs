@@ -0,0 +1,218 @@
+use crossterm::event::KeyCode;
+use ratatui::{prelude::*, widgets::*};
+
+use super::*;
+use crate::client::{
+    config::{ClientConfig, Theme},
+    input_controller::InputController,
+};
+
+/// Steps of the wizard, in the order they are asked
+const STEPS: [&str; 4] = [
+    "Server host",
+    "Server port",
+    "Display name (optional)",
+    "Theme",
+];
+
+/// Step choosing the theme, the others are lines of text
+const THEME_STEP: usize = 3;
+
+/// Popups asking for the settings of the first launch one after the other
+///
+/// Closes once the last step is answered, with the settings in `finished`, or when the first
+/// one is cancelled. Esc on the other steps goes back to the previous one.
+pub struct OnboardingPopupManager {
+    step: usize,
+
+    /// Host, port and display name
+    inputs: [InputController; 3],
+    theme: usize,
+
+    /// Why the answer of the current step was refused
+    error: Option<String>,
+    pub finished: Option<ClientConfig>,
+}
+
+impl OnboardingPopupManager {
+    pub fn new() -> Self {
+        let defaults = ClientConfig::default();
+        let mut inputs: [InputController; 3] = Default::default();
+        inputs[0].insert_str(&defaults.host, false);
+        inputs[1].insert_str(&defaults.port, false);
+        Self {
+            step: 0,
+            inputs,
+            theme: 0,
+            error: None,
+            finished: None,
+        }
+    }
+
+    /// The answer of the current step is fine, or why it isn't
+    fn check_step(&self) -> Result<(), String> {
+        let answer = self.inputs.get(self.step).map(|input| input.buf.trim());
+        match (self.step, answer) {
+            (0, Some("")) => Err("The host can't be empty".to_owned()),
+            (1, Some(port)) if port.parse::<u16>().map_or(true, |port| port == 0) => {
+                Err(format!("'{}' is not a port number", port))
+            }
+            (2, Some(name)) if name.contains(char::is_whitespace) => {
+                Err("The display name can't contain spaces".to_owned())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn config(&self) -> ClientConfig {
+        let name = self.inputs[2].buf.trim();
+        ClientConfig {
+            host: self.inputs[0].buf.trim().to_owned(),
+            port: self.inputs[1].buf.trim().to_owned(),
+            display_name: (!name.is_empty()).then(|| name.to_owned()),
+            theme: Theme::ALL[self.theme],
+            ..ClientConfig::default()
+        }
+    }
+}
+
+impl Default for OnboardingPopupManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PopupManager for OnboardingPopupManager {
+    fn ui(&self, f: &mut Frame) {
+        // instruction line, the welcome line, the field and the error
+        let height = if self.step == THEME_STEP {
+            Theme::ALL.len() as u16 + 2
+        } else {
+            3
+        };
+        let popup_area = centered_rect_lines(50, height + 3, f.size());
+
+        // clear out the background
+        f.render_widget(Clear, popup_area);
+
+        let (x, y) = (popup_area.x, popup_area.y);
+
+        // instruction
+        f.render_widget(
+            Paragraph::new({
+                let mut line = Line::from(vec![
+                    "Esc".bold(),
+                    if self.step == 0 {
+                        " to quit |".into()
+                    } else {
+                        " to go back |".into()
+                    },
+                    " Enter".bold(),
+                    " to continue".into(),
+                ]);
+                line.patch_style(Style::default().add_modifier(Modifier::RAPID_BLINK));
+                line
+            }),
+            rows(popup_area, 0, 1),
+        );
+        f.render_widget(
+            Paragraph::new(format!(
+                "Welcome to rschat! Setting up, step {} of {}",
+                self.step + 1,
+                STEPS.len()
+            )),
+            rows(popup_area, 1, 1),
+        );
+
+        let title = STEPS[self.step];
+        let field = rows(popup_area, 2, height);
+        if self.step == THEME_STEP {
+            let items: Vec<ListItem> = Theme::ALL
+                .iter()
+                .map(|theme| ListItem::new(theme.as_str()))
+                .collect();
+            let mut state = ListState::default().with_selected(Some(self.theme));
+            f.render_stateful_widget(
+                List::new(items)
+                    .block(Block::default().borders(Borders::ALL).title(title))
+                    .highlight_style(Style::default().fg(Color::Yellow).bold())
+                    .highlight_symbol("> "),
+                field,
+                &mut state,
+            );
+        } else {
+            let input = &self.inputs[self.step];
+            f.render_widget(
+                Paragraph::new(input.buf.as_str())
+                    .style(Style::default().fg(Color::Yellow))
+                    .block(Block::default().borders(Borders::ALL).title(title)),
+                field,
+            );
+            f.set_cursor(x + input.cursor_col() + 1, y + 3);
+        }
+
+        if let Some(error) = &self.error {
+            f.render_widget(
+                Paragraph::new(error.as_str()).style(Style::default().fg(Color::LightRed)),
+                rows(popup_area, 2 + height, 1),
+            );
+        }
+    }
+
+    fn hook_key_event(&mut self, key_event: &KeyEvent) -> PostKeyCaptureAction {
+        match key_event.code {
+            KeyCode::Enter => {
+                if let Err(e) = self.check_step() {
+                    self.error = Some(e);
+                    return PostKeyCaptureAction::Break;
+                }
+                self.error = None;
+                if self.step == STEPS.len() - 1 {
+                    self.finished = Some(self.config());
+                    return PostKeyCaptureAction::ClosePopup;
+                }
+                self.step += 1;
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Esc if self.step == 0 => PostKeyCaptureAction::ClosePopup,
+            KeyCode::Esc => {
+                self.error = None;
+                self.step -= 1;
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Up if self.step == THEME_STEP => {
+                self.theme = self.theme.saturating_sub(1);
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Down | KeyCode::Tab if self.step == THEME_STEP => {
+                self.theme = (self.theme + 1).min(Theme::ALL.len() - 1);
+                PostKeyCaptureAction::Break
+            }
+            _ if self.step == THEME_STEP => PostKeyCaptureAction::Break,
+            KeyCode::Char(ch) => {
+                self.inputs[self.step].enter_char(ch);
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Backspace => {
+                self.inputs[self.step].delete_char();
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Left => {
+                self.inputs[self.step].move_cursor_left();
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Right => {
+                self.inputs[self.step].move_cursor_right();
+                PostKeyCaptureAction::Break
+            }
+            _ => PostKeyCaptureAction::Break,
+        }
+    }
+
+    fn hook_paste_event(&mut self, text: &str) -> PostKeyCaptureAction {
+        if let Some(input) = self.inputs.get_mut(self.step) {
+            input.insert_str(text, false);
+        }
+        PostKeyCaptureAction::Break
+    }
+}
@@ -0,0 +1,136 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::{prelude::*, widgets::*};
+
+use super::*;
+use crate::client::{input_controller::InputController, util};
+
+/// Asks for a single line of text, handed to `action` as `key` along with the other `args`
+pub struct PromptPopupManager {
+    title: String,
+    key: String,
+    action: app::CommandAction,
+    args: serde_json::Map<String, serde_json::Value>,
+    input: InputController,
+
+    /// The input is shown as asterisks, e.g. for passwords
+    masked: bool,
+}
+
+impl PromptPopupManager {
+    pub fn new(title: &str, key: &str, action: app::CommandAction) -> Self {
+        Self {
+            title: title.to_owned(),
+            key: key.to_owned(),
+            action,
+            args: serde_json::Map::new(),
+            input: InputController::default(),
+            masked: false,
+        }
+    }
+
+    /// Hide the input behind asterisks
+    pub fn masked(mut self) -> Self {
+        self.masked = true;
+        self
+    }
+
+    /// Hand `args` to the action as well, `args` must be an object
+    pub fn with_args(mut self, args: serde_json::Value) -> Self {
+        if let serde_json::Value::Object(args) = args {
+            self.args = args;
+        }
+        self
+    }
+}
+
+impl PopupManager for PromptPopupManager {
+    fn ui(&self, f: &mut Frame) {
+        let popup_area = centered_rect_lines(50, 4, f.size());
+
+        // clear out the background
+        f.render_widget(Clear, popup_area);
+
+        let (x, y) = (popup_area.x, popup_area.y);
+
+        // instruction
+        f.render_widget(
+            Paragraph::new({
+                let mut line = Line::from(vec![
+                    "Esc".bold(),
+                    " to cancel |".into(),
+                    " Enter".bold(),
+                    " to submit".into(),
+                ]);
+                line.patch_style(Style::default().add_modifier(Modifier::RAPID_BLINK));
+                line
+            }),
+            rows(popup_area, 0, 1),
+        );
+
+        f.render_widget(
+            Paragraph::new(if self.masked {
+                self.input.masked()
+            } else {
+                self.input.buf.clone()
+            })
+            .style(Style::default().fg(Color::Yellow))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(self.title.as_str()),
+            ),
+            rows(popup_area, 1, 3),
+        );
+
+        let cursor = if self.masked {
+            self.input.masked_cursor_col()
+        } else {
+            self.input.cursor_col()
+        };
+        f.set_cursor(x + cursor + 1, y + 2);
+    }
+
+    fn hook_key_event(&mut self, key_event: &KeyEvent) -> PostKeyCaptureAction {
+        match key_event.code {
+            KeyCode::Enter => {
+                let mut args = self.args.clone();
+                args.insert(self.key.clone(), self.input.buf.clone().into());
+                PostKeyCaptureAction::CloseAndRunAction(
+                    self.action.clone(),
+                    Some(serde_json::Value::Object(args)),
+                )
+            }
+            // Paste from the system clipboard
+            KeyCode::Char('v') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                if let Some(text) = util::read_clipboard() {
+                    self.input.insert_str(&text, false);
+                }
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Char(ch) => {
+                self.input.enter_char(ch);
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Backspace => {
+                self.input.delete_char();
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Left => {
+                self.input.move_cursor_left();
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Right => {
+                self.input.move_cursor_right();
+                PostKeyCaptureAction::Break
+            }
+            // Cancellation
+            KeyCode::Esc => PostKeyCaptureAction::ClosePopup,
+            _ => PostKeyCaptureAction::Break,
+        }
+    }
+
+    fn hook_paste_event(&mut self, text: &str) -> PostKeyCaptureAction {
+        self.input.insert_str(text, false);
+        PostKeyCaptureAction::Break
+    }
+}
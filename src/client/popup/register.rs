@@ -1,8 +1,8 @@
-use crossterm::event::KeyCode;
+use crossterm::event::{KeyCode, KeyModifiers};
 use ratatui::{prelude::*, widgets::*};
 
 use super::*;
-use crate::client::input_controller::InputController;
+use crate::client::{input_controller::InputController, util};
 
 pub struct RegisterPopupManager {
     id_input: InputController,
@@ -12,6 +12,13 @@ pub struct RegisterPopupManager {
 
     // index of the currently focus field
     focus_idx: usize,
+
+    // true if the registered account replaces the current guest
+    upgrade: bool,
+
+    /// The password is shown as typed, toggled with Ctrl+R
+    reveal: bool,
+    caps_lock: bool,
 }
 
 impl RegisterPopupManager {
@@ -22,26 +29,18 @@ impl RegisterPopupManager {
             bio_input: InputController::default(),
             location_input: InputController::default(),
             focus_idx: 0usize,
+            upgrade: false,
+            reveal: false,
+            caps_lock: false,
         }
     }
 
-    fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
-        let center_y = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Percentage((100 - percent_y) / 2),
-                Constraint::Percentage(percent_y),
-                Constraint::Percentage((100 - percent_y) / 2),
-            ])
-            .split(r);
-        Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints([
-                Constraint::Percentage((100 - percent_x) / 2),
-                Constraint::Percentage(percent_x),
-                Constraint::Percentage((100 - percent_x) / 2),
-            ])
-            .split(center_y[1])[1]
+    /// Register popup upgrading the current guest to the new account
+    pub fn for_upgrade() -> Self {
+        Self {
+            upgrade: true,
+            ..Self::new()
+        }
     }
 
     /// return reference to the currently focused input controller
@@ -69,14 +68,21 @@ impl RegisterPopupManager {
     }
 }
 
+impl Default for RegisterPopupManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PopupManager for RegisterPopupManager {
     fn ui(&self, f: &mut Frame) {
-        let popup_area = RegisterPopupManager::centered_rect(50, 11, f.size());
+        // instruction line, the four fields and the hint under the password
+        let popup_area = centered_rect_lines(50, 14, f.size());
 
         // clear out the background
         f.render_widget(Clear, popup_area);
 
-        let (x, y, width) = (popup_area.x, popup_area.y, popup_area.width);
+        let (x, y) = (popup_area.x, popup_area.y);
 
         // instruction
         f.render_widget(
@@ -85,14 +91,18 @@ impl PopupManager for RegisterPopupManager {
                     "Esc".bold(),
                     " to cancel |".into(),
                     " Enter".bold(),
-                    " to login |".into(),
+                    if self.upgrade {
+                        " to upgrade |".into()
+                    } else {
+                        " to register |".into()
+                    },
                     " Tab".bold(),
                     " to switch focus".into(),
                 ]);
                 line.patch_style(Style::default().add_modifier(Modifier::RAPID_BLINK));
                 line
             }),
-            Rect::new(x, y, width, 1),
+            rows(popup_area, 0, 1),
         );
 
         // ID input box
@@ -104,22 +114,28 @@ impl PopupManager for RegisterPopupManager {
                     Color::default()
                 }))
                 .block(Block::default().borders(Borders::ALL).title("ID")),
-            Rect::new(x, y + 1, width, 3),
+            rows(popup_area, 1, 3),
         );
 
         // Password input box
+        let (password, password_cursor) = secret_field(&self.password_input, self.reveal);
         f.render_widget(
-            Paragraph::new("*".repeat(self.password_input.buf.len()))
+            Paragraph::new(password)
                 .style(Style::default().fg(if self.focus_idx == 1 {
                     Color::Yellow
                 } else {
                     Color::default()
                 }))
                 .block(Block::default().borders(Borders::ALL).title("Password")),
-            Rect::new(x, y + 4, width, 3),
+            rows(popup_area, 4, 3),
         );
 
-        // Password input box
+        f.render_widget(
+            password_hint(self.caps_lock, self.reveal),
+            rows(popup_area, 7, 1),
+        );
+
+        // Bio input box
         f.render_widget(
             Paragraph::new(self.bio_input.buf.as_str())
                 .style(Style::default().fg(if self.focus_idx == 2 {
@@ -128,10 +144,10 @@ impl PopupManager for RegisterPopupManager {
                     Color::default()
                 }))
                 .block(Block::default().borders(Borders::ALL).title("bio")),
-            Rect::new(x, y + 7, width, 3),
+            rows(popup_area, 8, 3),
         );
 
-        // Password input box
+        // Location input box
         f.render_widget(
             Paragraph::new(self.location_input.buf.as_str())
                 .style(Style::default().fg(if self.focus_idx == 3 {
@@ -140,28 +156,47 @@ impl PopupManager for RegisterPopupManager {
                     Color::default()
                 }))
                 .block(Block::default().borders(Borders::ALL).title("location")),
-            Rect::new(x, y + 10, width, 3),
+            rows(popup_area, 11, 3),
         );
 
         // cursor position depends on its focusing input field
+        let cursor = match self.focus_idx {
+            1 => password_cursor,
+            _ => self.focused_input().cursor_col(),
+        };
+        // the fields under the hint are one row further down
+        let hint_row = u16::from(self.focus_idx > 1);
         f.set_cursor(
-            x + self.focused_input().cursor_pos as u16 + 1,
-            y + 1 + self.focus_idx as u16 * 3 + 1,
+            x + cursor + 1,
+            y + 1 + self.focus_idx as u16 * 3 + hint_row + 1,
         );
     }
 
     fn hook_key_event(&mut self, key_event: &KeyEvent) -> PostKeyCaptureAction {
+        if is_reveal_key(key_event) {
+            self.reveal = !self.reveal;
+            return PostKeyCaptureAction::Break;
+        }
         match key_event.code {
             // Switch focus
             KeyCode::Tab => {
                 self.focus_idx = (self.focus_idx + 1) % 4;
                 PostKeyCaptureAction::Break
             }
+            // the password is asked for rather than sent empty
+            KeyCode::Enter if self.focus_idx == 0 && self.password_input.buf.is_empty() => {
+                self.focus_idx = 1;
+                PostKeyCaptureAction::Break
+            }
             // Enter key entered,
             KeyCode::Enter => {
                 // construct register action request
                 PostKeyCaptureAction::CloseAndRunAction(
-                    app::CommandAction::Register,
+                    if self.upgrade {
+                        app::CommandAction::Upgrade
+                    } else {
+                        app::CommandAction::Register
+                    },
                     Some(serde_json::json!({
                         "id": self.id_input.buf.clone(),
                         "password": self.password_input.buf.clone(),
@@ -170,12 +205,25 @@ impl PopupManager for RegisterPopupManager {
                     })),
                 )
             }
+            // Paste from the system clipboard
+            KeyCode::Char('v') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                if let Some(text) = util::read_clipboard() {
+                    self.focused_input_mut().insert_str(&text, false);
+                }
+                PostKeyCaptureAction::Break
+            }
             KeyCode::Char(ch) => {
                 self.focused_input_mut().enter_char(ch);
+                if self.focus_idx == 1 {
+                    self.caps_lock = caps_lock_on(key_event, &self.password_input.buf);
+                }
                 PostKeyCaptureAction::Break
             }
             KeyCode::Backspace => {
                 self.focused_input_mut().delete_char();
+                if self.focus_idx == 1 {
+                    self.caps_lock = caps_lock_on(key_event, &self.password_input.buf);
+                }
                 PostKeyCaptureAction::Break
             }
             KeyCode::Left => {
@@ -191,4 +239,10 @@ impl PopupManager for RegisterPopupManager {
             _ => PostKeyCaptureAction::Break,
         }
     }
+
+    fn hook_paste_event(&mut self, text: &str) -> PostKeyCaptureAction {
+        // popup fields are single-line
+        self.focused_input_mut().insert_str(text, false);
+        PostKeyCaptureAction::Break
+    }
 }
@@ -0,0 +1,128 @@
+use crossterm::event::KeyCode;
+use ratatui::{prelude::*, widgets::*};
+
+use super::*;
+
+/// Lets the user pick one of several choices, the choice is handed to `action` as `"choice"`
+/// along with the other `args`
+pub struct SelectPopupManager {
+    title: String,
+    choices: Vec<String>,
+
+    /// Lines shown for the choices, the choices themselves if `None`
+    labels: Option<Vec<String>>,
+    selected: usize,
+    action: app::CommandAction,
+    args: serde_json::Map<String, serde_json::Value>,
+}
+
+impl SelectPopupManager {
+    pub fn new(title: &str, choices: Vec<String>, action: app::CommandAction) -> Self {
+        Self {
+            title: title.to_owned(),
+            choices,
+            labels: None,
+            selected: 0,
+            action,
+            args: serde_json::Map::new(),
+        }
+    }
+
+    /// Show `labels` for the choices, one per choice, e.g. to describe them
+    pub fn with_labels(mut self, labels: Vec<String>) -> Self {
+        if labels.len() == self.choices.len() {
+            self.labels = Some(labels);
+        }
+        self
+    }
+
+    /// Start with the choice `selected`
+    pub fn with_selected(mut self, selected: usize) -> Self {
+        self.selected = selected.min(self.choices.len().saturating_sub(1));
+        self
+    }
+
+    /// Hand `args` to the action as well, `args` must be an object
+    pub fn with_args(mut self, args: serde_json::Value) -> Self {
+        if let serde_json::Value::Object(args) = args {
+            self.args = args;
+        }
+        self
+    }
+}
+
+impl PopupManager for SelectPopupManager {
+    fn ui(&self, f: &mut Frame) {
+        // instruction line, the borders and one row per choice, the labels take more room
+        let percent_x = if self.labels.is_some() { 70 } else { 40 };
+        let popup_area = centered_rect_lines(percent_x, self.choices.len() as u16 + 3, f.size());
+
+        // clear out the background
+        f.render_widget(Clear, popup_area);
+
+        // instruction
+        f.render_widget(
+            Paragraph::new({
+                let mut line = Line::from(vec![
+                    "Esc".bold(),
+                    " to cancel |".into(),
+                    " Up/Down".bold(),
+                    " to move |".into(),
+                    " Enter".bold(),
+                    " to select".into(),
+                ]);
+                line.patch_style(Style::default().add_modifier(Modifier::RAPID_BLINK));
+                line
+            }),
+            rows(popup_area, 0, 1),
+        );
+
+        let items: Vec<ListItem> = self
+            .labels
+            .as_ref()
+            .unwrap_or(&self.choices)
+            .iter()
+            .map(|choice| ListItem::new(choice.as_str()))
+            .collect();
+        let mut state = ListState::default().with_selected(Some(self.selected));
+        f.render_stateful_widget(
+            List::new(items)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(self.title.as_str()),
+                )
+                .highlight_style(Style::default().fg(Color::Yellow).bold())
+                .highlight_symbol("> "),
+            rows(popup_area, 1, popup_area.height.saturating_sub(1)),
+            &mut state,
+        );
+    }
+
+    fn hook_key_event(&mut self, key_event: &KeyEvent) -> PostKeyCaptureAction {
+        match key_event.code {
+            KeyCode::Enter => {
+                let mut args = self.args.clone();
+                args.insert(
+                    "choice".to_owned(),
+                    self.choices[self.selected].clone().into(),
+                );
+                PostKeyCaptureAction::CloseAndRunAction(
+                    self.action.clone(),
+                    Some(serde_json::Value::Object(args)),
+                )
+            }
+            KeyCode::Up => {
+                self.selected = self.selected.saturating_sub(1);
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Down | KeyCode::Tab => {
+                self.selected = (self.selected + 1).min(self.choices.len() - 1);
+                PostKeyCaptureAction::Break
+            }
+            // Cancellation
+            KeyCode::Esc => PostKeyCaptureAction::ClosePopup,
+            _ => PostKeyCaptureAction::Break,
+        }
+    }
+}
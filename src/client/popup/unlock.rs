@@ -0,0 +1,106 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::{prelude::*, widgets::*};
+
+use super::*;
+use crate::client::{input_controller::InputController, util};
+
+/// Asks for the passphrase of the saved credentials
+pub struct UnlockPopupManager {
+    passphrase_input: InputController,
+}
+
+impl UnlockPopupManager {
+    pub fn new() -> Self {
+        Self {
+            passphrase_input: InputController::default(),
+        }
+    }
+}
+
+impl Default for UnlockPopupManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PopupManager for UnlockPopupManager {
+    fn ui(&self, f: &mut Frame) {
+        let popup_area = centered_rect(50, 8, f.size());
+
+        // clear out the background
+        f.render_widget(Clear, popup_area);
+
+        let (x, y) = (popup_area.x, popup_area.y);
+
+        // instruction
+        f.render_widget(
+            Paragraph::new({
+                let mut line = Line::from(vec![
+                    "Esc".bold(),
+                    " to stay as a guest |".into(),
+                    " Enter".bold(),
+                    " to login".into(),
+                ]);
+                line.patch_style(Style::default().add_modifier(Modifier::RAPID_BLINK));
+                line
+            }),
+            rows(popup_area, 0, 1),
+        );
+
+        // Passphrase input box
+        f.render_widget(
+            Paragraph::new(self.passphrase_input.masked())
+                .style(Style::default().fg(Color::Yellow))
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title("Passphrase of the saved credentials"),
+                ),
+            rows(popup_area, 1, 3),
+        );
+
+        f.set_cursor(x + self.passphrase_input.masked_cursor_col() + 1, y + 2);
+    }
+
+    fn hook_key_event(&mut self, key_event: &KeyEvent) -> PostKeyCaptureAction {
+        match key_event.code {
+            KeyCode::Enter => PostKeyCaptureAction::CloseAndRunAction(
+                app::CommandAction::AutoLogin,
+                Some(serde_json::json!({
+                    "passphrase": self.passphrase_input.buf.clone(),
+                })),
+            ),
+            // Paste from the system clipboard
+            KeyCode::Char('v') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                if let Some(text) = util::read_clipboard() {
+                    self.passphrase_input.insert_str(&text, false);
+                }
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Char(ch) => {
+                self.passphrase_input.enter_char(ch);
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Backspace => {
+                self.passphrase_input.delete_char();
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Left => {
+                self.passphrase_input.move_cursor_left();
+                PostKeyCaptureAction::Break
+            }
+            KeyCode::Right => {
+                self.passphrase_input.move_cursor_right();
+                PostKeyCaptureAction::Break
+            }
+            // Cancellation
+            KeyCode::Esc => PostKeyCaptureAction::ClosePopup,
+            _ => PostKeyCaptureAction::Break,
+        }
+    }
+
+    fn hook_paste_event(&mut self, text: &str) -> PostKeyCaptureAction {
+        self.passphrase_input.insert_str(text, false);
+        PostKeyCaptureAction::Break
+    }
+}
@@ -0,0 +1,114 @@
+//! Recovery file, `recovery.json` of the configuration directory
+//!
+//! The layout of the session is checkpointed to it every few seconds and the file is removed by
+//! `/exit`, so a file found at the launch is left by a client that was killed or crashed. The next
+//! launch offers to restore it once connected: the channel, the panes, the draft of the input, the
+//! scroll position and the unread counts.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::credentials;
+
+const RECOVERY_FILE: &str = "recovery.json";
+
+/// Interval of the checkpoints, a killed client loses at most this much of its layout
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Layout of a session as of its latest checkpoint
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Recovery {
+    pub id: String,
+    pub channel: String,
+
+    /// Text left in the input, and the message it replied to
+    pub draft: String,
+    pub reply_to: Option<u64>,
+
+    /// Root of the thread shown next to the messages
+    pub thread: Option<u64>,
+    pub activity_open: bool,
+    pub pins_collapsed: bool,
+
+    /// Names of the kinds of messages left out, see `/filter`
+    pub hidden_kinds: Vec<String>,
+
+    /// Number of messages hidden below the view of the message section
+    pub scroll: usize,
+    pub unread: BTreeMap<String, usize>,
+    pub mentioned: BTreeSet<String>,
+}
+
+impl Recovery {
+    /// Layout left by the last session, `None` if it exited cleanly
+    pub fn load() -> Option<Self> {
+        let s = fs::read_to_string(path().ok()?).ok()?;
+        serde_json::from_str(&s).ok()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let json = serde_json::to_string_pretty(self).unwrap();
+        fs::write(&path, json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// The session exited cleanly, there is nothing to recover
+    pub fn remove() {
+        if let Ok(path) = path() {
+            _ = fs::remove_file(path);
+        }
+    }
+
+    /// What restoring brings back, for the question asked at the launch
+    pub fn summary(&self) -> String {
+        let mut parts = vec![format!("channel '{}'", self.channel)];
+        if !self.draft.is_empty() {
+            parts.push("a draft".to_owned());
+        }
+        if let Some(root) = self.thread {
+            parts.push(format!("the thread of #{}", root));
+        }
+        let unread: usize = self.unread.values().sum();
+        if unread > 0 {
+            parts.push(format!("{} unread", unread));
+        }
+        parts.join(", ")
+    }
+}
+
+fn path() -> Result<PathBuf, String> {
+    credentials::config_dir()
+        .map(|dir| dir.join(RECOVERY_FILE))
+        .ok_or_else(|| "no configuration directory, HOME is not set".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn older_files_fill_in_the_defaults() {
+        let recovery: Recovery =
+            serde_json::from_str(r#"{"channel": "dev", "draft": "half a", "scroll": 3}"#).unwrap();
+        assert_eq!(
+            recovery,
+            Recovery {
+                channel: "dev".to_owned(),
+                draft: "half a".to_owned(),
+                scroll: 3,
+                ..Recovery::default()
+            }
+        );
+        assert_eq!(recovery.summary(), "channel 'dev', a draft");
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Raised whenever something shown on the screen changes, the screen is drawn only then
+///
+/// Clones share the flag, so the background tasks can ask the main loop for a redraw.
+#[derive(Debug, Clone)]
+pub struct RedrawFlag(Arc<AtomicBool>);
+
+impl Default for RedrawFlag {
+    /// Raised, nothing has been drawn yet
+    fn default() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+}
+
+impl RedrawFlag {
+    pub fn raise(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// True if the flag was raised since the last call, lowers it
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::AcqRel)
+    }
+}
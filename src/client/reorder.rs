@@ -0,0 +1,116 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use crate::packet::{JoinSnapshot, Message};
+
+/// Messages held back at most, the oldest is released once more are waiting
+const MAX_PENDING: usize = 16;
+
+/// Longest a message waits for the ones before it
+pub const MAX_WAIT: Duration = Duration::from_millis(500);
+
+/// Releases the messages of the current channel in the order of their sequence numbers
+///
+/// Messages arriving ahead of their turn are held back until the missing ones arrive, or given up
+/// on after `MAX_WAIT`. Messages without a sequence number, e.g. notices or direct messages, are
+/// never held back.
+#[derive(Default)]
+pub struct ReorderBuffer {
+    /// Sequence number of the next message to release, `None` until the first one arrives
+    next: Option<u64>,
+
+    pending: BTreeMap<u64, Message>,
+
+    /// When the oldest pending message arrived
+    waiting_since: Option<Instant>,
+}
+
+impl ReorderBuffer {
+    /// The channel `snapshot` is of has been joined, its history is already in order
+    ///
+    /// Returns the messages of the previous channel that were still held back.
+    pub fn on_snapshot(&mut self, snapshot: &JoinSnapshot) -> Vec<Message> {
+        let mut released = Vec::new();
+        while !self.pending.is_empty() {
+            released.extend(self.skip_gap());
+        }
+        self.next = snapshot
+            .messages
+            .iter()
+            .filter_map(|msg| msg.seq)
+            .next_back()
+            .map(|seq| seq + 1);
+        self.waiting_since = None;
+        released
+    }
+
+    /// Messages that can be shown now that `msg` arrived, in order
+    pub fn push(&mut self, msg: Message) -> Vec<Message> {
+        let Some(seq) = msg.seq else {
+            return vec![msg];
+        };
+        match self.next {
+            // resent after a resynchronization
+            Some(next) if seq < next => return Vec::new(),
+            Some(_) => {
+                self.pending.insert(seq, msg);
+            }
+            None => {
+                self.next = Some(seq);
+                self.pending.insert(seq, msg);
+            }
+        }
+
+        let mut released = self.release_ready();
+        while self.pending.len() > MAX_PENDING {
+            released.extend(self.skip_gap());
+        }
+        self.waiting_since = if self.pending.is_empty() {
+            None
+        } else {
+            self.waiting_since.or(Some(Instant::now()))
+        };
+        released
+    }
+
+    /// Give up on the missing messages if the pending ones have waited long enough
+    pub fn release_expired(&mut self) -> Vec<Message> {
+        match self.waiting_since {
+            Some(since) if since.elapsed() >= MAX_WAIT => {
+                let mut released = Vec::new();
+                while !self.pending.is_empty() {
+                    released.extend(self.skip_gap());
+                }
+                self.waiting_since = None;
+                released
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// True if messages are held back
+    pub fn is_waiting(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Pop the pending messages continuing from `next`
+    fn release_ready(&mut self) -> Vec<Message> {
+        let mut released = Vec::new();
+        while let Some(next) = self.next {
+            let Some(msg) = self.pending.remove(&next) else {
+                break;
+            };
+            released.push(msg);
+            self.next = Some(next + 1);
+        }
+        released
+    }
+
+    /// Jump over the missing messages to the oldest pending one
+    fn skip_gap(&mut self) -> Vec<Message> {
+        self.next = self.pending.keys().next().copied();
+        self.release_ready()
+    }
+}
@@ -0,0 +1,284 @@
+//! User scripts reacting to the events of the client
+//!
+//! Scripts are the `*.rhai` files in the `scripts` directory of the config directory, written in
+//! [Rhai](https://rhai.rs). A script may define any of the event handlers
+//!
+//! - `on_connect(id)`, once connected and after every login,
+//! - `on_message(from, text, channel)`, for the messages of others, `channel` is empty for direct
+//!   messages,
+//! - `on_mention(from, text, channel)`, for the messages mentioning you,
+//!
+//! and commands as functions named `command_<name>(args)`, run by `/<name> args` unless a built-in
+//! command has the name. Scripts can't reach files or the network, they `send(text)` to the current
+//! channel and `print(text)` to the message section. Every call is cut short past a number of
+//! operations and a duration, and strings and collections are limited in size.
+
+use std::{
+    cell::{Cell, RefCell},
+    path::PathBuf,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use rhai::{Dynamic, Engine, FuncArgs, Scope, AST};
+
+use super::credentials;
+
+/// Longest a single call into a script may run
+const CALL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Operations a single call into a script may take
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Bytes of a string and items of an array or a map a script may build
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_COLLECTION_SIZE: usize = 10_000;
+
+/// Depth of the calls and the expressions of a script
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPR_DEPTH: usize = 64;
+
+/// Prefix of the functions run as commands
+const COMMAND_PREFIX: &str = "command_";
+
+/// What a script asked the client to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptAction {
+    /// Send the text to the current channel
+    Send(String),
+    /// Show the text in the message section
+    Print(String),
+    /// Show the text as an error, e.g. a script failing
+    Error(String),
+}
+
+struct Script {
+    name: String,
+    ast: AST,
+
+    /// Variables of the top level, kept between the calls
+    scope: Scope<'static>,
+}
+
+impl Script {
+    fn defines(&self, fn_name: &str, num_params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == fn_name && f.params.len() == num_params)
+    }
+}
+
+pub struct ScriptHost {
+    engine: Engine,
+    scripts: Vec<Script>,
+
+    /// Actions of the call in progress, taken once it returns
+    actions: Rc<RefCell<Vec<ScriptAction>>>,
+
+    /// Time the call in progress is terminated at
+    deadline: Rc<Cell<Option<Instant>>>,
+}
+
+impl Default for ScriptHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptHost {
+    /// Host without any script, see `reload`
+    pub fn new() -> Self {
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        let deadline = Rc::new(Cell::new(None::<Instant>));
+
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_COLLECTION_SIZE)
+            .set_max_map_size(MAX_COLLECTION_SIZE)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH)
+            .disable_symbol("eval");
+
+        let progress_deadline = deadline.clone();
+        engine.on_progress(move |_| match progress_deadline.get() {
+            Some(deadline) if Instant::now() >= deadline => Some("timed out".into()),
+            _ => None,
+        });
+        let print_actions = actions.clone();
+        engine.on_print(move |text| {
+            print_actions
+                .borrow_mut()
+                .push(ScriptAction::Print(text.to_owned()))
+        });
+        let debug_actions = actions.clone();
+        engine.on_debug(move |text, _, _| {
+            debug_actions
+                .borrow_mut()
+                .push(ScriptAction::Print(text.to_owned()))
+        });
+        let send_actions = actions.clone();
+        engine.register_fn("send", move |text: &str| {
+            send_actions
+                .borrow_mut()
+                .push(ScriptAction::Send(text.to_owned()))
+        });
+
+        Self {
+            engine,
+            scripts: Vec::new(),
+            actions,
+            deadline,
+        }
+    }
+
+    /// Directory the scripts are loaded from
+    pub fn dir() -> Option<PathBuf> {
+        credentials::config_dir().map(|dir| dir.join("scripts"))
+    }
+
+    /// Names of the loaded scripts along with the commands they define
+    pub fn list(&self) -> Vec<(String, Vec<String>)> {
+        self.scripts
+            .iter()
+            .map(|script| {
+                let commands = script
+                    .ast
+                    .iter_functions()
+                    .filter(|f| f.params.len() == 1)
+                    .filter_map(|f| f.name.strip_prefix(COMMAND_PREFIX))
+                    .map(|name| format!("/{}", name))
+                    .collect();
+                (script.name.clone(), commands)
+            })
+            .collect()
+    }
+
+    /// Drop the loaded scripts and load the ones in `dir` again, in the order of their names
+    ///
+    /// The top level of every script is run once loaded, broken scripts are reported and skipped.
+    pub fn reload(&mut self) -> Vec<ScriptAction> {
+        self.scripts.clear();
+        let Some(dir) = Self::dir() else {
+            return vec![ScriptAction::Error(
+                "No config directory to load the scripts from".to_owned(),
+            )];
+        };
+        let mut paths: Vec<PathBuf> = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "rhai"))
+                .collect(),
+            // no scripts, nothing to report
+            Err(_) => return Vec::new(),
+        };
+        paths.sort();
+
+        let mut reports = Vec::new();
+        for path in paths {
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let ast = match std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|source| self.engine.compile(source).map_err(|e| e.to_string()))
+            {
+                Ok(ast) => ast,
+                Err(e) => {
+                    reports.push(ScriptAction::Error(format!(
+                        "Script '{}' failed to load: {}",
+                        name, e
+                    )));
+                    continue;
+                }
+            };
+
+            let mut scope = Scope::new();
+            self.deadline.set(Some(Instant::now() + CALL_TIMEOUT));
+            let result = self.engine.run_ast_with_scope(&mut scope, &ast);
+            self.deadline.set(None);
+            reports.append(&mut self.actions.borrow_mut());
+            match result {
+                Ok(()) => self.scripts.push(Script { name, ast, scope }),
+                Err(e) => reports.push(ScriptAction::Error(format!(
+                    "Script '{}' failed to start: {}",
+                    name, e
+                ))),
+            }
+        }
+        reports
+    }
+
+    pub fn on_connect(&mut self, id: &str) -> Vec<ScriptAction> {
+        self.call_all("on_connect", 1, (id.to_owned(),))
+    }
+
+    pub fn on_message(&mut self, from: &str, text: &str, channel: &str) -> Vec<ScriptAction> {
+        self.call_all(
+            "on_message",
+            3,
+            (from.to_owned(), text.to_owned(), channel.to_owned()),
+        )
+    }
+
+    pub fn on_mention(&mut self, from: &str, text: &str, channel: &str) -> Vec<ScriptAction> {
+        self.call_all(
+            "on_mention",
+            3,
+            (from.to_owned(), text.to_owned(), channel.to_owned()),
+        )
+    }
+
+    /// True if a script defines the command `name`
+    pub fn has_command(&self, name: &str) -> bool {
+        let fn_name = format!("{}{}", COMMAND_PREFIX, name);
+        self.scripts
+            .iter()
+            .any(|script| script.defines(&fn_name, 1))
+    }
+
+    /// Run the command `name` with `args`, the first script defining it runs it
+    pub fn command(&mut self, name: &str, args: &str) -> Vec<ScriptAction> {
+        let fn_name = format!("{}{}", COMMAND_PREFIX, name);
+        match self.scripts.iter().position(|s| s.defines(&fn_name, 1)) {
+            Some(idx) => self.call(idx, &fn_name, (args.to_owned(),)),
+            None => Vec::new(),
+        }
+    }
+
+    /// Call `fn_name` taking `num_params` of every script defining it
+    fn call_all(
+        &mut self,
+        fn_name: &str,
+        num_params: usize,
+        args: impl FuncArgs + Clone,
+    ) -> Vec<ScriptAction> {
+        let mut actions = Vec::new();
+        for idx in 0..self.scripts.len() {
+            if self.scripts[idx].defines(fn_name, num_params) {
+                actions.append(&mut self.call(idx, fn_name, args.clone()));
+            }
+        }
+        actions
+    }
+
+    fn call(&mut self, idx: usize, fn_name: &str, args: impl FuncArgs) -> Vec<ScriptAction> {
+        let script = &mut self.scripts[idx];
+        self.deadline.set(Some(Instant::now() + CALL_TIMEOUT));
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(&mut script.scope, &script.ast, fn_name, args);
+        self.deadline.set(None);
+
+        let mut actions = std::mem::take(&mut *self.actions.borrow_mut());
+        if let Err(e) = result {
+            actions.push(ScriptAction::Error(format!(
+                "Script '{}' failed in '{}': {}",
+                script.name, fn_name, e
+            )));
+        }
+        actions
+    }
+}
@@ -1,5 +1,10 @@
+use crate::packet::{HelloRes, LITE_CAPABILITY, PROTOCOL_VERSION};
+
 const DEFAULT_ENTRY_CHANNEL: &str = "public";
 
+/// How the client reaches the server, TLS and compression aren't in this build
+const TRANSPORT: &str = "TCP, uncompressed";
+
 /// Session state container for Client
 #[derive(Debug, Clone)]
 pub struct State {
@@ -11,6 +16,40 @@ pub struct State {
 
     /// True if you are a guest
     pub is_guest: bool,
+
+    /// Server-wide role of your account
+    pub role: crate::db::user::Role,
+
+    /// Handshake response of the server
+    pub server: Option<HelloRes>,
+
+    /// Settings of the current channel
+    pub channel_info: Option<crate::packet::ChannelInfo>,
+
+    /// Channel of the latest invitation, joined by `/accept`
+    pub pending_invite: Option<String>,
+
+    /// Channel of the latest mention from another channel, joined by `/jump`
+    pub last_mention: Option<String>,
+
+    /// Latest user list query, paged by `/fetch next` and `/fetch prev`
+    pub user_list_query: Option<UserListQuery>,
+
+    /// Presence as confirmed by the server
+    pub presence: crate::packet::Presence,
+
+    /// Names of the channels as of the latest fetch, for completion
+    pub channel_list: Vec<String>,
+}
+
+/// Page of the user list requested by the client
+#[derive(Debug, Clone, Default)]
+pub struct UserListQuery {
+    pub offset: usize,
+    pub filter: Option<String>,
+
+    /// Number of matching users as of the latest response
+    pub total: usize,
 }
 
 impl State {
@@ -20,6 +59,91 @@ impl State {
             id: id.to_owned(),
             channel: DEFAULT_ENTRY_CHANNEL.to_owned(),
             is_guest: true,
+            role: Default::default(),
+            server: None,
+            channel_info: None,
+            pending_invite: None,
+            last_mention: None,
+            user_list_query: None,
+            presence: Default::default(),
+            channel_list: Vec::new(),
         }
     }
+
+    /// True if you can't post in the current channel
+    pub fn is_read_only(&self) -> bool {
+        self.channel_info.as_ref().is_some_and(|info| {
+            info.channel_name == self.channel && info.is_read_only_for(&self.id, self.role)
+        })
+    }
+}
+
+/// Lines of `/get server`, what the handshake `res` said, `lite` if the client asked for it
+pub fn describe_server(res: &HelloRes, lite: bool) -> Vec<String> {
+    let mut lines = vec![
+        format!(
+            "Server: '{}', protocol v{} (client v{}, server v{})",
+            res.software,
+            res.version.min(PROTOCOL_VERSION),
+            PROTOCOL_VERSION,
+            res.version
+        ),
+        format!("Transport: {}", TRANSPORT),
+    ];
+    let taken_up = res.capabilities.iter().any(|c| c == LITE_CAPABILITY);
+    lines.push(match (lite, taken_up) {
+        (false, _) => "Lite mode: off".to_owned(),
+        (true, true) => "Lite mode: on".to_owned(),
+        (true, false) => "Lite mode: asked for, the server doesn't support it".to_owned(),
+    });
+    lines.push(match &res.limits {
+        Some(limits) => format!(
+            "Limits: packets of {} bytes, messages of {} bytes and {} characters, {}, retracting for {}s",
+            limits.max_packet_size,
+            limits.max_message_size,
+            limits.max_message_len,
+            match limits.messages_per_minute {
+                0 => "no rate limit".to_owned(),
+                n => format!("{} messages per minute", n),
+            },
+            limits.retract_window_secs
+        ),
+        None => "Limits: not reported by the server".to_owned(),
+    });
+    lines.push(match &res.motd {
+        Some(motd) => format!("MOTD: {}", motd),
+        None => "MOTD: none".to_owned(),
+    });
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ServerLimits;
+
+    #[test]
+    fn servers_not_reporting_limits_are_told_apart() {
+        let mut res = HelloRes::new(PROTOCOL_VERSION);
+        let lines = describe_server(&res, true);
+        assert_eq!(
+            lines[2],
+            "Lite mode: asked for, the server doesn't support it"
+        );
+        assert_eq!(lines[3], "Limits: not reported by the server");
+
+        res.version = 1;
+        res.capabilities = vec![LITE_CAPABILITY.to_owned()];
+        res.limits = Some(ServerLimits {
+            messages_per_minute: 30,
+            ..ServerLimits::default()
+        });
+        res.motd = Some("be nice".to_owned());
+        let lines = describe_server(&res, true);
+        let protocol = format!("protocol v1 (client v{}, server v1)", PROTOCOL_VERSION);
+        assert!(lines[0].ends_with(&protocol));
+        assert_eq!(lines[2], "Lite mode: on");
+        assert!(lines[3].contains("30 messages per minute"));
+        assert_eq!(lines[4], "MOTD: be nice");
+    }
 }
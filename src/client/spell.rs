@@ -0,0 +1,174 @@
+//! Spell checking of the input box
+//!
+//! The dictionary is loaded and the input is checked on a thread of its own, the input thread only
+//! hands the text over and picks up the misspelled words found in it the next time it draws.
+
+use std::{
+    collections::HashSet,
+    ops::Range,
+    sync::{mpsc, Arc, Mutex, OnceLock},
+};
+
+use super::{message_channel::MessageChannel, redraw::RedrawFlag, util};
+
+/// Most suggestions offered for a word
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Farthest a suggestion may be from the misspelled word
+const MAX_DISTANCE: usize = 2;
+
+/// Words known to the checker, lowercased
+type Dictionary = HashSet<String>;
+
+/// Misspelled words of the text checked last
+#[derive(Default)]
+struct Checked {
+    text: String,
+    misspelled: Vec<Range<usize>>,
+}
+
+pub struct SpellChecker {
+    text_tx: mpsc::Sender<String>,
+    checked: Arc<Mutex<Checked>>,
+    dictionary: Arc<OnceLock<Dictionary>>,
+
+    /// Text handed to the checker last, unchanged text isn't checked again
+    sent: String,
+}
+
+impl SpellChecker {
+    /// Load the dictionary at `path`, one word per line, and start checking in the background
+    ///
+    /// A dictionary failing to load is reported on `messages`, nothing is underlined then.
+    pub fn start(path: &str, mut messages: MessageChannel) -> Self {
+        let (text_tx, text_rx) = mpsc::channel::<String>();
+        let checked = Arc::new(Mutex::new(Checked::default()));
+        let dictionary = Arc::new(OnceLock::new());
+
+        let (path, worker_checked, worker_dictionary) =
+            (path.to_owned(), checked.clone(), dictionary.clone());
+        std::thread::spawn(move || {
+            let words = match std::fs::read_to_string(&path) {
+                Ok(content) => content
+                    .lines()
+                    .map(|line| line.trim().to_lowercase())
+                    .filter(|word| !word.is_empty())
+                    .collect::<Dictionary>(),
+                Err(e) => {
+                    messages
+                        .push_sys_err(format!("Failed to load the dictionary '{}': {}", path, e));
+                    return;
+                }
+            };
+            let dictionary = worker_dictionary.get_or_init(|| words);
+            let redraw: RedrawFlag = messages.redraw.clone();
+
+            // only the latest text is worth checking, the ones typed over are skipped
+            while let Ok(mut text) = text_rx.recv() {
+                while let Ok(newer) = text_rx.try_recv() {
+                    text = newer;
+                }
+                let misspelled = misspelled(&text, dictionary);
+                if let Ok(mut checked) = worker_checked.lock() {
+                    *checked = Checked { text, misspelled };
+                }
+                redraw.raise();
+            }
+        });
+
+        Self {
+            text_tx,
+            checked,
+            dictionary,
+            sent: String::new(),
+        }
+    }
+
+    /// Have `text` checked unless it was already, never blocks
+    pub fn check(&mut self, text: &str) {
+        if self.sent != text {
+            self.sent = text.to_owned();
+            _ = self.text_tx.send(self.sent.clone());
+        }
+    }
+
+    /// Byte ranges of the misspelled words of `text`
+    ///
+    /// While `text` is still being checked, the words found in an older text are kept as long as
+    /// they're still in place.
+    pub fn misspelled(&self, text: &str) -> Vec<Range<usize>> {
+        let Ok(checked) = self.checked.lock() else {
+            return Vec::new();
+        };
+        if checked.text == text {
+            return checked.misspelled.clone();
+        }
+        checked
+            .misspelled
+            .iter()
+            .filter(|range| text.get((*range).clone()) == checked.text.get((*range).clone()))
+            .cloned()
+            .collect()
+    }
+
+    /// Closest words of the dictionary to `word`, `None` until the dictionary is loaded
+    pub async fn suggest(&self, word: &str) -> Option<Vec<String>> {
+        let dictionary = self.dictionary.clone();
+        dictionary.get()?;
+        let word = word.to_lowercase();
+        tokio::task::spawn_blocking(move || {
+            let dictionary = dictionary.get().unwrap();
+            let len = word.chars().count();
+            let mut candidates: Vec<(usize, &String)> = dictionary
+                .iter()
+                .filter(|candidate| candidate.chars().count().abs_diff(len) <= MAX_DISTANCE)
+                .map(|candidate| (util::edit_distance(&word, candidate), candidate))
+                .filter(|(distance, _)| *distance <= MAX_DISTANCE)
+                .collect();
+            candidates.sort();
+            candidates
+                .into_iter()
+                .take(MAX_SUGGESTIONS)
+                .map(|(_, candidate)| candidate.clone())
+                .collect()
+        })
+        .await
+        .ok()
+    }
+}
+
+/// Byte range of the word at or right before `cursor`, if it's one the checker would check
+pub fn word_at(text: &str, cursor: usize) -> Option<Range<usize>> {
+    words(text).find(|range| range.start <= cursor && cursor <= range.end)
+}
+
+/// Words of `text` worth checking by their byte ranges
+///
+/// Command names, mentions, channels, emoji shortcodes and links are left alone, so are words with
+/// digits and wide characters, which usually aren't in a dictionary of words split by spaces.
+fn words(text: &str) -> impl Iterator<Item = Range<usize>> + '_ {
+    text.split_whitespace()
+        .map(move |token| {
+            let start = token.as_ptr() as usize - text.as_ptr() as usize;
+            (start, token)
+        })
+        .filter(|(_, token)| !(token.starts_with(['@', '#', ':', '/']) || token.contains("://")))
+        .filter_map(|(start, token)| {
+            let trimmed = token.trim_matches(|c: char| !c.is_alphabetic());
+            let offset = start + token.find(trimmed)?;
+            let checkable = trimmed.chars().count() > 1
+                && trimmed
+                    .chars()
+                    .all(|c| (c.is_alphabetic() || c == '\'') && util::char_width(c) == 1);
+            checkable.then(|| offset..offset + trimmed.len())
+        })
+}
+
+fn misspelled(text: &str, dictionary: &Dictionary) -> Vec<Range<usize>> {
+    words(text)
+        .filter(|range| {
+            let word = text[range.clone()].to_lowercase();
+            !dictionary.contains(&word) && !dictionary.contains(word.trim_end_matches("'s"))
+        })
+        .collect()
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::redraw::RedrawFlag;
+
+/// Number of round-trip samples the average latency is computed over
+const NUM_LATENCY_SAMPLES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    #[default]
+    Connected,
+    /// Lost, trying to resume the session on a new connection
+    Reconnecting,
+    Disconnected,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    state: ConnectionState,
+
+    /// The latest round-trip times, from the oldest
+    latencies: VecDeque<Duration>,
+
+    /// Timestamp of the ping the user asked for with `/ping`, its pong is reported
+    requested_ping: Option<u64>,
+
+    /// Session resumed if the connection is lost, `None` for guests and servers without tokens
+    resume: Option<crate::packet::ResumeSession>,
+
+    /// Ask the server for less traffic, see `LITE_CAPABILITY`
+    lite: bool,
+}
+
+/// Connection state shared between the background tasks and the status bar
+#[derive(Debug, Default, Clone)]
+pub struct ConnectionStatus {
+    inner: Arc<Mutex<Inner>>,
+
+    /// Raised when the state or the latency shown in the status bar changes
+    pub redraw: RedrawFlag,
+}
+
+impl ConnectionStatus {
+    pub fn state(&self) -> ConnectionState {
+        self.inner.lock().unwrap().state
+    }
+
+    pub fn set_state(&self, state: ConnectionState) {
+        self.inner.lock().unwrap().state = state;
+        self.redraw.raise();
+    }
+
+    pub fn set_lite(&self, lite: bool) {
+        self.inner.lock().unwrap().lite = lite;
+    }
+
+    pub fn is_lite(&self) -> bool {
+        self.inner.lock().unwrap().lite
+    }
+
+    /// Handshake of the connections, the first one and the ones resuming the session
+    pub fn hello(&self) -> crate::packet::Hello {
+        let mut hello = crate::packet::Hello::new();
+        if self.is_lite() {
+            hello
+                .capabilities
+                .push(crate::packet::LITE_CAPABILITY.to_owned());
+        }
+        hello
+    }
+
+    /// Resume the session of `token` once the connection is lost, `None` to give up on it
+    pub fn set_resume_token(&self, token: Option<String>, channel: &str) {
+        self.inner.lock().unwrap().resume = token.map(|token| crate::packet::ResumeSession {
+            token,
+            channel: Some(channel.to_owned()),
+        });
+    }
+
+    /// Rejoin `channel` when the session is resumed
+    pub fn set_resume_channel(&self, channel: &str) {
+        if let Some(resume) = self.inner.lock().unwrap().resume.as_mut() {
+            resume.channel = Some(channel.to_owned());
+        }
+    }
+
+    pub fn resume(&self) -> Option<crate::packet::ResumeSession> {
+        self.inner.lock().unwrap().resume.clone()
+    }
+
+    /// Rolling average of the round-trip time to the server, `None` until it's measured
+    pub fn latency(&self) -> Option<Duration> {
+        let inner = self.inner.lock().unwrap();
+        if inner.latencies.is_empty() {
+            return None;
+        }
+        Some(inner.latencies.iter().sum::<Duration>() / inner.latencies.len() as u32)
+    }
+
+    /// Record the round-trip time of a pong
+    pub fn record_latency(&self, rtt: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.latencies.push_back(rtt);
+        if inner.latencies.len() > NUM_LATENCY_SAMPLES {
+            inner.latencies.pop_front();
+        }
+        self.redraw.raise();
+    }
+
+    /// Remember the ping sent at `timestamp` was asked for by the user
+    pub fn request_ping(&self, timestamp: u64) {
+        self.inner.lock().unwrap().requested_ping = Some(timestamp);
+    }
+
+    /// True once for the pong of the ping the user asked for
+    pub fn take_requested_pong(&self, timestamp: u64) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.requested_ping == Some(timestamp) {
+            inner.requested_ping = None;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Thread view, the messages of a thread next to the message section
+//!
+//! `/thread <id>` fetches the thread from the history of the server, the replies arriving after
+//! that are picked up from the message section as they come.
+
+use std::collections::BTreeMap;
+
+use super::message_channel::Entry;
+use crate::packet::Message;
+
+/// A message of the thread as the pane shows it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadLine {
+    pub seq: u64,
+    pub id: String,
+    pub msg: String,
+}
+
+pub struct ThreadView {
+    pub channel: String,
+
+    /// Sequence number of the message the thread started from
+    pub root: u64,
+
+    /// Messages of the thread when it was fetched
+    fetched: Vec<Message>,
+}
+
+impl ThreadView {
+    pub fn new(channel: &str, root: u64, fetched: Vec<Message>) -> Self {
+        Self {
+            channel: channel.to_owned(),
+            root,
+            fetched,
+        }
+    }
+
+    /// Messages of the thread by their sequence number, the fetched ones along with `entries`
+    /// of the message section
+    ///
+    /// The entries are the latest state, e.g. a message retracted since the fetch.
+    pub fn lines(&self, entries: &[Entry]) -> Vec<ThreadLine> {
+        let mut lines: BTreeMap<u64, ThreadLine> = BTreeMap::new();
+        for msg in &self.fetched {
+            if let Some(seq) = msg.seq {
+                let line = ThreadLine {
+                    seq,
+                    id: msg.id.clone(),
+                    msg: msg_text(&msg.msg, msg.retracted),
+                };
+                lines.insert(seq, line);
+            }
+        }
+        for entry in entries {
+            let Some(seq) = entry.seq else {
+                continue;
+            };
+            if entry.channel != self.channel
+                || (seq != self.root && entry.thread != Some(self.root))
+            {
+                continue;
+            }
+            let line = ThreadLine {
+                seq,
+                id: entry.id.clone(),
+                msg: msg_text(&entry.msg, entry.retracted),
+            };
+            lines.insert(seq, line);
+        }
+        lines.into_values().collect()
+    }
+}
+
+fn msg_text(msg: &str, retracted: bool) -> String {
+    if retracted {
+        "message retracted".to_owned()
+    } else {
+        msg.replace('\n', " ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::message_channel::MessageChannel;
+
+    fn message(id: &str, msg: &str, seq: u64, thread: Option<u64>) -> Message {
+        Message {
+            id: id.to_owned(),
+            msg: msg.to_owned(),
+            is_system: false,
+            to: None,
+            seq: Some(seq),
+            retracted: false,
+            display_name: None,
+            reply_to: thread,
+            thread,
+        }
+    }
+
+    #[test]
+    fn replies_arriving_later_join_the_thread() {
+        let view = ThreadView::new(
+            "",
+            3,
+            vec![
+                message("alice", "lunch?", 3, None),
+                message("bob", "sure", 5, Some(3)),
+            ],
+        );
+        let messages = MessageChannel::default();
+        messages.push_message("alice".to_owned(), message("alice", "lunch?", 3, None));
+        messages.push_message("carol".to_owned(), message("carol", "unrelated", 6, None));
+        messages.push_message("carol".to_owned(), message("carol", "me too", 7, Some(3)));
+
+        let lines: Vec<(u64, String)> = view
+            .lines(&messages.channel_entries(""))
+            .into_iter()
+            .map(|line| (line.seq, line.msg))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (3, "lunch?".to_owned()),
+                (5, "sure".to_owned()),
+                (7, "me too".to_owned())
+            ]
+        );
+        // the reply is counted under the message the thread started from
+        assert_eq!(messages.channel_entries("")[0].replies, 1);
+    }
+}
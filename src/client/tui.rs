@@ -1,26 +1,110 @@
 use std::{error::Error, io};
 
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+    event::{
+        self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+        Event, KeyCode, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{prelude::*, widgets::*};
 
 use super::{
+    activity::Level,
     app::{App, HandleCommandStatus},
     background_task,
+    config::{ClientConfig, Theme},
     input_controller::*,
+    markdown::StyledLine,
+    message_view::MessageView,
     popup::*,
+    recovery,
+    status::ConnectionState,
+    util,
 };
+use crate::packet::Presence;
 
-pub async fn set_tui(app: App) -> Result<(), Box<dyn Error>> {
-    // setup terminal
+/// Interval of the pings measuring the latency
+const PING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Interval of the pings with `--lite`
+const LITE_PING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Longest wait for input before checking for changes from the background tasks
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Smallest terminal the chat is laid out in, a notice takes the screen below it
+const MIN_WIDTH: u16 = 30;
+const MIN_HEIGHT: u16 = 8;
+
+/// Narrower than this, the activity and the thread panes stay hidden
+const PANE_MIN_WIDTH: u16 = 80;
+
+/// Narrower than this, the status bar only shows the connection, the identity and the channel
+const COMPACT_STATUS_WIDTH: u16 = 70;
+
+/// Shorter than this, the help line on top is left out
+const HELP_LINE_MIN_HEIGHT: u16 = 14;
+
+/// Take over the terminal, it's given back by `leave_terminal`
+fn enter_terminal() -> Result<Terminal<CrosstermBackend<io::Stdout>>, Box<dyn Error>> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+    execute!(
+        stdout,
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        EnableBracketedPaste
+    )?;
+    Ok(Terminal::new(CrosstermBackend::new(stdout))?)
+}
+
+fn leave_terminal(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+) -> Result<(), Box<dyn Error>> {
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableBracketedPaste
+    )?;
+    _ = terminal.show_cursor();
+    Ok(())
+}
+
+/// Run the onboarding wizard of the first launch, `None` if it's cancelled
+pub fn run_onboarding() -> Result<Option<ClientConfig>, Box<dyn Error>> {
+    let mut terminal = enter_terminal()?;
+    let mut wizard = onboarding::OnboardingPopupManager::new();
+    let result = loop {
+        if let Err(e) = terminal.draw(|f| wizard.ui(f)) {
+            break Err(e);
+        }
+        let action = match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                // Ctrl+C quits the wizard at any step
+                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    break Ok(());
+                }
+                wizard.hook_key_event(&key)
+            }
+            Ok(Event::Paste(text)) => wizard.hook_paste_event(&text),
+            Ok(_) => continue,
+            Err(e) => break Err(e),
+        };
+        if let PostKeyCaptureAction::ClosePopup = action {
+            break Ok(());
+        }
+    };
+    leave_terminal(&mut terminal)?;
+    result?;
+    Ok(wizard.finished)
+}
+
+pub async fn set_tui(app: App) -> Result<(), Box<dyn Error>> {
+    let mut terminal = enter_terminal()?;
 
     // Hook panic callback
     let original_hook = std::panic::take_hook();
@@ -33,54 +117,116 @@ pub async fn set_tui(app: App) -> Result<(), Box<dyn Error>> {
     tokio::task::spawn(background_task::print_message_packets(
         app.incoming_tx.subscribe(),
         app.messages.clone(),
+        app.connection.clone(),
+    ));
+
+    // Task for recording the protocol events shown in the activity pane
+    tokio::task::spawn(background_task::log_activity(
+        app.incoming_tx.subscribe(),
+        app.activity.clone(),
+    ));
+
+    // Task for measuring the latency shown in the status bar
+    tokio::task::spawn(background_task::ping_periodically(
+        app.outgoing_tx.clone(),
+        if app.connection.is_lite() {
+            LITE_PING_INTERVAL
+        } else {
+            PING_INTERVAL
+        },
     ));
 
     // create app and run it
     _ = run_app(&mut terminal, app).await;
 
     // restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    _ = terminal.show_cursor();
-    Ok(())
+    leave_terminal(&mut terminal)
 }
 
 fn reset_terminal() -> Result<(), Box<dyn Error>> {
     disable_raw_mode()?;
-    execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
+    execute!(
+        io::stdout(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableBracketedPaste
+    )?;
     Ok(())
 }
 
 async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
     app.messages
         .push_sys_msg(format!("Welcome {}!", &app.state.id));
+
+    // drawn only when something changed, the changes since the last frame are drawn at once
+    let mut dirty = true;
+    let mut checkpointed_at = std::time::Instant::now();
     loop {
-        terminal.draw(|f| main_ui(f, &app))?;
+        dirty |= app.handle_pushed_packets();
+        dirty |= app.messages.redraw.take();
+        dirty |= app.connection.redraw.take();
+        dirty |= app.activity.redraw.take() && app.activity_open;
+        app.check_idle().await;
+        app.messages.expire_outgoing(util::unix_time());
+        if checkpointed_at.elapsed() >= recovery::CHECKPOINT_INTERVAL {
+            app.checkpoint();
+            checkpointed_at = std::time::Instant::now();
+        }
+
+        // queued input is handled first, a burst of keys makes a single frame
+        if dirty && !event::poll(std::time::Duration::ZERO)? {
+            if let Some(spell) = &mut app.spell {
+                spell.check(&app.main_input.buf);
+            }
+            terminal.draw(|f| main_ui(f, &mut app))?;
+            dirty = false;
+        }
 
         // non-blocking event reading
-        if !event::poll(std::time::Duration::from_millis(100))? {
+        if !event::poll(POLL_INTERVAL)? {
             continue;
         }
 
-        // Capture key event
-        let Event::Key(key) = event::read()? else {
-            continue;
+        // every event may change the screen, a resize included
+        dirty = true;
+
+        // Capture key event, pasted text goes straight to the focused input field
+        let key = match event::read()? {
+            Event::Key(key) => {
+                app.on_activity().await;
+                key
+            }
+            Event::Paste(text) => {
+                app.on_activity().await;
+                paste_text(&mut app, &text);
+                continue;
+            }
+            Event::Mouse(mouse) => {
+                handle_mouse(&mut app, &mouse);
+                continue;
+            }
+            // laid out again for the new size on the next frame
+            Event::Resize(_, _) => {
+                terminal.autoresize()?;
+                continue;
+            }
+            _ => continue,
         };
 
-        if let Some(p) = &mut app.popup {
+        // any key press means the user has seen the current channel
+        app.mark_read().await;
+
+        // only the popup on top takes the keys
+        if let Some(p) = app.popups.last_mut() {
             match p.hook_key_event(&key) {
                 PostKeyCaptureAction::CloseAndRunAction(action, args) => {
-                    // Extra action needs to be run after the popup is closed
+                    // Extra action needs to be run after the popup is closed, it may open another
+                    app.close_popup();
                     app.run_action(&action, args).await;
-                    app.popup = None;
                     continue;
                 }
                 PostKeyCaptureAction::ClosePopup => {
-                    app.popup = None;
+                    app.close_popup();
                     continue;
                 }
                 PostKeyCaptureAction::Break => continue,
@@ -88,36 +234,114 @@ async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Re
             }
         }
 
+        if key.code == KeyCode::F(1) && key.kind == KeyEventKind::Press {
+            app.open_popup(help::HelpPopupManager::new());
+            continue;
+        }
+        if key.code == KeyCode::F(2) && key.kind == KeyEventKind::Press {
+            app.pins_collapsed = !app.pins_collapsed;
+            continue;
+        }
+        if key.code == KeyCode::F(3) && key.kind == KeyEventKind::Press {
+            app.activity_open = !app.activity_open;
+            continue;
+        }
+        if key.code == KeyCode::F(4) && key.kind == KeyEventKind::Press {
+            app.suggest_spelling().await;
+            continue;
+        }
+        if key.code == KeyCode::F(5) && key.kind == KeyEventKind::Press {
+            app.retry_failed().await;
+            continue;
+        }
+        if key.code == KeyCode::Char('r')
+            && key.modifiers.contains(KeyModifiers::CONTROL)
+            && key.kind == KeyEventKind::Press
+        {
+            app.retract_last().await;
+            continue;
+        }
+
         match app.main_input.input_mode {
-            InputMode::Normal => {
-                if key.code == KeyCode::Char('i') {
-                    app.main_input.editing_mode();
-                }
+            InputMode::Normal if key.code == KeyCode::Char('i') => {
+                app.main_input.editing_mode();
             }
+            // pick a message of the channel to reply to
+            InputMode::Normal if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Up | KeyCode::Down => {
+                    let shown = app.messages.shown(&app.hidden_kinds);
+                    let messages = &app.messages;
+                    app.view.move_cursor(
+                        &shown,
+                        |idx| messages.reply_target(idx).is_some(),
+                        key.code == KeyCode::Up,
+                    );
+                }
+                KeyCode::Enter => {
+                    if let Some(idx) = app.view.cursor() {
+                        app.reply_to = app.messages.reply_target(idx);
+                        app.view.clear_cursor();
+                        app.main_input.editing_mode();
+                    }
+                }
+                KeyCode::Esc => app.view.clear_cursor(),
+                _ => {}
+            },
             InputMode::Editing if key.kind == KeyEventKind::Press => match key.code {
+                // Alt+Enter opens the compose mode, or sends the composed message
+                KeyCode::Enter
+                    if !app.main_input.compose_mode
+                        && key.modifiers.contains(KeyModifiers::ALT) =>
+                {
+                    app.main_input.toggle_compose_mode();
+                }
+                // Enter breaks the line while composing
+                KeyCode::Enter
+                    if app.main_input.compose_mode
+                        && !key.modifiers.contains(KeyModifiers::ALT) =>
+                {
+                    app.main_input.enter_newline();
+                }
                 KeyCode::Enter => {
                     if app.main_input.buf.is_empty() {
                         continue;
                     }
 
+                    // leave compose mode once the message is out
+                    app.main_input.compose_mode = false;
                     if app.main_input.buf.starts_with('/') {
                         // handle command
                         if app.handle_command().await == HandleCommandStatus::Exit {
                             return Ok(());
                         }
                         app.main_input.clear_input_box();
+                    } else if app.state.is_read_only() {
+                        app.messages.push_sys_err(
+                            "This channel is read-only, only commands are accepted".to_owned(),
+                        );
                     } else {
+                        // marked as sent once the server echoes it
                         app.send_message().await;
-                        app.messages
-                            .push(app.state.id.clone(), app.main_input.buf.clone());
                         app.main_input.clear_input_box();
                     }
                 }
+                // Paste from the system clipboard
+                KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    if let Some(text) = util::read_clipboard() {
+                        paste_text(&mut app, &text);
+                    }
+                }
+                // Complete the channel name of commands taking one
+                KeyCode::Tab => app.complete_channel().await,
                 KeyCode::Char(ch) => app.main_input.enter_char(ch),
                 KeyCode::Backspace => app.main_input.delete_char(),
                 KeyCode::Left => app.main_input.move_cursor_left(),
                 KeyCode::Right => app.main_input.move_cursor_right(),
-                KeyCode::Esc => app.main_input.normal_mode(),
+                // a reply is given up along with the editing
+                KeyCode::Esc => {
+                    app.reply_to = None;
+                    app.main_input.normal_mode();
+                }
                 _ => {}
             },
             _ => {}
@@ -125,20 +349,142 @@ async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Re
     }
 }
 
+/// Lines of the input with the misspelled words underlined
+fn spelled_lines(app: &App) -> Vec<StyledLine> {
+    let buf = &app.main_input.buf;
+    let misspelled = app
+        .spell
+        .as_ref()
+        .map(|spell| spell.misspelled(buf))
+        .unwrap_or_default();
+    let typo = Style::default()
+        .fg(Color::LightRed)
+        .add_modifier(Modifier::UNDERLINED);
+
+    let mut lines = vec![StyledLine::new()];
+    for (i, c) in buf.char_indices() {
+        if c == '\n' {
+            lines.push(StyledLine::new());
+            continue;
+        }
+        let style = if misspelled.iter().any(|range| range.contains(&i)) {
+            typo
+        } else {
+            Style::default()
+        };
+        lines.last_mut().unwrap().push((c, style));
+    }
+    lines
+}
+
+/// Scroll the message section with the wheel, focus the clicked section and copy the messages
+/// selected by dragging to the system clipboard
+fn handle_mouse(app: &mut App, mouse: &MouseEvent) {
+    if !app.popups.is_empty() {
+        return;
+    }
+
+    let (x, y) = (mouse.column, mouse.row);
+    let in_messages = MessageView::contains(app.view.messages_area, x, y);
+    match mouse.kind {
+        MouseEventKind::ScrollUp if in_messages => app.view.scroll_up(),
+        MouseEventKind::ScrollDown if in_messages => app.view.scroll_down(),
+        MouseEventKind::Down(MouseButton::Left) if in_messages => {
+            app.main_input.normal_mode();
+            match app.view.message_at(y) {
+                Some(idx) => app.view.start_selection(idx),
+                None => app.view.clear_selection(),
+            }
+        }
+        MouseEventKind::Down(MouseButton::Left)
+            if MessageView::contains(app.view.input_area, x, y) =>
+        {
+            app.view.clear_selection();
+            app.main_input.editing_mode();
+        }
+        MouseEventKind::Drag(MouseButton::Left) => {
+            if let Some(idx) = app.view.message_at(y) {
+                app.view.extend_selection(idx);
+            }
+        }
+        MouseEventKind::Up(MouseButton::Left) => {
+            let Some(range) = app.view.selection() else {
+                return;
+            };
+            // a plain click selects nothing
+            if range.start() == range.end() {
+                app.view.clear_selection();
+                return;
+            }
+
+            app.view.clear_selection();
+            let count = range.end() - range.start() + 1;
+            let text = app.messages.entries_text(range, &app.hidden_kinds);
+            if util::write_clipboard(&text) {
+                app.messages
+                    .push_sys_msg(format!("Copied {} messages to the clipboard", count));
+            } else {
+                app.messages
+                    .push_sys_err("No clipboard utility found to copy the messages".to_owned());
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Insert pasted `text` to the popup if it captures pastes, or to the main input box
+///
+/// A multi-line paste into the main input box opens the compose mode so the line breaks survive.
+fn paste_text(app: &mut App, text: &str) {
+    if let Some(p) = app.popups.last_mut() {
+        if let PostKeyCaptureAction::Break = p.hook_paste_event(text) {
+            return;
+        }
+    }
+
+    if !app.main_input.is_editing_mode() {
+        return;
+    }
+    if text.trim_end().contains('\n') {
+        app.main_input.compose_mode = true;
+    }
+    app.main_input.insert_str(text, app.main_input.compose_mode);
+}
+
 pub fn render_help_messages(f: &mut Frame, app: &App, chunk: Rect) {
     // Helper messages
     let (msg, style) = match app.main_input.input_mode {
         InputMode::Normal => (
-            vec!["Press ".into(), "'i'".bold(), " to start editing.".into()],
+            vec![
+                "Press ".into(),
+                "'i'".bold(),
+                " to start editing, ".into(),
+                "Up".bold(),
+                " and ".into(),
+                "Enter".bold(),
+                " to reply to a message.".into(),
+            ],
             Style::default().add_modifier(Modifier::RAPID_BLINK),
         ),
+        InputMode::Editing if app.main_input.compose_mode => (
+            vec![
+                "Composing: ".into(),
+                "Enter".bold(),
+                " for a new line, ".into(),
+                "Alt+Enter".bold(),
+                " to send the message".into(),
+            ],
+            Style::default(),
+        ),
         InputMode::Editing => (
             vec![
                 "Press ".into(),
                 "Esc".bold(),
                 " to stop editing, ".into(),
                 "Enter".bold(),
-                " to record the message".into(),
+                " to record the message, ".into(),
+                "Alt+Enter".bold(),
+                " to compose multiple lines".into(),
             ],
             Style::default(),
         ),
@@ -154,50 +500,437 @@ pub fn render_help_messages(f: &mut Frame, app: &App, chunk: Rect) {
     );
 }
 
-pub fn main_ui(f: &mut Frame, app: &App) {
+/// One-line summary of the connection, identity, channel and unread messages
+pub fn render_status_bar(f: &mut Frame, app: &App, chunk: Rect) {
+    let (state, color) = match app.connection.state() {
+        ConnectionState::Connected => ("connected", Color::Green),
+        ConnectionState::Reconnecting => ("reconnecting", Color::Yellow),
+        ConnectionState::Disconnected => ("disconnected", Color::Red),
+    };
+    let latency = match app.connection.latency() {
+        Some(rtt) => format!("{}ms", rtt.as_millis()),
+        None => "-".to_owned(),
+    };
+    let unread = match app.notifications.total_unread() {
+        0 => "no unread".to_owned(),
+        n => format!("{} unread", n),
+    };
+
+    let separator = || Span::raw(" | ");
+    let identity = Span::raw(format!(
+        "{}{}",
+        util::get_mark(app.state.role, app.state.is_guest),
+        app.state.id
+    ))
+    .bold();
+    let channel = Span::raw(format!("#{}", app.state.channel));
+
+    // narrow, the rest is left out
+    let compact = chunk.width < COMPACT_STATUS_WIDTH;
+    if compact {
+        let line = Line::from(vec![
+            Span::styled(format!(" {}", state), Style::default().fg(color)),
+            separator(),
+            identity,
+            separator(),
+            channel,
+        ]);
+        f.render_widget(
+            Paragraph::new(line).style(app.render_options.theme.bar()),
+            chunk,
+        );
+        return;
+    }
+
+    let mut line = Line::from(vec![
+        Span::styled(format!(" {}", state), Style::default().fg(color)),
+        separator(),
+        Span::raw(latency),
+        separator(),
+        identity,
+        separator(),
+        channel,
+        separator(),
+        Span::raw(unread),
+    ]);
+
+    // your own presence, guests are always online
+    if !app.state.is_guest {
+        line.spans.push(separator());
+        line.spans.push(Span::styled(
+            app.state.presence.as_str(),
+            Style::default().fg(match app.state.presence {
+                Presence::Online => Color::Green,
+                _ => Color::Magenta,
+            }),
+        ));
+    }
+
+    // other channels you've been mentioned in
+    if !app.notifications.mentioned.is_empty() {
+        let mentioned = app
+            .notifications
+            .mentioned
+            .iter()
+            .map(|channel| format!("@#{}", channel))
+            .collect::<Vec<_>>()
+            .join(" ");
+        line.spans.push(separator());
+        line.spans.push(Span::styled(
+            mentioned,
+            app.render_options.theme.highlight(),
+        ));
+    }
+    f.render_widget(
+        Paragraph::new(line).style(app.render_options.theme.bar()),
+        chunk,
+    );
+}
+
+/// Pinned messages of the current channel on top of `area`, returns the rest of `area`
+///
+/// Collapsed, only the title is shown. The section takes at most a third of `area`.
+fn render_pinned_messages(f: &mut Frame, app: &App, area: Rect) -> Rect {
+    let pins = app.messages.pinned(&app.state.channel);
+    if pins.is_empty() {
+        return area;
+    }
+
+    let title = util::truncate(
+        &format!(
+            "[Pinned: {}] F2 to {}",
+            pins.len(),
+            if app.pins_collapsed {
+                "expand"
+            } else {
+                "collapse"
+            }
+        ),
+        area.width.saturating_sub(2) as usize,
+    );
+    // collapsed, the section is just the title on its top border
+    let (rows, height) = if app.pins_collapsed {
+        (0, 1)
+    } else {
+        let rows = (pins.len() as u16).min(area.height / 3);
+        (rows, rows + 2)
+    };
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(height), Constraint::Min(1)])
+        .split(area);
+
+    // one line per message, the newest pins last
+    let lines: Vec<Line> = pins
+        .iter()
+        .rev()
+        .take(rows as usize)
+        .rev()
+        .map(|e| {
+            Line::from(vec![
+                Span::raw(format!("[#{}] ", e.seq.unwrap_or_default())),
+                Span::raw(format!("{}: ", e.id)).bold(),
+                Span::raw(e.msg.replace('\n', " ")),
+            ])
+        })
+        .collect();
+    f.render_widget(
+        Paragraph::new(lines).block(
+            Block::default()
+                .borders(if app.pins_collapsed {
+                    Borders::TOP
+                } else {
+                    Borders::ALL
+                })
+                .title(title)
+                .border_style(app.render_options.theme.highlight()),
+        ),
+        chunks[0],
+    );
+    chunks[1]
+}
+
+/// Activity log on the right of `area` while it's open, returns the rest of `area`
+///
+/// The pane takes a third of `area`, the newest events at the bottom.
+fn render_activity(f: &mut Frame, app: &App, area: Rect) -> Rect {
+    // the messages need the room on narrow terminals
+    if !app.activity_open || area.width < PANE_MIN_WIDTH {
+        return area;
+    }
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(1), Constraint::Length(area.width / 3)])
+        .split(area);
+
+    // events are wrapped to the pane, only the rows of the newest fit
+    let rows = chunks[1].height.saturating_sub(2) as usize;
+    let width = chunks[1].width.saturating_sub(2) as usize;
+    let styled: Vec<StyledLine> = app
+        .activity
+        .latest(rows)
+        .into_iter()
+        .map(|event| {
+            let theme = app.render_options.theme;
+            let style = match event.level {
+                Level::Info => Style::default(),
+                Level::Error => theme.error(),
+            };
+            // the time of the day is enough, the log only spans the session
+            let time = util::format_time(event.time);
+            let time = match event.level {
+                Level::Error if theme == Theme::Accessible => format!("{} [ERR] ", &time[11..]),
+                _ => format!("{} ", &time[11..]),
+            };
+            time.chars()
+                .map(|c| (c, theme.muted()))
+                .chain(event.text.chars().map(|c| (c, style)))
+                .collect()
+        })
+        .collect();
+    let mut lines = util::wrap_styled(styled, width, Style::default());
+    let lines = lines.split_off(lines.len().saturating_sub(rows));
+    f.render_widget(
+        Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("[Activity] F3 to hide")
+                .border_style(Style::default().fg(Color::Cyan)),
+        ),
+        chunks[1],
+    );
+    chunks[0]
+}
+
+/// Thread on the right of `area` while it's open, returns the rest of `area`
+///
+/// The pane takes a third of `area`, the replies below the message the thread started from.
+fn render_thread(f: &mut Frame, app: &App, area: Rect) -> Rect {
+    let Some(thread) = &app.thread else {
+        return area;
+    };
+    if area.width < PANE_MIN_WIDTH {
+        return area;
+    }
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(1), Constraint::Length(area.width / 3)])
+        .split(area);
+
+    // the newest replies stay in view
+    let rows = chunks[1].height.saturating_sub(2) as usize;
+    let width = chunks[1].width.saturating_sub(2) as usize;
+    let theme = app.render_options.theme;
+    let styled: Vec<StyledLine> = thread
+        .lines(&app.messages.channel_entries(&thread.channel))
+        .into_iter()
+        .map(|line| {
+            format!("#{} ", line.seq)
+                .chars()
+                .map(|c| (c, theme.muted()))
+                .chain(
+                    format!("{}: {}", line.id, line.msg)
+                        .chars()
+                        .map(|c| (c, Style::default())),
+                )
+                .collect()
+        })
+        .collect();
+    let mut lines = util::wrap_styled(styled, width, Style::default());
+    let lines = lines.split_off(lines.len().saturating_sub(rows));
+    let title = format!("[Thread #{}] /thread to close", thread.root);
+    f.render_widget(
+        Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(util::truncate(&title, width))
+                .border_style(theme.highlight()),
+        ),
+        chunks[1],
+    );
+    chunks[0]
+}
+
+/// Notice of a terminal too small for the chat, in place of everything else
+fn render_too_small(f: &mut Frame, app: &mut App) {
+    // nothing to click on
+    app.view.messages_area = Rect::default();
+    app.view.input_area = Rect::default();
+
+    let area = f.size();
+    let text = format!(
+        "Terminal too small: {}x{}, at least {}x{} needed",
+        area.width, area.height, MIN_WIDTH, MIN_HEIGHT
+    );
+    let lines: Vec<Line> = util::wrap_text(&text, area.width as usize)
+        .into_iter()
+        .map(Line::from)
+        .collect();
+    let height = (lines.len() as u16).min(area.height);
+    f.render_widget(
+        Paragraph::new(lines)
+            .alignment(Alignment::Center)
+            .style(Style::default().fg(Color::LightRed)),
+        Rect::new(
+            area.x,
+            area.y + (area.height - height) / 2,
+            area.width,
+            height,
+        ),
+    );
+}
+
+pub fn main_ui(f: &mut Frame, app: &mut App) {
+    render_main(f, app);
+    if app.render_options.theme == Theme::Accessible {
+        monochrome(f.buffer_mut());
+    }
+}
+
+/// Leave the colors to the terminal and stop the blinking, the modifiers tell things apart
+fn monochrome(buf: &mut Buffer) {
+    for cell in &mut buf.content {
+        cell.fg = Color::Reset;
+        cell.bg = Color::Reset;
+        cell.modifier
+            .remove(Modifier::RAPID_BLINK | Modifier::SLOW_BLINK);
+    }
+}
+
+fn render_main(f: &mut Frame, app: &mut App) {
+    let size = f.size();
+    if size.width < MIN_WIDTH || size.height < MIN_HEIGHT {
+        render_too_small(f, app);
+        return;
+    }
+
+    // the help line goes first on short terminals, the status bar is always there
+    let help_rows = u16::from(size.height >= HELP_LINE_MIN_HEIGHT);
+
+    // Compose mode expands the input box up to `MAX_COMPOSE_ROWS` rows, the messages keep a row
+    // of their own
+    const MAX_COMPOSE_ROWS: usize = 10;
+    let input_width = size.width.saturating_sub(2) as usize;
+    let room = size.height.saturating_sub(help_rows + 1 + 2 + 3).max(1) as usize;
+    let input_rows = if app.main_input.compose_mode {
+        app.main_input
+            .wrapped_height(input_width)
+            .clamp(1, MAX_COMPOSE_ROWS)
+            .min(room)
+    } else {
+        1
+    };
+
     // Layout chunks
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
+            Constraint::Length(help_rows),
+            Constraint::Min(3),
+            Constraint::Length(input_rows as u16 + 2),
             Constraint::Length(1),
-            Constraint::Min(1),
-            Constraint::Length(3),
         ])
-        .split(f.size());
+        .split(size);
 
     // input messages
-    render_help_messages(f, app, chunks[0]);
+    if help_rows > 0 {
+        render_help_messages(f, app, chunks[0]);
+    }
+
+    // the activity log takes the right of the message section, pinned messages its top
+    let message_area = render_activity(f, app, chunks[1]);
+    let message_area = render_thread(f, app, message_area);
+    let message_area = render_pinned_messages(f, app, message_area);
 
-    let messages = app.messages.collect_list_item();
-    let messages = List::new(messages).block(
+    // only the messages in view are styled, the styling of each is cached
+    let width = message_area.width.saturating_sub(2) as usize;
+    let selection = app.view.selection();
+    let cursor = app.view.cursor();
+    let shown = app.messages.shown(&app.hidden_kinds);
+    let visible = app.view.layout(message_area, &shown, |idx| {
+        let item = app.messages.list_item(idx, width, &app.render_options)?;
+        Some(match &selection {
+            Some(selection) if selection.contains(&idx) => {
+                item.style(Style::default().add_modifier(Modifier::REVERSED))
+            }
+            _ if cursor == Some(idx) => {
+                item.style(Style::default().add_modifier(Modifier::REVERSED))
+            }
+            _ => item,
+        })
+    });
+    app.view.input_area = chunks[2];
+    let history = app
+        .state
+        .channel_info
+        .as_ref()
+        .and_then(|info| info.history.describe())
+        .map(|policy| format!(" ({})", policy))
+        .unwrap_or_default();
+    let title = match app.notifications.unread_count(&app.state.channel) {
+        0 => format!("[Channel: {}{}]", app.state.channel, history),
+        n => format!("[Channel: {}{}] ({} unread)", app.state.channel, history, n),
+    };
+    let messages = List::new(visible).block(
         Block::default()
             .borders(Borders::ALL)
-            .title(format!("[Channel: {}]", app.state.channel.clone())),
+            .title(util::truncate(&title, width)),
     );
-    f.render_widget(messages, chunks[1]);
+    f.render_widget(messages, message_area);
 
-    let input = Paragraph::new(app.main_input.buf.as_str())
+    // soft wrap the content so the box and the cursor math agree on the rows
+    let input_text = Text::from(util::wrap_styled(
+        spelled_lines(app),
+        if app.main_input.compose_mode {
+            input_width
+        } else {
+            0
+        },
+        Style::default(),
+    ));
+
+    // keep the cursor row visible once the content outgrows the box
+    let (cursor_x, cursor_y) = if app.main_input.compose_mode {
+        app.main_input.wrapped_cursor_pos(input_width)
+    } else {
+        (app.main_input.cursor_col(), 0)
+    };
+    let scroll = cursor_y.saturating_sub(input_rows as u16 - 1);
+
+    let input = Paragraph::new(input_text)
+        .scroll((scroll, 0))
         .style(match app.main_input.input_mode {
+            _ if app.state.is_read_only() => app.render_options.theme.muted(),
             InputMode::Normal => Style::default(),
-            InputMode::Editing => Style::default().fg(Color::Yellow),
+            InputMode::Editing => app.render_options.theme.highlight(),
         })
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title(app.state.id.clone()),
-        );
+        .block(Block::default().borders(Borders::ALL).title(util::truncate(
+            &if app.state.is_read_only() {
+                format!("{} (read-only)", app.state.id)
+            } else if let Some(seq) = app.reply_to {
+                let quote = app.messages.quote(&app.state.channel, seq);
+                format!("{} (replying to {})", app.state.id, quote)
+            } else if app.main_input.compose_mode {
+                format!("{} (compose)", app.state.id)
+            } else {
+                app.state.id.clone()
+            },
+            input_width,
+        )));
     f.render_widget(input, chunks[2]);
 
     // Set cursor position if current input mode is Editing
     if app.main_input.is_editing_mode() {
         f.set_cursor(
-            chunks[2].x + app.main_input.cursor_pos as u16 + 1,
-            chunks[2].y + 1,
+            chunks[2].x + cursor_x + 1,
+            chunks[2].y + cursor_y - scroll + 1,
         );
     }
 
-    // Call popup UI handler
-    if let Some(p) = &app.popup {
+    render_status_bar(f, app, chunks[3]);
+
+    // Call popup UI handlers, from the bottom of the stack up
+    for p in &app.popups {
         p.ui(f)
     }
 }
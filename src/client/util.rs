@@ -1,14 +1,526 @@
+use ratatui::{
+    style::Style,
+    text::{Line, Span},
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use super::markdown::StyledLine;
+use crate::{
+    db::user::Role,
+    packet::{stream, AsJson, JoinSnapshot, Message, PartChunk, PartEnd, PartStart},
+};
+
 /// Consumes broadcast channel until encounter the packet type `P`
+///
+/// serde doesn't verify the "type" tag of a struct, so it's compared here explicitly; otherwise
+/// packets sharing the same fields (e.g. `LoginRes` and `GotoRes`) would be mixed up. A `P`
+/// streamed in parts is returned once its last part arrived.
 pub async fn consume_til<P>(mut incoming_rx: tokio::sync::broadcast::Receiver<String>) -> P
 where
-    P: serde::de::DeserializeOwned,
+    P: serde::de::DeserializeOwned + AsJson,
 {
+    let mut partial: Option<stream::Partial> = None;
     loop {
-        if let Ok(msg) = incoming_rx.recv().await {
-            let j: serde_json::Value = serde_json::from_str(msg.as_str()).unwrap();
-            if let Ok(res) = serde_json::from_value::<P>(j) {
-                return res;
+        let Ok(msg) = incoming_rx.recv().await else {
+            continue;
+        };
+        if let Some(res) = parse_packet::<P>(&msg) {
+            return res;
+        }
+
+        // a streamed response is put together once it ends
+        if let Some(start) = parse_packet::<PartStart>(&msg) {
+            if start.packet == P::PACKET_TYPE {
+                partial = Some(stream::Partial::new(start));
+            }
+        } else if let Some(chunk) = parse_packet::<PartChunk>(&msg) {
+            if let Some(partial) = partial.as_mut() {
+                partial.push(chunk);
+            }
+        } else if let Some(end) = parse_packet::<PartEnd>(&msg) {
+            if partial
+                .as_ref()
+                .is_some_and(|p| p.start.stream == end.stream)
+            {
+                let whole = partial.take().unwrap().assemble();
+                if let Ok(res) = serde_json::from_value::<P>(whole) {
+                    return res;
+                }
+            }
+        }
+    }
+}
+
+/// Parse `msg` as the packet type `P`, `None` if it's another type of packet
+pub fn parse_packet<P>(msg: &str) -> Option<P>
+where
+    P: serde::de::DeserializeOwned + AsJson,
+{
+    let j: serde_json::Value = serde_json::from_str(msg).ok()?;
+    if j.get("type")?.as_str()? != P::PACKET_TYPE {
+        return None;
+    }
+    serde_json::from_value::<P>(j).ok()
+}
+
+/// Headers of the `JoinSnapshot`s being streamed, by the ids of their streams
+pub type SnapshotStreams = std::collections::HashMap<u64, JoinSnapshot>;
+
+/// Parse `msg` as a `JoinSnapshot` or a part of a streamed one
+///
+/// Every chunk of a streamed snapshot is returned as a snapshot of its own holding the messages of
+/// the chunk, so the history is shown as it arrives.
+pub fn parse_snapshot(streams: &mut SnapshotStreams, msg: &str) -> Option<JoinSnapshot> {
+    if let Some(snapshot) = parse_packet::<JoinSnapshot>(msg) {
+        return Some(snapshot);
+    }
+    if let Some(start) = parse_packet::<PartStart>(msg) {
+        if start.packet == JoinSnapshot::PACKET_TYPE {
+            let header = serde_json::from_value::<JoinSnapshot>(start.header).ok()?;
+            streams.insert(start.stream, header);
+        }
+    } else if let Some(chunk) = parse_packet::<PartChunk>(msg) {
+        let header = streams.get(&chunk.stream)?;
+        let messages = chunk
+            .items
+            .into_iter()
+            .filter_map(|item| serde_json::from_value::<Message>(item).ok())
+            .collect();
+        return Some(JoinSnapshot {
+            messages,
+            ..header.clone()
+        });
+    } else if let Some(end) = parse_packet::<PartEnd>(msg) {
+        streams.remove(&end.stream);
+    }
+    None
+}
+
+/// Normalize line breaks of pasted text and strip the remaining control characters
+pub fn sanitize_pasted(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\t', "    ")
+        .chars()
+        .filter(|c| *c == '\n' || !c.is_control())
+        .collect()
+}
+
+/// Read the system clipboard through the platform clipboard utilities
+///
+/// Returns `None` if none of the utilities is available.
+pub fn read_clipboard() -> Option<String> {
+    const PASTE_COMMANDS: [&[&str]; 4] = [
+        &["wl-paste", "--no-newline"],
+        &["xclip", "-selection", "clipboard", "-o"],
+        &["xsel", "--clipboard", "--output"],
+        &["pbpaste"],
+    ];
+
+    PASTE_COMMANDS.iter().find_map(|cmd| {
+        let output = std::process::Command::new(cmd[0])
+            .args(&cmd[1..])
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()?;
+        if output.status.success() {
+            String::from_utf8(output.stdout).ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Write `text` to the system clipboard through the platform clipboard utilities
+///
+/// Returns false if none of the utilities is available.
+pub fn write_clipboard(text: &str) -> bool {
+    use std::io::Write;
+    const COPY_COMMANDS: [&[&str]; 4] = [
+        &["wl-copy"],
+        &["xclip", "-selection", "clipboard", "-i"],
+        &["xsel", "--clipboard", "--input"],
+        &["pbcopy"],
+    ];
+
+    COPY_COMMANDS.iter().any(|cmd| {
+        let Ok(mut child) = std::process::Command::new(cmd[0])
+            .args(&cmd[1..])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+        else {
+            return false;
+        };
+        let written = child
+            .stdin
+            .take()
+            .is_some_and(|mut stdin| stdin.write_all(text.as_bytes()).is_ok());
+        child.wait().is_ok_and(|status| status.success()) && written
+    })
+}
+
+/// Mark shown in front of an identity: `#` for admins, `%` for moderators, `~` for guests and
+/// `@` for members
+pub fn get_mark(role: Role, is_guest: bool) -> char {
+    match role {
+        Role::Admin => '#',
+        Role::Moderator => '%',
+        Role::User if is_guest => '~',
+        Role::User => '@',
+    }
+}
+
+/// Columns `text` takes in the terminal, CJK and other wide characters take two
+pub fn display_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// Columns `c` takes in the terminal, control characters take none
+pub fn char_width(c: char) -> usize {
+    UnicodeWidthChar::width(c).unwrap_or(0)
+}
+
+/// `text` cut to `width` columns, ending with an ellipsis if it had to be cut
+pub fn truncate(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_owned();
+    }
+    let mut truncated = String::new();
+    let mut columns = 0;
+    for c in text.chars() {
+        // room is left for the ellipsis
+        if columns + char_width(c) + 1 > width {
+            break;
+        }
+        columns += char_width(c);
+        truncated.push(c);
+    }
+    if width > 0 {
+        truncated.push('…');
+    }
+    truncated
+}
+
+/// Split `items` into rows of at most `width` columns
+///
+/// A wide character that doesn't fit the rest of a row starts the next one, a character wider
+/// than `width` gets a row of its own.
+fn chunk_by_width<T>(items: &[T], width: usize, char_of: impl Fn(&T) -> char) -> Vec<&[T]> {
+    let mut rows = Vec::new();
+    let (mut start, mut columns) = (0, 0);
+    for (i, item) in items.iter().enumerate() {
+        let w = char_width(char_of(item));
+        if columns + w > width && i > start {
+            rows.push(&items[start..i]);
+            (start, columns) = (i, 0);
+        }
+        columns += w;
+    }
+    rows.push(&items[start..]);
+    rows
+}
+
+/// Split `text` on line breaks and soft wrap every line to `width` columns
+///
+/// An empty line is kept as an empty row so the number of rows always reflects what the user
+/// typed.
+pub fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut rows = Vec::new();
+    for line in text.split('\n') {
+        let chars: Vec<char> = line.chars().collect();
+        if width == 0 || chars.is_empty() {
+            rows.push(line.to_owned());
+            continue;
+        }
+        rows.extend(
+            chunk_by_width(&chars, width, |c| *c)
+                .into_iter()
+                .map(|c| c.iter().collect::<String>()),
+        );
+    }
+    rows
+}
+
+/// Soft wrap styled `lines` to `width` columns and merge runs of the same style into spans
+pub fn wrap_styled(lines: Vec<StyledLine>, width: usize, base: Style) -> Vec<Line<'static>> {
+    let mut rows = Vec::new();
+    for line in lines {
+        if width == 0 || line.is_empty() {
+            rows.push(line);
+            continue;
+        }
+        rows.extend(
+            chunk_by_width(&line, width, |(c, _)| *c)
+                .into_iter()
+                .map(|c| c.to_vec()),
+        );
+    }
+
+    rows.into_iter()
+        .map(|row| {
+            let mut spans: Vec<Span> = Vec::new();
+            let mut run = String::new();
+            let mut run_style = None;
+            for (c, style) in row {
+                if run_style.is_some_and(|s| s != style) {
+                    spans.push(Span::styled(
+                        std::mem::take(&mut run),
+                        base.patch(run_style.unwrap()),
+                    ));
+                }
+                run_style = Some(style);
+                run.push(c);
+            }
+            if let Some(style) = run_style {
+                spans.push(Span::styled(run, base.patch(style)));
+            }
+            Line::from(spans)
+        })
+        .collect()
+}
+
+/// Seconds since the unix epoch
+pub fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Unix timestamp in milliseconds
+pub fn unix_time_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Parse a duration like `90s`, `10m` or `1h30m`, the units are s, m, h and d
+pub fn parse_duration(s: &str) -> Option<std::time::Duration> {
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return None,
+        };
+        total = total.checked_add(number.parse::<u64>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+    if !number.is_empty() || total == 0 {
+        return None;
+    }
+    Some(std::time::Duration::from_secs(total))
+}
+
+/// Unix time of `s`, either a duration from now or the next `HH:MM` in UTC
+pub fn parse_schedule_time(s: &str, now: u64) -> Option<u64> {
+    if let Some((hour, minute)) = s.split_once(':') {
+        let (hour, minute): (u64, u64) = (hour.parse().ok()?, minute.parse().ok()?);
+        if hour >= 24 || minute >= 60 {
+            return None;
+        }
+        let today = now - now % 86400 + hour * 3600 + minute * 60;
+        return Some(if today > now { today } else { today + 86400 });
+    }
+    parse_duration(s).map(|d| now + d.as_secs())
+}
+
+/// Names of `candidates` matching `query` best first, case is ignored
+///
+/// An exact match is the only result. Otherwise names starting with `query` come first, then
+/// names containing its characters in order, tighter matches first.
+pub fn fuzzy_match<'a>(query: &str, candidates: &'a [String]) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    if let Some(exact) = candidates.iter().find(|c| c.to_lowercase() == query) {
+        return vec![exact.as_str()];
+    }
+
+    // (is not a prefix match, span of the matched characters, name)
+    let mut matches: Vec<(bool, usize, &str)> = candidates
+        .iter()
+        .filter_map(|candidate| {
+            let lower = candidate.to_lowercase();
+            if lower.starts_with(&query) {
+                return Some((false, query.len(), candidate.as_str()));
+            }
+            let mut positions = Vec::new();
+            let mut chars = lower.char_indices();
+            for q in query.chars() {
+                positions.push(chars.find(|(_, c)| *c == q)?.0);
+            }
+            let span = match (positions.first(), positions.last()) {
+                (Some(first), Some(last)) => last - first + 1,
+                _ => 0,
+            };
+            Some((true, span, candidate.as_str()))
+        })
+        .collect();
+    matches.sort();
+    matches.into_iter().map(|(_, _, name)| name).collect()
+}
+
+/// Longest prefix shared by every word of `words`
+pub fn common_prefix(words: &[&str]) -> String {
+    let Some(first) = words.first() else {
+        return String::new();
+    };
+    let mut len = first.len();
+    for word in &words[1..] {
+        len = first
+            .char_indices()
+            .zip(word.chars())
+            .take_while(|((_, a), b)| a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0)
+            .min(len);
+    }
+    first[..len].to_owned()
+}
+
+/// Number of single character edits turning `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            cur.push(substitution.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Format the unix timestamp `secs` as `YYYY-MM-DD HH:MM:SS` in UTC
+pub fn format_time(secs: u64) -> String {
+    // civil date from the number of days since the epoch (Howard Hinnant's algorithm)
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let secs_of_day = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("한국어"), 6);
+        assert_eq!(display_width("日本a"), 5);
+        assert_eq!(display_width("שלום"), 4);
+    }
+
+    #[test]
+    fn wrap_text_by_columns() {
+        assert_eq!(wrap_text("abcdef", 4), vec!["abcd", "ef"]);
+        assert_eq!(wrap_text("한국어", 4), vec!["한국", "어"]);
+
+        // a wide character that doesn't fit the rest of a row starts the next one
+        assert_eq!(wrap_text("a한국", 4), vec!["a한", "국"]);
+        assert_eq!(wrap_text("ab\n日本語x", 5), vec!["ab", "日本", "語x"]);
+
+        // too wide for a row at all, it gets a row of its own
+        assert_eq!(wrap_text("한a", 1), vec!["한", "a"]);
+    }
+
+    #[test]
+    fn truncate_to_columns() {
+        assert_eq!(truncate("public", 6), "public");
+        assert_eq!(truncate("[Channel: public]", 10), "[Channel:…");
+        assert_eq!(truncate("한국어", 4), "한…");
+        assert_eq!(truncate("public", 1), "…");
+        assert_eq!(truncate("public", 0), "");
+    }
+
+    #[test]
+    fn wrap_styled_by_columns() {
+        let line: StyledLine = "a日本b".chars().map(|c| (c, Style::default())).collect();
+        let rows: Vec<String> = wrap_styled(vec![line], 3, Style::default())
+            .into_iter()
+            .map(|row| row.spans.iter().map(|s| s.content.as_ref()).collect())
+            .collect();
+        assert_eq!(rows, vec!["a日", "本b"]);
+    }
+
+    #[test]
+    fn streamed_snapshot_arrives_by_chunks() {
+        let messages: Vec<Message> = (0..100)
+            .map(|seq| Message {
+                seq: Some(seq),
+                ..Message::system_notice(&seq.to_string())
+            })
+            .collect();
+        let header = serde_json::json!({
+            "type": "JoinSnapshot",
+            "channel_name": "main",
+            "messages": [],
+            "reactions": {},
+        });
+        let frames = stream::frames(JoinSnapshot::PACKET_TYPE, header, "/messages", &messages);
+
+        let mut streams = SnapshotStreams::new();
+        let chunks: Vec<JoinSnapshot> = frames
+            .map(|frame| String::from_utf8(frame).unwrap())
+            .filter_map(|frame| parse_snapshot(&mut streams, &frame))
+            .collect();
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks.iter().all(|chunk| chunk.channel_name == "main"));
+        let seqs: Vec<u64> = chunks
+            .iter()
+            .flat_map(|chunk| chunk.messages.iter().filter_map(|msg| msg.seq))
+            .collect();
+        assert_eq!(seqs, (0..100).collect::<Vec<_>>());
+        assert!(streams.is_empty());
+    }
+
+    #[test]
+    fn streamed_response_is_put_together() {
+        let header = serde_json::json!({ "type": "AccountRes", "result": { "Ok": { "id": "a" } } });
+        let items: Vec<u64> = (0..130).collect();
+        let mut frames = stream::frames("AccountRes", header, "/result/Ok/messages", &items);
+
+        let start = parse_packet::<PartStart>(&String::from_utf8(frames.next().unwrap()).unwrap());
+        let mut partial = stream::Partial::new(start.unwrap());
+        for frame in frames {
+            if let Some(chunk) = parse_packet::<PartChunk>(&String::from_utf8(frame).unwrap()) {
+                assert!(partial.push(chunk));
             }
         }
+        let whole = partial.assemble();
+        assert_eq!(whole["result"]["Ok"]["id"], "a");
+        assert_eq!(
+            whole["result"]["Ok"]["messages"].as_array().unwrap().len(),
+            130
+        );
     }
 }
@@ -0,0 +1,201 @@
+//! Salted challenge-response authentication, modeled after SCRAM (RFC 5802)
+//!
+//! The server keeps a `Verifier` of every account: a random salt and the hash of a key derived
+//! from the password with it. To log in, the client asks for a `Challenge` with a nonce of its
+//! own, the server extends the nonce and answers with the salt. The client then proves it knows
+//! the key without sending it, the proof is bound to the nonce and useless for any other login.
+//! A stolen verifier doesn't let anyone log in either, the key can't be recovered from its hash.
+//!
+//! The password is the hash of `hash::sha256_password`, so verifiers can be made of the hashes
+//! the server used to store.
+
+use std::sync::OnceLock;
+
+use base64ct::{Base64, Encoding};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// PBKDF2 rounds deriving the key from the password
+pub const ITERATIONS: u32 = 4096;
+
+/// Most rounds a client takes from a challenge, so a server can't keep it busy
+const MAX_ITERATIONS: u32 = 1 << 20;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 18;
+
+/// What the server keeps to check the proofs of an account
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Verifier {
+    pub salt: String,
+    pub iterations: u32,
+    pub stored_key: String,
+}
+
+/// Salt and nonce the client has to prove the knowledge of the key with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    pub salt: String,
+    pub iterations: u32,
+
+    /// Nonce of the client extended by the server
+    pub nonce: String,
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+fn client_key(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut salted = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut salted);
+    hmac(&salted, b"Client Key")
+}
+
+/// What the proof of `id` answering to the challenge signs
+fn auth_message(id: &str, challenge: &Challenge) -> Vec<u8> {
+    format!(
+        "{},{},{},{}",
+        id, challenge.salt, challenge.iterations, challenge.nonce
+    )
+    .into_bytes()
+}
+
+fn xor(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    std::array::from_fn(|i| a[i] ^ b[i])
+}
+
+/// Random nonce encoded in base64
+pub fn nonce() -> String {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    Base64::encode_string(&nonce)
+}
+
+impl Verifier {
+    /// Verifier of `password` with a fresh salt
+    pub fn new(password: &str) -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let stored_key = Sha256::digest(client_key(password, &salt, ITERATIONS));
+        Self {
+            salt: Base64::encode_string(&salt),
+            iterations: ITERATIONS,
+            stored_key: Base64::encode_string(&stored_key),
+        }
+    }
+
+    /// Check `proof` of `id` answering to `challenge`
+    pub fn check(&self, id: &str, challenge: &Challenge, proof: &str) -> bool {
+        let (Ok(stored_key), Ok(proof)) = (
+            Base64::decode_vec(&self.stored_key),
+            Base64::decode_vec(proof),
+        ) else {
+            return false;
+        };
+        let (Ok(stored), Ok(proof)) = (
+            <[u8; 32]>::try_from(stored_key),
+            <[u8; 32]>::try_from(proof),
+        ) else {
+            return false;
+        };
+
+        // the proof is the key hidden by the signature, the key has to hash to the stored one
+        let signature = hmac(&stored, &auth_message(id, challenge));
+        let client_key = xor(&proof, &signature);
+        Sha256::digest(client_key).as_slice() == stored
+    }
+
+    /// Check `password` itself, for secrets sent as they are such as the passwords of channels
+    pub fn matches(&self, password: &str) -> bool {
+        let Ok(salt) = Base64::decode_vec(&self.salt) else {
+            return false;
+        };
+        let stored_key = Sha256::digest(client_key(password, &salt, self.iterations));
+        Base64::encode_string(&stored_key) == self.stored_key
+    }
+}
+
+impl Challenge {
+    /// Challenge of `verifier` extending `client_nonce`
+    ///
+    /// Ids without a verifier are challenged all the same with a salt made up for the id, so
+    /// the challenges don't tell whether an account exists.
+    pub fn new(id: &str, verifier: Option<&Verifier>, client_nonce: &str) -> Self {
+        static DECOY_SECRET: OnceLock<[u8; 32]> = OnceLock::new();
+        let (salt, iterations) = match verifier {
+            Some(verifier) => (verifier.salt.clone(), verifier.iterations),
+            None => {
+                let secret = DECOY_SECRET.get_or_init(|| {
+                    let mut secret = [0u8; 32];
+                    rand::thread_rng().fill_bytes(&mut secret);
+                    secret
+                });
+                let salt = hmac(secret, id.as_bytes());
+                (Base64::encode_string(&salt[..SALT_LEN]), ITERATIONS)
+            }
+        };
+        Self {
+            salt,
+            iterations,
+            nonce: format!("{}{}", client_nonce, nonce()),
+        }
+    }
+
+    /// Proof of `id` knowing `password`, fails if the challenge isn't an answer to `client_nonce`
+    pub fn prove(&self, id: &str, client_nonce: &str, password: &str) -> Result<String, String> {
+        if !self.nonce.starts_with(client_nonce) || self.nonce.len() <= client_nonce.len() {
+            return Err("the server answered with a nonce of another challenge".to_owned());
+        }
+        if self.iterations == 0 || self.iterations > MAX_ITERATIONS {
+            return Err(format!(
+                "unreasonable number of rounds: {}",
+                self.iterations
+            ));
+        }
+        let salt = Base64::decode_vec(&self.salt).map_err(|_| "malformed salt".to_owned())?;
+        let client_key = client_key(password, &salt, self.iterations);
+        let stored_key = Sha256::digest(client_key);
+        let signature = hmac(&stored_key, &auth_message(id, self));
+        Ok(Base64::encode_string(&xor(&client_key, &signature)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proof_answers_only_its_challenge() {
+        let password = "hashed password";
+        let verifier = Verifier::new(password);
+        let client_nonce = nonce();
+        let challenge = Challenge::new("alice", Some(&verifier), &client_nonce);
+
+        let proof = challenge.prove("alice", &client_nonce, password).unwrap();
+        assert!(verifier.check("alice", &challenge, &proof));
+
+        // replayed to another challenge, or claimed by another id
+        let other = Challenge::new("alice", Some(&verifier), &client_nonce);
+        assert!(!verifier.check("alice", &other, &proof));
+        assert!(!verifier.check("bob", &challenge, &proof));
+
+        // a wrong password, or a server answering to another nonce
+        let wrong = challenge.prove("alice", &client_nonce, "guess").unwrap();
+        assert!(!verifier.check("alice", &challenge, &wrong));
+        assert!(challenge.prove("alice", &nonce(), password).is_err());
+    }
+
+    #[test]
+    fn unknown_ids_get_a_stable_salt() {
+        let first = Challenge::new("nobody", None, &nonce());
+        let second = Challenge::new("nobody", None, &nonce());
+        assert_eq!(first.salt, second.salt);
+        assert_ne!(first.nonce, second.nonce);
+        assert_ne!(first.salt, Challenge::new("someone", None, &nonce()).salt);
+    }
+}
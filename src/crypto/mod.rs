@@ -1 +1,3 @@
+pub mod auth;
 pub mod hash;
+pub mod vault;
@@ -0,0 +1,50 @@
+use base64ct::{Base64, Encoding};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::RngCore;
+use sha2::Sha256;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// PBKDF2 rounds deriving the key from the passphrase
+const KDF_ROUNDS: u32 = 100_000;
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Key {
+    let mut key = Key::default();
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, KDF_ROUNDS, &mut key);
+    key
+}
+
+/// Encrypt `plaintext` with a key derived from `passphrase`, the result is encoded in base64
+pub fn seal(passphrase: &str, plaintext: &[u8]) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .expect("encryption of an in-memory buffer");
+
+    // salt || nonce || ciphertext
+    let sealed = [&salt[..], &nonce[..], &ciphertext[..]].concat();
+    Base64::encode_string(&sealed)
+}
+
+/// Decrypt what `seal` produced, fails on a wrong passphrase or a tampered input
+pub fn open(passphrase: &str, sealed: &str) -> Result<Vec<u8>, String> {
+    let sealed = Base64::decode_vec(sealed.trim()).map_err(|_| "malformed data".to_owned())?;
+    if sealed.len() < SALT_LEN + NONCE_LEN {
+        return Err("malformed data".to_owned());
+    }
+    let (salt, rest) = sealed.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    ChaCha20Poly1305::new(&derive_key(passphrase, salt))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "wrong passphrase".to_owned())
+}
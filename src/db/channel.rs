@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use mysql::{prelude::*, *};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::Database;
+use crate::packet::HistoryPolicy;
+
+/// Channel created at runtime as stored in the `channel` table
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ChannelRecord {
+    pub name: String,
+    pub owner: Option<String>,
+    pub archived: bool,
+    pub announce_only: bool,
+
+    /// Slow mode interval in seconds
+    pub slow_mode: Option<u64>,
+    pub moderators: Vec<String>,
+    pub topic: Option<String>,
+
+    /// Verifier of the password required to join as JSON, see `crypto::auth::Verifier`
+    pub password: Option<String>,
+
+    /// System channel created by an admin, the built-in ones aren't stored
+    pub system: bool,
+
+    /// What is kept of the messages of the channel
+    pub history_policy: HistoryPolicy,
+
+    /// Kept even when nobody has been in it for a while
+    pub gc_exempt: bool,
+
+    /// Nicknames of the members in the channel, by id
+    pub nicknames: BTreeMap<String, String>,
+
+    /// Users kept out of the channel
+    #[serde(default)]
+    pub banned: Vec<String>,
+
+    /// Users who can't post, until the unix time or until unmuted if `None`
+    #[serde(default)]
+    pub muted: BTreeMap<String, Option<u64>>,
+}
+
+impl ChannelRecord {
+    /// Insert the channel or update the stored one
+    pub fn save(&self, db: &Database) -> Result<(), String> {
+        let mut conn = db.get_conn()?;
+        conn.exec_drop(
+            r"REPLACE INTO channel (
+                name, owner, archived, announce_only, slow_mode, moderators, topic, password, system,
+                history, gc_exempt, nicknames, bans, mutes
+            ) VALUES (
+                :name, :owner, :archived, :announce_only, :slow_mode, :moderators, :topic, :password,
+                :system, :history, :gc_exempt, :nicknames, :bans, :mutes
+            )",
+            params! {
+                "name" => &self.name,
+                "owner" => &self.owner,
+                "archived" => self.archived,
+                "announce_only" => self.announce_only,
+                "slow_mode" => self.slow_mode,
+                "moderators" => serde_json::to_string(&self.moderators).unwrap(),
+                "topic" => &self.topic,
+                "password" => &self.password,
+                "system" => self.system,
+                "history" => serde_json::to_string(&self.history_policy).unwrap(),
+                "gc_exempt" => self.gc_exempt,
+                "nicknames" => serde_json::to_string(&self.nicknames).unwrap(),
+                "bans" => serde_json::to_string(&self.banned).unwrap(),
+                "mutes" => serde_json::to_string(&self.muted).unwrap(),
+            },
+        )
+        .map_err(|e| format!("Failed to save the channel '{}': {}", self.name, e))
+    }
+
+    pub fn delete(name: &str, db: &Database) -> Result<(), String> {
+        let mut conn = db.get_conn()?;
+        conn.exec_drop(
+            "DELETE FROM channel WHERE name = :name",
+            params! { "name" => name },
+        )
+        .map_err(|e| format!("Failed to delete the channel '{}': {}", name, e))
+    }
+
+    /// Every stored channel
+    pub fn load_all(db: &Database) -> Result<Vec<Self>, String> {
+        let mut conn = db.get_conn()?;
+        // more columns than a tuple takes, they're taken from the row by name
+        conn.query_map(
+            r"SELECT name, owner, archived, announce_only, slow_mode, moderators, topic, password,
+                system, history, gc_exempt, nicknames, bans, mutes
+            FROM channel",
+            |mut row: Row| Self {
+                name: row.take("name").unwrap_or_default(),
+                owner: row.take("owner").flatten(),
+                archived: row.take("archived").unwrap_or_default(),
+                announce_only: row.take("announce_only").unwrap_or_default(),
+                slow_mode: row.take("slow_mode").flatten(),
+                moderators: json_column(&mut row, "moderators"),
+                topic: row.take("topic").flatten(),
+                password: row.take("password").flatten(),
+                system: row.take("system").unwrap_or_default(),
+                history_policy: json_column(&mut row, "history"),
+                gc_exempt: row.take("gc_exempt").unwrap_or_default(),
+                nicknames: json_column(&mut row, "nicknames"),
+                banned: json_column(&mut row, "bans"),
+                muted: json_column(&mut row, "mutes"),
+            },
+        )
+        .map_err(|e| format!("Failed to load channels: {}", e))
+    }
+}
+
+/// Value of the JSON column `name`, the default if it's null or broken
+fn json_column<T: DeserializeOwned + Default>(row: &mut Row, name: &str) -> T {
+    row.take::<Option<String>, _>(name)
+        .flatten()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
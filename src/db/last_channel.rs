@@ -0,0 +1,31 @@
+use mysql::{prelude::*, *};
+
+use super::Database;
+
+/// Channel the member `id` was last in, as stored in the `last_channel` table
+pub fn load(id: &str, db: &Database) -> Result<Option<String>, String> {
+    let mut conn = db.get_conn()?;
+    conn.exec_first(
+        "SELECT channel FROM last_channel WHERE id = :id",
+        params! { "id" => id },
+    )
+    .map_err(|e| format!("Failed to load the last channel of '{}': {}", id, e))
+}
+
+pub fn save(id: &str, channel: &str, db: &Database) -> Result<(), String> {
+    let mut conn = db.get_conn()?;
+    conn.exec_drop(
+        "REPLACE INTO last_channel (id, channel) VALUES (:id, :channel)",
+        params! { "id" => id, "channel" => channel },
+    )
+    .map_err(|e| format!("Failed to save the last channel of '{}': {}", id, e))
+}
+
+pub fn delete(id: &str, db: &Database) -> Result<(), String> {
+    let mut conn = db.get_conn()?;
+    conn.exec_drop(
+        "DELETE FROM last_channel WHERE id = :id",
+        params! { "id" => id },
+    )
+    .map_err(|e| format!("Failed to delete the last channel of '{}': {}", id, e))
+}
@@ -0,0 +1,98 @@
+use mysql::{prelude::*, *};
+
+use super::Database;
+use crate::packet::Message;
+
+/// Log `msg` of `channel`, sent at the unix time `at`
+///
+/// Nodes of a cluster sharing the database log the messages relayed to them too, the first one
+/// logged is kept.
+pub fn append(channel: &str, at: u64, msg: &Message, db: &Database) -> Result<(), String> {
+    let mut conn = db.get_conn()?;
+    conn.exec_drop(
+        r"INSERT IGNORE INTO message_log (channel, seq, at, sender, body)
+        VALUES (:channel, :seq, :at, :sender, :body)",
+        params! {
+            "channel" => channel,
+            "seq" => msg.seq.unwrap_or_default(),
+            "at" => at,
+            "sender" => &msg.id,
+            "body" => serde_json::to_string(msg).unwrap(),
+        },
+    )
+    .map_err(|e| format!("Failed to log a message of '{}': {}", channel, e))
+}
+
+/// The latest `limit` messages of `channel` with the unix time they were sent at, oldest first
+pub fn recent(channel: &str, limit: usize, db: &Database) -> Result<Vec<(u64, Message)>, String> {
+    let mut conn = db.get_conn()?;
+    let rows: Vec<(u64, String)> = conn
+        .exec(
+            r"SELECT at, body FROM message_log WHERE channel = :channel
+            ORDER BY seq DESC LIMIT :limit",
+            params! { "channel" => channel, "limit" => limit as u64 },
+        )
+        .map_err(|e| format!("Failed to load the messages of '{}': {}", channel, e))?;
+    Ok(rows
+        .into_iter()
+        .rev()
+        .filter_map(|(at, body)| Some((at, serde_json::from_str(&body).ok()?)))
+        .collect())
+}
+
+/// Replace the logged message `seq` of `channel` with `msg`
+pub fn update(channel: &str, msg: &Message, db: &Database) -> Result<(), String> {
+    let mut conn = db.get_conn()?;
+    conn.exec_drop(
+        r"UPDATE message_log SET sender = :sender, body = :body
+        WHERE channel = :channel AND seq = :seq",
+        params! {
+            "channel" => channel,
+            "seq" => msg.seq.unwrap_or_default(),
+            "sender" => &msg.id,
+            "body" => serde_json::to_string(msg).unwrap(),
+        },
+    )
+    .map_err(|e| format!("Failed to update a message of '{}': {}", channel, e))
+}
+
+/// Logged messages of `sender` in every channel, as (channel, message)
+pub fn of_sender(sender: &str, db: &Database) -> Result<Vec<(String, Message)>, String> {
+    let mut conn = db.get_conn()?;
+    let rows: Vec<(String, String)> = conn
+        .exec(
+            "SELECT channel, body FROM message_log WHERE sender = :sender",
+            params! { "sender" => sender },
+        )
+        .map_err(|e| format!("Failed to load the messages of '{}': {}", sender, e))?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(channel, body)| Some((channel, serde_json::from_str(&body).ok()?)))
+        .collect())
+}
+
+/// Delete the messages sent before the unix time `before`, in `channel` or in every channel
+pub fn prune(channel: Option<&str>, before: u64, db: &Database) -> Result<usize, String> {
+    let mut conn = db.get_conn()?;
+    match channel {
+        Some(channel) => conn.exec_drop(
+            "DELETE FROM message_log WHERE channel = :channel AND at < :before",
+            params! { "channel" => channel, "before" => before },
+        ),
+        None => conn.exec_drop(
+            "DELETE FROM message_log WHERE at < :before",
+            params! { "before" => before },
+        ),
+    }
+    .map_err(|e| format!("Failed to prune the message log: {}", e))?;
+    Ok(conn.affected_rows() as usize)
+}
+
+pub fn delete_channel(channel: &str, db: &Database) -> Result<(), String> {
+    let mut conn = db.get_conn()?;
+    conn.exec_drop(
+        "DELETE FROM message_log WHERE channel = :channel",
+        params! { "channel" => channel },
+    )
+    .map_err(|e| format!("Failed to delete the messages of '{}': {}", channel, e))
+}
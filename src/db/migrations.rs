@@ -0,0 +1,306 @@
+//! Versioned schema of the database
+//!
+//! Every change to the schema is a new migration appended to `MIGRATIONS`, applied migrations are
+//! never edited. The version of the schema is kept in the `schema_version` table, migrations newer
+//! than it are applied in order at startup.
+
+use mysql::{prelude::*, *};
+
+use crate::crypto::{auth::Verifier, hash};
+
+struct Migration {
+    version: u32,
+    name: &'static str,
+    up: fn(&mut PooledConn) -> Result<()>,
+}
+
+/// Every migration in the order of its version
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create user table",
+        up: create_user_table,
+    },
+    Migration {
+        version: 2,
+        name: "create channel table",
+        up: create_channel_table,
+    },
+    Migration {
+        version: 3,
+        name: "create scheduled table",
+        up: create_scheduled_table,
+    },
+    Migration {
+        version: 4,
+        name: "create presence table",
+        up: create_presence_table,
+    },
+    Migration {
+        version: 5,
+        name: "add roles and bans to users",
+        up: add_user_roles,
+    },
+    Migration {
+        version: 6,
+        name: "add system flag to channels",
+        up: add_channel_system_flag,
+    },
+    Migration {
+        version: 7,
+        name: "add history policy to channels",
+        up: add_channel_history_policy,
+    },
+    Migration {
+        version: 8,
+        name: "add gc exemption to channels",
+        up: add_channel_gc_exemption,
+    },
+    Migration {
+        version: 9,
+        name: "create notice table",
+        up: create_notice_table,
+    },
+    Migration {
+        version: 10,
+        name: "replace password hashes with verifiers",
+        up: add_user_verifiers,
+    },
+    Migration {
+        version: 11,
+        name: "add approval state to users",
+        up: add_user_approval,
+    },
+    Migration {
+        version: 12,
+        name: "add nicknames to channels",
+        up: add_channel_nicknames,
+    },
+    Migration {
+        version: 13,
+        name: "create read marker table",
+        up: create_read_marker_table,
+    },
+    Migration {
+        version: 14,
+        name: "create last channel table",
+        up: create_last_channel_table,
+    },
+    Migration {
+        version: 15,
+        name: "add bans and mutes to channels",
+        up: add_channel_sanctions,
+    },
+    Migration {
+        version: 16,
+        name: "create message log table",
+        up: create_message_log_table,
+    },
+];
+
+// Tables may have been created before the migrations were versioned, hence `IF NOT EXISTS`
+fn create_user_table(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS user (
+            id          VARCHAR(14) PRIMARY KEY,
+            password    TEXT NOT NULL,
+            bio         TEXT,
+            location    TEXT
+        )",
+    )?;
+    conn.exec_drop(
+        r"INSERT IGNORE INTO user (id, password, bio, location)
+        VALUES ('root', :password, 'root account', '')",
+        params! { "password" => hash::sha256_password("alpine") },
+    )
+}
+
+fn create_channel_table(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS channel (
+            name            VARCHAR(64) PRIMARY KEY,
+            owner           VARCHAR(14),
+            archived        BOOLEAN NOT NULL DEFAULT FALSE,
+            announce_only   BOOLEAN NOT NULL DEFAULT FALSE,
+            slow_mode       BIGINT UNSIGNED,
+            moderators      TEXT,
+            topic           TEXT,
+            password        TEXT
+        )",
+    )
+}
+
+fn create_scheduled_table(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS scheduled (
+            id          BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+            owner       VARCHAR(14) NOT NULL,
+            channel     VARCHAR(64) NOT NULL,
+            at          BIGINT UNSIGNED NOT NULL,
+            msg         TEXT NOT NULL
+        )",
+    )
+}
+
+fn create_presence_table(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS presence (
+            id          VARCHAR(14) PRIMARY KEY,
+            state       VARCHAR(16) NOT NULL
+        )",
+    )
+}
+
+// root used to be special-cased in the code, it's the first admin
+fn add_user_roles(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop(
+        r"ALTER TABLE user
+            ADD COLUMN role     VARCHAR(16) NOT NULL DEFAULT 'user',
+            ADD COLUMN banned   BOOLEAN NOT NULL DEFAULT FALSE",
+    )?;
+    conn.query_drop("UPDATE user SET role = 'admin' WHERE id = 'root'")
+}
+
+fn add_channel_system_flag(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop("ALTER TABLE channel ADD COLUMN system BOOLEAN NOT NULL DEFAULT FALSE")
+}
+
+fn add_channel_history_policy(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop("ALTER TABLE channel ADD COLUMN history TEXT")
+}
+
+fn add_channel_gc_exemption(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop("ALTER TABLE channel ADD COLUMN gc_exempt BOOLEAN NOT NULL DEFAULT FALSE")
+}
+
+fn create_notice_table(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS notice (
+            id          BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+            recipient   VARCHAR(14) NOT NULL,
+            msg         TEXT NOT NULL
+        )",
+    )
+}
+
+// the stored hashes are what the clients derive the keys from, so every account keeps its password
+fn add_user_verifiers(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop(
+        r"ALTER TABLE user
+            ADD COLUMN salt         TEXT,
+            ADD COLUMN iterations   INT UNSIGNED,
+            ADD COLUMN stored_key   TEXT",
+    )?;
+    let users: Vec<(String, String)> =
+        conn.query("SELECT id, password FROM user WHERE password <> ''")?;
+    for (id, password) in users {
+        let verifier = Verifier::new(&password);
+        conn.exec_drop(
+            r"UPDATE user SET password = '', salt = :salt, iterations = :iterations,
+                stored_key = :stored_key
+            WHERE id = :id",
+            params! {
+                "id" => id,
+                "salt" => verifier.salt,
+                "iterations" => verifier.iterations,
+                "stored_key" => verifier.stored_key,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+// accounts registered so far were never waiting for anyone
+fn add_user_approval(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop("ALTER TABLE user ADD COLUMN approval VARCHAR(16) NOT NULL DEFAULT 'approved'")
+}
+
+fn add_channel_nicknames(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop("ALTER TABLE channel ADD COLUMN nicknames TEXT")
+}
+
+fn add_channel_sanctions(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop("ALTER TABLE channel ADD COLUMN bans TEXT, ADD COLUMN mutes TEXT")
+}
+
+fn create_read_marker_table(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS read_marker (
+            id          VARCHAR(14) NOT NULL,
+            channel     VARCHAR(64) NOT NULL,
+            seq         BIGINT UNSIGNED NOT NULL,
+            PRIMARY KEY (id, channel)
+        )",
+    )
+}
+
+fn create_last_channel_table(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS last_channel (
+            id          VARCHAR(14) PRIMARY KEY,
+            channel     VARCHAR(64) NOT NULL
+        )",
+    )
+}
+
+fn create_message_log_table(conn: &mut PooledConn) -> Result<()> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS message_log (
+            channel     VARCHAR(64) NOT NULL,
+            seq         BIGINT UNSIGNED NOT NULL,
+            at          BIGINT UNSIGNED NOT NULL,
+            sender      VARCHAR(64) NOT NULL,
+            body        TEXT NOT NULL,
+            PRIMARY KEY (channel, seq),
+            INDEX (at)
+        )",
+    )
+}
+
+/// Version of the schema, 0 for an empty database
+fn current_version(conn: &mut PooledConn) -> Result<u32> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS schema_version (
+            version     INT UNSIGNED PRIMARY KEY,
+            name        TEXT NOT NULL,
+            applied_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+    )?;
+    let version: Option<Option<u32>> =
+        conn.query_first("SELECT MAX(version) FROM schema_version")?;
+    Ok(version.flatten().unwrap_or(0))
+}
+
+/// Apply the pending migrations, returns the version of the schema afterwards
+///
+/// Stops at the first failing migration, the ones applied before it stay applied.
+pub fn run(conn: &mut PooledConn) -> std::result::Result<u32, String> {
+    let mut version =
+        current_version(conn).map_err(|e| format!("Failed to read the schema version: {}", e))?;
+
+    let applied = version;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > applied) {
+        (migration.up)(conn).map_err(|e| {
+            format!(
+                "Migration {} '{}' failed at version {}: {}",
+                migration.version, migration.name, version, e
+            )
+        })?;
+        conn.exec_drop(
+            "INSERT INTO schema_version (version, name) VALUES (:version, :name)",
+            params! { "version" => migration.version, "name" => migration.name },
+        )
+        .map_err(|e| {
+            format!(
+                "Failed to record migration {} '{}': {}",
+                migration.version, migration.name, e
+            )
+        })?;
+        crate::server::log::info(format_args!(
+            "Database migration {} applied: {}",
+            migration.version, migration.name
+        ));
+        version = migration.version;
+    }
+    Ok(version)
+}
@@ -1 +1,171 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use mysql::{
+    prelude::Queryable, DriverError, Opts, OptsBuilder, Pool, PoolConstraints, PoolOpts, PooledConn,
+};
+
+pub mod channel;
+pub mod last_channel;
+pub mod message_log;
+pub mod migrations;
+pub mod notice;
+pub mod presence;
+pub mod read_marker;
+pub mod schedule;
 pub mod user;
+
+/// Error message for requests that can't be served while the database is unreachable
+pub const UNAVAILABLE: &str = "service temporarily unavailable, try again later";
+
+/// Error message for requests that found every connection of the pool taken
+pub const BUSY: &str = "the server is busy, try again later";
+
+/// Number of attempts to get a connection before giving up
+const CONNECT_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry, doubled on every failure
+const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Period in which requests fail fast without touching the database after giving up
+const DEGRADED_PERIOD: Duration = Duration::from_secs(5);
+
+/// Size of the pool and how long a request waits for one of its connections
+#[derive(Debug, Clone, Copy)]
+pub struct PoolLimits {
+    /// Connections kept open, and opened at most
+    pub min_connections: usize,
+    pub max_connections: usize,
+    pub acquire_timeout: Duration,
+}
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        Self {
+            min_connections: 1,
+            max_connections: 10,
+            acquire_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Default)]
+struct PoolState {
+    pool: Option<Pool>,
+    degraded_until: Option<Instant>,
+}
+
+/// Lazily (re)connected MySQL pool
+///
+/// The server keeps running without a database: guests can still chat, and requests that need
+/// the database are answered with `UNAVAILABLE` until it comes back. Requests finding every
+/// connection taken wait `PoolLimits::acquire_timeout` at most and are answered with `BUSY`, so
+/// the sessions never hang on a saturated pool.
+pub struct Database {
+    url: String,
+    limits: PoolLimits,
+
+    /// Called every time a new pool is established, e.g. to set up the schema
+    on_connect: fn(&Pool),
+
+    state: Mutex<PoolState>,
+}
+
+impl Database {
+    pub fn new(url: &str, limits: PoolLimits, on_connect: fn(&Pool)) -> Self {
+        let db = Self {
+            url: url.to_owned(),
+            limits,
+            on_connect,
+            state: Mutex::new(PoolState::default()),
+        };
+        if let Err(e) = db.get_conn() {
+            println!(
+                "[!] Database is not available, running in degraded mode: {}",
+                e
+            );
+        }
+        db
+    }
+
+    /// Get a connection, retrying with exponential backoff
+    ///
+    /// The pool is only locked to be looked up, waiting for a free connection doesn't hold up the
+    /// requests on other connections.
+    pub fn get_conn(&self) -> Result<PooledConn, String> {
+        if self
+            .state()
+            .degraded_until
+            .is_some_and(|until| Instant::now() < until)
+        {
+            return Err(UNAVAILABLE.to_owned());
+        }
+
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=CONNECT_ATTEMPTS {
+            let e = match self
+                .pool()
+                .and_then(|pool| pool.try_get_conn(self.limits.acquire_timeout))
+            {
+                Ok(conn) => return Ok(conn),
+                // the database is fine, every connection is in use
+                Err(mysql::Error::DriverError(DriverError::Timeout)) => {
+                    println!(
+                        "[!] No database connection is free after {}ms, the pool is exhausted",
+                        self.limits.acquire_timeout.as_millis()
+                    );
+                    return Err(BUSY.to_owned());
+                }
+                Err(e) => e,
+            };
+            println!(
+                "[!] Database connection failed ({}/{}): {}",
+                attempt, CONNECT_ATTEMPTS, e
+            );
+            // the pool may be broken, build a new one on the next attempt
+            self.state().pool = None;
+            if attempt < CONNECT_ATTEMPTS {
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+        }
+
+        self.state().degraded_until = Some(Instant::now() + DEGRADED_PERIOD);
+        Err(UNAVAILABLE.to_owned())
+    }
+
+    /// Check that the database answers, a broken pool is dropped and built again on the next use
+    pub fn check_health(&self) -> Result<(), String> {
+        let mut conn = self.get_conn()?;
+        if conn.query_drop("SELECT 1").is_ok() {
+            return Ok(());
+        }
+        self.state().pool = None;
+        Err("the database doesn't answer".to_owned())
+    }
+
+    /// The pool, built if there's none
+    fn pool(&self) -> Result<Pool, mysql::Error> {
+        let mut state = self.state();
+        if let Some(pool) = &state.pool {
+            return Ok(pool.clone());
+        }
+
+        let constraints =
+            PoolConstraints::new(self.limits.min_connections, self.limits.max_connections)
+                .unwrap_or_default();
+        let opts = OptsBuilder::from_opts(Opts::from_url(&self.url)?)
+            .pool_opts(PoolOpts::default().with_constraints(constraints));
+        let pool = Pool::new(opts)?;
+        (self.on_connect)(&pool);
+        state.degraded_until = None;
+        state.pool = Some(pool.clone());
+        Ok(pool)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
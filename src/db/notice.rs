@@ -0,0 +1,30 @@
+use mysql::{prelude::*, *};
+
+use super::Database;
+
+/// Keep `msg` for the member `recipient` until their next login
+pub fn save(recipient: &str, msg: &str, db: &Database) -> Result<(), String> {
+    let mut conn = db.get_conn()?;
+    conn.exec_drop(
+        "INSERT INTO notice (recipient, msg) VALUES (:recipient, :msg)",
+        params! { "recipient" => recipient, "msg" => msg },
+    )
+    .map_err(|e| format!("Failed to save a notice for '{}': {}", recipient, e))
+}
+
+/// The notices kept for `recipient` in the order they were saved, they're deleted once taken
+pub fn take(recipient: &str, db: &Database) -> Result<Vec<String>, String> {
+    let mut conn = db.get_conn()?;
+    let notices = conn
+        .exec(
+            "SELECT msg FROM notice WHERE recipient = :recipient ORDER BY id",
+            params! { "recipient" => recipient },
+        )
+        .map_err(|e| format!("Failed to load the notices of '{}': {}", recipient, e))?;
+    conn.exec_drop(
+        "DELETE FROM notice WHERE recipient = :recipient",
+        params! { "recipient" => recipient },
+    )
+    .map_err(|e| format!("Failed to delete the notices of '{}': {}", recipient, e))?;
+    Ok(notices)
+}
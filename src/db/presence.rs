@@ -0,0 +1,34 @@
+use mysql::{prelude::*, *};
+
+use super::Database;
+use crate::packet::Presence;
+
+/// Presence of the member `id` as stored in the `presence` table, online if there's none
+pub fn load(id: &str, db: &Database) -> Result<Presence, String> {
+    let mut conn = db.get_conn()?;
+    let state: Option<String> = conn
+        .exec_first(
+            "SELECT state FROM presence WHERE id = :id",
+            params! { "id" => id },
+        )
+        .map_err(|e| format!("Failed to load the presence of '{}': {}", id, e))?;
+    Ok(state.and_then(|s| s.parse().ok()).unwrap_or_default())
+}
+
+pub fn save(id: &str, presence: Presence, db: &Database) -> Result<(), String> {
+    let mut conn = db.get_conn()?;
+    conn.exec_drop(
+        "REPLACE INTO presence (id, state) VALUES (:id, :state)",
+        params! { "id" => id, "state" => presence.as_str() },
+    )
+    .map_err(|e| format!("Failed to save the presence of '{}': {}", id, e))
+}
+
+pub fn delete(id: &str, db: &Database) -> Result<(), String> {
+    let mut conn = db.get_conn()?;
+    conn.exec_drop(
+        "DELETE FROM presence WHERE id = :id",
+        params! { "id" => id },
+    )
+    .map_err(|e| format!("Failed to delete the presence of '{}': {}", id, e))
+}
@@ -0,0 +1,37 @@
+use std::collections::BTreeMap;
+
+use mysql::{prelude::*, *};
+
+use super::Database;
+
+/// Latest message the member `id` has read in each channel, as stored in the `read_marker` table
+pub fn load(id: &str, db: &Database) -> Result<BTreeMap<String, u64>, String> {
+    let mut conn = db.get_conn()?;
+    let markers: Vec<(String, u64)> = conn
+        .exec(
+            "SELECT channel, seq FROM read_marker WHERE id = :id",
+            params! { "id" => id },
+        )
+        .map_err(|e| format!("Failed to load the read markers of '{}': {}", id, e))?;
+    Ok(markers.into_iter().collect())
+}
+
+/// Move the marker of `id` in `channel` to `seq`, a marker never goes back
+pub fn save(id: &str, channel: &str, seq: u64, db: &Database) -> Result<(), String> {
+    let mut conn = db.get_conn()?;
+    conn.exec_drop(
+        r"INSERT INTO read_marker (id, channel, seq) VALUES (:id, :channel, :seq)
+        ON DUPLICATE KEY UPDATE seq = GREATEST(seq, VALUES(seq))",
+        params! { "id" => id, "channel" => channel, "seq" => seq },
+    )
+    .map_err(|e| format!("Failed to save the read marker of '{}': {}", id, e))
+}
+
+pub fn delete(id: &str, db: &Database) -> Result<(), String> {
+    let mut conn = db.get_conn()?;
+    conn.exec_drop(
+        "DELETE FROM read_marker WHERE id = :id",
+        params! { "id" => id },
+    )
+    .map_err(|e| format!("Failed to delete the read markers of '{}': {}", id, e))
+}
@@ -0,0 +1,80 @@
+use mysql::{prelude::*, *};
+use serde::{Deserialize, Serialize};
+
+use super::Database;
+
+/// Message waiting for its time, as stored in the `scheduled` table
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledMessage {
+    pub id: u64,
+    pub owner: String,
+    pub channel: String,
+
+    /// Unix time in seconds the message is posted at
+    pub at: u64,
+    pub msg: String,
+}
+
+impl ScheduledMessage {
+    /// Store a new scheduled message, returns its id
+    pub fn insert(
+        owner: &str,
+        channel: &str,
+        at: u64,
+        msg: &str,
+        db: &Database,
+    ) -> Result<u64, String> {
+        let mut conn = db.get_conn()?;
+        conn.exec_drop(
+            "INSERT INTO scheduled (owner, channel, at, msg) VALUES (:owner, :channel, :at, :msg)",
+            params! {
+                "owner" => owner,
+                "channel" => channel,
+                "at" => at,
+                "msg" => msg,
+            },
+        )
+        .map_err(|e| format!("Failed to schedule a message: {}", e))?;
+        Ok(conn.last_insert_id())
+    }
+
+    pub fn delete(id: u64, db: &Database) -> Result<(), String> {
+        let mut conn = db.get_conn()?;
+        conn.exec_drop(
+            "DELETE FROM scheduled WHERE id = :id",
+            params! { "id" => id },
+        )
+        .map_err(|e| format!("Failed to delete the scheduled message {}: {}", id, e))
+    }
+
+    /// Delete every message `owner` has scheduled
+    pub fn delete_of(owner: &str, db: &Database) -> Result<(), String> {
+        let mut conn = db.get_conn()?;
+        conn.exec_drop(
+            "DELETE FROM scheduled WHERE owner = :owner",
+            params! { "owner" => owner },
+        )
+        .map_err(|e| {
+            format!(
+                "Failed to delete the scheduled messages of '{}': {}",
+                owner, e
+            )
+        })
+    }
+
+    /// Every message still waiting
+    pub fn load_all(db: &Database) -> Result<Vec<Self>, String> {
+        let mut conn = db.get_conn()?;
+        conn.query_map(
+            "SELECT id, owner, channel, at, msg FROM scheduled",
+            |(id, owner, channel, at, msg)| Self {
+                id,
+                owner,
+                channel,
+                at,
+                msg,
+            },
+        )
+        .map_err(|e| format!("Failed to load scheduled messages: {}", e))
+    }
+}
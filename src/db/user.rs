@@ -1,36 +1,352 @@
+use std::str::FromStr;
+
 use mysql::{prelude::*, *};
 use serde::{Deserialize, Serialize};
 
+use super::Database;
+use crate::crypto::auth::{Challenge, Verifier};
+use crate::packet::{ErrorCode, PacketError};
+
+/// MySQL error code of a duplicate key
+const ER_DUP_ENTRY: u16 = 1062;
+
+/// Server-wide role of an account, the permissions of each are decided by the server
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    Moderator,
+    #[default]
+    User,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Moderator => "moderator",
+            Role::User => "user",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "moderator" => Ok(Role::Moderator),
+            "user" => Ok(Role::User),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Decision of the admins on a registration, accounts can only log in once approved
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Approval {
+    Pending,
+    #[default]
+    Approved,
+    Rejected,
+}
+
+impl Approval {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Approval::Pending => "pending",
+            Approval::Approved => "approved",
+            Approval::Rejected => "rejected",
+        }
+    }
+
+    /// Refusal of a login of an account in this state, `None` if it may log in
+    fn refusal(&self) -> Option<PacketError> {
+        let reason = match self {
+            Approval::Approved => return None,
+            Approval::Pending => "the account is waiting for the approval of an admin",
+            Approval::Rejected => "the registration of the account was rejected",
+        };
+        Some(PacketError::new(ErrorCode::PermissionDenied, reason))
+    }
+}
+
+impl FromStr for Approval {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Approval::Pending),
+            "approved" => Ok(Approval::Approved),
+            "rejected" => Ok(Approval::Rejected),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct User {
     pub id: String,
-    pub password: String,
+
+    /// Made by the client, the password itself never leaves it
+    pub verifier: Verifier,
     pub bio: Option<String>,
     pub location: Option<String>,
 }
 
 impl User {
-    // check if self is valid
-    pub fn insert(&self, pool: Pool) -> Result<(), String> {
+    /// Store the account, `approval` is `Pending` if the server approves registrations
+    pub fn insert(&self, approval: Approval, db: &Database) -> Result<(), PacketError> {
         if self.id.starts_with("guest_") || self.id.starts_with("root") {
-            return Err("Reserved id format".to_owned());
-        } else if self.password.len() < 4 {
-            return Err("too short password! (password >= 4)".to_owned());
+            return Err(PacketError::new(
+                ErrorCode::InvalidArgument,
+                "Reserved id format",
+            ));
         }
 
-        let mut conn = pool.get_conn().unwrap();
+        let mut conn = db
+            .get_conn()
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
         match conn.exec_drop(
-            "INSERT INTO user (id, password, bio, location) VALUES (:id, :password, :bio, :location)",
+            r"INSERT INTO user (id, password, salt, iterations, stored_key, bio, location, approval)
+            VALUES (:id, '', :salt, :iterations, :stored_key, :bio, :location, :approval)",
             params! {
                 "id" => &self.id,
-                "password" => &self.password,
+                "approval" => approval.as_str(),
+                "salt" => &self.verifier.salt,
+                "iterations" => self.verifier.iterations,
+                "stored_key" => &self.verifier.stored_key,
                 "bio" => &self.bio.as_ref().unwrap_or(&"NULL".to_owned()),
                 "location" => &self.location.as_ref().unwrap_or(&"NULL".to_owned()),
             },
         ) {
             Ok(_) => Ok(()),
-            Err(e) => Err(format!("Failed to insert a new user: {}", e)),
+            // errors reported by the server itself, e.g. duplicate id
+            Err(Error::MySqlError(e)) => Err(PacketError::new(
+                if e.code == ER_DUP_ENTRY {
+                    ErrorCode::AlreadyExists
+                } else {
+                    ErrorCode::Internal
+                },
+                format!("Failed to insert a new user: {}", e),
+            )),
+            Err(_) => Err(PacketError::new(ErrorCode::Unavailable, super::UNAVAILABLE)),
+        }
+    }
+
+    /// Verifier of the member `id`, `None` if there's no such member
+    pub fn verifier_of(id: &str, db: &Database) -> Result<Option<Verifier>, PacketError> {
+        let mut conn = db
+            .get_conn()
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        let row: Option<(String, u32, String)> = conn
+            .exec_first(
+                "SELECT salt, iterations, stored_key FROM user WHERE id = :id",
+                params! { "id" => id },
+            )
+            .map_err(|e| PacketError::new(ErrorCode::Internal, e.to_string()))?;
+        Ok(row.map(|(salt, iterations, stored_key)| Verifier {
+            salt,
+            iterations,
+            stored_key,
+        }))
+    }
+
+    /// Role of the member `id`, `None` if there's no such member
+    pub fn role_of(id: &str, db: &Database) -> Result<Option<Role>, PacketError> {
+        let mut conn = db
+            .get_conn()
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        let role: Option<String> = conn
+            .exec_first(
+                "SELECT role FROM user WHERE id = :id",
+                params! { "id" => id },
+            )
+            .map_err(|e| PacketError::new(ErrorCode::Internal, e.to_string()))?;
+        Ok(role.map(|r| r.parse().unwrap_or_default()))
+    }
+
+    pub fn set_role(id: &str, role: Role, db: &Database) -> Result<(), PacketError> {
+        Self::role_of(id, db)?.ok_or_else(|| Self::not_found(id))?;
+        let mut conn = db
+            .get_conn()
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        conn.exec_drop(
+            "UPDATE user SET role = :role WHERE id = :id",
+            params! { "id" => id, "role" => role.as_str() },
+        )
+        .map_err(|e| PacketError::new(ErrorCode::Internal, e.to_string()))
+    }
+
+    /// Ban or unban the member `id`, banned members can't log in and admins can't be banned
+    pub fn set_banned(id: &str, banned: bool, db: &Database) -> Result<(), PacketError> {
+        match Self::role_of(id, db)? {
+            None => return Err(Self::not_found(id)),
+            Some(Role::Admin) => {
+                return Err(PacketError::new(
+                    ErrorCode::PermissionDenied,
+                    "admins can't be banned",
+                ))
+            }
+            Some(_) => (),
         }
+        let mut conn = db
+            .get_conn()
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        conn.exec_drop(
+            "UPDATE user SET banned = :banned WHERE id = :id",
+            params! { "id" => id, "banned" => banned },
+        )
+        .map_err(|e| PacketError::new(ErrorCode::Internal, e.to_string()))
+    }
+
+    /// Approve or reject the pending registration of `id`
+    pub fn decide(id: &str, approval: Approval, db: &Database) -> Result<(), PacketError> {
+        let mut conn = db
+            .get_conn()
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        let current: Option<String> = conn
+            .exec_first(
+                "SELECT approval FROM user WHERE id = :id",
+                params! { "id" => id },
+            )
+            .map_err(|e| PacketError::new(ErrorCode::Internal, e.to_string()))?;
+        let current = current.ok_or_else(|| Self::not_found(id))?;
+        if current.parse() != Ok(Approval::Pending) {
+            return Err(PacketError::new(
+                ErrorCode::InvalidArgument,
+                format!("the registration of '{}' is {} already", id, current),
+            ));
+        }
+        conn.exec_drop(
+            "UPDATE user SET approval = :approval WHERE id = :id",
+            params! { "id" => id, "approval" => approval.as_str() },
+        )
+        .map_err(|e| PacketError::new(ErrorCode::Internal, e.to_string()))
+    }
+
+    /// Ids of the accounts waiting for approval, in alphabetical order
+    pub fn pending(db: &Database) -> Result<Vec<String>, PacketError> {
+        let mut conn = db
+            .get_conn()
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        conn.query("SELECT id FROM user WHERE approval = 'pending' ORDER BY id")
+            .map_err(|e| PacketError::new(ErrorCode::Internal, e.to_string()))
+    }
+
+    /// Profile of the member `id` as stored, without the password
+    pub fn profile(id: &str, db: &Database) -> Result<serde_json::Value, PacketError> {
+        let mut conn = db
+            .get_conn()
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        type Row = (String, Option<String>, Option<String>, String, bool);
+        let row: Option<Row> = conn
+            .exec_first(
+                "SELECT id, bio, location, role, banned FROM user WHERE id = :id",
+                params! { "id" => id },
+            )
+            .map_err(|e| PacketError::new(ErrorCode::Internal, e.to_string()))?;
+        let (id, bio, location, role, banned) = row.ok_or_else(|| Self::not_found(id))?;
+        Ok(serde_json::json!({
+            "id": id,
+            "bio": bio,
+            "location": location,
+            "role": role,
+            "banned": banned,
+        }))
+    }
+
+    pub fn delete(id: &str, db: &Database) -> Result<(), PacketError> {
+        let mut conn = db
+            .get_conn()
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        conn.exec_drop("DELETE FROM user WHERE id = :id", params! { "id" => id })
+            .map_err(|e| PacketError::new(ErrorCode::Internal, e.to_string()))
+    }
+
+    fn not_found(id: &str) -> PacketError {
+        PacketError::new(ErrorCode::NotFound, format!("no such member: '{}'", id))
+    }
+}
+
+/// Account as kept in a backup, the verifier stands in for the password the server never saw
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountRecord {
+    pub id: String,
+    pub verifier: Verifier,
+    pub bio: Option<String>,
+    pub location: Option<String>,
+    pub role: Role,
+    pub banned: bool,
+    pub approval: Approval,
+}
+
+impl AccountRecord {
+    /// Every stored account, by id
+    pub fn load_all(db: &Database) -> Result<Vec<Self>, String> {
+        let mut conn = db.get_conn()?;
+        type Row = (
+            String,
+            String,
+            u32,
+            String,
+            Option<String>,
+            Option<String>,
+            String,
+            bool,
+            String,
+        );
+        let rows: Vec<Row> = conn
+            .query(
+                r"SELECT id, salt, iterations, stored_key, bio, location, role, banned, approval
+                FROM user ORDER BY id",
+            )
+            .map_err(|e| format!("Failed to load the accounts: {}", e))?;
+        Ok(rows
+            .into_iter()
+            .map(
+                |(id, salt, iterations, stored_key, bio, location, role, banned, approval)| Self {
+                    id,
+                    verifier: Verifier {
+                        salt,
+                        iterations,
+                        stored_key,
+                    },
+                    bio,
+                    location,
+                    role: role.parse().unwrap_or_default(),
+                    banned,
+                    approval: approval.parse().unwrap_or_default(),
+                },
+            )
+            .collect())
+    }
+
+    /// Store the account, false if there's one with the id already, it's left as it is
+    pub fn restore(&self, db: &Database) -> Result<bool, String> {
+        let mut conn = db.get_conn()?;
+        conn.exec_drop(
+            r"INSERT IGNORE INTO user (
+                id, password, salt, iterations, stored_key, bio, location, role, banned, approval
+            ) VALUES (
+                :id, '', :salt, :iterations, :stored_key, :bio, :location, :role, :banned, :approval
+            )",
+            params! {
+                "id" => &self.id,
+                "salt" => &self.verifier.salt,
+                "iterations" => self.verifier.iterations,
+                "stored_key" => &self.verifier.stored_key,
+                "bio" => &self.bio,
+                "location" => &self.location,
+                "role" => self.role.as_str(),
+                "banned" => self.banned,
+                "approval" => self.approval.as_str(),
+            },
+        )
+        .map_err(|e| format!("Failed to restore the account '{}': {}", self.id, e))?;
+        Ok(conn.affected_rows() > 0)
     }
 }
 
@@ -38,7 +354,10 @@ impl User {
 pub struct Login {
     pub guest: bool,
     pub id: Option<String>,
-    pub password: Option<String>,
+
+    /// Answer to the challenge asked for the id, see `crypto::auth`
+    #[serde(default)]
+    pub proof: Option<String>,
 }
 
 impl Login {
@@ -46,22 +365,85 @@ impl Login {
         Self {
             guest: true,
             id: None,
-            password: None,
+            proof: None,
         }
     }
 
-    pub fn login(&self, pool: Pool) -> Result<String, String> {
-        if let Ok(mut conn) = pool.get_conn() {
-            match conn.query_first::<String, _>(format!(
-                "SELECT id FROM user WHERE id='{}' AND password='{}'",
-                self.id.as_ref().unwrap(),
-                self.password.as_ref().unwrap(),
-            )) {
-                Ok(Some(s)) => Ok(s),
-                _ => Err("Wrong ID or Password".to_owned()),
-            }
-        } else {
-            Err("Failed to get sql connection".to_owned())
+    /// Check the proof answering to `challenge`, returns the id and the role of the member
+    ///
+    /// Without a challenge asked for the id, there's nothing the proof could answer to.
+    pub fn login(
+        &self,
+        challenge: Option<&Challenge>,
+        db: &Database,
+    ) -> Result<(String, Role), PacketError> {
+        let (Some(id), Some(proof)) = (&self.id, &self.proof) else {
+            return Err(PacketError::new(
+                ErrorCode::InvalidArgument,
+                "a login needs the id and a proof from a client speaking protocol version 4",
+            ));
+        };
+        let Some(challenge) = challenge else {
+            return Err(PacketError::new(
+                ErrorCode::InvalidArgument,
+                "no challenge was asked for the login",
+            ));
+        };
+        let wrong = || PacketError::new(ErrorCode::WrongCredentials, "Wrong ID or Password");
+
+        let mut conn = db
+            .get_conn()
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        type Row = (String, bool, String, String, u32, String);
+        let row: Option<Row> = conn
+            .exec_first(
+                r"SELECT role, banned, approval, salt, iterations, stored_key FROM user
+                WHERE id = :id",
+                params! { "id" => id },
+            )
+            .map_err(|_| PacketError::new(ErrorCode::Unavailable, super::UNAVAILABLE))?;
+        let (role, banned, approval, salt, iterations, stored_key) = row.ok_or_else(wrong)?;
+        let verifier = Verifier {
+            salt,
+            iterations,
+            stored_key,
+        };
+        if !verifier.check(id, challenge, proof) {
+            return Err(wrong());
+        }
+        if let Some(refusal) = approval.parse::<Approval>().unwrap_or_default().refusal() {
+            return Err(refusal);
+        }
+        if banned {
+            return Err(PacketError::new(
+                ErrorCode::PermissionDenied,
+                "the account is banned",
+            ));
+        }
+        Ok((id.clone(), role.parse().unwrap_or_default()))
+    }
+
+    /// Log in as `id` whose session token was checked already, the account must still exist and
+    /// not be banned since
+    pub fn resume(id: &str, db: &Database) -> Result<(String, Role), PacketError> {
+        let mut conn = db
+            .get_conn()
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        let row: Option<(String, bool)> = conn
+            .exec_first(
+                "SELECT role, banned FROM user WHERE id = :id",
+                params! { "id" => id },
+            )
+            .map_err(|_| PacketError::new(ErrorCode::Unavailable, super::UNAVAILABLE))?;
+        let (role, banned) = row.ok_or_else(|| {
+            PacketError::new(ErrorCode::WrongCredentials, "the account no longer exists")
+        })?;
+        if banned {
+            return Err(PacketError::new(
+                ErrorCode::PermissionDenied,
+                "the account is banned",
+            ));
         }
+        Ok((id.to_owned(), role.parse().unwrap_or_default()))
     }
 }
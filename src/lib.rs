@@ -0,0 +1,11 @@
+//! rschat, a chat server and its terminal client
+//!
+//! The binary runs either of them, the library is there for what runs the code without it, e.g.
+//! the fuzz targets of `fuzz/`.
+
+pub mod cli;
+pub mod client;
+pub mod crypto;
+pub mod db;
+pub mod packet;
+pub mod server;
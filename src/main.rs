@@ -1,31 +1,38 @@
-mod client;
-mod crypto;
-mod db;
-mod packet;
-mod server;
+use rschat::{cli, client, server};
 
-const DEFAULT_PORT_NUM: &str = "8080";
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // skip the program name
+    let cli = match cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
 
-fn usage() {
-    println!("Usage: ./rschat 'target'");
-    println!("   available targets: 'client', 'server'");
-}
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // running target: `client` or `server`
-    let target = std::env::args().nth(1);
-
-    // port number
-    let port = std::env::args()
-        .nth(2)
-        .unwrap_or(DEFAULT_PORT_NUM.to_owned());
+    // the server may limit the threads of the runtime
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let cli::Cli::Server(opts) = &cli {
+        if let Some(threads) = server::worker_threads(opts) {
+            runtime.worker_threads(threads);
+        }
+    }
 
     // run the target
-    match target.as_deref() {
-        Some("client") => client::run_client(port.as_str()).await?,
-        Some("server") => server::run_server(port.as_str()).await?,
-        _ => usage(),
-    }
-    Ok(())
+    runtime.build()?.block_on(async {
+        match cli {
+            cli::Cli::Client(opts) => client::run_client(&opts).await?,
+            cli::Cli::Server(opts) => server::run_server(&opts).await?,
+            cli::Cli::VerifyTranscript(files) => match server::transcript::verify(&files) {
+                Ok(count) => println!("{} lines, the chain is intact", count),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            },
+            cli::Cli::Print(text) => println!("{}", text),
+        }
+        Ok(())
+    })
 }
@@ -0,0 +1,67 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// What went wrong, for clients to react on without matching the message
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidArgument,
+    NotFound,
+    AlreadyExists,
+    PermissionDenied,
+    WrongCredentials,
+    /// The server is at its capacity, e.g. too many guests
+    Full,
+    /// Temporary failure, the request can be retried later
+    Unavailable,
+    Internal,
+    /// Errors of older peers that only carried a message, and codes this build doesn't know
+    #[serde(other)]
+    Unknown,
+}
+
+/// Error of a request, carried in the `result` of the response packets
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "CompatError")]
+pub struct PacketError {
+    pub code: ErrorCode,
+
+    /// Human readable description
+    pub message: String,
+}
+
+impl PacketError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Both the structured form and the plain string older peers send
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CompatError {
+    Structured { code: ErrorCode, message: String },
+    Legacy(String),
+}
+
+impl From<CompatError> for PacketError {
+    fn from(e: CompatError) -> Self {
+        match e {
+            CompatError::Structured { code, message } => Self { code, message },
+            CompatError::Legacy(message) => Self {
+                code: ErrorCode::Unknown,
+                message,
+            },
+        }
+    }
+}
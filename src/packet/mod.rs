@@ -1,11 +1,58 @@
-use std::str::FromStr;
+use std::{str::FromStr, sync::Arc};
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::db;
 
+pub mod error;
+pub mod stream;
+pub use error::{ErrorCode, PacketError};
+
+/// Version of the packet format spoken by this build
+///
+/// Version 2 carries `PacketError` in the results of the responses instead of plain strings.
+/// Version 3 receives large responses in parts, see `stream`.
+/// Version 4 logs in by answering a `ChallengeReq`, password hashes are no longer sent.
+/// Version 5 resumes sessions with the token of an earlier login, see `ResumeSession`.
+/// Version 6 receives bursts of the messages of a channel in a `MessageBatch`.
+pub const PROTOCOL_VERSION: u32 = 6;
+
+/// Oldest protocol version of a client receiving `MessageBatch`
+pub const BATCHING_VERSION: u32 = 6;
+
+/// Capability of a client asking for less traffic: shorter histories on join and no nicknames
+/// in the messages
+pub const LITE_CAPABILITY: &str = "lite";
+
+/// Oldest protocol version this build can still talk to, older peers can't parse `PacketError`
+/// and members can't log in without answering a challenge
+pub const MIN_PROTOCOL_VERSION: u32 = 4;
+
+/// Maximum size of a frame accepted from the server, larger frames are discarded unread
+pub const MAX_FRAME_SIZE: u32 = 4 * 1024 * 1024;
+
+/// Name and version of this build, exchanged in the handshake
+pub fn software_version() -> String {
+    format!("rschat {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Check whether the peer speaking `version` is compatible with this build
+pub fn check_protocol_version(version: u32) -> Result<(), String> {
+    if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        Ok(())
+    } else {
+        Err(format!(
+            "incompatible protocol version {} (supported: {}..={})",
+            version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ))
+    }
+}
+
 pub trait AsJson {
+    /// Value of the "type" tag of the packet
+    const PACKET_TYPE: &'static str;
+
     fn as_json_string(&self) -> String
     where
         Self: Serialize,
@@ -29,17 +76,220 @@ macro_rules! packet_declarations {
             #[serde(tag = "type")]
             $vis struct $name $body
 
-            impl AsJson for $name {}
+            impl AsJson for $name {
+                const PACKET_TYPE: &'static str = stringify!($name);
+            }
         )*
     }
 }
 
 packet_declarations! {
 
+// first packet of a connection, sent by the client
+pub struct Hello {
+    pub version: u32,
+    pub software: String,
+
+    /// Optional behaviors the client asks for, e.g. `LITE_CAPABILITY`, unknown ones are ignored
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+}
+
+pub struct HelloRes {
+    pub version: u32,
+    pub software: String,
+    pub result: Result<(), String>,
+
+    /// Capabilities of the `Hello` the server took up
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+
+    /// Limits the session runs into, older servers don't tell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<ServerLimits>,
+
+    /// Message of the day as of the handshake
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motd: Option<String>,
+}
+
 pub struct Message {
     pub id: String,
     pub msg: String,
     pub is_system: bool,
+
+    /// Recipient of a direct message, `None` if the message is for the whole channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+
+    /// Sequence number assigned by the server, unique within the channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+
+    /// Taken back by the sender, the text is gone and only moderators can see what it was
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retracted: bool,
+
+    /// Nickname of the sender in the channel, set by the server, `id` stays the one to moderate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+
+    /// Sequence number of the message of the channel this one replies to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<u64>,
+
+    /// Sequence number of the message the replies started from, set by the server for replies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<u64>,
+}
+
+// toggle the reaction `emoji` of the current user on the message `seq` of the current channel
+pub struct ReactionReq {
+    pub seq: u64,
+    pub emoji: String,
+}
+
+// pin or unpin the message `seq` of the current channel, only for moderators
+pub struct PinReq {
+    pub seq: u64,
+    pub pinned: bool,
+}
+
+// invitation of `to` to the channel, `from` is filled in by the server
+pub struct Invite {
+    #[serde(default)]
+    pub from: String,
+    pub to: String,
+    pub channel_name: String,
+}
+
+// post `msg` to the current channel at the unix time `at`, only for members
+pub struct ScheduleReq {
+    pub at: u64,
+    pub msg: String,
+}
+
+pub struct ScheduleRes {
+    pub at: u64,
+    pub result: Result<u64 /* id */, PacketError>,
+}
+
+// change the presence of the member, also sent right after the login if it isn't online
+pub struct PresenceReq {
+    pub presence: Presence,
+}
+
+pub struct PresenceRes {
+    pub result: Result<Presence, PacketError>,
+}
+
+// message of another channel mentioning the recipient
+pub struct Mention {
+    pub channel_name: String,
+    pub message: Message,
+}
+
+// a packet or a part of it was rejected for being larger than the limit
+pub struct LimitExceeded {
+    pub what: String,
+    pub size: usize,
+    pub limit: usize,
+}
+
+// a packet of the client was dropped, it isn't one or isn't expected at this point
+pub struct InvalidPacket {
+    pub error: PacketError,
+}
+
+// the server is at its capacity, the connection is closed right after
+pub struct ServerBusy {
+    pub reason: String,
+}
+
+// recent messages of a channel sent on join, live messages of the channel follow it
+pub struct JoinSnapshot {
+    pub channel_name: String,
+    pub messages: Vec<Message>,
+
+    /// Reactions of the messages, keyed by sequence number
+    pub reactions: std::collections::BTreeMap<u64, std::collections::BTreeMap<String, usize>>,
+
+    /// Pinned messages of the channel, in the order of their sequence numbers
+    #[serde(default)]
+    pub pins: Vec<Message>,
+}
+
+// messages of a channel broadcasted in a burst, shown one by one as if sent apart
+pub struct MessageBatch {
+    pub messages: Vec<Message>,
+}
+
+// first part of a streamed response, the response itself without the list at `pointer`
+pub struct PartStart {
+    pub stream: u64,
+
+    /// Type of the streamed response
+    pub packet: String,
+    pub header: Value,
+
+    /// JSON pointer of the list in the response, e.g. `/messages`
+    pub pointer: String,
+
+    /// Items of the list
+    pub total: usize,
+}
+
+// items of the list of a streamed response, in order
+pub struct PartChunk {
+    pub stream: u64,
+    pub items: Vec<Value>,
+}
+
+// the streamed response is complete
+pub struct PartEnd {
+    pub stream: u64,
+}
+
+// aggregated reactions of a message, broadcasted whenever they change
+pub struct ReactionUpdate {
+    pub channel_name: String,
+    pub seq: u64,
+    pub reactions: std::collections::BTreeMap<String, usize>,
+}
+
+// retract the own message `seq` of the current channel, shortly after sending it
+pub struct RetractReq {
+    pub seq: u64,
+}
+
+// the message `seq` of the channel was retracted by its sender
+pub struct RetractUpdate {
+    pub channel_name: String,
+    pub seq: u64,
+}
+
+// the member has read the channel up to the message `seq`, kept for their next login
+pub struct ReadMarkerReq {
+    pub channel_name: String,
+    pub seq: u64,
+}
+
+// where the member stopped reading each channel, sent on login
+pub struct ReadMarkers {
+    /// Sequence number of the latest message read, by channel
+    pub markers: std::collections::BTreeMap<String, u64>,
+
+    /// Messages after the marker still in the history, by channel
+    #[serde(default)]
+    pub unread: std::collections::BTreeMap<String, usize>,
+}
+
+// a message of the channel was pinned or unpinned by the moderator `by`
+pub struct PinUpdate {
+    pub channel_name: String,
+    pub by: String,
+    pub message: Message,
+    pub pinned: bool,
 }
 
 pub struct RegisterReq {
@@ -47,7 +297,21 @@ pub struct RegisterReq {
 }
 
 pub struct RegisterRes {
-    pub result: Result<(), String>,
+    pub result: Result<(), PacketError>,
+
+    /// The account can't log in until an admin approves it
+    #[serde(default)]
+    pub pending: bool,
+}
+
+// first step of a login, `nonce` is the part of the challenge chosen by the client
+pub struct ChallengeReq {
+    pub id: String,
+    pub nonce: String,
+}
+
+pub struct ChallengeRes {
+    pub result: Result<crate::crypto::auth::Challenge, PacketError>,
 }
 
 pub struct LoginReq {
@@ -55,40 +319,446 @@ pub struct LoginReq {
 }
 
 pub struct LoginRes {
-    pub result: Result<String /* id */, String>,
+    pub result: Result<String /* id */, PacketError>,
+
+    /// Server-wide role of the account, guests are plain users
+    #[serde(default)]
+    pub role: db::user::Role,
+
+    /// Token resuming the session later, on any server of the deployment, for members only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// Channel the member was in when they left, if it's still there to rejoin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_channel: Option<String>,
+}
+
+// log in with the token of an earlier login right after the handshake and rejoin `channel`
+pub struct ResumeSession {
+    pub token: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+}
+
+pub struct ResumeRes {
+    pub result: Result<String /* id */, PacketError>,
+
+    #[serde(default)]
+    pub role: db::user::Role,
+
+    /// Fresh token replacing the resumed one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+// register a new account and become it right away, only for guests
+pub struct UpgradeReq {
+    pub user: db::user::User,
+}
+
+pub struct UpgradeRes {
+    pub result: Result<String /* id */, PacketError>,
 }
 
 pub struct FetchReq {
-    pub item: String,
+    #[serde(flatten)]
+    pub item: FetchItem,
 }
 
+// the item is the one of the request, arguments included
 pub struct FetchRes {
-    pub item: String,
-    pub result: Result<serde_json::Value, String>,
+    #[serde(flatten)]
+    pub item: FetchItem,
+    pub result: Result<serde_json::Value, PacketError>,
 }
 
 pub struct GotoReq {
     pub channel_name: String,
+
+    /// Hashed password of a channel asking for one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
 }
 
 pub struct GotoRes {
+    pub result: Result<String, PacketError>,
+
+    /// Topic of the channel joined, if it has one
+    #[serde(default)]
+    pub topic: Option<String>,
+}
+
+pub struct ChannelReq {
+    pub action: ChannelAction,
+    pub channel_name: String,
+
+    /// Hashed password asked of everyone joining the channel created, only for `Create`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+pub struct ChannelRes {
     pub result: Result<String, String>,
 }
 
+// server-wide operation, the server checks the role of the requester
+pub struct AdminReq {
+    pub action: AdminAction,
+}
+
+pub struct AdminRes {
+    pub result: Result<String, PacketError>,
+}
+
+// export or erase the data kept about the member, confirmed with a proof answering to the
+// challenge asked right before
+pub struct AccountReq {
+    pub action: AccountAction,
+    pub proof: String,
+}
+
+// the bundle of the data for an export, a summary for an erasure
+pub struct AccountRes {
+    pub action: AccountAction,
+    pub result: Result<Value, PacketError>,
+}
+
+// settings of a channel, sent on join and whenever they change
+pub struct ChannelInfo {
+    pub channel_name: String,
+    pub announce_only: bool,
+
+    /// Users allowed to post in an announcement channel besides root
+    pub posters: Vec<String>,
+
+    #[serde(default)]
+    pub history: HistoryPolicy,
+
+    #[serde(default)]
+    pub topic: Option<String>,
+}
+
+// notify that the current channel was closed and the client has been moved to `moved_to`
+pub struct ChannelClosed {
+    pub channel_name: String,
+    pub reason: String,
+    pub moved_to: String,
+
+    /// The only user who has to leave, e.g. banned, everyone if `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+// message of the day, sent right before the response to the first login of a session
+pub struct Motd {
+    pub text: String,
+}
+
 // notify that a new client has connected
 pub struct Connected {}
 
 // notify that a client has disconnected
 pub struct Exit {}
 
+// latency probe, the server echoes `timestamp` back in a `Pong` right away
+pub struct Ping {
+    pub timestamp: u64,
+}
+
+pub struct Pong {
+    pub timestamp: u64,
+}
+
+}
+
+/// Limits of the server announced in the handshake
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerLimits {
+    /// Bytes of a packet
+    pub max_packet_size: usize,
+
+    /// Bytes of the text of a message
+    pub max_message_size: usize,
+
+    /// Characters of a message, in the channels filtering the messages
+    pub max_message_len: usize,
+
+    /// Messages a session sends per minute, 0 for no limit
+    pub messages_per_minute: usize,
+
+    /// Seconds a message can be retracted for after sending it, 0 if it can't
+    pub retract_window_secs: u64,
+}
+
+/// Management operations on a channel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ChannelAction {
+    Create,
+    /// Create a system channel, only for admins
+    CreateSystem,
+    Delete,
+    Archive,
+
+    /// Minimum interval between messages of a user in seconds, 0 disables slow mode
+    SetSlowMode(u64),
+    SetAnnounceOnly(bool),
+    SetHistory(HistoryPolicy),
+    /// Keep the channel even if nobody has been in it for a while, only for admins
+    SetGcExempt(bool),
+    AddModerator(String),
+    /// Nickname of the requester in the channel, `None` to go by the id again
+    SetNickname(Option<String>),
+    /// Topic told to everyone joining, `None` clears it
+    SetTopic(Option<String>),
+    /// Keep the user out of the channel, moving them out if they're in
+    Ban(String),
+    Unban(String),
+    /// Keep the user from posting for the seconds, until unmuted if `None`
+    Mute(String, Option<u64>),
+    Unmute(String),
+}
+
+/// What a `FetchReq` asks for, along with its arguments
+///
+/// Sent within the packet, e.g. `{"type":"FetchReq","item":"list","offset":20}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "item", rename_all = "snake_case")]
+pub enum FetchItem {
+    /// Page of the users of the current channel
+    #[serde(rename = "list")]
+    UserList {
+        /// Index of the first entry of the page
+        #[serde(default)]
+        offset: usize,
+
+        /// Maximum number of entries of the page, the server picks one if `None`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+
+        /// Only entries containing this substring
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filter: Option<String>,
+    },
+    /// Activity of every channel
+    Stats,
+    /// Channels that can be joined, with their users and topics
+    Channels,
+    /// Pinned messages of the current channel
+    Pins,
+    /// Messages of the thread `seq` is part of, in the current channel
+    Thread { seq: u64 },
+    /// Originals of the messages retracted in the current channel, for moderators
+    Retracted,
+    /// Users banned and muted in the current channel, for moderators
+    Moderation,
+    /// Traffic of the connections and the accounts, for admins
+    Traffic,
+    /// Packets the server failed to deliver, for admins
+    DeadLetters,
+    /// Backup of the state of the server, for admins
+    Dump,
+}
+
+/// What the server keeps of the messages of a channel, chosen by its owner
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryPolicy {
+    /// Recent messages are kept for the users joining later
+    #[default]
+    Persist,
+
+    /// Messages are never stored, only the users in the channel at the time receive them
+    Ephemeral,
+
+    /// Messages are kept for the given number of seconds
+    Retain(u64),
+}
+
+impl HistoryPolicy {
+    /// Description shown along with the channel, `None` for the default policy
+    pub fn describe(&self) -> Option<String> {
+        match self {
+            HistoryPolicy::Persist => None,
+            HistoryPolicy::Ephemeral => Some("ephemeral".to_owned()),
+            HistoryPolicy::Retain(secs) => Some(format!("kept for {}", compact_duration(*secs))),
+        }
+    }
+}
+
+/// `secs` in the largest unit it's a whole number of, e.g. `2h` or `90s`
+pub fn compact_duration(secs: u64) -> String {
+    let (n, unit) = [(86400, "d"), (3600, "h"), (60, "m")]
+        .into_iter()
+        .find(|(unit, _)| secs.is_multiple_of(*unit))
+        .map_or((secs, "s"), |(unit, name)| (secs / unit, name));
+    format!("{}{}", n, unit)
+}
+
+/// Requests of members about their own data
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountAction {
+    /// Everything the server keeps about the member
+    Export,
+    /// Delete the account, its messages stay but can't be traced back to it
+    Erase,
+}
+
+impl AccountAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountAction::Export => "export",
+            AccountAction::Erase => "erase",
+        }
+    }
+}
+
+/// Server-wide operations, see the permissions of the server for who may perform them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AdminAction {
+    /// Disconnect the user
+    Kick(String),
+    /// Disconnect the member and keep them from logging in again
+    Ban(String),
+    Unban(String),
+    /// Post a notice to every channel
+    Broadcast(String),
+    SetRole(String, db::user::Role),
+    /// Let the pending account log in, or turn its registration down
+    Approve(String),
+    Reject(String),
+    /// List the accounts waiting for approval
+    Pending,
+    /// Message of the day sent after login from now on, `None` stops sending one
+    SetMotd(Option<String>),
+}
+
+/// Visibility and availability of a member, chosen by the member
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    #[default]
+    Online,
+
+    /// Do not disturb: direct messages don't notify, senders get an automatic reply
+    Dnd,
+
+    /// Hidden from user lists and join notices, reading and writing still work
+    Invisible,
+
+    /// Away from the terminal, set by the client after a while without input and not stored
+    Away,
+}
+
+impl Presence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Presence::Online => "online",
+            Presence::Dnd => "dnd",
+            Presence::Invisible => "invisible",
+            Presence::Away => "away",
+        }
+    }
+}
+
+impl FromStr for Presence {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "online" => Ok(Presence::Online),
+            "dnd" => Ok(Presence::Dnd),
+            "invisible" => Ok(Presence::Invisible),
+            "away" => Ok(Presence::Away),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Hello {
+    pub fn new() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            software: software_version(),
+            capabilities: Vec::new(),
+        }
+    }
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HelloRes {
+    /// Response to the `Hello` of a client speaking `version`
+    pub fn new(version: u32) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            software: software_version(),
+            result: check_protocol_version(version),
+            capabilities: Vec::new(),
+            limits: None,
+            motd: None,
+        }
+    }
+}
+
+impl ChannelInfo {
+    /// True if `id` having `role` can't post in the channel
+    ///
+    /// Admins and moderators moderate every channel, so they can always post.
+    pub fn is_read_only_for(&self, id: &str, role: db::user::Role) -> bool {
+        self.announce_only && role == db::user::Role::User && !self.posters.iter().any(|p| p == id)
+    }
 }
 
 impl Message {
+    /// Words of the message that could be the id of a mentioned user, `@` is optional
+    pub fn mentioned_ids(&self) -> std::collections::BTreeSet<&str> {
+        self.msg
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .filter(|word| !word.is_empty() && *word != self.id)
+            .collect()
+    }
+
+    /// The message as sent to the clients asking for less traffic, without the nickname
+    pub fn lite(&self) -> Self {
+        Self {
+            display_name: None,
+            ..self.clone()
+        }
+    }
+
     pub fn connection(id: &str) -> Self {
         Self {
             id: id.to_owned(),
             msg: format!("'{}' has joined", id),
             is_system: true,
+            to: None,
+            seq: None,
+            retracted: false,
+            display_name: None,
+            reply_to: None,
+            thread: None,
+        }
+    }
+
+    /// System message addressed to a single client
+    pub fn system_notice(msg: &str) -> Self {
+        Self {
+            id: "System".to_owned(),
+            msg: msg.to_owned(),
+            is_system: true,
+            to: None,
+            seq: None,
+            retracted: false,
+            display_name: None,
+            reply_to: None,
+            thread: None,
         }
     }
 
@@ -97,31 +767,148 @@ impl Message {
             id: id.to_owned(),
             msg: format!("'{}' has left", id),
             is_system: true,
+            to: None,
+            seq: None,
+            retracted: false,
+            display_name: None,
+            reply_to: None,
+            thread: None,
         }
     }
 }
 
+/// Message of a channel along with its JSON, serialized once for every subscriber of the channel
+#[derive(Clone, Debug)]
+pub struct Broadcast {
+    pub message: Message,
+    pub frame: Arc<[u8]>,
+}
+
+impl Broadcast {
+    pub fn new(message: Message) -> Self {
+        let frame = message.as_json_bytes().into();
+        Self { message, frame }
+    }
+
+    /// Frame of the message for the clients asking for less traffic, the shared one if there's
+    /// nothing to leave out
+    pub fn lite_frame(&self) -> Arc<[u8]> {
+        if self.message.display_name.is_none() {
+            Arc::clone(&self.frame)
+        } else {
+            self.message.lite().as_json_bytes().into()
+        }
+    }
+}
+
+impl JoinSnapshot {
+    /// The latest `n` messages of the snapshot along with their reactions, for the clients
+    /// asking for less traffic
+    pub fn lite(mut self, n: usize) -> Self {
+        let skip = self.messages.len().saturating_sub(n);
+        self.messages = self.messages[skip..].iter().map(Message::lite).collect();
+        self.pins = self.pins.iter().map(Message::lite).collect();
+        match self.messages.iter().find_map(|msg| msg.seq) {
+            Some(first) => self.reactions.retain(|seq, _| *seq >= first),
+            None => self.reactions.clear(),
+        }
+        self
+    }
+}
+
+impl MessageBatch {
+    /// Frame of the batch of the messages already serialized as `frames`, they aren't serialized
+    /// again for every subscriber
+    pub fn join(frames: &[Arc<[u8]>]) -> Arc<[u8]> {
+        let mut bytes = br#"{"type":"MessageBatch","messages":["#.to_vec();
+        for (idx, frame) in frames.iter().enumerate() {
+            if idx > 0 {
+                bytes.push(b',');
+            }
+            bytes.extend_from_slice(frame);
+        }
+        bytes.extend_from_slice(b"]}");
+        bytes.into()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum PacketType {
+    Hello(Hello),
+    HelloRes(HelloRes),
     RegisterReq(RegisterReq),
     RegisterRes(RegisterRes),
+    ChallengeReq(ChallengeReq),
+    ChallengeRes(ChallengeRes),
     LoginReq(LoginReq),
     LoginRes(LoginRes),
+    ResumeSession(ResumeSession),
+    ResumeRes(ResumeRes),
+    UpgradeReq(UpgradeReq),
+    UpgradeRes(UpgradeRes),
     FetchReq(FetchReq),
     FetchRes(FetchRes),
     GotoReq(GotoReq),
     GotoRes(GotoRes),
+    ChannelReq(ChannelReq),
+    ChannelRes(ChannelRes),
+    AdminReq(AdminReq),
+    AdminRes(AdminRes),
+    AccountReq(AccountReq),
+    AccountRes(AccountRes),
+    ChannelInfo(ChannelInfo),
+    Invite(Invite),
+    Mention(Mention),
+    ScheduleReq(ScheduleReq),
+    PresenceReq(PresenceReq),
+    PresenceRes(PresenceRes),
+    ScheduleRes(ScheduleRes),
+    LimitExceeded(LimitExceeded),
+    InvalidPacket(InvalidPacket),
+    ReactionReq(ReactionReq),
+    ReactionUpdate(ReactionUpdate),
+    PinReq(PinReq),
+    PinUpdate(PinUpdate),
+    RetractReq(RetractReq),
+    RetractUpdate(RetractUpdate),
+    ReadMarkerReq(ReadMarkerReq),
+    ReadMarkers(ReadMarkers),
+    ChannelClosed(ChannelClosed),
+    Motd(Motd),
     Connected(Connected),
     Message(Message),
+
+    /// Never on the wire, channels broadcast their messages with this
+    Broadcast(Broadcast),
     Exit(Exit),
+    Ping(Ping),
+    Pong(Pong),
 }
 
+/// Why a text isn't a packet
 #[derive(Debug, PartialEq, Eq)]
-pub struct ParsePacketTypeError;
+pub enum ParsePacketTypeError {
+    /// Not a JSON object with a `type`
+    Malformed,
+    UnknownType(String),
+
+    /// Fields of a packet of the type are missing or of the wrong type
+    InvalidFields(String, String),
+}
 
 impl From<()> for ParsePacketTypeError {
     fn from(_: ()) -> Self {
-        ParsePacketTypeError {}
+        ParsePacketTypeError::Malformed
+    }
+}
+
+impl std::fmt::Display for ParsePacketTypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParsePacketTypeError::Malformed => f.write_str("not a JSON object with a type"),
+            ParsePacketTypeError::UnknownType(name) => write!(f, "unknown packet type '{}'", name),
+            ParsePacketTypeError::InvalidFields(name, e) => write!(f, "invalid {}: {}", name, e),
+        }
     }
 }
 
@@ -130,34 +917,657 @@ impl FromStr for PacketType {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let Ok(json_value): Result<Value, _> = serde_json::from_str(s) else {
-            return Err(ParsePacketTypeError);
+            return Err(ParsePacketTypeError::Malformed);
         };
 
         macro_rules! packet_from_str {
             ($packet:ident) => {{
-                let r: $packet = serde_json::from_value(json_value).unwrap();
+                let r: $packet = serde_json::from_value(json_value).map_err(|e| {
+                    ParsePacketTypeError::InvalidFields(
+                        stringify!($packet).to_owned(),
+                        e.to_string(),
+                    )
+                })?;
                 Ok(PacketType::$packet(r))
             }};
         }
 
         let packet_type = json_value.as_object().ok_or(())?.get("type").ok_or(())?;
         match packet_type.as_str() {
+            Some("Hello") => packet_from_str!(Hello),
+            Some("HelloRes") => packet_from_str!(HelloRes),
             Some("RegisterReq") => packet_from_str!(RegisterReq),
             Some("RegisterRes") => packet_from_str!(RegisterRes),
+            Some("ChallengeReq") => packet_from_str!(ChallengeReq),
+            Some("ChallengeRes") => packet_from_str!(ChallengeRes),
             Some("LoginReq") => packet_from_str!(LoginReq),
             Some("LoginRes") => packet_from_str!(LoginRes),
+            Some("ResumeSession") => packet_from_str!(ResumeSession),
+            Some("ResumeRes") => packet_from_str!(ResumeRes),
+            Some("UpgradeReq") => packet_from_str!(UpgradeReq),
+            Some("UpgradeRes") => packet_from_str!(UpgradeRes),
             Some("FetchReq") => packet_from_str!(FetchReq),
             Some("FetchRes") => packet_from_str!(FetchRes),
             Some("GotoReq") => packet_from_str!(GotoReq),
             Some("GotoRes") => packet_from_str!(GotoRes),
+            Some("ChannelReq") => packet_from_str!(ChannelReq),
+            Some("ChannelRes") => packet_from_str!(ChannelRes),
+            Some("AdminReq") => packet_from_str!(AdminReq),
+            Some("AdminRes") => packet_from_str!(AdminRes),
+            Some("AccountReq") => packet_from_str!(AccountReq),
+            Some("AccountRes") => packet_from_str!(AccountRes),
+            Some("ChannelInfo") => packet_from_str!(ChannelInfo),
+            Some("ChannelClosed") => packet_from_str!(ChannelClosed),
+            Some("Motd") => packet_from_str!(Motd),
+            Some("Invite") => packet_from_str!(Invite),
+            Some("Mention") => packet_from_str!(Mention),
+            Some("ScheduleReq") => packet_from_str!(ScheduleReq),
+            Some("PresenceReq") => packet_from_str!(PresenceReq),
+            Some("PresenceRes") => packet_from_str!(PresenceRes),
+            Some("ScheduleRes") => packet_from_str!(ScheduleRes),
+            Some("LimitExceeded") => packet_from_str!(LimitExceeded),
+            Some("InvalidPacket") => packet_from_str!(InvalidPacket),
+            Some("ReactionReq") => packet_from_str!(ReactionReq),
+            Some("ReactionUpdate") => packet_from_str!(ReactionUpdate),
+            Some("PinReq") => packet_from_str!(PinReq),
+            Some("PinUpdate") => packet_from_str!(PinUpdate),
+            Some("RetractReq") => packet_from_str!(RetractReq),
+            Some("RetractUpdate") => packet_from_str!(RetractUpdate),
+            Some("ReadMarkerReq") => packet_from_str!(ReadMarkerReq),
+            Some("ReadMarkers") => packet_from_str!(ReadMarkers),
             Some("Message") => packet_from_str!(Message),
             Some("Connected") => Ok(PacketType::Connected(Connected {})),
             Some("Exit") => Ok(PacketType::Exit(Exit {})),
-            Some(unknown_type) => {
-                println!("[!] Unknown packet type: {}", unknown_type);
-                Err(ParsePacketTypeError)
+            Some("Ping") => packet_from_str!(Ping),
+            Some("Pong") => packet_from_str!(Pong),
+            Some(unknown_type) => Err(ParsePacketTypeError::UnknownType(unknown_type.to_owned())),
+            None => Err(ParsePacketTypeError::Malformed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{option, prelude::*};
+
+    use super::*;
+
+    #[test]
+    fn fetch_items_are_tagged_within_the_packet() {
+        let req = FetchReq {
+            item: FetchItem::UserList {
+                offset: 20,
+                limit: None,
+                filter: Some("al".to_owned()),
+            },
+        };
+        assert_eq!(
+            req.as_json_string(),
+            r#"{"type":"FetchReq","item":"list","offset":20,"filter":"al"}"#
+        );
+        let Ok(PacketType::FetchReq(parsed)) = PacketType::from_str(&req.as_json_string()) else {
+            panic!("FetchReq not parsed");
+        };
+        assert_eq!(parsed.item, req.item);
+
+        let res = r#"{"type":"FetchRes","item":"thread","seq":3,"result":{"Ok":{}}}"#;
+        let Ok(PacketType::FetchRes(res)) = PacketType::from_str(res) else {
+            panic!("FetchRes not parsed");
+        };
+        assert_eq!(res.item, FetchItem::Thread { seq: 3 });
+
+        // unknown items and missing arguments are refused along with the packet
+        assert!(PacketType::from_str(r#"{"type":"FetchReq","item":"whatever"}"#).is_err());
+        assert!(PacketType::from_str(r#"{"type":"FetchReq","item":"thread"}"#).is_err());
+    }
+
+    #[test]
+    fn parse_errors_tell_what_is_wrong() {
+        assert_eq!(
+            PacketType::from_str("{]").unwrap_err(),
+            ParsePacketTypeError::Malformed
+        );
+        assert_eq!(
+            PacketType::from_str(r#"{"type":42}"#).unwrap_err(),
+            ParsePacketTypeError::Malformed
+        );
+        assert_eq!(
+            PacketType::from_str(r#"{"type":"Bogus"}"#).unwrap_err(),
+            ParsePacketTypeError::UnknownType("Bogus".to_owned())
+        );
+        let e = PacketType::from_str(r#"{"type":"Ping","timestamp":"soon"}"#).unwrap_err();
+        assert!(matches!(&e, ParsePacketTypeError::InvalidFields(name, _) if name == "Ping"));
+        assert!(e.to_string().starts_with("invalid Ping: "));
+    }
+
+    #[test]
+    fn lite_snapshots_keep_the_latest_messages() {
+        let messages = (0..5)
+            .map(|seq| Message {
+                seq: Some(seq),
+                display_name: Some("Al".to_owned()),
+                ..Message::connection("alice")
+            })
+            .collect();
+        let snapshot = JoinSnapshot {
+            channel_name: "public".to_owned(),
+            messages,
+            reactions: [(1, Default::default()), (4, Default::default())]
+                .into_iter()
+                .collect(),
+            pins: Vec::new(),
+        }
+        .lite(2);
+        let kept: Vec<(Option<u64>, Option<String>)> = snapshot
+            .messages
+            .into_iter()
+            .map(|msg| (msg.seq, msg.display_name))
+            .collect();
+        assert_eq!(kept, vec![(Some(3), None), (Some(4), None)]);
+        assert_eq!(snapshot.reactions.keys().collect::<Vec<_>>(), vec![&4]);
+    }
+
+    /// Text heavy on what JSON has to escape
+    fn text() -> impl Strategy<Value = String> {
+        const ESCAPED: [char; 9] = ['"', '\\', '/', '{', '}', '\n', '\t', '\u{0}', '🦀'];
+        let ch = prop_oneof![prop::sample::select(&ESCAPED[..]), any::<char>()];
+        prop::collection::vec(ch, 0..24).prop_map(String::from_iter)
+    }
+
+    fn names() -> impl Strategy<Value = Vec<String>> {
+        prop::collection::vec(text(), 0..3)
+    }
+
+    /// JSON of the free-form parts of the packets, without floats as they don't round-trip exactly
+    fn json() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            any::<u64>().prop_map(Value::from),
+            text().prop_map(Value::from),
+        ];
+        leaf.prop_recursive(3, 16, 4, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..4).prop_map(Value::from),
+                prop::collection::btree_map(text(), inner, 0..4)
+                    .prop_map(|map| Value::Object(map.into_iter().collect())),
+            ]
+        })
+    }
+
+    fn error() -> impl Strategy<Value = PacketError> {
+        let code = prop::sample::select(vec![
+            ErrorCode::InvalidArgument,
+            ErrorCode::NotFound,
+            ErrorCode::AlreadyExists,
+            ErrorCode::PermissionDenied,
+            ErrorCode::WrongCredentials,
+            ErrorCode::Full,
+            ErrorCode::Unavailable,
+            ErrorCode::Internal,
+            ErrorCode::Unknown,
+        ]);
+        (code, text()).prop_map(|(code, message)| PacketError::new(code, message))
+    }
+
+    fn result<T: std::fmt::Debug>(
+        ok: impl Strategy<Value = T>,
+    ) -> impl Strategy<Value = Result<T, PacketError>> {
+        prop_oneof![ok.prop_map(Ok), error().prop_map(Err)]
+    }
+
+    fn role() -> impl Strategy<Value = db::user::Role> {
+        use db::user::Role;
+        prop::sample::select(vec![Role::Admin, Role::Moderator, Role::User])
+    }
+
+    fn presence() -> impl Strategy<Value = Presence> {
+        prop::sample::select(vec![
+            Presence::Online,
+            Presence::Dnd,
+            Presence::Invisible,
+            Presence::Away,
+        ])
+    }
+
+    fn history_policy() -> impl Strategy<Value = HistoryPolicy> {
+        prop_oneof![
+            Just(HistoryPolicy::Persist),
+            Just(HistoryPolicy::Ephemeral),
+            any::<u64>().prop_map(HistoryPolicy::Retain),
+        ]
+    }
+
+    fn account_action() -> impl Strategy<Value = AccountAction> {
+        prop::sample::select(vec![AccountAction::Export, AccountAction::Erase])
+    }
+
+    fn user() -> impl Strategy<Value = db::user::User> {
+        let verifier = (text(), any::<u32>(), text()).prop_map(|(salt, iterations, stored_key)| {
+            crate::crypto::auth::Verifier {
+                salt,
+                iterations,
+                stored_key,
+            }
+        });
+        (text(), verifier, option::of(text()), option::of(text())).prop_map(
+            |(id, verifier, bio, location)| db::user::User {
+                id,
+                verifier,
+                bio,
+                location,
+            },
+        )
+    }
+
+    fn message() -> impl Strategy<Value = Message> {
+        (
+            (text(), text(), any::<bool>(), option::of(text())),
+            (option::of(any::<u64>()), any::<bool>(), option::of(text())),
+            (option::of(any::<u64>()), option::of(any::<u64>())),
+        )
+            .prop_map(
+                |((id, msg, is_system, to), (seq, retracted, display_name), (reply_to, thread))| {
+                    Message {
+                        id,
+                        msg,
+                        is_system,
+                        to,
+                        seq,
+                        retracted,
+                        display_name,
+                        reply_to,
+                        thread,
+                    }
+                },
+            )
+    }
+
+    fn fetch_item() -> impl Strategy<Value = FetchItem> {
+        prop_oneof![
+            (
+                any::<usize>(),
+                option::of(any::<usize>()),
+                option::of(text())
+            )
+                .prop_map(|(offset, limit, filter)| FetchItem::UserList {
+                    offset,
+                    limit,
+                    filter,
+                }),
+            Just(FetchItem::Stats),
+            Just(FetchItem::Channels),
+            Just(FetchItem::Pins),
+            any::<u64>().prop_map(|seq| FetchItem::Thread { seq }),
+            Just(FetchItem::Retracted),
+            Just(FetchItem::Moderation),
+            Just(FetchItem::Traffic),
+            Just(FetchItem::DeadLetters),
+            Just(FetchItem::Dump),
+        ]
+    }
+
+    fn channel_action() -> impl Strategy<Value = ChannelAction> {
+        prop_oneof![
+            Just(ChannelAction::Create),
+            Just(ChannelAction::CreateSystem),
+            Just(ChannelAction::Delete),
+            Just(ChannelAction::Archive),
+            any::<u64>().prop_map(ChannelAction::SetSlowMode),
+            any::<bool>().prop_map(ChannelAction::SetAnnounceOnly),
+            history_policy().prop_map(ChannelAction::SetHistory),
+            any::<bool>().prop_map(ChannelAction::SetGcExempt),
+            text().prop_map(ChannelAction::AddModerator),
+            option::of(text()).prop_map(ChannelAction::SetNickname),
+            option::of(text()).prop_map(ChannelAction::SetTopic),
+            text().prop_map(ChannelAction::Ban),
+            text().prop_map(ChannelAction::Unban),
+            (text(), option::of(any::<u64>())).prop_map(|(id, secs)| ChannelAction::Mute(id, secs)),
+            text().prop_map(ChannelAction::Unmute),
+        ]
+    }
+
+    fn admin_action() -> impl Strategy<Value = AdminAction> {
+        prop_oneof![
+            text().prop_map(AdminAction::Kick),
+            text().prop_map(AdminAction::Ban),
+            text().prop_map(AdminAction::Unban),
+            text().prop_map(AdminAction::Broadcast),
+            (text(), role()).prop_map(|(id, role)| AdminAction::SetRole(id, role)),
+            text().prop_map(AdminAction::Approve),
+            text().prop_map(AdminAction::Reject),
+            Just(AdminAction::Pending),
+            option::of(text()).prop_map(AdminAction::SetMotd),
+        ]
+    }
+
+    fn reactions() -> impl Strategy<Value = std::collections::BTreeMap<String, usize>> {
+        prop::collection::btree_map(text(), any::<usize>(), 0..3)
+    }
+
+    /// Every packet `PacketType::from_str` reads
+    fn packet() -> impl Strategy<Value = PacketType> {
+        let limits = any::<(usize, usize, usize, usize, u64)>().prop_map(
+            |(max_packet_size, max_message_size, max_message_len, messages_per_minute, secs)| {
+                ServerLimits {
+                    max_packet_size,
+                    max_message_size,
+                    max_message_len,
+                    messages_per_minute,
+                    retract_window_secs: secs,
+                }
+            },
+        );
+        let challenge = (text(), any::<u32>(), text()).prop_map(|(salt, iterations, nonce)| {
+            crate::crypto::auth::Challenge {
+                salt,
+                iterations,
+                nonce,
+            }
+        });
+        let login = (any::<bool>(), option::of(text()), option::of(text()))
+            .prop_map(|(guest, id, proof)| db::user::Login { guest, id, proof });
+        let string_result = prop_oneof![text().prop_map(Ok), text().prop_map(Err)];
+
+        prop_oneof![
+            (any::<u32>(), text(), names()).prop_map(|(version, software, capabilities)| {
+                PacketType::Hello(Hello {
+                    version,
+                    software,
+                    capabilities,
+                })
+            }),
+            (
+                (any::<u32>(), text()),
+                prop_oneof![Just(Ok(())), text().prop_map(Err)],
+                (names(), option::of(limits), option::of(text())),
+            )
+                .prop_map(
+                    |((version, software), result, (capabilities, limits, motd))| {
+                        PacketType::HelloRes(HelloRes {
+                            version,
+                            software,
+                            result,
+                            capabilities,
+                            limits,
+                            motd,
+                        })
+                    }
+                ),
+            user().prop_map(|user| PacketType::RegisterReq(RegisterReq { user })),
+            (result(Just(())), any::<bool>()).prop_map(|(result, pending)| {
+                PacketType::RegisterRes(RegisterRes { result, pending })
+            }),
+            (text(), text())
+                .prop_map(|(id, nonce)| { PacketType::ChallengeReq(ChallengeReq { id, nonce }) }),
+            result(challenge).prop_map(|result| PacketType::ChallengeRes(ChallengeRes { result })),
+            login.prop_map(|login_info| PacketType::LoginReq(LoginReq { login_info })),
+            (
+                result(text()),
+                role(),
+                option::of(text()),
+                option::of(text())
+            )
+                .prop_map(|(result, role, token, last_channel)| {
+                    PacketType::LoginRes(LoginRes {
+                        result,
+                        role,
+                        token,
+                        last_channel,
+                    })
+                }),
+            (text(), option::of(text())).prop_map(|(token, channel)| {
+                PacketType::ResumeSession(ResumeSession { token, channel })
+            }),
+            (result(text()), role(), option::of(text())).prop_map(|(result, role, token)| {
+                PacketType::ResumeRes(ResumeRes {
+                    result,
+                    role,
+                    token,
+                })
+            }),
+            user().prop_map(|user| PacketType::UpgradeReq(UpgradeReq { user })),
+            result(text()).prop_map(|result| PacketType::UpgradeRes(UpgradeRes { result })),
+            fetch_item().prop_map(|item| PacketType::FetchReq(FetchReq { item })),
+            (fetch_item(), result(json()))
+                .prop_map(|(item, result)| PacketType::FetchRes(FetchRes { item, result })),
+            (text(), option::of(text())).prop_map(|(channel_name, password)| {
+                PacketType::GotoReq(GotoReq {
+                    channel_name,
+                    password,
+                })
+            }),
+            (result(text()), option::of(text()))
+                .prop_map(|(result, topic)| PacketType::GotoRes(GotoRes { result, topic })),
+            (channel_action(), text(), option::of(text())).prop_map(
+                |(action, channel_name, password)| {
+                    PacketType::ChannelReq(ChannelReq {
+                        action,
+                        channel_name,
+                        password,
+                    })
+                }
+            ),
+            string_result.prop_map(|result| PacketType::ChannelRes(ChannelRes { result })),
+            admin_action().prop_map(|action| PacketType::AdminReq(AdminReq { action })),
+            result(text()).prop_map(|result| PacketType::AdminRes(AdminRes { result })),
+            (account_action(), text())
+                .prop_map(|(action, proof)| PacketType::AccountReq(AccountReq { action, proof })),
+            (account_action(), result(json())).prop_map(|(action, result)| {
+                PacketType::AccountRes(AccountRes { action, result })
+            }),
+            (
+                (text(), any::<bool>(), names()),
+                (history_policy(), option::of(text()))
+            )
+                .prop_map(
+                    |((channel_name, announce_only, posters), (history, topic))| {
+                        PacketType::ChannelInfo(ChannelInfo {
+                            channel_name,
+                            announce_only,
+                            posters,
+                            history,
+                            topic,
+                        })
+                    }
+                ),
+            (text(), text(), text()).prop_map(|(from, to, channel_name)| {
+                PacketType::Invite(Invite {
+                    from,
+                    to,
+                    channel_name,
+                })
+            }),
+            (text(), message()).prop_map(|(channel_name, message)| {
+                PacketType::Mention(Mention {
+                    channel_name,
+                    message,
+                })
+            }),
+            (any::<u64>(), text())
+                .prop_map(|(at, msg)| PacketType::ScheduleReq(ScheduleReq { at, msg })),
+            presence().prop_map(|presence| PacketType::PresenceReq(PresenceReq { presence })),
+            result(presence()).prop_map(|result| PacketType::PresenceRes(PresenceRes { result })),
+            (any::<u64>(), result(any::<u64>()))
+                .prop_map(|(at, result)| PacketType::ScheduleRes(ScheduleRes { at, result })),
+            (text(), any::<usize>(), any::<usize>()).prop_map(|(what, size, limit)| {
+                PacketType::LimitExceeded(LimitExceeded { what, size, limit })
+            }),
+            error().prop_map(|error| PacketType::InvalidPacket(InvalidPacket { error })),
+            (any::<u64>(), text())
+                .prop_map(|(seq, emoji)| PacketType::ReactionReq(ReactionReq { seq, emoji })),
+            (text(), any::<u64>(), reactions()).prop_map(|(channel_name, seq, reactions)| {
+                PacketType::ReactionUpdate(ReactionUpdate {
+                    channel_name,
+                    seq,
+                    reactions,
+                })
+            }),
+            (any::<u64>(), any::<bool>())
+                .prop_map(|(seq, pinned)| PacketType::PinReq(PinReq { seq, pinned })),
+            (text(), text(), message(), any::<bool>()).prop_map(
+                |(channel_name, by, message, pinned)| {
+                    PacketType::PinUpdate(PinUpdate {
+                        channel_name,
+                        by,
+                        message,
+                        pinned,
+                    })
+                }
+            ),
+            any::<u64>().prop_map(|seq| PacketType::RetractReq(RetractReq { seq })),
+            (text(), any::<u64>()).prop_map(|(channel_name, seq)| {
+                PacketType::RetractUpdate(RetractUpdate { channel_name, seq })
+            }),
+            (text(), any::<u64>()).prop_map(|(channel_name, seq)| {
+                PacketType::ReadMarkerReq(ReadMarkerReq { channel_name, seq })
+            }),
+            (
+                prop::collection::btree_map(text(), any::<u64>(), 0..3),
+                prop::collection::btree_map(text(), any::<usize>(), 0..3),
+            )
+                .prop_map(|(markers, unread)| {
+                    PacketType::ReadMarkers(ReadMarkers { markers, unread })
+                }),
+            (text(), text(), text(), option::of(text())).prop_map(
+                |(channel_name, reason, moved_to, user)| {
+                    PacketType::ChannelClosed(ChannelClosed {
+                        channel_name,
+                        reason,
+                        moved_to,
+                        user,
+                    })
+                }
+            ),
+            text().prop_map(|text| PacketType::Motd(Motd { text })),
+            Just(PacketType::Connected(Connected {})),
+            message().prop_map(PacketType::Message),
+            Just(PacketType::Exit(Exit {})),
+            any::<u64>().prop_map(|timestamp| PacketType::Ping(Ping { timestamp })),
+            any::<u64>().prop_map(|timestamp| PacketType::Pong(Pong { timestamp })),
+        ]
+    }
+
+    /// JSON of the packet as it's written to the wire
+    fn to_json(packet: &PacketType) -> String {
+        match packet {
+            PacketType::Hello(p) => p.as_json_string(),
+            PacketType::HelloRes(p) => p.as_json_string(),
+            PacketType::RegisterReq(p) => p.as_json_string(),
+            PacketType::RegisterRes(p) => p.as_json_string(),
+            PacketType::ChallengeReq(p) => p.as_json_string(),
+            PacketType::ChallengeRes(p) => p.as_json_string(),
+            PacketType::LoginReq(p) => p.as_json_string(),
+            PacketType::LoginRes(p) => p.as_json_string(),
+            PacketType::ResumeSession(p) => p.as_json_string(),
+            PacketType::ResumeRes(p) => p.as_json_string(),
+            PacketType::UpgradeReq(p) => p.as_json_string(),
+            PacketType::UpgradeRes(p) => p.as_json_string(),
+            PacketType::FetchReq(p) => p.as_json_string(),
+            PacketType::FetchRes(p) => p.as_json_string(),
+            PacketType::GotoReq(p) => p.as_json_string(),
+            PacketType::GotoRes(p) => p.as_json_string(),
+            PacketType::ChannelReq(p) => p.as_json_string(),
+            PacketType::ChannelRes(p) => p.as_json_string(),
+            PacketType::AdminReq(p) => p.as_json_string(),
+            PacketType::AdminRes(p) => p.as_json_string(),
+            PacketType::AccountReq(p) => p.as_json_string(),
+            PacketType::AccountRes(p) => p.as_json_string(),
+            PacketType::ChannelInfo(p) => p.as_json_string(),
+            PacketType::Invite(p) => p.as_json_string(),
+            PacketType::Mention(p) => p.as_json_string(),
+            PacketType::ScheduleReq(p) => p.as_json_string(),
+            PacketType::PresenceReq(p) => p.as_json_string(),
+            PacketType::PresenceRes(p) => p.as_json_string(),
+            PacketType::ScheduleRes(p) => p.as_json_string(),
+            PacketType::LimitExceeded(p) => p.as_json_string(),
+            PacketType::InvalidPacket(p) => p.as_json_string(),
+            PacketType::ReactionReq(p) => p.as_json_string(),
+            PacketType::ReactionUpdate(p) => p.as_json_string(),
+            PacketType::PinReq(p) => p.as_json_string(),
+            PacketType::PinUpdate(p) => p.as_json_string(),
+            PacketType::RetractReq(p) => p.as_json_string(),
+            PacketType::RetractUpdate(p) => p.as_json_string(),
+            PacketType::ReadMarkerReq(p) => p.as_json_string(),
+            PacketType::ReadMarkers(p) => p.as_json_string(),
+            PacketType::ChannelClosed(p) => p.as_json_string(),
+            PacketType::Motd(p) => p.as_json_string(),
+            PacketType::Connected(p) => p.as_json_string(),
+            PacketType::Message(p) => p.as_json_string(),
+            PacketType::Broadcast(p) => p.message.as_json_string(),
+            PacketType::Exit(p) => p.as_json_string(),
+            PacketType::Ping(p) => p.as_json_string(),
+            PacketType::Pong(p) => p.as_json_string(),
+        }
+    }
+
+    /// JSON of `packet` parsed as its own type, for the packets read apart from `PacketType`
+    fn reserialized<P: AsJson + Serialize + serde::de::DeserializeOwned>(packet: &P) -> String {
+        let json = packet.as_json_string();
+        let parsed: P =
+            serde_json::from_str(&json).unwrap_or_else(|e| panic!("{} not parsed: {}", json, e));
+        parsed.as_json_string()
+    }
+
+    proptest! {
+        #[test]
+        fn packets_survive_a_round_trip(packet in packet()) {
+            let json = to_json(&packet);
+            let parsed = PacketType::from_str(&json)
+                .unwrap_or_else(|e| panic!("{} not parsed: {}", json, e));
+            prop_assert_eq!(to_json(&parsed), json);
+        }
+
+        #[test]
+        fn joins_and_streams_survive_a_round_trip(
+            channel_name in text(),
+            messages in prop::collection::vec(message(), 0..4),
+            pins in prop::collection::vec(message(), 0..2),
+            reactions in prop::collection::btree_map(any::<u64>(), reactions(), 0..3),
+            (stream, header, pointer, total) in (any::<u64>(), json(), text(), any::<usize>()),
+            items in prop::collection::vec(json(), 0..4),
+        ) {
+            let snapshot = JoinSnapshot { channel_name, messages: messages.clone(), reactions, pins };
+            prop_assert_eq!(reserialized(&snapshot), snapshot.as_json_string());
+
+            // the batch joined from the frames of its messages is the one serialized whole
+            let batch = MessageBatch { messages };
+            prop_assert_eq!(reserialized(&batch), batch.as_json_string());
+            let frames: Vec<Arc<[u8]>> =
+                batch.messages.iter().map(|msg| msg.as_json_bytes().into()).collect();
+            prop_assert_eq!(&*MessageBatch::join(&frames), &batch.as_json_bytes()[..]);
+
+            let packet = JoinSnapshot::PACKET_TYPE.to_owned();
+            let start = PartStart { stream, packet, header, pointer, total };
+            prop_assert_eq!(reserialized(&start), start.as_json_string());
+            let chunk = PartChunk { stream, items };
+            prop_assert_eq!(reserialized(&chunk), chunk.as_json_string());
+            let end = PartEnd { stream };
+            prop_assert_eq!(reserialized(&end), end.as_json_string());
+        }
+
+        #[test]
+        fn mangled_packets_are_refused_without_panicking(
+            packet in packet(),
+            mangling in 0..4,
+            at in any::<prop::sample::Index>(),
+            junk in any::<[u8; 4]>(),
+        ) {
+            let mut bytes = to_json(&packet).into_bytes();
+            let at = at.index(bytes.len());
+            match mangling {
+                0 => bytes.truncate(at),
+                1 => bytes[at] = junk[0],
+                2 => {
+                    bytes.splice(at..at, junk);
+                }
+                _ => {
+                    bytes.remove(at);
+                }
             }
-            None => Err(ParsePacketTypeError),
+            // the result doesn't matter, only that there is one
+            let _ = PacketType::from_str(&String::from_utf8_lossy(&bytes));
         }
     }
 }
@@ -0,0 +1,104 @@
+//! Streaming of large responses
+//!
+//! A response carrying a long list is sent in parts: `PartStart` holds the response without the
+//! list, the items follow in `PartChunk`s and `PartEnd` closes the stream, all with the same
+//! `stream` id. Each part is serialized on its own, so neither side holds the whole response as a
+//! single JSON text. The receiver puts the items back at `pointer` of the response, or renders
+//! them as they come.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{AsJson, PartChunk, PartEnd, PartStart};
+
+/// Oldest protocol version of a peer that understands streamed responses
+pub const STREAMING_VERSION: u32 = 3;
+
+/// Items sent per chunk
+pub const CHUNK_ITEMS: usize = 64;
+
+/// Id of the next stream, unique for as long as the server runs
+static NEXT_STREAM: AtomicU64 = AtomicU64::new(1);
+
+/// `PartChunk` serialized from borrowed items
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "PartChunk")]
+struct ChunkRef<'a, T> {
+    stream: u64,
+    items: &'a [T],
+}
+
+/// Frames streaming the response `header` of the packet type `packet`, whose list at `pointer`
+/// is `items`
+///
+/// `header` is the response with the list left empty or out. Chunks are serialized as they're
+/// taken from the iterator.
+pub fn frames<'a, T: Serialize>(
+    packet: &str,
+    header: Value,
+    pointer: &str,
+    items: &'a [T],
+) -> impl Iterator<Item = Vec<u8>> + 'a {
+    let stream = NEXT_STREAM.fetch_add(1, Ordering::Relaxed);
+    let start = PartStart {
+        stream,
+        packet: packet.to_owned(),
+        header,
+        pointer: pointer.to_owned(),
+        total: items.len(),
+    }
+    .as_json_bytes();
+    std::iter::once(start)
+        .chain(
+            items
+                .chunks(CHUNK_ITEMS)
+                .map(move |items| serde_json::to_vec(&ChunkRef { stream, items }).unwrap()),
+        )
+        .chain(std::iter::once(PartEnd { stream }.as_json_bytes()))
+}
+
+/// A streamed response being received
+pub struct Partial {
+    pub start: PartStart,
+    pub items: Vec<Value>,
+}
+
+impl Partial {
+    pub fn new(start: PartStart) -> Self {
+        Self {
+            items: Vec::with_capacity(start.total.min(CHUNK_ITEMS * 16)),
+            start,
+        }
+    }
+
+    /// Add the items of `chunk`, false if it belongs to another stream
+    pub fn push(&mut self, chunk: PartChunk) -> bool {
+        if chunk.stream != self.start.stream {
+            return false;
+        }
+        self.items.extend(chunk.items);
+        true
+    }
+
+    /// The whole response, the items put back at the pointer
+    pub fn assemble(self) -> Value {
+        let mut packet = self.start.header;
+        match packet.pointer_mut(&self.start.pointer) {
+            Some(list) => *list = Value::Array(self.items),
+            None => {
+                // the list was left out, it's the last segment of the pointer
+                let (parent, key) = self
+                    .start
+                    .pointer
+                    .rsplit_once('/')
+                    .unwrap_or(("", self.start.pointer.as_str()));
+                if let Some(Value::Object(parent)) = packet.pointer_mut(parent) {
+                    parent.insert(key.to_owned(), Value::Array(self.items));
+                }
+            }
+        }
+        packet
+    }
+}
@@ -0,0 +1,163 @@
+//! Requests of members about the data the server keeps about them
+//!
+//! Both requests are confirmed with a proof of knowing the password of the account, answering to
+//! the challenge asked right before, and recorded in the audit log whatever their outcome.
+
+use std::sync::Arc;
+
+use super::ServerState;
+use crate::crypto::auth::Challenge;
+use crate::db::{
+    self,
+    user::{Login, Role, User},
+};
+use crate::packet::*;
+
+/// Perform `action` on the account `id` once `proof` answering to `challenge` is checked
+pub async fn run(
+    server: &Arc<ServerState>,
+    id: &str,
+    action: AccountAction,
+    proof: &str,
+    challenge: Option<&Challenge>,
+) -> Result<serde_json::Value, PacketError> {
+    let result = match confirm(server, id, proof, challenge).await {
+        Ok(role) => match action {
+            AccountAction::Export => export(server, id).await,
+            AccountAction::Erase => erase(server, id, role).await,
+        },
+        Err(e) => Err(e),
+    };
+    let outcome = match &result {
+        Ok(_) => "done".to_owned(),
+        Err(e) => format!("refused: {}", e),
+    };
+    server.audit.record(id, action.as_str(), &outcome);
+    result
+}
+
+/// Check the proof of the member `id` knowing the password, returns its role
+async fn confirm(
+    server: &Arc<ServerState>,
+    id: &str,
+    proof: &str,
+    challenge: Option<&Challenge>,
+) -> Result<Role, PacketError> {
+    if id.is_empty() || id.starts_with("guest_") {
+        return Err(PacketError::new(
+            ErrorCode::PermissionDenied,
+            "only members have an account",
+        ));
+    }
+    let login = Login {
+        guest: false,
+        id: Some(id.to_owned()),
+        proof: Some(proof.to_owned()),
+    };
+    let challenge = challenge.cloned();
+    server
+        .with_db(move |server| login.login(challenge.as_ref(), &server.db))
+        .await
+        .map(|(_, role)| role)
+}
+
+/// Everything kept about `id`: the profile, the presence, the messages still in the histories
+/// and the scheduled messages
+async fn export(server: &Arc<ServerState>, id: &str) -> Result<serde_json::Value, PacketError> {
+    let owner = id.to_owned();
+    let (profile, presence, read_markers, last_channel) = server
+        .with_db(move |server| {
+            let unavailable = |e| PacketError::new(ErrorCode::Unavailable, e);
+            let profile = User::profile(&owner, &server.db)?;
+            let presence = db::presence::load(&owner, &server.db).map_err(unavailable)?;
+            let read_markers = db::read_marker::load(&owner, &server.db).map_err(unavailable)?;
+            let last_channel = db::last_channel::load(&owner, &server.db).map_err(unavailable)?;
+            Ok::<_, PacketError>((profile, presence, read_markers, last_channel))
+        })
+        .await?;
+
+    let messages: Vec<serde_json::Value> = {
+        let channels_lock = server.channels.lock().await;
+        let mut names: Vec<&String> = channels_lock.channels.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .flat_map(|name| {
+                channels_lock.channels[name]
+                    .messages_of(id)
+                    .into_iter()
+                    .map(move |msg| {
+                        serde_json::json!({ "channel": name, "seq": msg.seq, "msg": msg.msg })
+                    })
+            })
+            .collect()
+    };
+    let scheduled = server.scheduler.pending_of(id);
+
+    Ok(serde_json::json!({
+        "profile": profile,
+        "presence": presence.as_str(),
+        "read_markers": read_markers,
+        "last_channel": last_channel,
+        "messages": messages,
+        "scheduled": scheduled,
+    }))
+}
+
+/// Delete the account `id` and anonymize its messages, admins have to give up the role first
+async fn erase(
+    server: &Arc<ServerState>,
+    id: &str,
+    role: Role,
+) -> Result<serde_json::Value, PacketError> {
+    if role == Role::Admin {
+        return Err(PacketError::new(
+            ErrorCode::PermissionDenied,
+            "admins can't erase their account, ask another admin to change the role first",
+        ));
+    }
+    let owner = id.to_owned();
+    server
+        .with_db(move |server| {
+            let unavailable = |e| PacketError::new(ErrorCode::Unavailable, e);
+            server
+                .scheduler
+                .forget_owner(&owner, &server.db)
+                .map_err(unavailable)?;
+            db::presence::delete(&owner, &server.db).map_err(unavailable)?;
+            db::read_marker::delete(&owner, &server.db).map_err(unavailable)?;
+            db::last_channel::delete(&owner, &server.db).map_err(unavailable)?;
+            User::delete(&owner, &server.db)
+        })
+        .await?;
+
+    let (anonymized, records) = {
+        let mut channels_lock = server.channels.lock().await;
+        let mut anonymized = 0;
+        let mut records = Vec::new();
+        for (name, channel) in channels_lock.channels.iter_mut() {
+            let managed = channel.owner.as_deref() == Some(id) || channel.moderators.contains(id);
+            anonymized += channel.erase_user(id);
+            if managed && !super::session::SYSTEM_CHANNELS.contains(&name.as_str()) {
+                records.push(channel.to_record(name));
+            }
+        }
+        if let Some(log) = channels_lock.history_log() {
+            log.anonymize(id);
+        }
+        (anonymized, records)
+    };
+    server
+        .with_db(move |server| {
+            for record in records {
+                if let Err(e) = record.save(&server.db) {
+                    println!("[!] {}", e);
+                }
+            }
+        })
+        .await;
+    server.roles.set(id, Role::User);
+    server.presence.forget(id);
+
+    Ok(serde_json::json!({ "anonymized_messages": anonymized }))
+}
@@ -0,0 +1,209 @@
+//! Server-wide operations, requested by admins and moderators or typed into the console
+
+use std::sync::Arc;
+
+use super::{dead_letter::Queue, permissions::Operation, ServerState};
+use crate::db::user::{Approval, Role, User};
+use crate::packet::*;
+
+/// Perform `action` on behalf of `id` if its role allows, returns what has been done
+pub async fn run(
+    server: &Arc<ServerState>,
+    id: &str,
+    action: AdminAction,
+) -> Result<String, PacketError> {
+    let op = match &action {
+        AdminAction::Kick(_) => Operation::Kick,
+        AdminAction::Ban(_) | AdminAction::Unban(_) => Operation::Ban,
+        AdminAction::Broadcast(_) | AdminAction::SetMotd(_) => Operation::Broadcast,
+        AdminAction::SetRole(..) => Operation::SetRole,
+        AdminAction::Approve(_) | AdminAction::Reject(_) | AdminAction::Pending => {
+            Operation::ApproveRegistration
+        }
+    };
+    if !server.roles.allows(id, op) {
+        return Err(PacketError::new(
+            ErrorCode::PermissionDenied,
+            format!(
+                "not permitted for the role '{}'",
+                server.roles.role_of(id).as_str()
+            ),
+        ));
+    }
+
+    match action {
+        AdminAction::Kick(user) => {
+            check_target(server, id, &user)?;
+            kick(server, &user, id)
+                .await
+                .map(|_| format!("'{}' is kicked", user))
+                .map_err(|e| PacketError::new(ErrorCode::NotFound, e))
+        }
+        AdminAction::Ban(user) => {
+            check_target(server, id, &user)?;
+            if user.starts_with("guest_") {
+                return Err(PacketError::new(
+                    ErrorCode::InvalidArgument,
+                    "guests can't be banned, kick them instead",
+                ));
+            }
+            let target = user.clone();
+            server
+                .with_db(move |server| User::set_banned(&target, true, &server.db))
+                .await?;
+            // the member may not be logged in
+            _ = kick(server, &user, id).await;
+            Ok(format!("'{}' is banned", user))
+        }
+        AdminAction::Unban(user) => {
+            let target = user.clone();
+            server
+                .with_db(move |server| User::set_banned(&target, false, &server.db))
+                .await?;
+            Ok(format!("'{}' is no longer banned", user))
+        }
+        AdminAction::Broadcast(msg) => {
+            let count = broadcast(server, id, &msg).await;
+            Ok(format!("broadcast to {} channels", count))
+        }
+        AdminAction::SetRole(user, role) => {
+            if user == id {
+                return Err(PacketError::new(
+                    ErrorCode::InvalidArgument,
+                    "you can't change your own role",
+                ));
+            }
+            let target = user.clone();
+            server
+                .with_db(move |server| User::set_role(&target, role, &server.db))
+                .await?;
+            server.roles.set(&user, role);
+            Ok(format!("'{}' is now {}", user, role.as_str()))
+        }
+        AdminAction::Approve(user) => {
+            let target = user.clone();
+            server
+                .with_db(move |server| User::decide(&target, Approval::Approved, &server.db))
+                .await?;
+            tell_registrant(
+                server,
+                &user,
+                &format!(
+                    "The account '{}' is approved, welcome! Log in to use it",
+                    user
+                ),
+            )
+            .await;
+            Ok(format!("'{}' is approved", user))
+        }
+        AdminAction::Reject(user) => {
+            let target = user.clone();
+            server
+                .with_db(move |server| User::decide(&target, Approval::Rejected, &server.db))
+                .await?;
+            tell_registrant(
+                server,
+                &user,
+                &format!("The registration of '{}' was rejected", user),
+            )
+            .await;
+            Ok(format!("the registration of '{}' is rejected", user))
+        }
+        AdminAction::SetMotd(motd) => Ok(set_motd(server, motd)),
+        AdminAction::Pending => {
+            let pending = server.with_db(|server| User::pending(&server.db)).await?;
+            Ok(if pending.is_empty() {
+                "no registrations are waiting for approval".to_owned()
+            } else {
+                format!("waiting for approval: {}", pending.join(", "))
+            })
+        }
+    }
+}
+
+/// Tell the registrant of `user` about the decision on its registration, the guest session it
+/// registered from if it's still connected, the account itself at its first login otherwise
+async fn tell_registrant(server: &Arc<ServerState>, user: &str, notice: &str) {
+    let guest = server
+        .registrants
+        .lock()
+        .ok()
+        .and_then(|mut registrants| registrants.remove(user));
+    let online = guest.filter(|guest| server.registry.lock().is_ok_and(|r| r.get(guest).is_some()));
+    match online {
+        Some(guest) => super::notify(server, &guest, notice).await,
+        // only an approved account logs in to read it
+        None => super::notify(server, user, notice).await,
+    }
+}
+
+/// Admins and yourself are out of reach of kicks and bans
+fn check_target(server: &ServerState, id: &str, user: &str) -> Result<(), PacketError> {
+    if user == id {
+        return Err(PacketError::new(
+            ErrorCode::InvalidArgument,
+            "you can't do that to yourself",
+        ));
+    }
+    if server.roles.role_of(user) == Role::Admin {
+        return Err(PacketError::new(
+            ErrorCode::PermissionDenied,
+            "admins can't be kicked or banned",
+        ));
+    }
+    Ok(())
+}
+
+/// Tell `user` who disconnected them and end the session, leaving the channel is left to the
+/// session. The session can't be resumed with the tokens issued so far.
+pub async fn kick(server: &ServerState, user: &str, by: &str) -> Result<(), String> {
+    let Some(res_tx) = server.registry.lock().ok().and_then(|r| r.get(user)) else {
+        return Err(format!("'{}' is not logged in", user));
+    };
+    server
+        .dead_letters
+        .send(
+            Queue::Responses,
+            &res_tx,
+            PacketType::Message(Message::system_notice(&format!(
+                "You have been disconnected by {}",
+                by
+            ))),
+        )
+        .await;
+    if let Ok(registry) = server.registry.lock() {
+        registry.kick(user);
+    }
+    server
+        .revocations
+        .revoke(user, server.session_tokens().lifetime_secs);
+    Ok(())
+}
+
+/// Send `motd` after the logins from now on, `None` or an empty one stops sending any
+pub fn set_motd(server: &ServerState, motd: Option<String>) -> String {
+    let motd = motd
+        .map(|motd| motd.trim().to_owned())
+        .filter(|motd| !motd.is_empty());
+    let done = match &motd {
+        Some(_) => "the message of the day is set".to_owned(),
+        None => "the message of the day is cleared".to_owned(),
+    };
+    server.set_motd(motd);
+    done
+}
+
+/// Post `msg` of `from` as a notice to every channel that isn't archived, returns the number of
+/// channels
+pub async fn broadcast(server: &ServerState, from: &str, msg: &str) -> usize {
+    let mut channels_lock = server.channels.lock().await;
+    let mut count = 0;
+    for channel in channels_lock.channels.values_mut() {
+        if channel.archived {
+            continue;
+        }
+        channel.broadcast(Message::system_notice(&format!("[{}] {}", from, msg)));
+        count += 1;
+    }
+    count
+}
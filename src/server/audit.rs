@@ -0,0 +1,48 @@
+//! Audit trail of the requests touching the personal data of members, and access log of the
+//! connections with their traffic
+//!
+//! Every record goes to the server log, and is appended to the audit file as a JSON line if one
+//! is configured.
+
+use std::{fs::File, io::Write, sync::Mutex};
+
+pub struct AuditLog {
+    file: Mutex<Option<File>>,
+}
+
+impl AuditLog {
+    /// Audit log appending to the file at `path`, only to the server log if it can't be opened
+    pub fn open(path: Option<&str>) -> Self {
+        let file = path.and_then(|path| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .inspect_err(|e| println!("[!] Audit log '{}' can't be opened: {}", path, e))
+                .ok()
+        });
+        Self {
+            file: Mutex::new(file),
+        }
+    }
+
+    /// Record that `action` of the member `id` ended with `outcome`
+    pub fn record(&self, id: &str, action: &str, outcome: &str) {
+        println!("[audit] '{}' {}: {}", id, action, outcome);
+        let line = serde_json::json!({
+            "at": std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            "id": id,
+            "action": action,
+            "outcome": outcome,
+        });
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        if let Some(Err(e)) = file.as_mut().map(|f| writeln!(f, "{}", line)) {
+            println!("[!] Audit record is not written: {}", e);
+        }
+    }
+}
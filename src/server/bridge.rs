@@ -0,0 +1,293 @@
+//! Outbound bridges mirroring channels to external services
+//!
+//! Messages are queued per bridge and delivered by a task of its own, retrying with a backoff,
+//! so a slow or unreachable service never holds the broadcast of the channel back.
+
+use std::{collections::HashMap, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+};
+
+use super::{
+    config::{BridgeConfig, BridgeTarget},
+    dead_letter::{DeadLetters, Letter, Queue},
+};
+use crate::packet::Message;
+
+/// Messages waiting for a bridge, newer messages are dropped once it's full
+const QUEUE_SIZE: usize = 256;
+
+/// Longest a single delivery may take
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before the first retry, doubled after every failure up to `MAX_BACKOFF`
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A message on its way to the external service
+#[derive(Debug, Clone)]
+struct Bridged {
+    channel: String,
+    user: String,
+    text: String,
+}
+
+impl Letter for Bridged {
+    fn packet_type(&self) -> String {
+        format!("Message of #{}", self.channel)
+    }
+}
+
+/// `http://host[:port]/path` split into its parts, TLS isn't supported
+#[derive(Debug, Clone)]
+struct HttpUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl HttpUrl {
+    fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or(format!("only http:// urls are supported: '{}'", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("invalid port in '{}'", url))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in '{}'", url));
+        }
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+/// Percent-encode everything but the unreserved characters, for path segments
+fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Send a JSON request and wait for the status line, `Ok` on 2xx
+async fn http_request(
+    method: &str,
+    url: &HttpUrl,
+    path: &str,
+    token: Option<&str>,
+    body: &str,
+) -> Result<(), String> {
+    let mut stream = TcpStream::connect((url.host.as_str(), url.port))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        method,
+        path,
+        url.host,
+        body.len()
+    );
+    if let Some(token) = token {
+        request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status_line)
+        .await
+        .map_err(|e| e.to_string())?;
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        Some(code) => Err(format!("HTTP status {}", code)),
+        None => Err("no HTTP response".to_owned()),
+    }
+}
+
+/// Where and how a bridge delivers messages
+struct Delivery {
+    target: BridgeTarget,
+    url: HttpUrl,
+
+    /// Transaction ids of Matrix events, the prefix keeps them unique over restarts
+    txn_prefix: u64,
+    txn: u64,
+}
+
+impl Delivery {
+    fn new(target: BridgeTarget) -> Result<Self, String> {
+        let url = match &target {
+            BridgeTarget::Webhook { url } => HttpUrl::parse(url)?,
+            BridgeTarget::Matrix { homeserver, .. } => HttpUrl::parse(homeserver)?,
+        };
+        let txn_prefix = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Ok(Self {
+            target,
+            url,
+            txn_prefix,
+            txn: 0,
+        })
+    }
+
+    async fn deliver(&self, msg: &Bridged) -> Result<(), String> {
+        match &self.target {
+            BridgeTarget::Webhook { .. } => {
+                let body = serde_json::json!({
+                    "channel": msg.channel,
+                    "user": msg.user,
+                    "text": msg.text,
+                });
+                http_request("POST", &self.url, &self.url.path, None, &body.to_string()).await
+            }
+            BridgeTarget::Matrix {
+                room_id,
+                access_token,
+                ..
+            } => {
+                // retries reuse the transaction id, so the homeserver drops duplicates
+                let path = format!(
+                    "{}/_matrix/client/v3/rooms/{}/send/m.room.message/rschat{}-{}",
+                    self.url.path.trim_end_matches('/'),
+                    encode_segment(room_id),
+                    self.txn_prefix,
+                    self.txn
+                );
+                let body = serde_json::json!({
+                    "msgtype": "m.text",
+                    "body": format!("<{}> {}", msg.user, msg.text),
+                });
+                http_request(
+                    "PUT",
+                    &self.url,
+                    &path,
+                    Some(access_token),
+                    &body.to_string(),
+                )
+                .await
+            }
+        }
+    }
+}
+
+/// Deliver queued messages in order, each one is retried up to `max_retries` times
+async fn bridge_task(
+    name: String,
+    mut delivery: Delivery,
+    max_retries: u32,
+    mut queue_rx: mpsc::Receiver<Bridged>,
+) {
+    while let Some(msg) = queue_rx.recv().await {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let result = match tokio::time::timeout(DELIVERY_TIMEOUT, delivery.deliver(&msg)).await
+            {
+                Ok(result) => result,
+                Err(_) => Err("timed out".to_owned()),
+            };
+            match result {
+                Ok(()) => break,
+                Err(e) if attempt >= max_retries => {
+                    println!("[!] Bridge '{}' dropped a message: {}", name, e);
+                    break;
+                }
+                Err(e) => {
+                    println!(
+                        "[!] Bridge '{}' failed, retrying in {}s: {}",
+                        name,
+                        backoff.as_secs(),
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    attempt += 1;
+                }
+            }
+        }
+        delivery.txn += 1;
+    }
+}
+
+/// Queues of the bridges per channel
+#[derive(Default)]
+pub struct Bridges {
+    queues: HashMap<String, Vec<mpsc::Sender<Bridged>>>,
+}
+
+impl Bridges {
+    /// Start a delivery task for every valid bridge of `config`
+    pub fn from_config(config: &[BridgeConfig]) -> Self {
+        let mut bridges = Self::default();
+        for bridge in config {
+            let name = format!("#{} -> {}", bridge.channel, bridge.target.kind());
+            let delivery = match Delivery::new(bridge.target.clone()) {
+                Ok(delivery) => delivery,
+                Err(e) => {
+                    println!("[!] Bridge '{}' is disabled: {}", name, e);
+                    continue;
+                }
+            };
+
+            let (queue_tx, queue_rx) = mpsc::channel(QUEUE_SIZE);
+            super::log::info(format_args!("Bridge started: {}", name));
+            tokio::spawn(bridge_task(name, delivery, bridge.max_retries, queue_rx));
+            bridges
+                .queues
+                .entry(bridge.channel.clone())
+                .or_default()
+                .push(queue_tx);
+        }
+        bridges
+    }
+
+    /// Queue `msg` broadcasted in `channel` for its bridges, it never waits
+    ///
+    /// A message a bridge has no room for is dropped and counted in `dead_letters`.
+    pub fn mirror(&self, channel: &str, msg: &Message, dead_letters: &DeadLetters) {
+        if msg.is_system || msg.to.is_some() {
+            return;
+        }
+        let Some(queues) = self.queues.get(channel) else {
+            return;
+        };
+
+        let bridged = Bridged {
+            channel: channel.to_owned(),
+            user: msg.id.clone(),
+            text: msg.msg.clone(),
+        };
+        for queue_tx in queues {
+            dead_letters.try_send(Queue::Bridge, queue_tx, bridged.clone());
+        }
+    }
+}
@@ -0,0 +1,417 @@
+//! Experimental clustering, servers sharing their channels over a TCP mesh
+//!
+//! Every node listens for the other nodes and connects to each node in `peers`. A node sends its
+//! own traffic over the connections it made and receives the traffic of the others over the
+//! connections they made, so nothing is ever sent twice or relayed further:
+//!
+//! - messages broadcasted in a channel are broadcasted in the channel of the same name on the
+//!   other nodes, where they get sequence numbers of their own,
+//! - the members of every channel are announced every few seconds, they're listed along with the
+//!   local ones and forgotten once the node is gone,
+//! - direct messages to members of other nodes are handed to every node, the one having the
+//!   recipient delivers them.
+//!
+//! A node proves it knows the shared secret whenever it connects. Channels aren't created on
+//! the other nodes, only the channels a node has take part, reactions, pins and settings stay on
+//! the node they're made on.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use base64ct::{Base64, Encoding};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+};
+
+use super::{config::ClusterConfig, dead_letter::Queue, Frame, ServerState};
+use crate::crypto::auth;
+use crate::packet::{Message, PacketType};
+
+/// Interval of the announcements of the members
+const MEMBERS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Events queued for the other nodes, a node falling further behind is sent the members again
+const BUS_CAPACITY: usize = 1024;
+
+/// Largest frame taken from another node
+const MAX_FRAME_SIZE: u32 = 4 * 1024 * 1024;
+
+/// Longest wait between the attempts to connect to a node
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Longest a node may take to answer the challenge
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What nodes tell each other
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+enum Event {
+    /// Members of every channel of the sending node
+    Members {
+        channels: BTreeMap<String, Vec<String>>,
+    },
+    Message {
+        channel: String,
+        message: Message,
+    },
+    Direct {
+        message: Message,
+    },
+}
+
+/// First frame of a connection, sent by the node accepting it
+#[derive(Serialize, Deserialize)]
+struct NodeChallenge {
+    nonce: String,
+}
+
+/// Answer of the connecting node
+#[derive(Serialize, Deserialize)]
+struct NodeHello {
+    node: String,
+    proof: String,
+}
+
+/// Sends the messages of a channel to the other nodes
+#[derive(Clone, Debug)]
+pub struct Relay {
+    channel: String,
+    bus: broadcast::Sender<Frame>,
+}
+
+impl Relay {
+    /// Relay of the channel `name` to the same nodes
+    pub fn of(&self, name: &str) -> Relay {
+        Relay {
+            channel: name.to_owned(),
+            bus: self.bus.clone(),
+        }
+    }
+
+    pub fn publish(&self, message: &Message) {
+        publish(
+            &self.bus,
+            &Event::Message {
+                channel: self.channel.clone(),
+                message: message.clone(),
+            },
+        );
+    }
+}
+
+pub struct Cluster {
+    config: ClusterConfig,
+    bus: broadcast::Sender<Frame>,
+
+    /// Latest announcement of the members of this node, sent first on every connection
+    members: Mutex<Option<Frame>>,
+
+    /// Members of every channel of the other nodes by the names of the nodes
+    remote: Mutex<HashMap<String, BTreeMap<String, Vec<String>>>>,
+}
+
+fn publish(bus: &broadcast::Sender<Frame>, event: &Event) -> Frame {
+    let frame: Frame = serde_json::to_vec(event).unwrap().into();
+    // no node connected, nobody to tell
+    _ = bus.send(frame.clone());
+    frame
+}
+
+fn sign(secret: &str, nonce: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("keys of any size");
+    mac.update(nonce.as_bytes());
+    mac
+}
+
+async fn write_frame(stream: &mut TcpStream, bytes: &[u8]) -> std::io::Result<()> {
+    let mut frame = (bytes.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(bytes);
+    stream.write_all(&frame).await
+}
+
+async fn read_frame(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let size = stream.read_u32().await?;
+    if size > MAX_FRAME_SIZE {
+        return Err(std::io::Error::other("frame too large"));
+    }
+    let mut bytes = vec![0; size as usize];
+    stream.read_exact(&mut bytes).await?;
+    Ok(bytes)
+}
+
+impl Cluster {
+    /// Cluster of the config, `None` if clustering is off
+    pub fn new(config: &ClusterConfig) -> Option<Self> {
+        config.node.as_ref()?;
+        Some(Self {
+            config: config.clone(),
+            bus: broadcast::channel(BUS_CAPACITY).0,
+            members: Mutex::new(None),
+            remote: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn node(&self) -> &str {
+        self.config.node.as_deref().unwrap_or_default()
+    }
+
+    /// Relay of the channel `name`
+    pub fn relay(&self, name: &str) -> Relay {
+        Relay {
+            channel: name.to_owned(),
+            bus: self.bus.clone(),
+        }
+    }
+
+    /// True if `id` is logged in on another node
+    pub fn is_online(&self, id: &str) -> bool {
+        self.remote.lock().is_ok_and(|remote| {
+            remote
+                .values()
+                .any(|channels| channels.values().any(|ids| ids.iter().any(|i| i == id)))
+        })
+    }
+
+    /// Hand the direct message `message` to the other nodes, false if the recipient isn't on any
+    pub fn send_direct(&self, message: &Message) -> bool {
+        let online = message.to.as_deref().is_some_and(|to| self.is_online(to));
+        if online {
+            publish(
+                &self.bus,
+                &Event::Direct {
+                    message: message.clone(),
+                },
+            );
+        }
+        online
+    }
+
+    /// Names of the other nodes connected to this one
+    pub fn nodes(&self) -> Vec<String> {
+        let mut nodes: Vec<String> = self
+            .remote
+            .lock()
+            .map(|remote| remote.keys().cloned().collect())
+            .unwrap_or_default();
+        nodes.sort();
+        nodes
+    }
+
+    /// Current members of the channels of the other nodes, merged
+    fn remote_members(&self) -> BTreeMap<String, BTreeSet<String>> {
+        let mut merged: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        if let Ok(remote) = self.remote.lock() {
+            for (channel, ids) in remote.values().flatten() {
+                merged
+                    .entry(channel.clone())
+                    .or_default()
+                    .extend(ids.iter().cloned());
+            }
+        }
+        merged
+    }
+
+    /// Show the members of the other nodes in the channels of this one
+    async fn apply_members(&self, server: &ServerState) {
+        let mut merged = self.remote_members();
+        let mut channels_lock = server.channels.lock().await;
+        for (name, channel) in channels_lock.channels.iter_mut() {
+            channel.remote_names = merged.remove(name).unwrap_or_default();
+        }
+    }
+}
+
+/// Run the node: accept the other nodes, connect to the peers and announce the members
+pub async fn run(server: Arc<ServerState>) {
+    let Some(cluster) = server.cluster.as_ref() else {
+        return;
+    };
+    match TcpListener::bind(("0.0.0.0", cluster.config.port)).await {
+        Ok(listener) => {
+            println!(
+                "[RsChat Sever] Cluster node '{}' on port {}...",
+                cluster.node(),
+                cluster.config.port
+            );
+            tokio::spawn(accept_nodes(listener, Arc::clone(&server)));
+        }
+        Err(e) => {
+            println!(
+                "[!] Cluster port {} can't be bound: {}",
+                cluster.config.port, e
+            );
+            return;
+        }
+    }
+    for peer in cluster.config.peers.clone() {
+        tokio::spawn(connect_node(peer, Arc::clone(&server)));
+    }
+
+    loop {
+        let announcement = {
+            let channels_lock = server.channels.lock().await;
+            let hidden = server.presence.invisible();
+            let channels = channels_lock
+                .channels
+                .iter()
+                .map(|(name, channel)| {
+                    let mut ids: Vec<String> = channel
+                        .state
+                        .names
+                        .iter()
+                        .filter(|id| !hidden.contains(*id))
+                        .cloned()
+                        .collect();
+                    ids.sort();
+                    (name.clone(), ids)
+                })
+                .filter(|(_, ids)| !ids.is_empty())
+                .collect();
+            Event::Members { channels }
+        };
+        let frame = publish(&cluster.bus, &announcement);
+        if let Ok(mut members) = cluster.members.lock() {
+            *members = Some(frame);
+        }
+        tokio::time::sleep(MEMBERS_INTERVAL).await;
+    }
+}
+
+async fn accept_nodes(listener: TcpListener, server: Arc<ServerState>) {
+    while let Ok((stream, addr)) = listener.accept().await {
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            let Some(node) = receive_node(stream, &server).await else {
+                println!("[!] Cluster connection from {:?} is refused", addr);
+                return;
+            };
+            super::log::info(format_args!("Cluster node '{}' is gone", node));
+            if let Some(cluster) = server.cluster.as_ref() {
+                if let Ok(mut remote) = cluster.remote.lock() {
+                    remote.remove(&node);
+                }
+                cluster.apply_members(&server).await;
+            }
+        });
+    }
+}
+
+/// Check the node connecting on `stream` and apply what it sends, returns its name once it's gone
+async fn receive_node(mut stream: TcpStream, server: &ServerState) -> Option<String> {
+    let cluster = server.cluster.as_ref()?;
+    let nonce = auth::nonce();
+    let challenge = serde_json::to_vec(&NodeChallenge {
+        nonce: nonce.clone(),
+    })
+    .ok()?;
+    write_frame(&mut stream, &challenge).await.ok()?;
+    let hello = tokio::time::timeout(HANDSHAKE_TIMEOUT, read_frame(&mut stream))
+        .await
+        .ok()?
+        .ok()?;
+    let hello: NodeHello = serde_json::from_slice(&hello).ok()?;
+    let proof = Base64::decode_vec(&hello.proof).ok()?;
+    sign(&cluster.config.secret, &nonce)
+        .verify_slice(&proof)
+        .ok()?;
+    if hello.node == cluster.node() {
+        return None;
+    }
+    super::log::info(format_args!("Cluster node '{}' has joined", hello.node));
+
+    while let Ok(bytes) = read_frame(&mut stream).await {
+        let Ok(event) = serde_json::from_slice::<Event>(&bytes) else {
+            continue;
+        };
+        match event {
+            Event::Members { channels } => {
+                if let Ok(mut remote) = cluster.remote.lock() {
+                    remote.insert(hello.node.clone(), channels);
+                }
+                cluster.apply_members(server).await;
+            }
+            Event::Message { channel, message } => {
+                if let Some(channel) = server.channels.lock().await.get_mut(&channel) {
+                    channel.broadcast_local(message);
+                }
+            }
+            Event::Direct { message } => {
+                let recipient = message
+                    .to
+                    .as_deref()
+                    .and_then(|to| server.registry.lock().ok().and_then(|r| r.get(to)));
+                if let Some(recipient_tx) = recipient {
+                    server
+                        .dead_letters
+                        .send(
+                            Queue::Responses,
+                            &recipient_tx,
+                            PacketType::Message(message),
+                        )
+                        .await;
+                }
+            }
+        }
+    }
+    Some(hello.node)
+}
+
+/// Keep a connection to the node at `peer` and send it the traffic of this node
+async fn connect_node(peer: String, server: Arc<ServerState>) {
+    let Some(cluster) = server.cluster.as_ref() else {
+        return;
+    };
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        match TcpStream::connect(&peer).await {
+            Ok(stream) => {
+                backoff = Duration::from_secs(1);
+                if let Err(e) = send_node(stream, cluster).await {
+                    println!("[!] Cluster connection to '{}' is lost: {}", peer, e);
+                }
+            }
+            Err(e) => super::log::info(format_args!(
+                "Cluster node '{}' is unreachable: {}",
+                peer, e
+            )),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn send_node(mut stream: TcpStream, cluster: &Cluster) -> std::io::Result<()> {
+    let challenge: NodeChallenge = serde_json::from_slice(&read_frame(&mut stream).await?)?;
+    let proof = sign(&cluster.config.secret, &challenge.nonce)
+        .finalize()
+        .into_bytes();
+    let hello = NodeHello {
+        node: cluster.node().to_owned(),
+        proof: Base64::encode_string(&proof),
+    };
+    write_frame(&mut stream, &serde_json::to_vec(&hello)?).await?;
+
+    let mut events = cluster.bus.subscribe();
+    let members = cluster.members.lock().ok().and_then(|m| m.clone());
+    if let Some(members) = members {
+        write_frame(&mut stream, &members).await?;
+    }
+    loop {
+        match events.recv().await {
+            Ok(frame) => write_frame(&mut stream, &frame).await?,
+            // the next announcement puts the members right, the skipped messages are lost
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                println!("[!] Cluster connection lagged behind by {} events", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
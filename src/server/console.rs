@@ -0,0 +1,167 @@
+//! Operator console on the standard input of the server
+//!
+//! Commands act on the same channels and registry the sessions use, so their effects are seen
+//! by the clients right away. The console ends quietly if the input is closed, e.g. when the
+//! server runs detached.
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio_util::sync::CancellationToken;
+
+use super::{admin, backup, ServerState};
+
+/// How long the sessions get to deliver the shutdown notice
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+const HELP: &str = "\
+[console] list channels: channels with their users
+[console] list users <optional:channel>: logged in users, or the users of the channel
+[console] kick [user]: disconnect the user
+[console] broadcast [message]: post a notice to every channel
+[console] motd <optional:message | clear>: show, set or clear the message of the day
+[console] load: sessions running and connections turned away
+[console] reload: apply the changed config file, SIGHUP does the same
+[console] export [file]: dump the channels, accounts and config to a JSON file, secrets left out
+[console] import [file]: restore a dump on this server, what exists already is kept
+[console] shutdown: disconnect everyone and stop the server";
+
+/// Run console commands read from stdin until it's closed or `shutdown` is requested
+pub async fn run(server: Arc<ServerState>, shutdown: CancellationToken) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (command, arg) = match line.split_once(' ') {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+        match (command, arg) {
+            ("help", _) => println!("{}", HELP),
+            ("list", "channels") => list_channels(&server).await,
+            ("list", "users") => list_logged_in(&server),
+            ("list", arg) if arg.starts_with("users ") => {
+                list_channel_users(&server, arg["users ".len()..].trim()).await
+            }
+            ("kick", user) if !user.is_empty() => {
+                match admin::kick(&server, user, "the operator").await {
+                    Ok(()) => println!("[console] '{}' is kicked", user),
+                    Err(e) => println!("[console] {}", e),
+                }
+            }
+            ("broadcast", msg) if !msg.is_empty() => {
+                let count = admin::broadcast(&server, "Operator", msg).await;
+                println!("[console] Broadcast to {} channels", count);
+            }
+            ("motd", "") => match server.motd() {
+                Some(motd) => println!("[console] Message of the day: {}", motd),
+                None => println!("[console] No message of the day"),
+            },
+            ("motd", "clear") => println!("[console] {}", admin::set_motd(&server, None)),
+            ("motd", motd) => {
+                println!(
+                    "[console] {}",
+                    admin::set_motd(&server, Some(motd.to_owned()))
+                )
+            }
+            ("load", _) => {
+                println!("[console] {}", server.sessions.report());
+                if let Some(cluster) = &server.cluster {
+                    println!(
+                        "[console] cluster node '{}', connected nodes: {}",
+                        cluster.node(),
+                        cluster.nodes().join(", ")
+                    );
+                }
+            }
+            ("reload", _) => super::reload::print_report(super::reload::reload(&server).await),
+            ("export", path) if !path.is_empty() => match export(&server, path).await {
+                Ok(()) => println!("[console] The server state is exported to '{}'", path),
+                Err(e) => println!("[console] {}", e),
+            },
+            ("import", path) if !path.is_empty() => match import(&server, path).await {
+                Ok(report) => {
+                    for skipped in &report.skipped {
+                        println!("[console] Skipped {}", skipped);
+                    }
+                    println!("[console] {}", report);
+                    println!(
+                        "[console] The config of the dump is left as it is, secrets left out, \
+                        copy what's needed to the config file and reload"
+                    );
+                }
+                Err(e) => println!("[console] {}", e),
+            },
+            ("shutdown", _) => {
+                admin::broadcast(&server, "Operator", "The server is shutting down").await;
+                println!("[console] Shutting down...");
+                shutdown.cancel();
+                return;
+            }
+            _ => println!("[console] Unknown command: '{}', try 'help'", line),
+        }
+    }
+}
+
+async fn export(server: &Arc<ServerState>, path: &str) -> Result<(), String> {
+    let dump = backup::export(server).await?;
+    let json = serde_json::to_string_pretty(&dump).unwrap();
+    std::fs::write(path, json).map_err(|e| format!("'{}' can't be written: {}", path, e))
+}
+
+async fn import(server: &Arc<ServerState>, path: &str) -> Result<backup::ImportReport, String> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("'{}' can't be read: {}", path, e))?;
+    let dump: backup::Dump =
+        serde_json::from_str(&content).map_err(|e| format!("'{}' is not a dump: {}", path, e))?;
+    backup::import(server, &dump).await
+}
+
+async fn list_channels(server: &ServerState) {
+    let channels_lock = server.channels.lock().await;
+    let mut names: Vec<&String> = channels_lock.channels.keys().collect();
+    names.sort();
+    for name in names {
+        let channel = &channels_lock.channels[name];
+        let kind = if channel.is_system {
+            "system".to_owned()
+        } else {
+            format!("owner: {}", channel.owner.as_deref().unwrap_or("-"))
+        };
+        println!(
+            "[console] #{}: {} users, {} guests ({}){}",
+            name,
+            channel.num_user(),
+            channel.num_guest(),
+            kind,
+            if channel.archived { ", archived" } else { "" }
+        );
+    }
+}
+
+fn list_logged_in(server: &ServerState) {
+    let Ok(registry) = server.registry.lock() else {
+        return;
+    };
+    let ids = registry.ids();
+    println!("[console] {} logged in: {}", ids.len(), ids.join(", "));
+}
+
+async fn list_channel_users(server: &ServerState, channel_name: &str) {
+    let channels_lock = server.channels.lock().await;
+    let Some(channel) = channels_lock.get(channel_name) else {
+        println!("[console] No such channel: '{}'", channel_name);
+        return;
+    };
+    let mut names: Vec<&String> = channel.state.names.iter().collect();
+    names.sort();
+    let names: Vec<&str> = names.into_iter().map(String::as_str).collect();
+    println!(
+        "[console] {} in #{}: {}",
+        names.len(),
+        channel_name,
+        names.join(", ")
+    );
+}
@@ -0,0 +1,230 @@
+//! Accounting of the packets the server failed to deliver
+//!
+//! Sending to a session or a channel doesn't fail the sender, the packet is dropped instead, e.g.
+//! once the session is closing. Every drop is counted by the queue it was sent to and the reason,
+//! and the latest ones are kept as samples, so the operators can see how much is lost and where.
+//! The stats carry the counts, admins see the samples with the `dead_letters` fetch item.
+//!
+//! A broadcast no one is subscribed to isn't a drop, there's no one to miss it.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+
+use super::Frame;
+use crate::packet::PacketType;
+
+/// Drops kept as samples, the latest ones
+const NUM_SAMPLES: usize = 32;
+
+/// Why a packet wasn't delivered
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// The receiving end is gone, e.g. the session has closed
+    Closed,
+    /// The queue had no room left and the sender couldn't wait
+    Full,
+    /// The receiver fell behind the channel and the oldest packets were overwritten
+    Lagged,
+}
+
+/// Queue a packet was dropped from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Queue {
+    /// Responses and notices to a session
+    Responses,
+    /// Frames written to the connection
+    Socket,
+    /// Broadcasts of a channel to its subscribers
+    Channel,
+    /// Control packets changing the state of a session
+    Control,
+    /// Messages relayed to a bridge
+    Bridge,
+}
+
+/// Something that can be dropped, described by the type of the packet
+pub trait Letter {
+    fn packet_type(&self) -> String;
+}
+
+impl Letter for PacketType {
+    fn packet_type(&self) -> String {
+        // the variants are named after the packets they carry
+        let debug = format!("{:?}", self);
+        debug.split('(').next().unwrap_or_default().to_owned()
+    }
+}
+
+impl Letter for Frame {
+    fn packet_type(&self) -> String {
+        #[derive(Deserialize)]
+        struct Tag {
+            #[serde(rename = "type")]
+            packet_type: String,
+        }
+        serde_json::from_slice::<Tag>(self)
+            .map(|tag| tag.packet_type)
+            .unwrap_or_else(|_| "unknown".to_owned())
+    }
+}
+
+/// A drop kept as a sample
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    pub reason: Reason,
+    pub queue: Queue,
+
+    /// Type of the packet, or the channel the receiver lagged behind
+    pub what: String,
+    pub count: u64,
+
+    /// Unix time of the drop
+    pub at: u64,
+}
+
+#[derive(Default)]
+struct Letters {
+    counts: BTreeMap<(Queue, Reason), u64>,
+    samples: VecDeque<Sample>,
+}
+
+#[derive(Default)]
+pub struct DeadLetters {
+    letters: Mutex<Letters>,
+}
+
+impl DeadLetters {
+    /// Count `count` packets of `queue` dropped for `reason`, `what` describing them
+    pub fn record(&self, queue: Queue, reason: Reason, what: String, count: u64) {
+        let Ok(mut letters) = self.letters.lock() else {
+            return;
+        };
+        *letters.counts.entry((queue, reason)).or_default() += count;
+        if letters.samples.len() >= NUM_SAMPLES {
+            letters.samples.pop_front();
+        }
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        letters.samples.push_back(Sample {
+            reason,
+            queue,
+            what,
+            count,
+            at,
+        });
+    }
+
+    /// Send `packet` to `tx` of `queue`, counting it if the receiver is gone
+    pub async fn send<T: Letter>(&self, queue: Queue, tx: &mpsc::Sender<T>, packet: T) {
+        if let Err(mpsc::error::SendError(packet)) = tx.send(packet).await {
+            self.record(queue, Reason::Closed, packet.packet_type(), 1);
+        }
+    }
+
+    /// Send `packet` to `tx` of `queue` without waiting, counting it if there's no room for it
+    pub fn try_send<T: Letter>(&self, queue: Queue, tx: &mpsc::Sender<T>, packet: T) {
+        match tx.try_send(packet) {
+            Ok(()) => (),
+            Err(mpsc::error::TrySendError::Full(packet)) => {
+                self.record(queue, Reason::Full, packet.packet_type(), 1)
+            }
+            Err(mpsc::error::TrySendError::Closed(packet)) => {
+                self.record(queue, Reason::Closed, packet.packet_type(), 1)
+            }
+        }
+    }
+
+    /// Broadcast `packet` to the subscribers of `tx`, counting it if they are all gone
+    pub fn broadcast(&self, tx: &broadcast::Sender<PacketType>, packet: PacketType) {
+        if let Err(broadcast::error::SendError(packet)) = tx.send(packet) {
+            self.record(Queue::Channel, Reason::Closed, packet.packet_type(), 1);
+        }
+    }
+
+    /// Packets dropped by reason since the start
+    pub fn totals(&self) -> BTreeMap<Reason, u64> {
+        let mut totals = BTreeMap::new();
+        if let Ok(letters) = self.letters.lock() {
+            for ((_, reason), count) in &letters.counts {
+                *totals.entry(*reason).or_default() += count;
+            }
+        }
+        totals
+    }
+
+    /// Drops by queue and reason along with the latest samples, for the admins
+    pub fn report(&self) -> serde_json::Value {
+        let Ok(letters) = self.letters.lock() else {
+            return serde_json::Value::Null;
+        };
+        let counts: Vec<serde_json::Value> = letters
+            .counts
+            .iter()
+            .map(|((queue, reason), count)| {
+                serde_json::json!({ "queue": queue, "reason": reason, "count": count })
+            })
+            .collect();
+        let samples: Vec<&Sample> = letters.samples.iter().rev().collect();
+        serde_json::json!({
+            "counts": counts,
+            "samples": samples,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::Pong;
+
+    #[test]
+    fn drops_are_counted_by_reason() {
+        let dead_letters = DeadLetters::default();
+        let (tx, rx) = mpsc::channel::<PacketType>(1);
+        dead_letters.try_send(
+            Queue::Responses,
+            &tx,
+            PacketType::Pong(Pong { timestamp: 1 }),
+        );
+        dead_letters.try_send(
+            Queue::Responses,
+            &tx,
+            PacketType::Pong(Pong { timestamp: 2 }),
+        );
+        drop(rx);
+        dead_letters.try_send(
+            Queue::Responses,
+            &tx,
+            PacketType::Pong(Pong { timestamp: 3 }),
+        );
+        dead_letters.record(Queue::Channel, Reason::Lagged, "#public".to_owned(), 5);
+
+        assert_eq!(
+            dead_letters.totals(),
+            BTreeMap::from([(Reason::Closed, 1), (Reason::Full, 1), (Reason::Lagged, 5)])
+        );
+        let report = dead_letters.report();
+        assert_eq!(report["samples"][0]["what"], "#public");
+        assert_eq!(report["samples"][1]["what"], "Pong");
+        assert_eq!(report["samples"][1]["reason"], "closed");
+        assert_eq!(report["samples"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn frames_are_described_by_their_type() {
+        let frame: Frame = br#"{"type":"LoginRes","result":{"Ok":"alice"}}"#.to_vec().into();
+        assert_eq!(frame.packet_type(), "LoginRes");
+        let garbage: Frame = b"\x00\x01".to_vec().into();
+        assert_eq!(garbage.packet_type(), "unknown");
+    }
+}
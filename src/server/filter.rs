@@ -0,0 +1,103 @@
+use super::config::FilterConfig;
+use crate::packet::Message;
+
+/// A check applied to every message before it gets broadcasted
+pub trait MessageFilter: Send + Sync {
+    /// Name of the filter shown in the rejection notice
+    fn name(&self) -> &str;
+
+    /// Returns the reason of the rejection if `msg` is not allowed
+    fn check(&self, msg: &Message) -> Result<(), String>;
+}
+
+/// Rejects messages containing any of the banned words
+pub struct BannedWords {
+    words: Vec<String>,
+}
+
+impl MessageFilter for BannedWords {
+    fn name(&self) -> &str {
+        "banned words"
+    }
+
+    fn check(&self, msg: &Message) -> Result<(), String> {
+        let lowercase = msg.msg.to_lowercase();
+        let banned = lowercase
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| self.words.iter().any(|w| w == word));
+        if banned {
+            Err("message contains a banned word".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Rejects messages longer than `max_len` characters
+pub struct MaxLength {
+    max_len: usize,
+}
+
+impl MessageFilter for MaxLength {
+    fn name(&self) -> &str {
+        "max length"
+    }
+
+    fn check(&self, msg: &Message) -> Result<(), String> {
+        let len = msg.msg.chars().count();
+        if len > self.max_len {
+            Err(format!(
+                "message is too long ({} > {} characters)",
+                len, self.max_len
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Ordered list of filters, the first rejection wins
+#[derive(Default)]
+pub struct FilterPipeline {
+    filters: Vec<Box<dyn MessageFilter>>,
+    disabled_channels: Vec<String>,
+}
+
+impl FilterPipeline {
+    /// Pipeline with the built-in filters set up by `config`
+    pub fn from_config(config: &FilterConfig) -> Self {
+        let mut pipeline = Self {
+            filters: Vec::new(),
+            disabled_channels: config.disabled_channels.clone(),
+        };
+        if !config.banned_words.is_empty() {
+            pipeline.add(Box::new(BannedWords {
+                words: config
+                    .banned_words
+                    .iter()
+                    .map(|w| w.to_lowercase())
+                    .collect(),
+            }));
+        }
+        pipeline.add(Box::new(MaxLength {
+            max_len: config.max_message_len,
+        }));
+        pipeline
+    }
+
+    /// Append a filter at the end of the pipeline
+    pub fn add(&mut self, filter: Box<dyn MessageFilter>) {
+        self.filters.push(filter);
+    }
+
+    /// Run every filter enabled for `channel` on `msg`
+    pub fn apply(&self, channel: &str, msg: &Message) -> Result<(), String> {
+        if self.disabled_channels.iter().any(|c| c == channel) {
+            return Ok(());
+        }
+        self.filters.iter().try_for_each(|f| {
+            f.check(msg)
+                .map_err(|reason| format!("rejected by {} filter: {}", f.name(), reason))
+        })
+    }
+}
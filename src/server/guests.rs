@@ -0,0 +1,84 @@
+//! What guests may do, the `guests` policy of the config
+//!
+//! The checks give why a guest is held back, it's told to the guest as is. Members pass them
+//! all, whatever the policy.
+
+use super::{
+    config::{GuestChat, GuestConfig},
+    session::DEFAULT_CHANNEL,
+};
+
+pub fn is_guest(id: &str) -> bool {
+    id.starts_with("guest_")
+}
+
+/// `id` may post in `channel`, now or scheduled for later
+pub fn check_post(policy: &GuestConfig, id: &str, channel: &str) -> Result<(), String> {
+    if !is_guest(id) {
+        return Ok(());
+    }
+    match policy.chat {
+        GuestChat::Anywhere => Ok(()),
+        GuestChat::PublicOnly if channel == DEFAULT_CHANNEL => Ok(()),
+        GuestChat::PublicOnly => Err(format!(
+            "guests can only post in '{}', register to post here",
+            DEFAULT_CHANNEL
+        )),
+        GuestChat::ReadOnly => Err("guests can only read here, register to post".to_owned()),
+    }
+}
+
+/// `id` may send direct messages
+pub fn check_direct_message(policy: &GuestConfig, id: &str) -> Result<(), String> {
+    if is_guest(id) && (!policy.direct_messages || policy.chat == GuestChat::ReadOnly) {
+        return Err("guests can't send direct messages here, register to send them".to_owned());
+    }
+    Ok(())
+}
+
+/// `id` may create user channels
+pub fn check_channel_creation(policy: &GuestConfig, id: &str) -> Result<(), String> {
+    if is_guest(id) && !policy.create_channels {
+        return Err("guests can't create channels here, register to create one".to_owned());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_guests_are_held_back() {
+        let policy = GuestConfig {
+            chat: GuestChat::PublicOnly,
+            direct_messages: false,
+            create_channels: false,
+        };
+        assert!(check_post(&policy, "guest_42", DEFAULT_CHANNEL).is_ok());
+        assert!(check_post(&policy, "guest_42", "dev").is_err());
+        assert!(check_direct_message(&policy, "guest_42").is_err());
+        assert!(check_channel_creation(&policy, "guest_42").is_err());
+        for check in [
+            check_post(&policy, "alice", "dev"),
+            check_direct_message(&policy, "alice"),
+            check_channel_creation(&policy, "alice"),
+        ] {
+            assert!(check.is_ok());
+        }
+
+        // read-only guests can't talk in private either
+        let policy = GuestConfig {
+            chat: GuestChat::ReadOnly,
+            ..GuestConfig::default()
+        };
+        assert!(check_post(&policy, "guest_42", DEFAULT_CHANNEL).is_err());
+        assert!(check_direct_message(&policy, "guest_42").is_err());
+        assert!(check_channel_creation(&policy, "guest_42").is_ok());
+
+        // as permissive as before by default
+        let policy = GuestConfig::default();
+        assert!(check_post(&policy, "guest_42", "dev").is_ok());
+        assert!(check_direct_message(&policy, "guest_42").is_ok());
+    }
+}
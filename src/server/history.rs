@@ -0,0 +1,697 @@
+//! Stored chat history, beyond the recent messages the channels keep in memory
+//!
+//! `history.backend` picks the store: `mysql` logs the messages to the `message_log` table,
+//! `sqlite` to the same table in the SQLite file `history.file`, and `files` appends them to a JSON
+//! lines file per channel and per day, `<dir>/<channel>/<YYYY-MM-DD>.jsonl`. The last two keep the
+//! logs of small deployments without a database server. The channels hand what they record to a writer thread, the broadcasting never waits for
+//! the store, and take their recent messages back from it at startup.
+//!
+//! Stored messages are pruned after `history.retention_days`, and after the retention of the
+//! channels keeping their messages only for a while. Ephemeral channels store nothing.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use rusqlite::{params, Connection};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    config::{Config, HistoryBackend},
+    log,
+    session::ERASED_ID,
+};
+use crate::{db::Database, packet::Message};
+
+/// A backend keeping the messages of the channels
+pub trait HistoryStore: Send + Sync {
+    /// Name of the backend shown in the logs
+    fn name(&self) -> &'static str;
+
+    /// Keep `msg` of `channel`, sent at the unix time `at`
+    fn append(&self, channel: &str, at: u64, msg: &Message) -> Result<(), String>;
+
+    /// The latest `limit` messages of `channel` with the unix time they were sent at, oldest first
+    fn recent(&self, channel: &str, limit: usize) -> Result<Vec<(u64, Message)>, String>;
+
+    /// Replace the message of `channel` with the sequence number of `msg`, e.g. once retracted
+    fn update(&self, channel: &str, msg: &Message) -> Result<(), String>;
+
+    /// Attribute the messages of `id` to `ERASED_ID`, returns the number of messages changed
+    fn anonymize(&self, id: &str) -> Result<usize, String>;
+
+    /// Drop the messages sent before the unix time `before`, of `channel` or of every channel,
+    /// returns the number of messages dropped
+    fn prune(&self, channel: Option<&str>, before: u64) -> Result<usize, String>;
+
+    /// Drop every message of `channel`, e.g. once it's deleted
+    fn delete_channel(&self, channel: &str) -> Result<(), String>;
+}
+
+/// The `message_log` table of the database
+pub struct MysqlStore {
+    db: Database,
+}
+
+impl HistoryStore for MysqlStore {
+    fn name(&self) -> &'static str {
+        "mysql"
+    }
+
+    fn append(&self, channel: &str, at: u64, msg: &Message) -> Result<(), String> {
+        crate::db::message_log::append(channel, at, msg, &self.db)
+    }
+
+    fn recent(&self, channel: &str, limit: usize) -> Result<Vec<(u64, Message)>, String> {
+        crate::db::message_log::recent(channel, limit, &self.db)
+    }
+
+    fn update(&self, channel: &str, msg: &Message) -> Result<(), String> {
+        crate::db::message_log::update(channel, msg, &self.db)
+    }
+
+    fn anonymize(&self, id: &str) -> Result<usize, String> {
+        let messages = crate::db::message_log::of_sender(id, &self.db)?;
+        for (channel, mut msg) in messages.iter().cloned() {
+            msg.id = ERASED_ID.to_owned();
+            crate::db::message_log::update(&channel, &msg, &self.db)?;
+        }
+        Ok(messages.len())
+    }
+
+    fn prune(&self, channel: Option<&str>, before: u64) -> Result<usize, String> {
+        crate::db::message_log::prune(channel, before, &self.db)
+    }
+
+    fn delete_channel(&self, channel: &str) -> Result<(), String> {
+        crate::db::message_log::delete_channel(channel, &self.db)
+    }
+}
+
+/// The `message_log` table of a SQLite file, laid out as the one of the database
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Store in the file at `path`, created along with the table if missing
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let conn = Connection::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        conn.execute_batch(
+            r"CREATE TABLE IF NOT EXISTS message_log (
+                channel TEXT NOT NULL,
+                seq     INTEGER NOT NULL,
+                at      INTEGER NOT NULL,
+                sender  TEXT NOT NULL,
+                body    TEXT NOT NULL,
+                PRIMARY KEY (channel, seq)
+            );
+            CREATE INDEX IF NOT EXISTS message_log_at ON message_log (at);",
+        )
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> Result<std::sync::MutexGuard<'_, Connection>, String> {
+        self.conn
+            .lock()
+            .map_err(|_| "the SQLite connection is broken".to_owned())
+    }
+}
+
+impl HistoryStore for SqliteStore {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn append(&self, channel: &str, at: u64, msg: &Message) -> Result<(), String> {
+        // relayed messages of a cluster are logged once, as in the database
+        self.conn()?
+            .execute(
+                r"INSERT OR IGNORE INTO message_log (channel, seq, at, sender, body)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    channel,
+                    msg.seq.unwrap_or_default(),
+                    at,
+                    msg.id,
+                    serde_json::to_string(msg).unwrap()
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| format!("Failed to log a message of '{}': {}", channel, e))
+    }
+
+    fn recent(&self, channel: &str, limit: usize) -> Result<Vec<(u64, Message)>, String> {
+        let conn = self.conn()?;
+        let rows: Vec<(u64, String)> = conn
+            .prepare(
+                r"SELECT at, body FROM message_log WHERE channel = ?1
+                ORDER BY seq DESC LIMIT ?2",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_map(params![channel, limit as u64], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .collect()
+            })
+            .map_err(|e| format!("Failed to load the messages of '{}': {}", channel, e))?;
+        Ok(rows
+            .into_iter()
+            .rev()
+            .filter_map(|(at, body)| Some((at, serde_json::from_str(&body).ok()?)))
+            .collect())
+    }
+
+    fn update(&self, channel: &str, msg: &Message) -> Result<(), String> {
+        self.conn()?
+            .execute(
+                r"UPDATE message_log SET sender = ?1, body = ?2 WHERE channel = ?3 AND seq = ?4",
+                params![
+                    msg.id,
+                    serde_json::to_string(msg).unwrap(),
+                    channel,
+                    msg.seq.unwrap_or_default()
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| format!("Failed to update a message of '{}': {}", channel, e))
+    }
+
+    fn anonymize(&self, id: &str) -> Result<usize, String> {
+        let mut conn = self.conn()?;
+        let anonymize = |conn: &mut Connection| -> rusqlite::Result<usize> {
+            let tx = conn.transaction()?;
+            let messages: Vec<(String, String)> = tx
+                .prepare("SELECT channel, body FROM message_log WHERE sender = ?1")?
+                .query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            let mut count = 0;
+            for (channel, body) in messages {
+                let Some(mut msg) = serde_json::from_str::<Message>(&body)
+                    .ok()
+                    .filter(|msg| !msg.is_system)
+                else {
+                    continue;
+                };
+                msg.id = ERASED_ID.to_owned();
+                count += tx.execute(
+                    r"UPDATE message_log SET sender = ?1, body = ?2
+                    WHERE channel = ?3 AND seq = ?4",
+                    params![
+                        msg.id,
+                        serde_json::to_string(&msg).unwrap(),
+                        channel,
+                        msg.seq.unwrap_or_default()
+                    ],
+                )?;
+            }
+            tx.commit()?;
+            Ok(count)
+        };
+        anonymize(&mut conn).map_err(|e| format!("Failed to anonymize '{}': {}", id, e))
+    }
+
+    fn prune(&self, channel: Option<&str>, before: u64) -> Result<usize, String> {
+        self.conn()?
+            .execute(
+                r"DELETE FROM message_log WHERE (?1 IS NULL OR channel = ?1) AND at < ?2",
+                params![channel, before],
+            )
+            .map_err(|e| format!("Failed to prune the message log: {}", e))
+    }
+
+    fn delete_channel(&self, channel: &str) -> Result<(), String> {
+        self.conn()?
+            .execute(
+                "DELETE FROM message_log WHERE channel = ?1",
+                params![channel],
+            )
+            .map(|_| ())
+            .map_err(|e| format!("Failed to delete the messages of '{}': {}", channel, e))
+    }
+}
+
+/// A line of the files of `FileStore`
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    at: u64,
+    message: Message,
+}
+
+/// A directory per channel under `dir`, a JSON lines file per day in it, named by the UTC date
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Channels with messages stored, the channel names are valid file names
+    fn channels(&self) -> Vec<String> {
+        fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Files of `channel` by their date, oldest first
+    fn days(&self, channel: &str) -> Vec<(String, PathBuf)> {
+        let mut days: Vec<(String, PathBuf)> = fs::read_dir(self.dir.join(channel))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                Some((name.strip_suffix(".jsonl")?.to_owned(), entry.path()))
+            })
+            .collect();
+        days.sort();
+        days
+    }
+
+    fn read(path: &Path) -> Result<Vec<Entry>, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Replace the file at `path` with `entries`, the file is removed if there are none left
+    fn write(path: &Path, entries: &[Entry]) -> Result<(), String> {
+        if entries.is_empty() {
+            return fs::remove_file(path).map_err(|e| format!("{}: {}", path.display(), e));
+        }
+        let content: String = entries
+            .iter()
+            .map(|entry| format!("{}\n", serde_json::to_string(entry).unwrap()))
+            .collect();
+        // a crash leaves either the old file or the new one
+        let temp = path.with_extension("jsonl.tmp");
+        fs::write(&temp, content)
+            .and_then(|_| fs::rename(&temp, path))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Rewrite the file at `path` with `edit` applied, returns the number of entries changed
+    fn edit(path: &Path, mut edit: impl FnMut(&mut Entry) -> bool) -> Result<usize, String> {
+        let mut entries = Self::read(path)?;
+        let changed = entries
+            .iter_mut()
+            .map(&mut edit)
+            .filter(|changed| *changed)
+            .count();
+        if changed > 0 {
+            Self::write(path, &entries)?;
+        }
+        Ok(changed)
+    }
+}
+
+impl HistoryStore for FileStore {
+    fn name(&self) -> &'static str {
+        "files"
+    }
+
+    fn append(&self, channel: &str, at: u64, msg: &Message) -> Result<(), String> {
+        let dir = self.dir.join(channel);
+        fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let path = dir.join(format!("{}.jsonl", day_of(at)));
+        let line = format!(
+            "{}\n",
+            serde_json::to_string(&Entry {
+                at,
+                message: msg.clone(),
+            })
+            .unwrap()
+        );
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn recent(&self, channel: &str, limit: usize) -> Result<Vec<(u64, Message)>, String> {
+        let mut recent = Vec::new();
+        for (_, path) in self.days(channel).iter().rev() {
+            let entries = Self::read(path)?;
+            recent.extend(entries.into_iter().rev().map(|e| (e.at, e.message)));
+            if recent.len() >= limit {
+                break;
+            }
+        }
+        recent.truncate(limit);
+        recent.reverse();
+        Ok(recent)
+    }
+
+    fn update(&self, channel: &str, msg: &Message) -> Result<(), String> {
+        // the latest messages are the ones changed, e.g. retracted shortly after sending
+        for (_, path) in self.days(channel).iter().rev() {
+            let changed = Self::edit(path, |entry| {
+                if entry.message.seq != msg.seq {
+                    return false;
+                }
+                entry.message = msg.clone();
+                true
+            })?;
+            if changed > 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn anonymize(&self, id: &str) -> Result<usize, String> {
+        let mut count = 0;
+        for channel in self.channels() {
+            for (_, path) in self.days(&channel) {
+                count += Self::edit(&path, |entry| {
+                    if entry.message.is_system || entry.message.id != id {
+                        return false;
+                    }
+                    entry.message.id = ERASED_ID.to_owned();
+                    true
+                })?;
+            }
+        }
+        Ok(count)
+    }
+
+    fn prune(&self, channel: Option<&str>, before: u64) -> Result<usize, String> {
+        let channels = match channel {
+            Some(channel) => vec![channel.to_owned()],
+            None => self.channels(),
+        };
+        let last_day = day_of(before);
+        let mut dropped = 0;
+        for channel in channels {
+            for (day, path) in self.days(&channel) {
+                if day > last_day {
+                    break;
+                }
+                let mut entries = Self::read(&path)?;
+                let kept = entries.len();
+                entries.retain(|entry| entry.at >= before);
+                if entries.len() < kept {
+                    dropped += kept - entries.len();
+                    Self::write(&path, &entries)?;
+                }
+            }
+        }
+        Ok(dropped)
+    }
+
+    fn delete_channel(&self, channel: &str) -> Result<(), String> {
+        let dir = self.dir.join(channel);
+        match fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(format!("{}: {}", dir.display(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// UTC date of the unix time `secs`, `YYYY-MM-DD`
+fn day_of(secs: u64) -> String {
+    // civil date from the number of days since the epoch (Howard Hinnant's algorithm)
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Changes the writer thread makes to the store, in the order they were made
+enum Op {
+    Append {
+        channel: String,
+        at: u64,
+        msg: Message,
+    },
+    Update {
+        channel: String,
+        msg: Message,
+    },
+    Anonymize(String),
+    Prune {
+        channel: Option<String>,
+        before: u64,
+    },
+    DeleteChannel(String),
+}
+
+/// The store of the config along with the thread writing to it
+pub struct HistoryLog {
+    store: Arc<dyn HistoryStore>,
+    ops: mpsc::Sender<Op>,
+
+    /// Seconds the messages are kept for, forever if `None`
+    retention_secs: Option<u64>,
+}
+
+impl std::fmt::Debug for HistoryLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HistoryLog")
+            .field("store", &self.store.name())
+            .field("retention_secs", &self.retention_secs)
+            .finish()
+    }
+}
+
+impl HistoryLog {
+    /// Store of `config`, `None` for the `memory` backend
+    pub fn open(config: &Config) -> Option<Arc<Self>> {
+        let store: Arc<dyn HistoryStore> = match config.history.backend {
+            HistoryBackend::Memory => return None,
+            // a pool of its own, the schema is set up by the one of the server
+            HistoryBackend::Mysql => Arc::new(MysqlStore {
+                db: Database::new(&config.db_url, config.db_pool.limits(), |_| {}),
+            }),
+            HistoryBackend::Sqlite => match SqliteStore::open(&config.history.file) {
+                Ok(store) => Arc::new(store),
+                Err(e) => {
+                    println!("[!] History is kept in memory only: {}", e);
+                    return None;
+                }
+            },
+            HistoryBackend::Files => Arc::new(FileStore::new(&config.history.dir)),
+        };
+        Some(Arc::new(Self::start(
+            store,
+            config.history.retention_days.map(|days| days * 86400),
+        )))
+    }
+
+    /// Log with the writer thread of `store`
+    pub fn start(store: Arc<dyn HistoryStore>, retention_secs: Option<u64>) -> Self {
+        log::info(format_args!(
+            "History is stored with the {} backend",
+            store.name()
+        ));
+        let (ops, rx) = mpsc::channel();
+        let writer = Arc::clone(&store);
+        thread::spawn(move || {
+            for op in rx {
+                let result = match op {
+                    Op::Append { channel, at, msg } => writer.append(&channel, at, &msg),
+                    Op::Update { channel, msg } => writer.update(&channel, &msg),
+                    Op::Anonymize(id) => writer.anonymize(&id).map(|count| {
+                        log::info(format_args!(
+                            "{} stored messages of '{}' are anonymized",
+                            count, id
+                        ))
+                    }),
+                    Op::Prune { channel, before } => {
+                        writer.prune(channel.as_deref(), before).map(|count| {
+                            if count > 0 {
+                                log::info(format_args!("{} stored messages are pruned", count))
+                            }
+                        })
+                    }
+                    Op::DeleteChannel(channel) => writer.delete_channel(&channel),
+                };
+                if let Err(e) = result {
+                    println!("[!] History store: {}", e);
+                }
+            }
+        });
+        Self {
+            store,
+            ops,
+            retention_secs,
+        }
+    }
+
+    /// The latest `limit` messages stored for `channel`, none if the store can't be read
+    pub fn recent(&self, channel: &str, limit: usize) -> Vec<(u64, Message)> {
+        self.store.recent(channel, limit).unwrap_or_else(|e| {
+            println!("[!] History of '{}' is not restored: {}", channel, e);
+            Vec::new()
+        })
+    }
+
+    pub fn retention_secs(&self) -> Option<u64> {
+        self.retention_secs
+    }
+
+    pub fn anonymize(&self, id: &str) {
+        _ = self.ops.send(Op::Anonymize(id.to_owned()));
+    }
+
+    pub fn prune(&self, channel: Option<&str>, before: u64) {
+        _ = self.ops.send(Op::Prune {
+            channel: channel.map(str::to_owned),
+            before,
+        });
+    }
+
+    pub fn delete_channel(&self, channel: &str) {
+        _ = self.ops.send(Op::DeleteChannel(channel.to_owned()));
+    }
+}
+
+/// Stores the messages of a channel
+#[derive(Clone, Debug)]
+pub struct Tap {
+    channel: String,
+    log: Arc<HistoryLog>,
+}
+
+impl Tap {
+    pub fn new(channel: &str, log: &Arc<HistoryLog>) -> Self {
+        Self {
+            channel: channel.to_owned(),
+            log: Arc::clone(log),
+        }
+    }
+
+    /// Store `msg`, sent at the unix time `at`
+    pub fn record(&self, at: u64, msg: &Message) {
+        _ = self.log.ops.send(Op::Append {
+            channel: self.channel.clone(),
+            at,
+            msg: msg.clone(),
+        });
+    }
+
+    /// Store `msg` in place of the stored message with its sequence number
+    pub fn update(&self, msg: &Message) {
+        _ = self.log.ops.send(Op::Update {
+            channel: self.channel.clone(),
+            msg: msg.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(seq: u64, id: &str, text: &str) -> Message {
+        Message {
+            id: id.to_owned(),
+            msg: text.to_owned(),
+            is_system: false,
+            to: None,
+            seq: Some(seq),
+            retracted: false,
+            display_name: None,
+            reply_to: None,
+            thread: None,
+        }
+    }
+
+    #[test]
+    fn files_keep_a_day_per_file() {
+        let dir = std::env::temp_dir().join(format!("rschat_history_{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        let store = FileStore::new(&dir);
+        let day = 86400;
+        for (seq, at) in [(0, 10), (1, 20), (2, day + 10), (3, 2 * day + 10)] {
+            store
+                .append("public", at, &message(seq, "alice", "hi"))
+                .unwrap();
+        }
+        store.append("dev", 30, &message(0, "bob", "yo")).unwrap();
+        assert_eq!(store.days("public").len(), 3);
+        assert_eq!(day_of(day + 10), "1970-01-02");
+
+        let recent = store.recent("public", 3).unwrap();
+        let seqs: Vec<_> = recent.iter().map(|(_, msg)| msg.seq.unwrap()).collect();
+        assert_eq!(seqs, [1, 2, 3]);
+
+        let mut retracted = message(3, "alice", "");
+        retracted.retracted = true;
+        store.update("public", &retracted).unwrap();
+        assert!(store.recent("public", 1).unwrap()[0].1.retracted);
+
+        assert_eq!(store.anonymize("alice").unwrap(), 4);
+        assert_eq!(store.recent("public", 1).unwrap()[0].1.id, ERASED_ID);
+
+        // the first day goes along with half of the second
+        assert_eq!(store.prune(None, day + 20).unwrap(), 4);
+        assert_eq!(store.recent("public", 10).unwrap().len(), 1);
+        assert!(store.recent("dev", 10).unwrap().is_empty());
+
+        store.delete_channel("public").unwrap();
+        assert!(store.days("public").is_empty());
+        _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sqlite_keeps_the_table_of_the_database() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        for (seq, at) in [(0, 10), (1, 20), (2, 30)] {
+            store
+                .append("public", at, &message(seq, "alice", "hi"))
+                .unwrap();
+        }
+        // a message relayed again by another node is logged once
+        store
+            .append("public", 30, &message(2, "alice", "hi"))
+            .unwrap();
+        store.append("dev", 40, &message(0, "bob", "yo")).unwrap();
+
+        let recent = store.recent("public", 2).unwrap();
+        let seqs: Vec<_> = recent.iter().map(|(_, msg)| msg.seq.unwrap()).collect();
+        assert_eq!(seqs, [1, 2]);
+
+        let mut retracted = message(2, "alice", "");
+        retracted.retracted = true;
+        store.update("public", &retracted).unwrap();
+        assert!(store.recent("public", 1).unwrap()[0].1.retracted);
+
+        assert_eq!(store.anonymize("alice").unwrap(), 3);
+        assert_eq!(store.recent("public", 1).unwrap()[0].1.id, ERASED_ID);
+
+        assert_eq!(store.prune(Some("public"), 25).unwrap(), 2);
+        assert_eq!(store.prune(None, 35).unwrap(), 1);
+        assert_eq!(store.recent("dev", 10).unwrap().len(), 1);
+
+        store.delete_channel("dev").unwrap();
+        assert!(store.recent("dev", 10).unwrap().is_empty());
+    }
+}
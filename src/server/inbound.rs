@@ -0,0 +1,200 @@
+//! Packets of a client as they arrive, JSON texts written back to back without framing
+//!
+//! Reads of the socket don't line up with the packets: a read may hold several packets, e.g.
+//! coalesced by Nagle's algorithm, and a packet may be cut anywhere across reads. The bytes are
+//! kept per connection until the packets they hold are complete.
+
+use serde::de::IgnoredAny;
+
+/// What a read has completed
+#[derive(Debug, PartialEq)]
+pub enum Inbound {
+    /// Text of a packet, or of malformed input left for the parser to report
+    Packet(String),
+
+    /// A packet larger than the limit of this size was dropped, or the start of one
+    TooLarge(usize),
+}
+
+/// Bytes of the packets being received on a connection
+pub struct PacketBuffer {
+    pending: Vec<u8>,
+    max_packet_size: usize,
+}
+
+impl PacketBuffer {
+    pub fn new(max_packet_size: usize) -> Self {
+        Self {
+            pending: Vec::new(),
+            max_packet_size,
+        }
+    }
+
+    /// Add the bytes of a read, returns what they completed
+    ///
+    /// An incomplete packet stays in the buffer for the next read unless it's larger than the
+    /// limit already.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Inbound> {
+        self.pending.extend_from_slice(bytes);
+
+        let mut completed = Vec::new();
+        let mut values =
+            serde_json::Deserializer::from_slice(&self.pending).into_iter::<IgnoredAny>();
+        let mut start = 0;
+        loop {
+            match values.next() {
+                Some(Ok(_)) => {
+                    let end = values.byte_offset();
+                    completed.push(self.packet(start, end));
+                    start = end;
+                }
+                // the rest of the packet is still on the way
+                Some(Err(e)) if e.is_eof() => break,
+                // leave malformed input to the parser, so it gets reported
+                Some(Err(_)) => {
+                    completed.push(self.packet(start, self.pending.len()));
+                    start = self.pending.len();
+                    break;
+                }
+                None => {
+                    start = self.pending.len();
+                    break;
+                }
+            }
+        }
+        self.pending.drain(..start);
+
+        if self.pending.len() > self.max_packet_size {
+            completed.push(Inbound::TooLarge(self.pending.len()));
+            self.pending.clear();
+        }
+        completed
+    }
+
+    fn packet(&self, start: usize, end: usize) -> Inbound {
+        if end - start > self.max_packet_size {
+            return Inbound::TooLarge(end - start);
+        }
+        let text = String::from_utf8_lossy(&self.pending[start..end]);
+        Inbound::Packet(text.trim().to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn packet(text: &str) -> Inbound {
+        Inbound::Packet(text.to_owned())
+    }
+
+    #[test]
+    fn packets_are_split_and_joined_across_reads() {
+        let mut buffer = PacketBuffer::new(64);
+        let first = r#"{"type":"Ping","timestamp":1}"#;
+        let second = r#"{"type":"Message","msg":"{\"}"}"#;
+
+        // two in one read
+        let both = format!("{}\n{}", first, second);
+        assert_eq!(
+            buffer.push(both.as_bytes()),
+            [packet(first), packet(second)]
+        );
+
+        // one cut anywhere, across three reads
+        let (a, rest) = second.split_at(7);
+        let (b, c) = rest.split_at(10);
+        assert!(buffer.push(a.as_bytes()).is_empty());
+        assert!(buffer.push(b.as_bytes()).is_empty());
+        assert_eq!(
+            buffer.push(format!("{} {}", c, &first[..5]).as_bytes()),
+            [packet(second)]
+        );
+        assert_eq!(buffer.push(&first.as_bytes()[5..]), [packet(first)]);
+
+        // broken input is handed over whole, the buffer starts over
+        assert_eq!(buffer.push(b"{]"), [packet("{]")]);
+        assert_eq!(buffer.push(first.as_bytes()), [packet(first)]);
+
+        // oversized, complete or not
+        let large = format!(r#"{{"type":"Message","msg":"{}"}}"#, "x".repeat(64));
+        assert_eq!(
+            buffer.push(large.as_bytes()),
+            [Inbound::TooLarge(large.len())]
+        );
+        assert_eq!(
+            buffer.push(&large.as_bytes()[..65]),
+            [Inbound::TooLarge(65)]
+        );
+    }
+
+    /// Packet with text JSON has to escape, and braces within strings
+    fn message_packet() -> impl Strategy<Value = String> {
+        const ALPHABET: [char; 8] = ['a', ' ', '"', '\\', '{', '}', '\n', '🦀'];
+        (
+            prop::collection::vec(prop::sample::select(&ALPHABET[..]), 0..24),
+            any::<u32>(),
+        )
+            .prop_map(|(msg, seq)| {
+                let msg = String::from_iter(msg);
+                serde_json::json!({"type": "Message", "msg": msg, "seq": seq}).to_string()
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn packets_come_out_as_they_went_in_however_they_are_read(
+            packets in prop::collection::vec(
+                (message_packet(), prop::sample::select(&["", " ", "\n", "\r\n"][..])),
+                1..6,
+            ),
+            cuts in prop::collection::vec(any::<prop::sample::Index>(), 0..8),
+        ) {
+            let stream: String = packets
+                .iter()
+                .map(|(p, separator)| format!("{}{}", p, separator))
+                .collect();
+            let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut.index(stream.len())).collect();
+            cuts.sort_unstable();
+            cuts.dedup();
+
+            let mut buffer = PacketBuffer::new(1024);
+            let mut received = Vec::new();
+            let mut from = 0;
+            for to in cuts.into_iter().chain([stream.len()]) {
+                received.extend(buffer.push(&stream.as_bytes()[from..to]));
+                from = to;
+            }
+            let expected: Vec<Inbound> = packets.iter().map(|(p, _)| packet(p)).collect();
+            prop_assert_eq!(received, expected);
+        }
+
+        #[test]
+        fn random_reads_stay_within_the_limit(
+            // random bytes, or pieces of packets that may be cut short
+            reads in prop::collection::vec(
+                prop_oneof![
+                    prop::collection::vec(any::<u8>(), 1..48),
+                    (message_packet(), any::<prop::sample::Index>()).prop_map(|(packet, cut)| {
+                        let end = cut.index(packet.len() + 1);
+                        packet.into_bytes()[..end].to_vec()
+                    }),
+                ],
+                1..200,
+            ),
+        ) {
+            let mut buffer = PacketBuffer::new(64);
+            for read in reads {
+                for completed in buffer.push(&read) {
+                    if let Inbound::Packet(text) = completed {
+                        // invalid UTF-8 is replaced, each byte by a character of up to 3 bytes
+                        prop_assert!(text.len() <= 64 * 3, "{} bytes handed over", text.len());
+                    }
+                }
+                prop_assert!(buffer.pending.len() <= 64);
+            }
+        }
+    }
+}
@@ -0,0 +1,588 @@
+//! IRC gateway, lets IRC clients take part in rschat channels
+//!
+//! Every IRC connection drives a regular rschat session over an in-memory pipe: IRC commands are
+//! translated into packets written to the session and packets of the session are relayed back
+//! as IRC lines. A session is in one channel at a time, so joining a channel parts the other.
+
+use std::{
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_util::sync::CancellationToken;
+
+use super::ServerState;
+use crate::crypto::{auth, hash};
+use crate::db::user::Login;
+use crate::packet::*;
+
+/// Name of the server in the prefix of numeric replies
+const SERVER_NAME: &str = "rschat";
+
+/// Capacity of the pipe between the gateway and the session, larger than any packet
+const PIPE_SIZE: usize = 256 * 1024;
+
+/// Longest IRC line accepted from a client, including the trailing CRLF
+const MAX_LINE_LEN: usize = 512;
+
+/// A line of the IRC protocol, the prefix of the sender is dropped
+#[derive(Debug, PartialEq)]
+struct IrcLine {
+    command: String,
+    params: Vec<String>,
+}
+
+impl IrcLine {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let line = match line.strip_prefix(':') {
+            Some(prefixed) => prefixed.split_once(' ')?.1,
+            None => line,
+        };
+
+        // everything after " :" is a single trailing parameter
+        let (middle, trailing) = match line.split_once(" :") {
+            Some((middle, trailing)) => (middle, Some(trailing)),
+            None => (line, None),
+        };
+        let mut words = middle.split_whitespace();
+        let command = words.next()?.to_uppercase();
+        let mut params: Vec<String> = words.map(String::from).collect();
+        params.extend(trailing.map(String::from));
+        Some(Self { command, params })
+    }
+}
+
+/// IRC channel name of the rschat channel
+fn irc_channel(channel: &str) -> String {
+    format!("#{}", channel)
+}
+
+/// rschat channel name of the IRC channel
+fn rschat_channel(channel: &str) -> &str {
+    channel.trim_start_matches(['#', '&'])
+}
+
+/// State of an IRC connection shared by the command and the relay tasks
+#[derive(Default)]
+struct IrcState {
+    /// Current nickname, it becomes the rschat id after the login
+    nick: String,
+
+    /// Channel the session is in, `None` until the login
+    channel: Option<String>,
+
+    /// Message of the day, it arrives right before the response to the login
+    motd: Option<String>,
+
+    /// Hashed password and nonce of the challenge asked to log in with them
+    pending_login: Option<(String, String)>,
+}
+
+/// Lines written back to the IRC client and packets written to the session
+#[derive(Clone)]
+struct IrcWriter {
+    line_tx: mpsc::Sender<String>,
+    packet_tx: mpsc::Sender<String>,
+    state: Arc<Mutex<IrcState>>,
+}
+
+impl IrcWriter {
+    fn nick(&self) -> String {
+        self.state
+            .lock()
+            .map(|s| s.nick.clone())
+            .unwrap_or_default()
+    }
+
+    fn channel(&self) -> Option<String> {
+        self.state.lock().ok().and_then(|s| s.channel.clone())
+    }
+
+    async fn send(&self, line: String) {
+        _ = self.line_tx.send(line).await;
+    }
+
+    async fn send_packet(&self, packet: String) {
+        _ = self.packet_tx.send(packet).await;
+    }
+
+    /// Numeric reply from the server to the current nickname
+    async fn numeric(&self, code: &str, params: &str) {
+        self.send(format!(
+            ":{} {} {} {}",
+            SERVER_NAME,
+            code,
+            self.nick(),
+            params
+        ))
+        .await;
+    }
+
+    async fn notice(&self, text: &str) {
+        for line in text.lines() {
+            self.send(format!(":{} NOTICE {} :{}", SERVER_NAME, self.nick(), line))
+                .await;
+        }
+    }
+
+    /// `from` talks to `target`, every line of a multi-line message is a PRIVMSG of its own
+    async fn privmsg(&self, from: &str, target: &str, text: &str) {
+        for line in text.lines() {
+            self.send(format!(
+                ":{0}!{0}@{1} PRIVMSG {2} :{3}",
+                from, SERVER_NAME, target, line
+            ))
+            .await;
+        }
+    }
+}
+
+/// Accept IRC clients forever
+pub async fn run_gateway(listener: TcpListener, server: Arc<ServerState>) {
+    while let Ok((stream, addr)) = listener.accept().await {
+        super::log::info(format_args!("New IRC connection from: {:?}", addr));
+        tokio::spawn(irc_session(stream, addr, Arc::clone(&server)));
+    }
+}
+
+async fn irc_session(mut stream: TcpStream, addr: SocketAddr, server: Arc<ServerState>) {
+    // the tarpit only speaks rschat, trapped IRC clients are let go right away
+    if server.tarpit.traps(addr.ip()) {
+        server.audit.record(
+            &addr.ip().to_string(),
+            "tarpit",
+            "refused an IRC connection",
+        );
+        _ = stream
+            .write_all(b"ERROR :Closing link: try again later\r\n")
+            .await;
+        return;
+    }
+    let Some(permit) = server.sessions.admit() else {
+        println!("[!] Server is full, IRC connection from {:?} is shed", addr);
+        _ = stream
+            .write_all(b"ERROR :Closing link: the server is busy, try again later\r\n")
+            .await;
+        return;
+    };
+    let (gateway_end, session_end) = tokio::io::duplex(PIPE_SIZE);
+    tokio::spawn(async move {
+        super::session_task(session_end, addr, server).await;
+        drop(permit);
+    });
+    let (pipe_rd, mut pipe_wr) = tokio::io::split(gateway_end);
+    let (packet_tx, mut packet_rx) = mpsc::channel::<String>(64);
+    tokio::spawn(async move {
+        while let Some(packet) = packet_rx.recv().await {
+            if pipe_wr.write_all(packet.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let (irc_rd, mut irc_wr) = tokio::io::split(stream);
+    let (line_tx, mut line_rx) = mpsc::channel::<String>(64);
+    tokio::spawn(async move {
+        while let Some(line) = line_rx.recv().await {
+            if irc_wr
+                .write_all(format!("{}\r\n", line).as_bytes())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let writer = IrcWriter {
+        line_tx,
+        packet_tx,
+        state: Arc::new(Mutex::new(IrcState::default())),
+    };
+    let closed = CancellationToken::new();
+    tokio::spawn(relay_packets(pipe_rd, writer.clone(), closed.clone()));
+
+    let mut lines = BufReader::new(irc_rd).lines();
+    let mut password: Option<String> = None;
+    let mut has_user = false;
+    let mut logged_in = false;
+    loop {
+        let line = tokio::select! {
+            _ = closed.cancelled() => break,
+            line = lines.next_line() => match line {
+                Ok(Some(line)) if line.len() <= MAX_LINE_LEN => line,
+                Ok(Some(_)) => continue,
+                Ok(None) | Err(_) => break,
+            },
+        };
+        let Some(IrcLine { command, params }) = IrcLine::parse(&line) else {
+            continue;
+        };
+
+        let packet = match (command.as_str(), &params[..]) {
+            ("PASS", [pass, ..]) => {
+                password = Some(pass.clone());
+                None
+            }
+            ("NICK", [nick, ..]) if !logged_in => {
+                if let Ok(mut state) = writer.state.lock() {
+                    state.nick = nick.clone();
+                }
+                None
+            }
+            ("NICK", _) => {
+                writer
+                    .numeric(
+                        "484",
+                        ":Nicknames are tied to the account, reconnect to change it",
+                    )
+                    .await;
+                None
+            }
+            ("USER", _) => {
+                has_user = true;
+                None
+            }
+            ("PING", token) => {
+                let token = token.first().cloned().unwrap_or_default();
+                writer
+                    .send(format!(":{0} PONG {0} :{1}", SERVER_NAME, token))
+                    .await;
+                None
+            }
+            ("QUIT", _) => break,
+            (_, _) if !logged_in => {
+                writer.numeric("451", ":You have not registered").await;
+                None
+            }
+            ("JOIN", [channels, keys @ ..]) => {
+                // only one channel at a time, the last one wins along with its key
+                let channel = channels.split(',').next_back().unwrap_or_default();
+                let key = keys
+                    .first()
+                    .and_then(|keys| keys.split(',').nth(channels.split(',').count() - 1))
+                    .filter(|key| !key.is_empty());
+                Some(
+                    GotoReq {
+                        channel_name: rschat_channel(channel).to_owned(),
+                        password: key.map(hash::sha256_password),
+                    }
+                    .as_json_string(),
+                )
+            }
+            ("PART", _) => {
+                writer
+                    .notice("A session is always in a channel, JOIN another one instead")
+                    .await;
+                None
+            }
+            ("NAMES", _) => Some(names_request()),
+            ("PRIVMSG", [target, text, ..]) => {
+                let to = if target.starts_with(['#', '&']) {
+                    if writer.channel().as_deref() != Some(rschat_channel(target)) {
+                        writer
+                            .numeric("404", &format!("{} :You are not in the channel", target))
+                            .await;
+                        continue;
+                    }
+                    None
+                } else {
+                    Some(target.clone())
+                };
+                let msg = Message {
+                    id: String::new(),
+                    msg: text.clone(),
+                    is_system: false,
+                    to,
+                    seq: None,
+                    retracted: false,
+                    display_name: None,
+                    reply_to: None,
+                    thread: None,
+                };
+                Some(msg.as_json_string())
+            }
+            ("PRIVMSG", _) => {
+                writer
+                    .numeric("461", "PRIVMSG :Not enough parameters")
+                    .await;
+                None
+            }
+            (unknown, _) => {
+                writer
+                    .numeric("421", &format!("{} :Unknown command", unknown))
+                    .await;
+                None
+            }
+        };
+
+        // NICK and USER are both in, log in as a member if PASS was given, as a guest otherwise
+        let packet = match packet {
+            None if !logged_in && has_user && !writer.nick().is_empty() => {
+                logged_in = true;
+                match password.take() {
+                    // the session challenges the login, the proof is sent once it does
+                    Some(pass) => {
+                        let nonce = auth::nonce();
+                        if let Ok(mut state) = writer.state.lock() {
+                            state.pending_login =
+                                Some((hash::sha256_password(&pass), nonce.clone()));
+                        }
+                        Some(
+                            ChallengeReq {
+                                id: writer.nick(),
+                                nonce,
+                            }
+                            .as_json_string(),
+                        )
+                    }
+                    None => Some(
+                        LoginReq {
+                            login_info: Login::guest(),
+                        }
+                        .as_json_string(),
+                    ),
+                }
+            }
+            packet => packet,
+        };
+        if let Some(packet) = packet {
+            writer.send_packet(packet).await;
+        }
+    }
+    closed.cancel();
+}
+
+fn names_request() -> String {
+    FetchReq {
+        item: FetchItem::UserList {
+            offset: 0,
+            limit: Some(super::session::MAX_PAGE_SIZE),
+            filter: None,
+        },
+    }
+    .as_json_string()
+}
+
+/// Join `channel` on the IRC side, parting the previous channel, and ask for its names
+async fn enter_channel(writer: &IrcWriter, channel: &str) {
+    let nick = writer.nick();
+    let previous = writer
+        .state
+        .lock()
+        .ok()
+        .and_then(|mut state| state.channel.replace(channel.to_owned()));
+    if let Some(previous) = previous.as_deref().filter(|p| *p != channel) {
+        writer
+            .send(format!(
+                ":{0}!{0}@{1} PART {2}",
+                nick,
+                SERVER_NAME,
+                irc_channel(previous)
+            ))
+            .await;
+    }
+    writer
+        .send(format!(
+            ":{0}!{0}@{1} JOIN {2}",
+            nick,
+            SERVER_NAME,
+            irc_channel(channel)
+        ))
+        .await;
+    writer.send_packet(names_request()).await;
+}
+
+/// Relay packets of the session to the IRC client as IRC lines
+async fn relay_packets(
+    mut pipe_rd: ReadHalf<DuplexStream>,
+    writer: IrcWriter,
+    closed: CancellationToken,
+) {
+    loop {
+        let packet = tokio::select! {
+            _ = closed.cancelled() => break,
+            size = pipe_rd.read_u32() => {
+                let Ok(size) = size else {
+                    break;
+                };
+                let mut bytes = vec![0; size as usize];
+                if pipe_rd.read_exact(&mut bytes).await.is_err() {
+                    break;
+                }
+                String::from_utf8(bytes).unwrap_or_default()
+            }
+        };
+
+        match PacketType::from_str(&packet) {
+            Ok(PacketType::ChallengeRes(res)) => {
+                let pending = writer
+                    .state
+                    .lock()
+                    .ok()
+                    .and_then(|mut state| state.pending_login.take());
+                let Some((password, nonce)) = pending else {
+                    continue;
+                };
+                let nick = writer.nick();
+                let proof = res
+                    .result
+                    .map_err(|e| e.to_string())
+                    .and_then(|challenge| challenge.prove(&nick, &nonce, &password));
+                match proof {
+                    Ok(proof) => {
+                        let login_info = Login {
+                            guest: false,
+                            id: Some(nick),
+                            proof: Some(proof),
+                        };
+                        writer
+                            .send_packet(LoginReq { login_info }.as_json_string())
+                            .await;
+                    }
+                    Err(e) => {
+                        writer.numeric("464", &format!(":{}", e)).await;
+                        writer.send(format!("ERROR :Closing link: {}", e)).await;
+                        break;
+                    }
+                }
+            }
+            Ok(PacketType::LoginRes(res)) => match res.result {
+                Ok(id) => {
+                    let nick = writer.nick();
+                    if nick != id {
+                        writer
+                            .send(format!(":{0}!{0}@{1} NICK {2}", nick, SERVER_NAME, id))
+                            .await;
+                        if let Ok(mut state) = writer.state.lock() {
+                            state.nick = id.clone();
+                        }
+                    }
+                    writer
+                        .numeric("001", &format!(":Welcome to rschat, {}", id))
+                        .await;
+                    let motd = writer.state.lock().ok().and_then(|mut s| s.motd.take());
+                    match motd {
+                        Some(motd) => {
+                            writer
+                                .numeric("375", &format!(":- {} Message of the day -", SERVER_NAME))
+                                .await;
+                            for line in motd.lines() {
+                                writer.numeric("372", &format!(":- {}", line)).await;
+                            }
+                            writer.numeric("376", ":End of /MOTD command").await;
+                        }
+                        None => writer.numeric("422", ":MOTD File is missing").await,
+                    }
+                    enter_channel(&writer, super::session::DEFAULT_CHANNEL).await;
+                }
+                Err(e) => {
+                    writer.numeric("464", &format!(":{}", e)).await;
+                    writer.send(format!("ERROR :Closing link: {}", e)).await;
+                    break;
+                }
+            },
+            Ok(PacketType::Motd(motd)) => {
+                if let Ok(mut state) = writer.state.lock() {
+                    state.motd = Some(motd.text);
+                }
+            }
+            Ok(PacketType::GotoRes(res)) => match res.result {
+                Ok(channel) => {
+                    enter_channel(&writer, &channel).await;
+                    if let Some(topic) = res.topic {
+                        writer
+                            .numeric("332", &format!("{} :{}", irc_channel(&channel), topic))
+                            .await;
+                    }
+                }
+                // ERR_BADCHANNELKEY for a missing or wrong password
+                Err(e) if e.code == ErrorCode::WrongCredentials => {
+                    writer.numeric("475", &format!("* :{}", e)).await;
+                }
+                Err(e) => {
+                    writer.numeric("403", &format!("* :{}", e)).await;
+                }
+            },
+            Ok(PacketType::ChannelClosed(closed)) => {
+                writer.notice(&closed.reason).await;
+                enter_channel(&writer, &closed.moved_to).await;
+            }
+            Ok(PacketType::FetchRes(res)) if matches!(res.item, FetchItem::UserList { .. }) => {
+                let channel = irc_channel(&writer.channel().unwrap_or_default());
+                if let Ok(page) = res.result {
+                    let names = page["user_list"]
+                        .as_array()
+                        .map(|users| {
+                            users
+                                .iter()
+                                .filter_map(|u| u.as_str())
+                                .collect::<Vec<_>>()
+                                .join(" ")
+                        })
+                        .unwrap_or_default();
+                    writer
+                        .numeric("353", &format!("= {} :{}", channel, names))
+                        .await;
+                }
+                writer
+                    .numeric("366", &format!("{} :End of /NAMES list", channel))
+                    .await;
+            }
+            Ok(PacketType::Message(msg)) => {
+                let channel = irc_channel(&writer.channel().unwrap_or_default());
+                if msg.is_system {
+                    for line in msg.msg.lines() {
+                        writer
+                            .send(format!(":{} NOTICE {} :{}", SERVER_NAME, channel, line))
+                            .await;
+                    }
+                } else if msg.to.is_some() {
+                    writer.privmsg(&msg.id, &writer.nick(), &msg.msg).await;
+                } else if msg.id == writer.nick() {
+                    // IRC clients show their own messages already, the echo isn't needed
+                } else {
+                    writer.privmsg(&msg.id, &channel, &msg.msg).await;
+                }
+            }
+            Ok(PacketType::Mention(mention)) => {
+                writer
+                    .notice(&format!(
+                        "{} mentioned you in {}: {}",
+                        mention.message.id,
+                        irc_channel(&mention.channel_name),
+                        mention.message.msg
+                    ))
+                    .await;
+            }
+            Ok(PacketType::Invite(invite)) => {
+                writer
+                    .send(format!(
+                        ":{0}!{0}@{1} INVITE {2} {3}",
+                        invite.from,
+                        SERVER_NAME,
+                        writer.nick(),
+                        irc_channel(&invite.channel_name)
+                    ))
+                    .await;
+            }
+            Ok(PacketType::LimitExceeded(exceeded)) => {
+                writer
+                    .notice(&format!(
+                        "{} is too large ({} > {} bytes)",
+                        exceeded.what, exceeded.size, exceeded.limit
+                    ))
+                    .await;
+            }
+            _ => (),
+        }
+    }
+    closed.cancel();
+}
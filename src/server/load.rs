@@ -0,0 +1,79 @@
+//! Admission of sessions
+//!
+//! Every session holds a permit for as long as it runs. Connections arriving while every permit
+//! is taken are shed: they're told the server is busy and closed, so the sessions running keep
+//! their share of a small machine.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+
+use crate::packet::{AsJson, ServerBusy};
+
+/// Longest a shed connection gets to take the notice
+const SHED_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct SessionLimiter {
+    permits: Arc<Semaphore>,
+    max_sessions: usize,
+
+    /// Connections turned away since the start
+    shed: AtomicU64,
+}
+
+impl SessionLimiter {
+    pub fn new(max_sessions: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_sessions)),
+            max_sessions,
+            shed: AtomicU64::new(0),
+        }
+    }
+
+    /// Permit to run a session, `None` if the server is full
+    pub fn admit(&self) -> Option<OwnedSemaphorePermit> {
+        let permit = Arc::clone(&self.permits).try_acquire_owned().ok();
+        if permit.is_none() {
+            self.shed.fetch_add(1, Ordering::Relaxed);
+        }
+        permit
+    }
+
+    /// Sessions running now
+    pub fn active(&self) -> usize {
+        self.max_sessions - self.permits.available_permits()
+    }
+
+    /// Current load, as reported in the stats
+    pub fn report(&self) -> serde_json::Value {
+        serde_json::json!({
+            "sessions": self.active(),
+            "max_sessions": self.max_sessions,
+            "shed": self.shed.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Tell a client turned away that the server is busy, then close the connection
+pub async fn shed<S: AsyncWrite + Unpin>(mut stream: S) {
+    let busy = ServerBusy {
+        reason: "the server is busy, try again later".to_owned(),
+    }
+    .as_json_bytes();
+    let mut frame = (busy.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(&busy);
+    _ = tokio::time::timeout(SHED_TIMEOUT, async {
+        _ = stream.write_all(&frame).await;
+        _ = stream.shutdown().await;
+    })
+    .await;
+}
@@ -0,0 +1,18 @@
+//! Verbosity of the server log, changeable while the server runs
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::config::LogLevel;
+
+static INFO: AtomicBool = AtomicBool::new(true);
+
+pub fn set_level(level: LogLevel) {
+    INFO.store(level >= LogLevel::Info, Ordering::Relaxed);
+}
+
+/// Print `msg` unless the log is limited to problems
+pub fn info(msg: std::fmt::Arguments) {
+    if INFO.load(Ordering::Relaxed) {
+        println!("{}", msg);
+    }
+}
@@ -1,27 +1,249 @@
 use std::{
+    collections::HashMap,
+    net::SocketAddr,
     str::FromStr,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex, RwLock,
     },
+    time::Duration,
 };
 
-use mysql::{prelude::*, *};
+use mysql::*;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, WriteHalf},
-    net::{TcpListener, TcpStream},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, WriteHalf},
+    net::TcpListener,
     sync::{broadcast, mpsc, Mutex as AsyncMutex},
 };
 use tokio_util::sync::CancellationToken;
 
-use crate::crypto::hash;
+use crate::cli::ServerOptions;
+use crate::crypto::auth;
+use crate::db::{
+    self,
+    channel::ChannelRecord,
+    user::{Approval, Login, Role},
+    Database,
+};
 use crate::packet::*;
+use dead_letter::Queue;
 
+pub mod account;
+pub mod admin;
+pub mod audit;
+pub mod backup;
+pub mod bridge;
+pub mod cluster;
+pub mod config;
+pub mod console;
+pub mod dead_letter;
+pub mod filter;
+pub mod guests;
+pub mod history;
+pub mod inbound;
+pub mod irc;
+pub mod load;
+pub mod log;
+pub mod normalize;
+pub mod permissions;
+pub mod plugin;
+pub mod presence;
+pub mod registry;
+pub mod reload;
+pub mod scheduler;
 pub mod session;
+pub mod tarpit;
+pub mod token;
+pub mod traffic;
+pub mod transcript;
+
+/// Bytes of a packet as written to a client, shared by the subscribers of a channel
+pub type Frame = Arc<[u8]>;
+
+/// What the client said about itself in the handshake
+#[derive(Default)]
+pub struct Peer {
+    /// Protocol version, e.g. to stream responses
+    pub version: AtomicU32,
+
+    /// Asked for less traffic, see `LITE_CAPABILITY`
+    pub lite: AtomicBool,
+}
+
+/// Messages of the history sent on join to the clients asking for less traffic
+const LITE_HISTORY_MESSAGES: usize = 10;
+
+/// Server-wide state shared by every session task
+pub struct ServerState {
+    pub audit: audit::AuditLog,
+    pub bridges: bridge::Bridges,
+    pub channels: AsyncMutex<session::Channels>,
+
+    /// Other servers sharing the channels, `None` unless the server is a cluster node
+    pub cluster: Option<cluster::Cluster>,
+
+    /// Config the server runs with, updated by reloads
+    pub config: Mutex<config::Config>,
+    pub db: Database,
+    pub dead_letters: dead_letter::DeadLetters,
+    pub filters: RwLock<filter::FilterPipeline>,
+    pub limits: RwLock<config::LimitConfig>,
+
+    /// Options the server was started with, to find the config again
+    pub options: ServerOptions,
+    pub plugins: plugin::PluginHost,
+    pub presence: presence::Presences,
+    pub registry: Mutex<registry::Registry>,
+    pub scheduler: scheduler::Scheduler,
+    pub sessions: load::SessionLimiter,
+    pub tarpit: tarpit::Tarpit,
+    pub traffic: traffic::Traffic,
+
+    /// Session tokens no longer accepted, e.g. of the kicked members
+    pub revocations: token::Revocations,
+
+    /// Server-wide roles of the members, shared with the channels
+    pub roles: Arc<permissions::Roles>,
+
+    /// Guest sessions waiting for the decision on the account they registered, by account
+    pub registrants: Mutex<HashMap<String, String>>,
+}
 
-/// write `bytes` to the TCP stream with size header
-async fn send_sized_bytes(
-    wr: &mut WriteHalf<TcpStream>,
+impl ServerState {
+    /// Limits as of now, they may change with a reload
+    pub fn limits(&self) -> config::LimitConfig {
+        self.limits.read().map(|l| l.clone()).unwrap_or_default()
+    }
+
+    /// Limits told to the clients, as of now
+    pub fn announced_limits(&self) -> ServerLimits {
+        let limits = self.limits();
+        let max_message_len = self
+            .config
+            .lock()
+            .map(|c| c.filter.max_message_len)
+            .unwrap_or_default();
+        ServerLimits {
+            max_packet_size: limits.max_packet_size,
+            max_message_size: limits.max_message_size,
+            max_message_len,
+            messages_per_minute: limits.messages_per_minute,
+            retract_window_secs: limits.retract_window_secs,
+        }
+    }
+
+    /// Message of the day as of now, changed by the admins or a reload
+    pub fn motd(&self) -> Option<String> {
+        self.config.lock().ok().and_then(|c| c.motd.clone())
+    }
+
+    /// Send `motd` after the logins from now on, until the next reload sets the one of the file
+    pub fn set_motd(&self, motd: Option<String>) {
+        if let Ok(mut config) = self.config.lock() {
+            config.motd = motd;
+        }
+    }
+
+    /// Keys and lifetime of the session tokens as of now, they may change with a reload
+    pub fn session_tokens(&self) -> config::SessionTokenConfig {
+        self.config
+            .lock()
+            .map(|c| c.session_tokens.clone())
+            .unwrap_or_default()
+    }
+
+    /// What guests may do as of now, it may change with a reload
+    pub fn guest_policy(&self) -> config::GuestConfig {
+        self.config
+            .lock()
+            .map(|c| c.guests.clone())
+            .unwrap_or_default()
+    }
+
+    /// True if new accounts wait for the approval of an admin, it may change with a reload
+    pub fn approves_registrations(&self) -> bool {
+        self.config.lock().is_ok_and(|c| c.registration.approval)
+    }
+
+    /// Run the filters of `channel` on `msg`, see `FilterPipeline::apply`
+    pub fn filter(&self, channel: &str, msg: &Message) -> Result<(), String> {
+        match self.filters.read() {
+            Ok(filters) => filters.apply(channel, msg),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Run `f` with the database on a blocking thread and wait for it
+    ///
+    /// The driver sleeps between its retries and blocks while the pool is exhausted, it must not
+    /// hold up the threads serving the sessions. Don't call it with the channels locked.
+    pub async fn with_db<T, F>(self: &Arc<Self>, f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&ServerState) -> T + Send + 'static,
+    {
+        let server = Arc::clone(self);
+        match tokio::task::spawn_blocking(move || f(&server)).await {
+            Ok(result) => result,
+            // as if it had panicked on the session
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+/// Removes the session from the registry once the session task ends
+struct RegistryGuard {
+    server: Arc<ServerState>,
+    id: Arc<Mutex<String>>,
+    res_tx: mpsc::Sender<PacketType>,
+}
+
+impl Drop for RegistryGuard {
+    fn drop(&mut self) {
+        if let (Ok(mut registry), Ok(id)) = (self.server.registry.lock(), self.id.lock()) {
+            // another session of the same member keeps the presence
+            if registry
+                .get(id.as_str())
+                .is_some_and(|tx| tx.same_channel(&self.res_tx))
+            {
+                self.server.presence.forget(id.as_str());
+            }
+            registry.unregister(id.as_str(), &self.res_tx);
+        }
+    }
+}
+
+/// Adds the traffic of the connection to its account once the session task ends
+struct TrafficGuard {
+    server: Arc<ServerState>,
+    meter: Arc<traffic::Meter>,
+}
+
+impl Drop for TrafficGuard {
+    fn drop(&mut self) {
+        self.server.traffic.close(&self.meter);
+
+        // the access log, the address stands for the connections that never logged in
+        let id = self.meter.id();
+        let totals = self.meter.totals();
+        self.server.audit.record(
+            if id.is_empty() { "-" } else { &id },
+            "connection",
+            &format!(
+                "from {} for {}s, {} bytes in, {} bytes out, {} messages",
+                self.meter.addr,
+                self.meter.open_secs(),
+                totals.bytes_in,
+                totals.bytes_out,
+                totals.messages_in
+            ),
+        );
+    }
+}
+
+/// write `bytes` to the stream with size header
+async fn send_sized_bytes<S: AsyncWrite>(
+    wr: &mut WriteHalf<S>,
     bytes: &[u8],
 ) -> Result<(), Box<dyn std::error::Error>> {
     // super simple message protocol [Size: u32][Message: bytes]
@@ -32,67 +254,498 @@ async fn send_sized_bytes(
     Ok(())
 }
 
+/// Time the messages of a burst are collected for, from the first one
+const BATCH_WINDOW: Duration = Duration::from_millis(5);
+
+/// Messages of a burst written in a single frame at most
+const MAX_BATCH_SIZE: usize = 16;
+
 /// Consume messages from `sock_rx` channel and write them to `wr` directly
-async fn stream_sender(mut wr: WriteHalf<TcpStream>, mut sock_rx: mpsc::Receiver<Vec<u8>>) {
-    loop {
-        if let Some(bytes) = sock_rx.recv().await {
-            _ = send_sized_bytes(&mut wr, bytes.as_slice()).await;
+///
+/// A stalled write is never abandoned halfway, which would break the framing, it's given
+/// `max_write_timeouts` timeouts instead. `dead_token` is notified if the client doesn't catch up
+/// by then or the stream fails.
+async fn stream_sender<S: AsyncWrite>(
+    mut wr: WriteHalf<S>,
+    mut sock_rx: mpsc::Receiver<Frame>,
+    limits: config::LimitConfig,
+    dead_token: CancellationToken,
+    meter: Arc<traffic::Meter>,
+) {
+    let write_timeout = Duration::from_secs(limits.write_timeout_secs.max(1));
+
+    // ends once every sender is gone
+    while let Some(bytes) = sock_rx.recv().await {
+        let write = send_sized_bytes(&mut wr, &bytes);
+        tokio::pin!(write);
+        let mut timeouts = 0;
+        loop {
+            tokio::select! {
+                written = &mut write => {
+                    if written.is_err() {
+                        dead_token.cancel();
+                        return;
+                    }
+                    meter.wrote(bytes.len() + 4);
+                    break;
+                }
+                _ = tokio::time::sleep(write_timeout) => {
+                    timeouts += 1;
+                    if timeouts >= limits.max_write_timeouts {
+                        println!("[!] Client stopped reading, the connection is closed");
+                        dead_token.cancel();
+                        return;
+                    }
+                    println!(
+                        "[!] Write to a client timed out ({}/{})",
+                        timeouts, limits.max_write_timeouts
+                    );
+                }
+            }
         }
     }
 }
 
+/// Remove the session `id` from the channel it's counted in and let the channel know, once it's
+/// gone
+async fn leave_channel(
+    server: &ServerState,
+    session: session::SessionKey,
+    id: &Arc<Mutex<String>>,
+) {
+    let id = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+    let mut channels_lock = server.channels.lock().await;
+    let Some(channel_name) = channels_lock.leave(&id, session) else {
+        return;
+    };
+
+    // disconnection broadcasting, invisible members leave silently
+    if let Some(channel) = channels_lock.get_mut(&channel_name) {
+        if server.presence.get(&id) != Presence::Invisible {
+            channel.broadcast(Message::disconnection(&id));
+        }
+    }
+}
+
+/// Send the recorded messages of `channel_name` after `last_seq` to a subscriber that has lagged
+/// behind or just logged in, along with the current settings of the channel
+///
+/// Messages that are gone from the history too are reported as a gap. Returns the sequence
+/// number of the latest message sent.
+async fn resync(
+    server: &ServerState,
+    channel_name: &str,
+    last_seq: Option<u64>,
+    sock_tx: &mpsc::Sender<Frame>,
+    id: &Arc<Mutex<String>>,
+    lite: bool,
+) -> Option<u64> {
+    let (missed, info) = {
+        let channels_lock = server.channels.lock().await;
+        let Some(channel) = channels_lock.get(channel_name) else {
+            return last_seq;
+        };
+        let missed: Vec<Message> = channel
+            .history
+            .iter()
+            .filter(|msg| msg.seq > last_seq)
+            .cloned()
+            .collect();
+        (missed, channel.info(channel_name))
+    };
+
+    let next_seq = last_seq.map_or(0, |seq| seq + 1);
+    let gap = missed
+        .first()
+        .and_then(|msg| msg.seq)
+        .map_or(0, |first| first.saturating_sub(next_seq));
+    if last_seq.is_some() && gap > 0 {
+        let notice = format!("{} messages were missed, they're too old to be sent", gap);
+        server
+            .dead_letters
+            .send(
+                Queue::Socket,
+                sock_tx,
+                Message::system_notice(&notice).as_json_bytes().into(),
+            )
+            .await;
+    }
+
+    let self_id = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+    let latest = missed.last().and_then(|msg| msg.seq).or(last_seq);
+    for msg in missed
+        .into_iter()
+        .filter(|msg| !(msg.is_system && msg.id == self_id))
+    {
+        let msg = if lite { msg.lite() } else { msg };
+        server
+            .dead_letters
+            .send(Queue::Socket, sock_tx, msg.as_json_bytes().into())
+            .await;
+    }
+    server
+        .dead_letters
+        .send(Queue::Socket, sock_tx, info.as_json_bytes().into())
+        .await;
+    latest
+}
+
 /// Consumer for the channel `msg_rx`
 ///
-/// This task can be gracefully terminated by notifying the `cancel_token`.
+/// This task can be gracefully terminated by notifying the `cancel_token`. Packets the session
+/// itself has to react on are forwarded to `ctl_tx`. If the subscriber lags behind the broadcast,
+/// it's resynchronized from the history of the channel.
+///
+/// Messages are sent only once the session has logged in. The history serves as the buffer of
+/// the messages broadcasted before, they're sent in order once the session announces itself with
+/// `Connected`.
+///
+/// Messages of a burst, queued while one is taken, are collected for `BATCH_WINDOW` and written in
+/// a single `MessageBatch` to the clients understanding it, a lone message goes out right away.
+#[allow(clippy::too_many_arguments)]
 async fn message_handler(
+    server: Arc<ServerState>,
     mut channel_tx: broadcast::Receiver<PacketType>,
-    sock_tx: mpsc::Sender<Vec<u8>>,
+    mut snapshot: JoinSnapshot,
+    sock_tx: mpsc::Sender<Frame>,
+    ctl_tx: mpsc::Sender<PacketType>,
     cancel_token: CancellationToken,
     id: Arc<Mutex<String>>,
+    peer: Arc<Peer>,
 ) {
-    let connected = AtomicBool::new(false);
+    // history of the channel goes first, then the messages broadcasted since the subscription
+    // the handshake may come after the history of the first channel, it's checked again later
+    let lite = || peer.lite.load(Ordering::Relaxed);
+    if lite() {
+        snapshot = snapshot.lite(LITE_HISTORY_MESSAGES);
+    }
+    let channel_name = snapshot.channel_name.clone();
+    let mut last_seq = snapshot
+        .messages
+        .iter()
+        .filter_map(|msg| msg.seq)
+        .next_back();
+    if peer.version.load(Ordering::Relaxed) >= stream::STREAMING_VERSION {
+        let messages = std::mem::take(&mut snapshot.messages);
+        let header = serde_json::to_value(&snapshot).unwrap();
+        send_streamed(
+            &sock_tx,
+            JoinSnapshot::PACKET_TYPE,
+            header,
+            "/messages",
+            &messages,
+        )
+        .await;
+    } else {
+        server
+            .dead_letters
+            .send(Queue::Socket, &sock_tx, snapshot.as_json_bytes().into())
+            .await;
+    }
+
+    let logged_in = || id.lock().is_ok_and(|lock| !lock.is_empty());
+    let mut connected = logged_in();
+    let mut batch: Vec<Frame> = Vec::new();
+    let mut flush_at = None;
     loop {
-        tokio::select! {
+        let message = tokio::select! {
             _ = cancel_token.cancelled() => {
                 break
             }
-            message = channel_tx.recv() => match message {
-                Ok(PacketType::Message(msg)) => {
-                    // Client hasn't connected successfully yet
-                    if !connected.load(Ordering::Relaxed) {
-                        continue;
-                    }
+            _ = tokio::time::sleep_until(flush_at.unwrap_or_else(tokio::time::Instant::now)),
+                if flush_at.is_some() =>
+            {
+                flush_at = None;
+                send_batch(&server, &sock_tx, &mut batch).await;
+                continue;
+            }
+            message = channel_tx.recv() => message,
+        };
+        // the other packets keep their place after the messages of the batch
+        if !matches!(message, Ok(PacketType::Broadcast(_))) {
+            flush_at = None;
+            send_batch(&server, &sock_tx, &mut batch).await;
+        }
+        match message {
+            Ok(PacketType::Broadcast(Broadcast {
+                message: msg,
+                frame,
+            })) => {
+                // Client hasn't connected successfully yet
+                if !connected {
+                    continue;
+                }
 
-                    // Skip message from the current client handler
-                    match id.lock() {
-                        Ok(lock) if lock.as_str() == msg.id => continue,
-                        Err(_) => continue,
-                        Ok(_) => (),
-                    }
+                // Already sent while resynchronizing
+                if msg.seq.is_some() && msg.seq <= last_seq {
+                    continue;
+                }
+                last_seq = msg.seq.or(last_seq);
+
+                // Own messages are echoed so the client can show them in the order of the
+                // channel, but not the notices of joining and leaving
+                match id.lock() {
+                    Ok(lock) if msg.is_system && lock.as_str() == msg.id => continue,
+                    Err(_) => continue,
+                    Ok(_) => (),
+                }
 
-                    // Write message to the stream
-                    _ = sock_tx.send(msg.as_json_bytes()).await;
+                // Write message to the stream, along with the rest of the burst
+                batch.push(if lite() {
+                    Broadcast {
+                        message: msg,
+                        frame,
+                    }
+                    .lite_frame()
+                } else {
+                    frame
+                });
+                let batching = peer.version.load(Ordering::Relaxed) >= BATCHING_VERSION;
+                if flush_at.is_none() && batching && !channel_tx.is_empty() {
+                    flush_at = Some(tokio::time::Instant::now() + BATCH_WINDOW);
+                } else if flush_at.is_none() || batch.len() >= MAX_BATCH_SIZE {
+                    flush_at = None;
+                    send_batch(&server, &sock_tx, &mut batch).await;
                 }
-                Ok(PacketType::Connected(_)) => {
-                    connected.store(true, Ordering::Relaxed);
+            }
+            // Any session of the channel may have logged in, this one only counts if its id is set
+            Ok(PacketType::Connected(_)) => {
+                if !connected && logged_in() {
+                    connected = true;
+                    last_seq =
+                        resync(&server, &channel_name, last_seq, &sock_tx, &id, lite()).await;
                 }
-                _ => continue,
             }
+            Ok(PacketType::ReactionUpdate(update)) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, update.as_json_bytes().into())
+                    .await;
+            }
+            Ok(PacketType::PinUpdate(update)) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, update.as_json_bytes().into())
+                    .await;
+            }
+            Ok(PacketType::RetractUpdate(update)) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, update.as_json_bytes().into())
+                    .await;
+            }
+            // Settings of the channel have changed
+            Ok(PacketType::ChannelInfo(info)) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, info.as_json_bytes().into())
+                    .await;
+            }
+            // The channel is going away, the session has to move to another channel
+            Ok(PacketType::ChannelClosed(closed))
+                if closed
+                    .user
+                    .as_ref()
+                    .is_none_or(|user| id.lock().is_ok_and(|id| *id == *user)) =>
+            {
+                server
+                    .dead_letters
+                    .send(Queue::Control, &ctl_tx, PacketType::ChannelClosed(closed))
+                    .await;
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                println!(
+                    "[!] A subscriber of #{} lagged behind by {} packets",
+                    channel_name, skipped
+                );
+                server.dead_letters.record(
+                    Queue::Channel,
+                    dead_letter::Reason::Lagged,
+                    format!("#{}", channel_name),
+                    skipped,
+                );
+
+                // `Connected` may be among the skipped packets, a logged in session is connected
+                if logged_in() {
+                    connected = true;
+                    last_seq =
+                        resync(&server, &channel_name, last_seq, &sock_tx, &id, lite()).await;
+                }
+            }
+            // The channel has been deleted
+            Err(broadcast::error::RecvError::Closed) => break,
+            _ => continue,
         }
     }
 }
 
+/// Write the messages of `batch`, in a single frame if there are several
+async fn send_batch(server: &ServerState, sock_tx: &mpsc::Sender<Frame>, batch: &mut Vec<Frame>) {
+    let frames = std::mem::take(batch);
+    let frame = match &frames[..] {
+        [] => return,
+        [frame] => frame.clone(),
+        frames => MessageBatch::join(frames),
+    };
+    server
+        .dead_letters
+        .send(Queue::Socket, sock_tx, frame)
+        .await;
+}
+
+/// Let the channel and the plugins know `new_id` logged in on the session in `current_channel`
+///
+/// Returns the packets following the response: the presence restored from the last time, where
+/// the member stopped reading the channels, the notices of what happened while they were away
+/// and the greetings of the plugins.
+async fn welcome(
+    server: &Arc<ServerState>,
+    new_id: &str,
+    current_channel: &str,
+    channel_tx: &broadcast::Sender<PacketType>,
+) -> Vec<PacketType> {
+    server.plugins.on_login(new_id);
+    server.plugins.on_channel_join(new_id, current_channel);
+    let id = new_id.to_owned();
+    let (presence, notices, markers) = server
+        .with_db(move |server| {
+            let presence = server.presence.on_login(&id, &server.db);
+            let notices = db::notice::take(&id, &server.db).unwrap_or_else(|e| {
+                println!("[!] {}", e);
+                Vec::new()
+            });
+            let markers = db::read_marker::load(&id, &server.db).unwrap_or_else(|e| {
+                println!("[!] {}", e);
+                Default::default()
+            });
+            (presence, notices, markers)
+        })
+        .await;
+    if presence != Presence::Invisible {
+        if let Some(channel) = server.channels.lock().await.get_mut(current_channel) {
+            channel.broadcast(Message::connection(new_id));
+        }
+    }
+    server
+        .dead_letters
+        .broadcast(channel_tx, PacketType::Connected(Connected {}));
+
+    let mut packets = Vec::new();
+    if presence != Presence::Online {
+        packets.push(PacketType::PresenceRes(PresenceRes {
+            result: Ok(presence),
+        }));
+    }
+    if !markers.is_empty() {
+        let channels_lock = server.channels.lock().await;
+        let unread = markers
+            .iter()
+            .filter_map(|(name, seq)| {
+                Some((name.clone(), channels_lock.get(name)?.unread_after(*seq)))
+            })
+            .collect();
+        drop(channels_lock);
+        packets.push(PacketType::ReadMarkers(ReadMarkers { markers, unread }));
+    }
+    packets.extend(
+        notices
+            .iter()
+            .chain(&server.plugins.on_greet(new_id, current_channel))
+            .map(|notice| PacketType::Message(Message::system_notice(notice))),
+    );
+    packets
+}
+
+/// True for the requests acting as the id of the session, they're refused before the login
+fn needs_login(packet: &PacketType) -> bool {
+    matches!(
+        packet,
+        PacketType::Message(_)
+            | PacketType::FetchReq(_)
+            | PacketType::GotoReq(_)
+            | PacketType::ChannelReq(_)
+            | PacketType::AdminReq(_)
+            | PacketType::AccountReq(_)
+            | PacketType::Invite(_)
+            | PacketType::PinReq(_)
+            | PacketType::PresenceReq(_)
+            | PacketType::ReactionReq(_)
+            | PacketType::ReadMarkerReq(_)
+            | PacketType::RetractReq(_)
+            | PacketType::ScheduleReq(_)
+            | PacketType::UpgradeReq(_)
+    )
+}
+
+/// Channel `id` was last in, unless it's `current_channel` or can't be joined anymore
+async fn last_channel(
+    server: &Arc<ServerState>,
+    id: &str,
+    current_channel: &str,
+) -> Option<String> {
+    let member = id.to_owned();
+    let channel = server
+        .with_db(move |server| db::last_channel::load(&member, &server.db))
+        .await
+        .unwrap_or_else(|e| {
+            println!("[!] {}", e);
+            None
+        })
+        .filter(|channel| channel != current_channel)?;
+    let channels_lock = server.channels.lock().await;
+    channels_lock
+        .get(&channel)
+        .is_some_and(|c| !c.archived && !c.banned.contains(id))
+        .then_some(channel)
+}
+
+/// Write the response `header` of the type `packet` in parts, `items` being its list at `pointer`
+async fn send_streamed<T: serde::Serialize>(
+    sock_tx: &mpsc::Sender<Frame>,
+    packet: &str,
+    header: serde_json::Value,
+    pointer: &str,
+    items: &[T],
+) {
+    for frame in stream::frames(packet, header, pointer, items) {
+        if sock_tx.send(frame.into()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Consumer of the responses to the current client
+///
+/// Long lists of the responses are streamed to the clients that said they understand streams.
 async fn response_handler(
+    server: Arc<ServerState>,
     mut res_rx: mpsc::Receiver<PacketType>,
-    sock_tx: mpsc::Sender<Vec<u8>>,
+    sock_tx: mpsc::Sender<Frame>,
     id: Arc<Mutex<String>>,
+    peer: Arc<Peer>,
 ) {
-    loop {
-        match res_rx.recv().await {
-            Some(PacketType::RegisterRes(r)) => {
-                _ = sock_tx.send(r.as_json_bytes()).await;
+    // ends once the session and every other sender are gone
+    while let Some(packet) = res_rx.recv().await {
+        match packet {
+            PacketType::HelloRes(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::ChallengeRes(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
             }
-            Some(PacketType::LoginRes(mut r)) => {
+            PacketType::RegisterRes(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::LoginRes(mut r) => {
                 // Login was successful, update the id
                 if let Ok(mut lock) = id.lock() {
                     if let Ok(login_id) = &r.result {
@@ -100,250 +753,1964 @@ async fn response_handler(
                     }
                 } else if r.result.is_ok() {
                     // somehow failed to lock the id
-                    r.result = Err("failed to login".to_owned());
+                    r.result = Err(PacketError::new(ErrorCode::Internal, "failed to login"));
+                }
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::ResumeRes(r) => {
+                if let (Ok(resumed_id), Ok(mut lock)) = (&r.result, id.lock()) {
+                    *lock = resumed_id.clone();
                 }
-                _ = sock_tx.send(r.as_json_bytes()).await;
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
             }
-            Some(PacketType::FetchRes(r)) => {
-                _ = sock_tx.send(r.as_json_bytes()).await;
+            PacketType::UpgradeRes(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
             }
-            Some(PacketType::GotoRes(r)) => {
-                _ = sock_tx.send(r.as_json_bytes()).await;
+            PacketType::FetchRes(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::GotoRes(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::ChannelRes(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::AdminRes(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::AccountRes(mut r) => {
+                // the messages of an export are its bulk
+                let messages = match &mut r.result {
+                    Ok(bundle)
+                        if peer.version.load(Ordering::Relaxed) >= stream::STREAMING_VERSION =>
+                    {
+                        bundle
+                            .get_mut("messages")
+                            .map(serde_json::Value::take)
+                            .filter(serde_json::Value::is_array)
+                    }
+                    _ => None,
+                };
+                match messages {
+                    Some(serde_json::Value::Array(messages)) => {
+                        let header = serde_json::to_value(&r).unwrap();
+                        let pointer = "/result/Ok/messages";
+                        send_streamed(
+                            &sock_tx,
+                            AccountRes::PACKET_TYPE,
+                            header,
+                            pointer,
+                            &messages,
+                        )
+                        .await;
+                    }
+                    _ => {
+                        server
+                            .dead_letters
+                            .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                            .await
+                    }
+                }
+            }
+            PacketType::ChannelInfo(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::LimitExceeded(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::InvalidPacket(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::Pong(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::Invite(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::Mention(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::ScheduleRes(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::PresenceRes(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::ChannelClosed(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::Motd(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            PacketType::ReadMarkers(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
+            }
+            // Messages addressed only to the current client, e.g. system notices
+            PacketType::Message(r) => {
+                server
+                    .dead_letters
+                    .send(Queue::Socket, &sock_tx, r.as_json_bytes().into())
+                    .await;
             }
             _ => (),
         }
     }
 }
 
-// Handler for each connection
-async fn session_task(stream: TcpStream, channels: Arc<AsyncMutex<session::Channels>>, pool: Pool) {
+// Handler for each connection, `stream` is a TCP stream or a pipe of a gateway
+pub async fn session_task<S>(stream: S, addr: SocketAddr, server: Arc<ServerState>)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    server.plugins.on_connect(&addr);
+
     // Split into two unidirectional stream
     let (mut rd, wr) = tokio::io::split(stream);
 
     // Thread-safe id container
     let id = Arc::new(Mutex::new(String::new()));
 
+    // counts the traffic of the connection for the account logged in
+    let meter = server.traffic.open(addr, Arc::clone(&id));
+    let _traffic_guard = TrafficGuard {
+        server: Arc::clone(&server),
+        meter: Arc::clone(&meter),
+    };
+
     // Channel for consuming and send to the TCP stream
-    let (sock_tx, sock_rx) = mpsc::channel::<Vec<u8>>(32);
-    tokio::task::spawn(stream_sender(wr, sock_rx));
+    // notified by the sender once the client is gone
+    let dead_token = CancellationToken::new();
+    let (sock_tx, sock_rx) = mpsc::channel::<Frame>(32);
+    tokio::task::spawn(stream_sender(
+        wr,
+        sock_rx,
+        server.limits(),
+        dead_token.clone(),
+        Arc::clone(&meter),
+    ));
 
     // Channel for sending response back to client, or any type of packet that needs to be sent
     // to only current client
     let (res_tx, res_rx) = mpsc::channel::<PacketType>(32);
-    tokio::task::spawn(response_handler(res_rx, sock_tx.clone(), Arc::clone(&id)));
+    // set by the handshake
+    let peer = Arc::new(Peer::default());
+    tokio::task::spawn(response_handler(
+        Arc::clone(&server),
+        res_rx,
+        sock_tx.clone(),
+        Arc::clone(&id),
+        Arc::clone(&peer),
+    ));
+
+    let _registry_guard = RegistryGuard {
+        server: Arc::clone(&server),
+        id: Arc::clone(&id),
+        res_tx: res_tx.clone(),
+    };
+
+    // Channel for packets from the broadcasting task that change the state of this session
+    let (ctl_tx, mut ctl_rx) = mpsc::channel::<PacketType>(8);
 
     // default meessage channel
-    let mut channel_tx = channels
-        .lock()
-        .await
-        .get_channel(session::DEFAULT_CHANNEL)
-        .expect("Failed to get default channel");
+    let (mut channel_tx, (channel_rx, snapshot)) = {
+        let channels_lock = server.channels.lock().await;
+        let channel = channels_lock
+            .get(session::DEFAULT_CHANNEL)
+            .expect("Failed to get default channel");
+        (
+            channel.channel.clone(),
+            channel.subscribe(session::DEFAULT_CHANNEL),
+        )
+    };
 
     // channel name container
     let mut current_channel: String = session::DEFAULT_CHANNEL.to_owned();
 
+    // tells this session apart from the others logged in as the same id
+    let session = session::new_session_key();
+
+    // Every broadcasting task of this session is cancelled once the session task ends
+    let session_token = CancellationToken::new();
+    let _session_guard = session_token.clone().drop_guard();
+
     // Default channel broadcasting task, notify `cancel_token` to terminate this task gracefully
     // so current client can connect to other chatting channel
-    let mut cancel_token = CancellationToken::new();
+    let mut cancel_token = session_token.child_token();
     tokio::task::spawn(message_handler(
-        channel_tx.subscribe(),
+        Arc::clone(&server),
+        channel_rx,
+        snapshot,
         sock_tx.clone(),
+        ctl_tx.clone(),
         cancel_token.clone(),
         Arc::clone(&id),
+        Arc::clone(&peer),
     ));
 
-    let mut buf = [0; 1024];
+    // packets may span reads, the bytes are kept until they're complete
+    let max_packet_size = server.limits().max_packet_size;
+    let mut buf = vec![0; max_packet_size];
+    let mut inbound = inbound::PacketBuffer::new(max_packet_size);
+    let mut message_rate = session::MessageRate::default();
+
+    // last challenge issued to the client along with the id it was asked for
+    let mut challenge: Option<(String, auth::Challenge)> = None;
+
+    // the message of the day is sent once, the guest login of a client included
+    let mut motd_sent = false;
     loop {
-        // read data from client
-        let n = match rd.read(&mut buf).await {
-            Ok(0) | Err(_) => return,
-            Ok(n) => n,
-        };
+        // read data from client, or handle a control packet of the session
+        let n = tokio::select! {
+            read = rd.read(&mut buf) => match read {
+                Ok(0) | Err(_) => {
+                    leave_channel(&server, session, &id).await;
+                    return;
+                }
+                Ok(n) => n,
+            },
+            _ = dead_token.cancelled() => {
+                leave_channel(&server, session, &id).await;
+                return;
+            }
+            Some(PacketType::ChannelClosed(closed)) = ctl_rx.recv() => {
+                if closed.channel_name != current_channel {
+                    continue;
+                }
 
-        let Ok(msg_str) = std::str::from_utf8(&buf[0..n]) else {
-            continue;
+                // Move back to the fallback channel, the closed one has no users left to leave
+                let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                let switched = server.channels.lock().await.switch_user(
+                    &current_channel,
+                    &closed.moved_to,
+                    &user,
+                    session,
+                    None,
+                );
+                let switch = match switched {
+                    Ok(switch) => switch,
+                    Err(e) => {
+                        println!("[!] '{}' can't move to '{}': {}", user, closed.moved_to, e);
+                        continue;
+                    }
+                };
+                cancel_token.cancel();
+                cancel_token = session_token.child_token();
+                channel_tx = switch.sender;
+                current_channel = closed.moved_to.clone();
+                tokio::task::spawn(message_handler(
+                    Arc::clone(&server),
+                    switch.receiver,
+                    switch.snapshot,
+                    sock_tx.clone(),
+                    ctl_tx.clone(),
+                    cancel_token.clone(),
+                    Arc::clone(&id),
+                    Arc::clone(&peer),
+                ));
+                server.dead_letters.broadcast(&channel_tx, PacketType::Connected(Connected {}));
+                server.plugins.on_channel_join(&user, &current_channel);
+                let info = switch.info;
+                server.dead_letters.send(Queue::Responses, &res_tx, PacketType::ChannelClosed(closed)).await;
+                server.dead_letters.send(Queue::Responses, &res_tx, PacketType::ChannelInfo(info)).await;
+                continue;
+            }
         };
 
-        match PacketType::from_str(msg_str) {
-            // Received a request to create a new account
-            Ok(PacketType::RegisterReq(req)) => {
-                let res = RegisterRes {
-                    result: req.user.insert(pool.clone()),
+        meter.read(n);
+        let mut packets = Vec::new();
+        for completed in inbound.push(&buf[0..n]) {
+            match completed {
+                inbound::Inbound::Packet(packet) => packets.push(packet),
+                inbound::Inbound::TooLarge(size) => {
+                    server.tarpit.strike(&server, addr.ip(), "packet too large");
+                    let exceeded = LimitExceeded {
+                        what: "packet".to_owned(),
+                        size,
+                        limit: max_packet_size,
+                    };
+                    server
+                        .dead_letters
+                        .send(
+                            Queue::Responses,
+                            &res_tx,
+                            PacketType::LimitExceeded(exceeded),
+                        )
+                        .await;
+                }
+            }
+        }
+
+        for msg_str in packets.iter().map(String::as_str) {
+            let packet = PacketType::from_str(msg_str);
+            meter.packet(matches!(packet, Ok(PacketType::Message(_))));
+
+            // only the handshake and the login itself come before the login
+            let logged_in = id.lock().is_ok_and(|lock| !lock.is_empty());
+            if !logged_in && packet.as_ref().is_ok_and(needs_login) {
+                let invalid = InvalidPacket {
+                    error: PacketError::new(ErrorCode::PermissionDenied, "log in first"),
                 };
-                _ = res_tx.send(PacketType::RegisterRes(res)).await;
-            }
-            // Received a request to login
-            Ok(PacketType::LoginReq(req)) => {
-                let res = LoginRes {
-                    result: {
-                        let mut channels_lock = channels.lock().await;
-                        let channel = channels_lock
-                            .get_mut(&current_channel)
-                            .expect("Channel not found");
-                        if req.login_info.guest {
-                            channel.connect_guest()
-                        } else {
-                            channel.connect_user(&req, id.lock().unwrap().as_str(), pool.clone())
+                server
+                    .dead_letters
+                    .send(
+                        Queue::Responses,
+                        &res_tx,
+                        PacketType::InvalidPacket(invalid),
+                    )
+                    .await;
+                continue;
+            }
+            match packet {
+                // Handshake, incompatible clients are disconnected right after the response
+                Ok(PacketType::Hello(hello)) => {
+                    let mut res = HelloRes::new(hello.version);
+                    let compatible = res.result.is_ok();
+                    peer.version.store(hello.version, Ordering::Relaxed);
+                    let lite = hello.capabilities.iter().any(|c| c == LITE_CAPABILITY);
+                    peer.lite.store(lite, Ordering::Relaxed);
+                    if lite {
+                        res.capabilities.push(LITE_CAPABILITY.to_owned());
+                    }
+                    res.limits = Some(server.announced_limits());
+                    res.motd = server.motd();
+                    if !compatible {
+                        println!(
+                            "[!] Rejected '{}' speaking protocol version {}",
+                            hello.software, hello.version
+                        );
+                    }
+
+                    // queued responses are still written after the session ends
+                    server
+                        .dead_letters
+                        .send(Queue::Responses, &res_tx, PacketType::HelloRes(res))
+                        .await;
+                    if !compatible {
+                        return;
+                    }
+                }
+                // Received a request to create a new account
+                Ok(PacketType::RegisterReq(req)) => {
+                    let pending = server.approves_registrations();
+                    let approval = if pending {
+                        Approval::Pending
+                    } else {
+                        Approval::Approved
+                    };
+                    let user = req.user.clone();
+                    let result = server
+                        .with_db(move |server| user.insert(approval, &server.db))
+                        .await;
+                    if result.is_ok() && pending {
+                        log::info(format_args!(
+                            "'{}' registered, waiting for approval",
+                            req.user.id
+                        ));
+                        let registrant = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                        if let Ok(mut registrants) = server.registrants.lock() {
+                            registrants.insert(req.user.id.clone(), registrant);
                         }
-                    },
-                };
-                // Send packets in case login was successful
-                if res.result.is_ok() {
-                    _ = channel_tx.send(PacketType::Message(Message::connection(
-                        &res.clone().result.unwrap(),
-                    )));
-                    _ = channel_tx.send(PacketType::Connected(Connected {}));
-                }
-                _ = res_tx.send(PacketType::LoginRes(res)).await;
-            }
-            Ok(PacketType::FetchReq(fetch)) => {
-                let fetch_res = match fetch.item.as_str() {
-                    "list" => {
-                        let mut channels_lock = channels.lock().await;
-                        let channel = channels_lock
-                            .get_mut(&current_channel)
-                            .expect("Channel not found");
-                        FetchRes {
-                            item: fetch.item,
-                            result: Ok(serde_json::json!({
-                                "user_list": channel.user_list(),
-                                "num_user": channel.num_user(),
-                                "num_guest": channel.num_guest(),
-                            })),
-                        }
-                    }
-                    // Handling unknown fetch items
-                    _ => FetchRes {
-                        item: fetch.item,
-                        result: Err("unknown fetch item".to_owned()),
-                    },
-                };
-                _ = res_tx.send(PacketType::FetchRes(fetch_res)).await;
-            }
-            Ok(PacketType::GotoReq(req)) => {
-                let mut previous_channel_name = "".to_owned();
-                let packet = PacketType::GotoRes(GotoRes {
-                    result: match channels.lock().await.get_mut(req.channel_name.as_str()) {
-                        Some(req_channel) => {
-                            // save channel name and reassign
-                            previous_channel_name = current_channel.clone();
-                            current_channel = req.channel_name;
-
-                            // notify the existing channel for termination and generate a new token
-                            cancel_token.cancel();
-                            cancel_token = CancellationToken::new();
-
-                            // new broadcasting channel
-                            channel_tx = req_channel.channel.clone();
-                            tokio::task::spawn(message_handler(
-                                channel_tx.subscribe(),
-                                sock_tx.clone(),
-                                cancel_token.clone(),
-                                Arc::clone(&id),
-                            ));
-                            _ = channel_tx.send(PacketType::Connected(Connected {}));
-
-                            // update state
-                            if let Ok(lock) = id.lock() {
-                                req_channel.add_connection(lock.as_str());
-                                Ok(current_channel.clone())
+                    }
+                    let res = RegisterRes {
+                        pending: pending && result.is_ok(),
+                        result,
+                    };
+                    server
+                        .dead_letters
+                        .send(Queue::Responses, &res_tx, PacketType::RegisterRes(res))
+                        .await;
+                }
+                // Received a request for a challenge to log in or confirm an account request with
+                Ok(PacketType::ChallengeReq(req)) => {
+                    let member = req.id.clone();
+                    let verifier = server
+                        .with_db(move |server| db::user::User::verifier_of(&member, &server.db))
+                        .await;
+                    let result = verifier.map(|verifier| {
+                        let issued = auth::Challenge::new(&req.id, verifier.as_ref(), &req.nonce);
+                        challenge = Some((req.id, issued.clone()));
+                        issued
+                    });
+                    server
+                        .dead_letters
+                        .send(
+                            Queue::Responses,
+                            &res_tx,
+                            PacketType::ChallengeRes(ChallengeRes { result }),
+                        )
+                        .await;
+                }
+                // Received a request to login
+                Ok(PacketType::LoginReq(req)) => {
+                    // a challenge is answered once, right or wrong
+                    let issued = challenge
+                        .take()
+                        .filter(|(for_id, _)| req.login_info.id.as_ref() == Some(for_id))
+                        .map(|(_, issued)| issued);
+                    // guest ids are unique server-wide, not only in the current channel
+                    let guest_id = match req.login_info.guest {
+                        true => server
+                            .registry
+                            .lock()
+                            .ok()
+                            .and_then(|mut r| r.reserve_guest()),
+                        false => None,
+                    };
+                    let cur_id = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let result = if req.login_info.guest {
+                        let mut channels_lock = server.channels.lock().await;
+                        match &guest_id {
+                            Some(guest_id) => channels_lock
+                                .connect_guest(&current_channel, &cur_id, guest_id, session)
+                                .map(|id| (id, Role::User)),
+                            None => Err(PacketError::new(ErrorCode::Full, "too many guests")),
+                        }
+                    } else {
+                        // the credentials are checked before the channels are locked
+                        let login_info = req.login_info.clone();
+                        let checked = server
+                            .with_db(move |server| login_info.login(issued.as_ref(), &server.db))
+                            .await;
+                        match checked {
+                            Ok((new_id, role)) => server
+                                .channels
+                                .lock()
+                                .await
+                                .connect_user(&current_channel, &new_id, &cur_id, session)
+                                .map(|_| (new_id, role)),
+                            Err(e) => Err(e),
+                        }
+                    };
+                    let res = match result {
+                        Ok((new_id, role)) => {
+                            server.roles.set(&new_id, role);
+                            let (token, last_channel) = if req.login_info.guest {
+                                (None, None)
                             } else {
-                                Err("Failed to get identifier".to_owned())
+                                (
+                                    token::issue(&server.session_tokens(), &new_id),
+                                    last_channel(&server, &new_id, &current_channel).await,
+                                )
+                            };
+                            LoginRes {
+                                result: Ok(new_id),
+                                role,
+                                token,
+                                last_channel,
                             }
                         }
-                        None => Err("Invalid or not permitted to join the channel".to_owned()),
-                    },
-                });
+                        Err(e) => {
+                            if e.code == ErrorCode::WrongCredentials {
+                                server.tarpit.strike(&server, addr.ip(), "failed login");
+                            }
+                            LoginRes {
+                                result: Err(e),
+                                role: Role::User,
+                                token: None,
+                                last_channel: None,
+                            }
+                        }
+                    };
+                    if let (Some(guest_id), Err(_)) = (&guest_id, &res.result) {
+                        if let Ok(mut registry) = server.registry.lock() {
+                            registry.release_guest(guest_id);
+                        }
+                    }
+                    // Send packets in case login was successful
+                    if let (Ok(new_id), Ok(mut registry)) = (&res.result, server.registry.lock()) {
+                        if let Ok(cur_id) = id.lock() {
+                            registry.unregister(cur_id.as_str(), &res_tx);
+                        }
+                        registry.register(new_id, res_tx.clone(), dead_token.clone());
+                    }
+                    let follow_ups = match &res.result {
+                        Ok(new_id) => welcome(&server, new_id, &current_channel, &channel_tx).await,
+                        Err(_) => Vec::new(),
+                    };
+                    if res.result.is_ok() && !motd_sent {
+                        motd_sent = true;
+                        if let Some(text) = server.motd() {
+                            server
+                                .dead_letters
+                                .send(Queue::Responses, &res_tx, PacketType::Motd(Motd { text }))
+                                .await;
+                        }
+                    }
+                    server
+                        .dead_letters
+                        .send(Queue::Responses, &res_tx, PacketType::LoginRes(res))
+                        .await;
+                    for packet in follow_ups {
+                        server
+                            .dead_letters
+                            .send(Queue::Responses, &res_tx, packet)
+                            .await;
+                    }
+                }
+                // Received the token of an earlier login, e.g. reconnecting through a load balancer
+                Ok(PacketType::ResumeSession(req)) => {
+                    let resumed = token::verify(&server.session_tokens(), &req.token)
+                        .map_err(|e| PacketError::new(ErrorCode::WrongCredentials, e))
+                        .and_then(|claims| match server.revocations.is_revoked(&claims) {
+                            true => Err(PacketError::new(
+                                ErrorCode::PermissionDenied,
+                                "the session has been ended, log in again",
+                            )),
+                            false => Ok(claims.id),
+                        });
+                    let result = match resumed {
+                        Ok(resumed_id) => {
+                            let cur_id = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                            let checked = server
+                                .with_db(move |server| Login::resume(&resumed_id, &server.db))
+                                .await;
+                            match checked {
+                                Ok((new_id, role)) => server
+                                    .channels
+                                    .lock()
+                                    .await
+                                    .connect_user(&current_channel, &new_id, &cur_id, session)
+                                    .map(|_| (new_id, role)),
+                                Err(e) => Err(e),
+                            }
+                        }
+                        Err(e) => Err(e),
+                    };
+                    let res = match result {
+                        Ok((new_id, role)) => {
+                            server.roles.set(&new_id, role);
+                            ResumeRes {
+                                token: token::issue(&server.session_tokens(), &new_id),
+                                result: Ok(new_id),
+                                role,
+                            }
+                        }
+                        Err(e) => {
+                            if e.code == ErrorCode::WrongCredentials {
+                                server
+                                    .tarpit
+                                    .strike(&server, addr.ip(), "invalid session token");
+                            }
+                            ResumeRes {
+                                result: Err(e),
+                                role: Role::User,
+                                token: None,
+                            }
+                        }
+                    };
+                    let Ok(new_id) = res.result.clone() else {
+                        server
+                            .dead_letters
+                            .send(Queue::Responses, &res_tx, PacketType::ResumeRes(res))
+                            .await;
+                        continue;
+                    };
+                    if let Ok(mut registry) = server.registry.lock() {
+                        if let Ok(cur_id) = id.lock() {
+                            registry.unregister(cur_id.as_str(), &res_tx);
+                        }
+                        registry.register(&new_id, res_tx.clone(), dead_token.clone());
+                    }
+                    // the channel is switched below, before the response would update the id
+                    if let Ok(mut lock) = id.lock() {
+                        *lock = new_id.clone();
+                    }
+                    let follow_ups = welcome(&server, &new_id, &current_channel, &channel_tx).await;
+                    server
+                        .dead_letters
+                        .send(Queue::Responses, &res_tx, PacketType::ResumeRes(res))
+                        .await;
+                    for packet in follow_ups {
+                        server
+                            .dead_letters
+                            .send(Queue::Responses, &res_tx, packet)
+                            .await;
+                    }
 
-                // FIXME: Mutex lock for `channels` is valid til the end of the above statement,
-                // so we cannot update state of the current channel. Looks ugly.
-                match &packet {
-                    PacketType::GotoRes(res) if res.result.is_ok() => channels
+                    // and back to the channel the session was in
+                    let Some(channel_name) = req.channel.filter(|c| *c != current_channel) else {
+                        continue;
+                    };
+                    let switched = server.channels.lock().await.switch_user(
+                        &current_channel,
+                        &channel_name,
+                        &new_id,
+                        session,
+                        None,
+                    );
+                    let result = switched.map(|switch| {
+                        cancel_token.cancel();
+                        cancel_token = session_token.child_token();
+                        channel_tx = switch.sender;
+                        current_channel = channel_name.clone();
+                        tokio::task::spawn(message_handler(
+                            Arc::clone(&server),
+                            switch.receiver,
+                            switch.snapshot,
+                            sock_tx.clone(),
+                            ctl_tx.clone(),
+                            cancel_token.clone(),
+                            Arc::clone(&id),
+                            Arc::clone(&peer),
+                        ));
+                        server
+                            .dead_letters
+                            .broadcast(&channel_tx, PacketType::Connected(Connected {}));
+                        switch.info
+                    });
+                    let joined_info = result.as_ref().ok().cloned();
+                    let res = GotoRes {
+                        topic: joined_info.as_ref().and_then(|info| info.topic.clone()),
+                        result: result.map(|info| info.channel_name),
+                    };
+                    server
+                        .dead_letters
+                        .send(Queue::Responses, &res_tx, PacketType::GotoRes(res))
+                        .await;
+                    if let Some(info) = joined_info {
+                        server.plugins.on_channel_join(&new_id, &info.channel_name);
+                        server
+                            .dead_letters
+                            .send(Queue::Responses, &res_tx, PacketType::ChannelInfo(info))
+                            .await;
+                    }
+                }
+                // Received a request to turn the guest into a new account in place
+                Ok(PacketType::UpgradeReq(req)) => {
+                    let guest_id = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let res = UpgradeRes {
+                        result: {
+                            let checked = if server.approves_registrations() {
+                                Err(PacketError::new(
+                                    ErrorCode::PermissionDenied,
+                                    "new accounts wait for the approval of an admin here, register one instead",
+                                ))
+                            } else {
+                                server
+                                    .channels
+                                    .lock()
+                                    .await
+                                    .check_upgrade(&guest_id, session)
+                            };
+                            // the account is registered before the channels are locked
+                            let user = req.user.clone();
+                            let registered = match checked {
+                                Ok(()) => {
+                                    server
+                                        .with_db(move |server| {
+                                            user.insert(Approval::Approved, &server.db)
+                                        })
+                                        .await
+                                }
+                                Err(e) => Err(e),
+                            };
+                            let mut channels_lock = server.channels.lock().await;
+                            let result = registered.and_then(|_| {
+                                channels_lock.upgrade_guest(&guest_id, &req.user.id, session)
+                            });
+
+                            // swap the id while the channel is still locked, so no one sees both
+                            let channel = channels_lock.get_mut(&current_channel);
+                            if let (Ok(new_id), Ok(mut lock), Some(channel)) =
+                                (&result, id.lock(), channel)
+                            {
+                                *lock = new_id.clone();
+                                channel.broadcast(Message::system_notice(&format!(
+                                    "'{}' is now known as '{}'",
+                                    guest_id, new_id
+                                )));
+                            }
+                            result
+                        },
+                    };
+                    if let Ok(new_id) = &res.result {
+                        server.plugins.on_login(new_id);
+                        if let Ok(mut registry) = server.registry.lock() {
+                            registry.unregister(&guest_id, &res_tx);
+                            registry.register(new_id, res_tx.clone(), dead_token.clone());
+                        }
+                    }
+                    server
+                        .dead_letters
+                        .send(Queue::Responses, &res_tx, PacketType::UpgradeRes(res))
+                        .await;
+                }
+                Ok(PacketType::FetchReq(fetch)) => {
+                    let result = match &fetch.item {
+                        FetchItem::UserList {
+                            offset,
+                            limit,
+                            filter,
+                        } => {
+                            let channels_lock = server.channels.lock().await;
+                            match channels_lock.get(&current_channel) {
+                                Some(channel) => {
+                                    let limit = limit
+                                        .unwrap_or(session::DEFAULT_PAGE_SIZE)
+                                        .clamp(1, session::MAX_PAGE_SIZE);
+                                    // invisible members are left out, except for the requester themselves
+                                    let requester =
+                                        id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                                    let mut hidden = server.presence.invisible();
+                                    hidden.remove(&requester);
+                                    let (users, total) = channel.user_page(
+                                        *offset,
+                                        limit,
+                                        filter.as_deref(),
+                                        &hidden,
+                                    );
+                                    let num_hidden =
+                                        hidden.iter().filter(|h| channel.has_user(h)).count();
+                                    Ok(serde_json::json!({
+                                        "user_list": users,
+                                        "total": total,
+                                        "offset": offset,
+                                        "limit": limit,
+                                        "num_user": channel.num_user().saturating_sub(num_hidden),
+                                        "num_guest": channel.num_guest(),
+                                    }))
+                                }
+                                None => {
+                                    Err(PacketError::new(ErrorCode::NotFound, "channel not found"))
+                                }
+                            }
+                        }
+                        FetchItem::Stats => {
+                            let channels_lock = server.channels.lock().await;
+                            let mut names: Vec<&String> = channels_lock.channels.keys().collect();
+                            names.sort();
+                            let stats: Vec<serde_json::Value> = names
+                                .into_iter()
+                                .map(|name| channels_lock.channels[name].stats(name))
+                                .collect();
+                            Ok(serde_json::json!({
+                                "channels": stats,
+                                "load": server.sessions.report(),
+                                "traffic": server.traffic.totals(),
+                                "dead_letters": server.dead_letters.totals(),
+                            }))
+                        }
+                        // Names of the channels that can be joined, for completion, and their
+                        // users and topics, for browsing
+                        FetchItem::Channels => {
+                            let channels_lock = server.channels.lock().await;
+                            let mut names: Vec<&String> = channels_lock
+                                .channels
+                                .iter()
+                                .filter(|(_, channel)| !channel.archived)
+                                .map(|(name, _)| name)
+                                .collect();
+                            names.sort();
+                            // invisible members aren't counted, like in the user list
+                            let requester = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                            let mut hidden = server.presence.invisible();
+                            hidden.remove(&requester);
+                            let details: Vec<serde_json::Value> = names
+                                .iter()
+                                .map(|name| {
+                                    let channel = &channels_lock.channels[*name];
+                                    let num_hidden =
+                                        hidden.iter().filter(|h| channel.has_user(h)).count();
+                                    serde_json::json!({
+                                        "channel": name,
+                                        "num_user": channel.num_user().saturating_sub(num_hidden),
+                                        "num_guest": channel.num_guest(),
+                                        "topic": channel.topic,
+                                    })
+                                })
+                                .collect();
+                            Ok(serde_json::json!({
+                                "channels": names,
+                                "details": details,
+                            }))
+                        }
+                        // Pinned messages of the current channel
+                        FetchItem::Pins => {
+                            let channels_lock = server.channels.lock().await;
+                            let pins: Vec<&Message> = channels_lock
+                                .get(&current_channel)
+                                .map(|channel| channel.pins.values().collect())
+                                .unwrap_or_default();
+                            Ok(serde_json::json!({ "pins": pins }))
+                        }
+                        // Messages of a thread of the current channel, from its first one
+                        FetchItem::Thread { seq } => {
+                            let channels_lock = server.channels.lock().await;
+                            match channels_lock.get(&current_channel) {
+                                Some(channel) => {
+                                    let root = channel.thread_root(*seq);
+                                    let messages = channel.thread(root);
+                                    Ok(serde_json::json!({ "root": root, "messages": messages }))
+                                }
+                                None => {
+                                    Err(PacketError::new(ErrorCode::NotFound, "channel not found"))
+                                }
+                            }
+                        }
+                        // Traffic of the connections and the accounts, for admins
+                        FetchItem::Traffic => {
+                            let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                            if server
+                                .roles
+                                .allows(&user, permissions::Operation::ViewTraffic)
+                            {
+                                Ok(server.traffic.report())
+                            } else {
+                                Err(PacketError::new(
+                                    ErrorCode::PermissionDenied,
+                                    "only admins can see the traffic",
+                                ))
+                            }
+                        }
+                        // Packets the server failed to deliver, for admins
+                        FetchItem::DeadLetters => {
+                            let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                            if server
+                                .roles
+                                .allows(&user, permissions::Operation::ViewDeadLetters)
+                            {
+                                Ok(server.dead_letters.report())
+                            } else {
+                                Err(PacketError::new(
+                                    ErrorCode::PermissionDenied,
+                                    "only admins can see the dropped packets",
+                                ))
+                            }
+                        }
+                        // Backup of the state of the server, see `backup`, for admins
+                        FetchItem::Dump => {
+                            let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                            if server
+                                .roles
+                                .allows(&user, permissions::Operation::ExportState)
+                            {
+                                backup::export(&server)
+                                    .await
+                                    .map(|dump| serde_json::to_value(dump).unwrap_or_default())
+                                    .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))
+                            } else {
+                                Err(PacketError::new(
+                                    ErrorCode::PermissionDenied,
+                                    "only admins can dump the server state",
+                                ))
+                            }
+                        }
+                        // Bans and mutes of the current channel, for moderators
+                        FetchItem::Moderation => {
+                            let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                            let channels_lock = server.channels.lock().await;
+                            match channels_lock.get(&current_channel) {
+                                Some(channel) if channel.is_moderator(&user) => {
+                                    Ok(channel.sanctions())
+                                }
+                                Some(_) => Err(PacketError::new(
+                                    ErrorCode::PermissionDenied,
+                                    "only moderators can see who is banned or muted",
+                                )),
+                                None => {
+                                    Err(PacketError::new(ErrorCode::NotFound, "channel not found"))
+                                }
+                            }
+                        }
+                        // Originals of the retracted messages of the current channel, for moderators
+                        FetchItem::Retracted => {
+                            let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                            let channels_lock = server.channels.lock().await;
+                            match channels_lock.get(&current_channel) {
+                                Some(channel) if channel.is_moderator(&user) => {
+                                    let messages: Vec<&Message> =
+                                        channel.retracted.values().collect();
+                                    Ok(serde_json::json!({ "messages": messages }))
+                                }
+                                Some(_) => Err(PacketError::new(
+                                    ErrorCode::PermissionDenied,
+                                    "only moderators can see retracted messages",
+                                )),
+                                None => {
+                                    Err(PacketError::new(ErrorCode::NotFound, "channel not found"))
+                                }
+                            }
+                        }
+                    };
+                    let fetch_res = FetchRes {
+                        item: fetch.item,
+                        result,
+                    };
+                    server
+                        .dead_letters
+                        .send(Queue::Responses, &res_tx, PacketType::FetchRes(fetch_res))
+                        .await;
+                }
+                Ok(PacketType::GotoReq(req)) => {
+                    let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let switched = server.channels.lock().await.switch_user(
+                        &current_channel,
+                        &req.channel_name,
+                        &user,
+                        session,
+                        req.password.as_deref(),
+                    );
+                    let result = switched.map(|switch| {
+                        // notify the existing channel for termination and generate a new token
+                        cancel_token.cancel();
+                        cancel_token = session_token.child_token();
+                        channel_tx = switch.sender;
+                        current_channel = req.channel_name.clone();
+                        tokio::task::spawn(message_handler(
+                            Arc::clone(&server),
+                            switch.receiver,
+                            switch.snapshot,
+                            sock_tx.clone(),
+                            ctl_tx.clone(),
+                            cancel_token.clone(),
+                            Arc::clone(&id),
+                            Arc::clone(&peer),
+                        ));
+                        server
+                            .dead_letters
+                            .broadcast(&channel_tx, PacketType::Connected(Connected {}));
+                        switch.info
+                    });
+
+                    let joined_info = result.as_ref().ok().cloned();
+                    let res = GotoRes {
+                        topic: joined_info.as_ref().and_then(|info| info.topic.clone()),
+                        result: result.map(|info| info.channel_name),
+                    };
+                    server
+                        .dead_letters
+                        .send(Queue::Responses, &res_tx, PacketType::GotoRes(res))
+                        .await;
+                    if let Some(info) = joined_info {
+                        server.plugins.on_channel_join(&user, &info.channel_name);
+                        // rejoined at the next login, guests are remembered by their client
+                        if !user.starts_with("guest_") {
+                            let (member, channel) = (user.clone(), info.channel_name.clone());
+                            let saved = server
+                                .with_db(move |server| {
+                                    db::last_channel::save(&member, &channel, &server.db)
+                                })
+                                .await;
+                            if let Err(e) = saved {
+                                println!("[!] {}", e);
+                            }
+                        }
+                        server
+                            .dead_letters
+                            .send(Queue::Responses, &res_tx, PacketType::ChannelInfo(info))
+                            .await;
+                    }
+                }
+                // The member has read a channel this far, guests have nothing to keep it for
+                Ok(PacketType::ReadMarkerReq(req)) => {
+                    let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let known = server
+                        .channels
                         .lock()
                         .await
-                        .get_mut(previous_channel_name.as_str())
-                        .expect("Channel not found")
-                        .leave_user(id.lock().as_deref().unwrap()),
-                    _ => (),
-                };
+                        .get(&req.channel_name)
+                        .is_some_and(|channel| req.seq < channel.next_seq);
+                    if known && !user.starts_with("guest_") {
+                        let saved = server
+                            .with_db(move |server| {
+                                db::read_marker::save(&user, &req.channel_name, req.seq, &server.db)
+                            })
+                            .await;
+                        if let Err(e) = saved {
+                            println!("[!] {}", e);
+                        }
+                    }
+                }
+                // Received a reaction to a message of the current channel
+                Ok(PacketType::ReactionReq(req)) => {
+                    let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let mut channels_lock = server.channels.lock().await;
+                    let result = match channels_lock.get_mut(&current_channel) {
+                        Some(channel) => channel.toggle_reaction(&user, req.seq, &req.emoji),
+                        None => Err("channel not found".to_owned()),
+                    };
+                    drop(channels_lock);
 
-                if let Err(e) = res_tx.send(packet).await {
-                    println!("{}", e);
+                    match result {
+                        Ok(reactions) => {
+                            server.dead_letters.broadcast(
+                                &channel_tx,
+                                PacketType::ReactionUpdate(ReactionUpdate {
+                                    channel_name: current_channel.clone(),
+                                    seq: req.seq,
+                                    reactions,
+                                }),
+                            );
+                        }
+                        Err(e) => {
+                            server
+                                .dead_letters
+                                .send(
+                                    Queue::Responses,
+                                    &res_tx,
+                                    PacketType::Message(Message::system_notice(&e)),
+                                )
+                                .await;
+                        }
+                    }
                 }
-            }
-            // Received a request to broadcast message
-            Ok(PacketType::Message(msg)) => {
-                // Send message to the channel for broadcasting to connected clients
-                _ = channel_tx.send(PacketType::Message(msg));
-            }
-            // Received exit notification from client, remove the client from current session
-            Ok(PacketType::Exit(_)) => {
-                let mut channels_lock = channels.lock().await;
-                let channel = channels_lock
-                    .get_mut(&current_channel)
-                    .expect("Channel not found");
+                // Received a request to pin or unpin a message of the current channel
+                Ok(PacketType::PinReq(req)) => {
+                    let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let mut channels_lock = server.channels.lock().await;
+                    let result = match channels_lock.get_mut(&current_channel) {
+                        Some(channel) => channel.set_pinned(&user, req.seq, req.pinned),
+                        None => Err("channel not found".to_owned()),
+                    };
+                    drop(channels_lock);
 
-                if let Ok(lock) = id.lock() {
-                    channel.leave_user(lock.as_str());
+                    match result {
+                        Ok(message) => {
+                            server.dead_letters.broadcast(
+                                &channel_tx,
+                                PacketType::PinUpdate(PinUpdate {
+                                    channel_name: current_channel.clone(),
+                                    by: user,
+                                    message,
+                                    pinned: req.pinned,
+                                }),
+                            );
+                        }
+                        Err(e) => {
+                            server
+                                .dead_letters
+                                .send(
+                                    Queue::Responses,
+                                    &res_tx,
+                                    PacketType::Message(Message::system_notice(&e)),
+                                )
+                                .await;
+                        }
+                    }
+                }
+                // Received a retraction of a message the session sent to the current channel
+                Ok(PacketType::RetractReq(req)) => {
+                    let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let window_secs = server.limits().retract_window_secs;
+                    let mut channels_lock = server.channels.lock().await;
+                    let result = match channels_lock.get_mut(&current_channel) {
+                        _ if window_secs == 0 => {
+                            Err("retracting messages is disabled on this server".to_owned())
+                        }
+                        Some(channel) => {
+                            channel.retract(&user, req.seq, Duration::from_secs(window_secs))
+                        }
+                        None => Err("channel not found".to_owned()),
+                    };
+                    drop(channels_lock);
 
-                    // disconnection broadcasting
-                    _ = channel_tx.send(PacketType::Message(Message::disconnection(&lock.clone())));
+                    match result {
+                        Ok(()) => {
+                            server.dead_letters.broadcast(
+                                &channel_tx,
+                                PacketType::RetractUpdate(RetractUpdate {
+                                    channel_name: current_channel.clone(),
+                                    seq: req.seq,
+                                }),
+                            );
+                        }
+                        Err(e) => {
+                            server
+                                .dead_letters
+                                .send(
+                                    Queue::Responses,
+                                    &res_tx,
+                                    PacketType::Message(Message::system_notice(&e)),
+                                )
+                                .await;
+                        }
+                    }
                 }
-                return;
+                // Received a server-wide operation, the role of the requester decides
+                Ok(PacketType::AdminReq(req)) => {
+                    let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let result = admin::run(&server, &user, req.action).await;
+                    if let Ok(done) = &result {
+                        log::info(format_args!("Admin operation by '{}': {}", user, done));
+                    }
+                    server
+                        .dead_letters
+                        .send(
+                            Queue::Responses,
+                            &res_tx,
+                            PacketType::AdminRes(AdminRes { result }),
+                        )
+                        .await;
+                }
+                // Received a request of the member about its own data
+                Ok(PacketType::AccountReq(req)) => {
+                    let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let issued = challenge
+                        .take()
+                        .filter(|(for_id, _)| *for_id == user)
+                        .map(|(_, issued)| issued);
+                    let result =
+                        account::run(&server, &user, req.action, &req.proof, issued.as_ref()).await;
+                    let erased = req.action == AccountAction::Erase && result.is_ok();
+                    server
+                        .dead_letters
+                        .send(
+                            Queue::Responses,
+                            &res_tx,
+                            PacketType::AccountRes(AccountRes {
+                                action: req.action,
+                                result,
+                            }),
+                        )
+                        .await;
+
+                    // the account is gone, so are its sessions
+                    if erased {
+                        if let Ok(registry) = server.registry.lock() {
+                            registry.kick(&user);
+                        }
+                    }
+                }
+                // Received a request to manage a channel
+                Ok(PacketType::ChannelReq(req)) => {
+                    let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let mut channels_lock = server.channels.lock().await;
+                    let result = match req.action {
+                        ChannelAction::Create => {
+                            guests::check_channel_creation(&server.guest_policy(), &user).and_then(
+                                |_| {
+                                    channels_lock.create_user_channel(
+                                        &req.channel_name,
+                                        &user,
+                                        req.password.as_deref(),
+                                    )
+                                },
+                            )
+                        }
+                        ChannelAction::CreateSystem => {
+                            channels_lock.create_system_channel(&req.channel_name, &user)
+                        }
+                        ChannelAction::Delete => {
+                            channels_lock.close_channel(&req.channel_name, &user, false)
+                        }
+                        ChannelAction::Archive => {
+                            channels_lock.close_channel(&req.channel_name, &user, true)
+                        }
+                        ChannelAction::SetSlowMode(secs) => {
+                            match channels_lock.get_mut(&req.channel_name) {
+                                Some(channel) => channel.set_slow_mode(&user, secs),
+                                None => Err(format!("channel '{}' not found", req.channel_name)),
+                            }
+                        }
+                        ChannelAction::SetAnnounceOnly(enabled) => {
+                            match channels_lock.get_mut(&req.channel_name) {
+                                Some(channel) => {
+                                    channel.set_announce_only(&user, enabled).inspect(|_| {
+                                        let info = channel.info(&req.channel_name);
+                                        _ = channel.channel.send(PacketType::ChannelInfo(info));
+                                    })
+                                }
+                                None => Err(format!("channel '{}' not found", req.channel_name)),
+                            }
+                        }
+                        ChannelAction::SetHistory(policy) => {
+                            match channels_lock.get_mut(&req.channel_name) {
+                                Some(channel) => {
+                                    channel.set_history_policy(&user, policy).inspect(|_| {
+                                        let info = channel.info(&req.channel_name);
+                                        _ = channel.channel.send(PacketType::ChannelInfo(info));
+                                    })
+                                }
+                                None => Err(format!("channel '{}' not found", req.channel_name)),
+                            }
+                        }
+                        ChannelAction::SetGcExempt(exempt) => {
+                            match channels_lock.get_mut(&req.channel_name) {
+                                Some(channel) => channel.set_gc_exempt(&user, exempt),
+                                None => Err(format!("channel '{}' not found", req.channel_name)),
+                            }
+                        }
+                        ChannelAction::AddModerator(target) => {
+                            match channels_lock.get_mut(&req.channel_name) {
+                                Some(channel) => channel.add_moderator(&user, &target),
+                                None => Err(format!("channel '{}' not found", req.channel_name)),
+                            }
+                        }
+                        ChannelAction::Ban(target) => {
+                            match channels_lock.get_mut(&req.channel_name) {
+                                Some(channel) => channel.ban(&req.channel_name, &user, &target),
+                                None => Err(format!("channel '{}' not found", req.channel_name)),
+                            }
+                        }
+                        ChannelAction::Unban(target) => {
+                            match channels_lock.get_mut(&req.channel_name) {
+                                Some(channel) => channel.unban(&user, &target),
+                                None => Err(format!("channel '{}' not found", req.channel_name)),
+                            }
+                        }
+                        ChannelAction::Mute(target, secs) => {
+                            match channels_lock.get_mut(&req.channel_name) {
+                                Some(channel) => channel.mute(&user, &target, secs),
+                                None => Err(format!("channel '{}' not found", req.channel_name)),
+                            }
+                        }
+                        ChannelAction::Unmute(target) => {
+                            match channels_lock.get_mut(&req.channel_name) {
+                                Some(channel) => channel.unmute(&user, &target),
+                                None => Err(format!("channel '{}' not found", req.channel_name)),
+                            }
+                        }
+                        ChannelAction::SetNickname(nickname) => {
+                            match channels_lock.get_mut(&req.channel_name) {
+                                Some(channel) => channel.set_nickname(&user, nickname),
+                                None => Err(format!("channel '{}' not found", req.channel_name)),
+                            }
+                        }
+                        ChannelAction::SetTopic(topic) => {
+                            match channels_lock.get_mut(&req.channel_name) {
+                                Some(channel) => channel.set_topic(&user, topic).inspect(|_| {
+                                    let info = channel.info(&req.channel_name);
+                                    let notice = match &info.topic {
+                                        Some(topic) => {
+                                            format!("'{}' set the topic: {}", user, topic)
+                                        }
+                                        None => format!("'{}' cleared the topic", user),
+                                    };
+                                    _ = channel.channel.send(PacketType::ChannelInfo(info));
+                                    channel.broadcast(Message::system_notice(&notice));
+                                }),
+                                None => Err(format!("channel '{}' not found", req.channel_name)),
+                            }
+                        }
+                    };
+                    // channels outlive the server, the built-in ones are created on every start
+                    let record = match channels_lock.get(&req.channel_name) {
+                        Some(_)
+                            if session::SYSTEM_CHANNELS.contains(&req.channel_name.as_str()) =>
+                        {
+                            None
+                        }
+                        channel => Some(channel.map(|c| c.to_record(&req.channel_name))),
+                    };
+                    drop(channels_lock);
+
+                    let name = req.channel_name.clone();
+                    let persisted = match (&result, record) {
+                        (Ok(_), Some(Some(record))) => {
+                            server.with_db(move |server| record.save(&server.db)).await
+                        }
+                        (Ok(_), Some(None)) => {
+                            server
+                                .with_db(move |server| ChannelRecord::delete(&name, &server.db))
+                                .await
+                        }
+                        _ => Ok(()),
+                    };
+                    if let Err(e) = persisted {
+                        println!("[!] {}", e);
+                    }
+                    server
+                        .dead_letters
+                        .send(
+                            Queue::Responses,
+                            &res_tx,
+                            PacketType::ChannelRes(ChannelRes { result }),
+                        )
+                        .await;
+                }
+                // Received a request to broadcast message
+                Ok(PacketType::Message(mut msg)) => {
+                    // The sender is always the identity of this session, never the system, and
+                    // the server decides what name it goes by
+                    msg.id = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    msg.is_system = false;
+                    msg.display_name = None;
+                    msg.retracted = false;
+
+                    // guests held back by the policy don't count against any limit
+                    let policy = server.guest_policy();
+                    let allowed = match &msg.to {
+                        Some(_) => guests::check_direct_message(&policy, &msg.id),
+                        None => guests::check_post(&policy, &msg.id, &current_channel),
+                    };
+                    if let Err(reason) = allowed {
+                        server
+                            .dead_letters
+                            .send(
+                                Queue::Responses,
+                                &res_tx,
+                                PacketType::Message(Message::system_notice(&reason)),
+                            )
+                            .await;
+                        continue;
+                    }
+
+                    let limits = server.limits();
+                    if msg.msg.len() > limits.max_message_size {
+                        server
+                            .tarpit
+                            .strike(&server, addr.ip(), "message too large");
+                        let exceeded = LimitExceeded {
+                            what: "message".to_owned(),
+                            size: msg.msg.len(),
+                            limit: limits.max_message_size,
+                        };
+                        server
+                            .dead_letters
+                            .send(
+                                Queue::Responses,
+                                &res_tx,
+                                PacketType::LimitExceeded(exceeded),
+                            )
+                            .await;
+                        continue;
+                    }
+
+                    match normalize::normalize(&msg.msg) {
+                        Ok(text) => msg.msg = text,
+                        Err(reason) => {
+                            server
+                                .dead_letters
+                                .send(
+                                    Queue::Responses,
+                                    &res_tx,
+                                    PacketType::Message(Message::system_notice(&reason)),
+                                )
+                                .await;
+                            continue;
+                        }
+                    }
+
+                    if !message_rate.try_send(limits.messages_per_minute) {
+                        server
+                            .tarpit
+                            .strike(&server, addr.ip(), "messages too fast");
+                        server
+                            .dead_letters
+                            .send(
+                                Queue::Responses,
+                                &res_tx,
+                                PacketType::Message(Message::system_notice(
+                                    "you're sending messages too fast, wait a moment",
+                                )),
+                            )
+                            .await;
+                        continue;
+                    }
+
+                    // Direct messages are delivered only to the recipient
+                    if let Some(to) = msg.to.clone() {
+                        // filters and plugins have their say on direct messages too
+                        if let Err(reason) = server.filter(&current_channel, &msg).and_then(|_| {
+                            server
+                                .plugins
+                                .on_message(&current_channel, &msg)
+                                .map(|_| ())
+                        }) {
+                            server
+                                .dead_letters
+                                .send(
+                                    Queue::Responses,
+                                    &res_tx,
+                                    PacketType::Message(Message::system_notice(&reason)),
+                                )
+                                .await;
+                            continue;
+                        }
+
+                        // they're not numbered, there's nothing to reply to
+                        msg.reply_to = None;
+                        msg.thread = None;
+                        let recipient = server.registry.lock().ok().and_then(|r| r.get(&to));
+                        match recipient {
+                            Some(recipient_tx) => {
+                                server
+                                    .dead_letters
+                                    .send(Queue::Responses, &recipient_tx, PacketType::Message(msg))
+                                    .await;
+
+                                // the message is kept, but the sender knows not to expect a reply
+                                let absence = match server.presence.get(&to) {
+                                    Presence::Dnd => Some("in do not disturb mode"),
+                                    Presence::Away => Some("away"),
+                                    _ => None,
+                                };
+                                if let Some(absence) = absence {
+                                    let notice = format!(
+                                        "user '{}' is {} and may not see this soon",
+                                        to, absence
+                                    );
+                                    server
+                                        .dead_letters
+                                        .send(
+                                            Queue::Responses,
+                                            &res_tx,
+                                            PacketType::Message(Message::system_notice(&notice)),
+                                        )
+                                        .await;
+                                }
+                            }
+                            // the node having the recipient delivers it
+                            None if server
+                                .cluster
+                                .as_ref()
+                                .is_some_and(|c| c.send_direct(&msg)) => {}
+                            None => {
+                                let notice = format!("user '{}' is not online", to);
+                                server
+                                    .dead_letters
+                                    .send(
+                                        Queue::Responses,
+                                        &res_tx,
+                                        PacketType::Message(Message::system_notice(&notice)),
+                                    )
+                                    .await;
+                            }
+                        }
+                        continue;
+                    }
+
+                    // The channel has the last word on who may post what, plugins may answer it
+                    let mut channels_lock = server.channels.lock().await;
+                    let Some(channel) = channels_lock.get_mut(&current_channel) else {
+                        continue;
+                    };
+                    let checked = channel
+                        .check_post(
+                            &msg,
+                            |msg| server.filter(&current_channel, msg),
+                            session::Posting::Now,
+                        )
+                        .and_then(|_| server.plugins.on_message(&current_channel, &msg));
+                    let replies = match checked {
+                        Ok(replies) => replies,
+                        Err(reason) => {
+                            drop(channels_lock);
+                            server
+                                .dead_letters
+                                .send(
+                                    Queue::Responses,
+                                    &res_tx,
+                                    PacketType::Message(Message::system_notice(&reason)),
+                                )
+                                .await;
+                            continue;
+                        }
+                    };
+
+                    // a reply refers to an earlier message of the channel
+                    if msg.reply_to.is_some_and(|seq| seq >= channel.next_seq) {
+                        msg.reply_to = None;
+                    }
+                    msg.thread = msg.reply_to.map(|seq| channel.thread_root(seq));
+
+                    // Mentioned users outside of the channel hear about it through their own session
+                    let absent: Vec<String> = msg
+                        .mentioned_ids()
+                        .into_iter()
+                        .filter(|id| !channel.has_user(id))
+                        .map(String::from)
+                        .collect();
+
+                    // Send message to the channel for broadcasting to connected clients, the
+                    // replies of the plugins follow the message
+                    let msg = channel.broadcast(msg);
+                    for reply in replies {
+                        channel.broadcast(Message::system_notice(&reply));
+                    }
+                    drop(channels_lock);
+                    server
+                        .bridges
+                        .mirror(&current_channel, &msg, &server.dead_letters);
+
+                    let recipients: Vec<_> = match server.registry.lock() {
+                        Ok(registry) => absent.iter().filter_map(|id| registry.get(id)).collect(),
+                        Err(_) => Vec::new(),
+                    };
+                    for recipient_tx in recipients {
+                        let mention = Mention {
+                            channel_name: current_channel.clone(),
+                            message: msg.clone(),
+                        };
+                        server
+                            .dead_letters
+                            .send(
+                                Queue::Responses,
+                                &recipient_tx,
+                                PacketType::Mention(mention),
+                            )
+                            .await;
+                    }
+                }
+                // A message to be posted to the current channel later
+                Ok(PacketType::ScheduleReq(req)) => {
+                    let owner = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let normalized = normalize::normalize(&req.msg);
+                    let draft = Message {
+                        id: owner.clone(),
+                        msg: normalized.clone().unwrap_or_default(),
+                        is_system: false,
+                        to: None,
+                        seq: None,
+                        retracted: false,
+                        display_name: None,
+                        reply_to: None,
+                        thread: None,
+                    };
+                    let allowed =
+                        guests::check_post(&server.guest_policy(), &owner, &current_channel);
+                    // slow mode waits for the message to be posted
+                    let checked =
+                        server
+                            .channels
+                            .lock()
+                            .await
+                            .get_mut(&current_channel)
+                            .map(|channel| {
+                                channel.check_post(
+                                    &draft,
+                                    |msg| server.filter(&current_channel, msg),
+                                    session::Posting::Scheduled,
+                                )
+                            });
+                    let result = if let Err(reason) = allowed {
+                        Err(PacketError::new(ErrorCode::PermissionDenied, reason))
+                    } else if req.msg.len() > server.limits().max_message_size {
+                        Err(PacketError::new(
+                            ErrorCode::InvalidArgument,
+                            "the message is too large",
+                        ))
+                    } else if let Err(reason) = &normalized {
+                        Err(PacketError::new(ErrorCode::InvalidArgument, reason))
+                    } else if let Some(Err(reason)) = checked {
+                        Err(PacketError::new(ErrorCode::PermissionDenied, reason))
+                    } else {
+                        let channel = current_channel.clone();
+                        server
+                            .with_db(move |server| {
+                                server
+                                    .scheduler
+                                    .schedule(&owner, &channel, req.at, &draft.msg, &server.db)
+                            })
+                            .await
+                    };
+                    server
+                        .dead_letters
+                        .send(
+                            Queue::Responses,
+                            &res_tx,
+                            PacketType::ScheduleRes(ScheduleRes { at: req.at, result }),
+                        )
+                        .await;
+                }
+                Ok(PacketType::PresenceReq(req)) => {
+                    let user = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+                    let result = server
+                        .with_db(move |server| server.presence.set(&user, req.presence, &server.db))
+                        .await;
+                    server
+                        .dead_letters
+                        .send(
+                            Queue::Responses,
+                            &res_tx,
+                            PacketType::PresenceRes(PresenceRes { result }),
+                        )
+                        .await;
+                }
+                // Invitation to a channel, only members of the channel can invite
+                Ok(PacketType::Invite(mut invite)) => {
+                    invite.from = id.lock().map(|lock| lock.clone()).unwrap_or_default();
+
+                    let checked = match server.channels.lock().await.get(&invite.channel_name) {
+                        None => Err(format!("channel '{}' not found", invite.channel_name)),
+                        Some(channel) if channel.archived => {
+                            Err(format!("channel '{}' is archived", invite.channel_name))
+                        }
+                        Some(channel) if !channel.has_user(&invite.from) => Err(format!(
+                            "you must be in the channel '{}' to invite",
+                            invite.channel_name
+                        )),
+                        Some(channel) if channel.has_user(&invite.to) => Err(format!(
+                            "'{}' is already in the channel '{}'",
+                            invite.to, invite.channel_name
+                        )),
+                        Some(_) => Ok(()),
+                    };
+                    let recipient = server.registry.lock().ok().and_then(|r| r.get(&invite.to));
+
+                    let notice = match (checked, recipient) {
+                        (Err(e), _) => e,
+                        (Ok(_), None) => format!("user '{}' is not online", invite.to),
+                        (Ok(_), Some(recipient_tx)) => {
+                            let notice = format!(
+                                "'{}' has been invited to the channel '{}'",
+                                invite.to, invite.channel_name
+                            );
+                            server
+                                .dead_letters
+                                .send(Queue::Responses, &recipient_tx, PacketType::Invite(invite))
+                                .await;
+                            notice
+                        }
+                    };
+                    server
+                        .dead_letters
+                        .send(
+                            Queue::Responses,
+                            &res_tx,
+                            PacketType::Message(Message::system_notice(&notice)),
+                        )
+                        .await;
+                }
+                // Latency probe, answered right away without touching any shared state
+                Ok(PacketType::Ping(ping)) => {
+                    let pong = Pong {
+                        timestamp: ping.timestamp,
+                    };
+                    server
+                        .dead_letters
+                        .send(Queue::Responses, &res_tx, PacketType::Pong(pong))
+                        .await;
+                }
+                // Received exit notification from client, remove the client from current session
+                Ok(PacketType::Exit(_)) => {
+                    leave_channel(&server, session, &id).await;
+                    return;
+                }
+                Err(e) => {
+                    println!("[!] Failed to parse packet from: '{}': {}", msg_str, e);
+                    let invalid = InvalidPacket {
+                        error: PacketError::new(ErrorCode::InvalidArgument, e.to_string()),
+                    };
+                    server
+                        .dead_letters
+                        .send(
+                            Queue::Responses,
+                            &res_tx,
+                            PacketType::InvalidPacket(invalid),
+                        )
+                        .await;
+                }
+                // responses and notifications of the server
+                Ok(_) => {
+                    let invalid = InvalidPacket {
+                        error: PacketError::new(
+                            ErrorCode::InvalidArgument,
+                            "the packet is not a request",
+                        ),
+                    };
+                    server
+                        .dead_letters
+                        .send(
+                            Queue::Responses,
+                            &res_tx,
+                            PacketType::InvalidPacket(invalid),
+                        )
+                        .await;
+                }
+            };
+        }
+    }
+}
+
+/// Bring the schema of the database up to date, a failure is reported and the server goes on
+/// with the schema as it is
+pub fn default_db_setup(pool: &Pool) {
+    let mut conn = match pool.get_conn() {
+        Ok(conn) => conn,
+        Err(e) => {
+            println!("[!] Database schema is not checked: {}", e);
+            return;
+        }
+    };
+    match db::migrations::run(&mut conn) {
+        Ok(version) => log::info(format_args!("Database schema is at version {}", version)),
+        Err(e) => println!("[!] {}", e),
+    }
+}
+
+/// Config of the server started with `opts`, the options override the file
+pub fn load_config(opts: &ServerOptions) -> Result<config::Config, String> {
+    // an explicitly given config file must exist
+    let mut config = match &opts.config {
+        Some(path) => config::Config::from_file(path)?,
+        None => config::Config::load_or_default(config::DEFAULT_CONFIG_PATH)?,
+    };
+    if let Some(db_url) = &opts.db_url {
+        config.db_url = db_url.clone();
+    }
+    if let Some(irc_port) = opts.irc_port {
+        config.irc_port = Some(irc_port);
+    }
+    Ok(config)
+}
+
+/// Drop the messages the channels don't keep anymore, forever
+async fn prune_histories(server: Arc<ServerState>) {
+    let mut interval = tokio::time::interval(session::MIN_RETENTION);
+    loop {
+        interval.tick().await;
+        let pruned = server.channels.lock().await.prune_histories();
+        if pruned > 0 {
+            log::info(format_args!("{} expired messages are pruned", pruned));
+        }
+    }
+}
+
+/// Check that the database answers every `period`, the changes are logged
+async fn check_database(server: Arc<ServerState>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    let mut healthy = true;
+    loop {
+        interval.tick().await;
+        let result = server.with_db(|server| server.db.check_health()).await;
+        match &result {
+            Ok(()) if !healthy => log::info(format_args!("Database is available again")),
+            Err(e) if healthy => println!("[!] Database health check failed: {}", e),
+            _ => (),
+        }
+        healthy = result.is_ok();
+    }
+}
+
+/// Delete the user channels nobody has been in for `grace`, their owners are told
+async fn collect_channels(server: Arc<ServerState>, grace: Duration) {
+    let mut interval = tokio::time::interval(session::GC_INTERVAL);
+    loop {
+        interval.tick().await;
+        let collected = server.channels.lock().await.collect_empty(grace);
+        for (name, owner) in collected {
+            log::info(format_args!("Empty channel '{}' is deleted", name));
+            let deleted = name.clone();
+            let result = server
+                .with_db(move |server| ChannelRecord::delete(&deleted, &server.db))
+                .await;
+            if let Err(e) = result {
+                println!("[!] {}", e);
             }
-            Err(_) => {
-                println!("[!] Failed to parse packet from: '{}'", msg_str);
+            if let Some(owner) = owner {
+                let notice = format!(
+                    "Your channel '{}' has been deleted after being empty for {}",
+                    name,
+                    compact_duration(grace.as_secs())
+                );
+                notify(&server, &owner, &notice).await;
             }
-            _ => {}
-        };
+        }
     }
 }
 
-// setup default schema for database, it doesn't panic even if those setups failed.
-pub async fn default_db_setup(pool: Pool) {
-    let mut conn = pool.get_conn().unwrap();
-    _ = conn.query_drop(
-        r"CREATE TABLE user (
-            id          VARCHAR(14) PRIMARY KEY,
-            password    TEXT NOT NULL,
-            bio         TEXT,
-            location    TEXT
-        )",
-    );
+/// Send `notice` to the member `id` right away, or at its next login if it's not logged in
+async fn notify(server: &Arc<ServerState>, id: &str, notice: &str) {
+    let online = server.registry.lock().ok().and_then(|r| r.get(id));
+    match online {
+        Some(res_tx) => {
+            server
+                .dead_letters
+                .send(
+                    Queue::Responses,
+                    &res_tx,
+                    PacketType::Message(Message::system_notice(notice)),
+                )
+                .await;
+        }
+        None if !id.starts_with("guest_") => {
+            let (id, notice) = (id.to_owned(), notice.to_owned());
+            let saved = server
+                .with_db(move |server| db::notice::save(&id, &notice, &server.db))
+                .await;
+            if let Err(e) = saved {
+                println!("[!] {}", e);
+            }
+        }
+        None => (),
+    }
+}
 
-    let root_password = hash::sha256_password("alpine");
-    _ = conn.query_drop(format!(
-        r"INSERT INTO user (
-            id, password, bio, location
-        ) VALUES (
-            'root',
-            '{}',
-            'root account',
-            ''
-        )
-        ",
-        root_password
-    ));
+/// Threads of the runtime configured for the server, `None` for the default
+///
+/// A config that can't be loaded is reported by `run_server` later.
+pub fn worker_threads(opts: &ServerOptions) -> Option<usize> {
+    load_config(opts).ok()?.workers.threads
 }
 
-pub async fn run_server(port: &str) -> Result<(), Box<dyn std::error::Error>> {
-    println!("[RsChat Sever] Bining on port {}...", port);
-    let listener = match TcpListener::bind(format!("0.0.0.0:{}", port)).await {
+pub async fn run_server(opts: &ServerOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let config = load_config(opts)?;
+    config.validate()?;
+    log::set_level(config.log_level);
+
+    println!("[RsChat Sever] Bining on port {}...", opts.port);
+    let listener = match TcpListener::bind(format!("0.0.0.0:{}", opts.port)).await {
         Ok(l) => l,
         Err(e) => panic!("{}", e),
     };
 
+    // The server starts even without a database, guests can chat in the meantime
+    let db = Database::new(&config.db_url, config.db_pool.limits(), default_db_setup);
+
     // Chatting channel list
-    let channels = Arc::new(AsyncMutex::new(session::Channels::with_system_channels()));
+    let roles = Arc::new(permissions::Roles::default());
+    let mut channels =
+        session::Channels::with_system_channels(&db, &config.channel_capacity, &roles);
+    channels.set_user_limits(
+        config.limits.max_users_per_channel,
+        config.limits.max_guests_per_channel,
+    );
+
+    let cluster = cluster::Cluster::new(&config.cluster);
+    if let Some(cluster) = &cluster {
+        channels.set_relay(cluster.relay(cluster.node()));
+    }
+
+    // compliance mode, every message of the channels goes to the transcript
+    if let Some(transcript) = transcript::Transcript::open(&config.compliance)? {
+        channels.set_transcript(&transcript);
+    }
+
+    // the channels get their recent messages back from the stored history
+    if let Some(log) = history::HistoryLog::open(&config) {
+        channels.set_history_log(&log);
+    }
 
-    let pool =
-        Pool::new("mysql://root@localhost:3306/rschat").expect("Make sure MySQL server is running");
-    default_db_setup(pool.clone()).await;
+    let server = Arc::new(ServerState {
+        audit: audit::AuditLog::open(config.audit_log.as_deref()),
+        bridges: bridge::Bridges::from_config(&config.bridges),
+        channels: AsyncMutex::new(channels),
+        cluster,
+        scheduler: scheduler::Scheduler::load(&db),
+        db,
+        dead_letters: dead_letter::DeadLetters::default(),
+        filters: RwLock::new(filter::FilterPipeline::from_config(&config.filter)),
+        limits: RwLock::new(config.limits.clone()),
+        options: opts.clone(),
+        plugins: plugin::PluginHost::from_config(&config.plugins),
+        presence: presence::Presences::default(),
+        registry: Mutex::new(registry::Registry::default()),
+        sessions: load::SessionLimiter::new(config.workers.max_sessions),
+        tarpit: tarpit::Tarpit::new(&config.tarpit),
+        traffic: traffic::Traffic::default(),
+        revocations: token::Revocations::default(),
+        roles,
+        registrants: Mutex::new(HashMap::new()),
+        config: Mutex::new(config.clone()),
+    });
+    tokio::spawn(scheduler::run(Arc::clone(&server)));
+    tokio::spawn(prune_histories(Arc::clone(&server)));
+    if let Some(period) = config.db_pool.health_check_secs {
+        tokio::spawn(check_database(
+            Arc::clone(&server),
+            Duration::from_secs(period),
+        ));
+    }
+    if let Some(grace) = config.channel_gc.grace_secs {
+        tokio::spawn(collect_channels(
+            Arc::clone(&server),
+            Duration::from_secs(grace),
+        ));
+    }
+    if server.cluster.is_some() {
+        tokio::spawn(cluster::run(Arc::clone(&server)));
+    }
+    #[cfg(unix)]
+    tokio::spawn(reload::on_sighup(Arc::clone(&server)));
+
+    // the operator console may shut the server down
+    let shutdown = CancellationToken::new();
+    tokio::spawn(console::run(Arc::clone(&server), shutdown.clone()));
+
+    // IRC clients are served on their own port
+    if let Some(irc_port) = config.irc_port {
+        let irc_listener = TcpListener::bind(format!("0.0.0.0:{}", irc_port)).await?;
+        println!("[RsChat Sever] IRC gateway on port {}...", irc_port);
+        tokio::spawn(irc::run_gateway(irc_listener, Arc::clone(&server)));
+    }
 
     // We're good to go
-    while let Ok(s) = listener.accept().await {
-        println!("New connection from: {:?}", s.0);
-        tokio::spawn(session_task(s.0, Arc::clone(&channels), pool.clone()));
+    loop {
+        let s = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(s) => s,
+                Err(_) => break,
+            },
+            _ = shutdown.cancelled() => {
+                // let the sessions deliver the notice before the runtime goes away
+                tokio::time::sleep(console::SHUTDOWN_GRACE).await;
+                log::info(format_args!("Server is shut down"));
+                break;
+            }
+        };
+        // trapped connections don't take a permit, the real sessions keep their share
+        if server.tarpit.traps(s.1.ip()) {
+            log::info(format_args!("Connection from {:?} goes to the tarpit", s.1));
+            let server = Arc::clone(&server);
+            tokio::spawn(async move { server.tarpit.hold(s.0, s.1, Arc::clone(&server)).await });
+            continue;
+        }
+        let Some(permit) = server.sessions.admit() else {
+            println!("[!] Server is full, connection from {:?} is shed", s.1);
+            tokio::spawn(load::shed(s.0));
+            continue;
+        };
+        log::info(format_args!("New connection from: {:?}", s.0));
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            session_task(s.0, s.1, server).await;
+            drop(permit);
+        });
     }
     Ok(())
 }
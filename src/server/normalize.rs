@@ -0,0 +1,82 @@
+//! Normalization of the text of inbound messages, applied before the filters see it
+//!
+//! Clients differ in the line endings and the stray characters they send, e.g. carriage returns
+//! of terminals or whitespace left by the editor. Every message is normalized once here, so all
+//! clients render the same text and nobody posts messages made of invisible characters:
+//!
+//! - CRLF and lone CRs become LF, tabs become spaces,
+//! - other control characters are dropped, along with the invisible characters used to spoof
+//!   text, zero-width spaces and bidirectional overrides,
+//! - trailing whitespace is stripped from every line, and runs of blank lines are collapsed into
+//!   one,
+//! - messages left without any visible character are rejected.
+
+/// Spaces a tab is expanded to
+const TAB_WIDTH: usize = 4;
+
+/// Invisible characters that hide or reorder text, zero-width joiners are kept for the emojis
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}' | '\u{2060}' | '\u{FEFF}' | '\u{180E}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+    )
+}
+
+/// True if `c` shows nothing on its own
+fn is_blank(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '\u{200C}' | '\u{200D}')
+}
+
+/// Normalized `text`, or the reason it's rejected
+pub fn normalize(text: &str) -> Result<String, String> {
+    let unified = text.replace("\r\n", "\n").replace('\r', "\n");
+
+    let mut lines: Vec<String> = Vec::new();
+    for line in unified.split('\n') {
+        let mut cleaned = String::with_capacity(line.len());
+        for c in line.chars() {
+            match c {
+                '\t' => cleaned.push_str(&" ".repeat(TAB_WIDTH)),
+                c if c.is_control() || is_invisible(c) => (),
+                c => cleaned.push(c),
+            }
+        }
+        let cleaned = cleaned.trim_end_matches(is_blank).to_owned();
+
+        // one blank line at most between paragraphs
+        let after_blank = lines.last().is_some_and(|l| l.is_empty());
+        if !(cleaned.is_empty() && (lines.is_empty() || after_blank)) {
+            lines.push(cleaned);
+        }
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+
+    let text = lines.join("\n");
+    if text.chars().all(is_blank) {
+        return Err("the message is empty".to_owned());
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_normalized() {
+        assert_eq!(normalize("hello  \r\nthere\r").unwrap(), "hello\nthere");
+        assert_eq!(normalize("\n\na\n\n\n\nb\n\n").unwrap(), "a\n\nb");
+        assert_eq!(normalize("a\tb\u{7}\u{1b}[31m").unwrap(), "a    b[31m");
+        assert_eq!(normalize("evil\u{202E}txt").unwrap(), "eviltxt");
+
+        // emojis joined by zero-width joiners survive
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        assert_eq!(normalize(family).unwrap(), family);
+
+        for empty in ["", "   ", "\r\n\t", "\u{200B}\u{200D}", "\u{0}\u{7f}"] {
+            assert!(normalize(empty).is_err(), "{:?}", empty);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Server-wide operations and the roles allowed to perform them
+//!
+//! Roles are looked up by id in the `Roles` of the server, they're learned from the database when
+//! members log in and kept for as long as the server runs. Guests and unknown ids are plain users.
+
+use std::{collections::BTreeMap, sync::RwLock};
+
+use crate::db::user::Role;
+
+/// Operation reserved to some of the roles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Disconnect a user from the server
+    Kick,
+    /// Keep a member from logging in
+    Ban,
+    /// Post a notice to every channel or change the message of the day
+    Broadcast,
+    CreateSystemChannel,
+    /// Keep a user channel from being deleted once it's been empty for a while
+    ExemptChannel,
+    /// Act as the owner of any channel, e.g. to close it
+    ManageAnyChannel,
+    /// Act as a moderator of any channel, e.g. to post in announcement channels
+    ModerateAnyChannel,
+    SetRole,
+    /// Decide on registrations waiting for approval
+    ApproveRegistration,
+    /// See the traffic of every connection and account
+    ViewTraffic,
+    /// See the packets the server failed to deliver
+    ViewDeadLetters,
+    /// Dump the state of the server for a backup
+    ExportState,
+}
+
+/// Roles allowed to perform each operation
+const PERMISSIONS: &[(Operation, &[Role])] = &[
+    (Operation::Kick, &[Role::Admin, Role::Moderator]),
+    (Operation::Ban, &[Role::Admin, Role::Moderator]),
+    (Operation::Broadcast, &[Role::Admin]),
+    (Operation::CreateSystemChannel, &[Role::Admin]),
+    (Operation::ExemptChannel, &[Role::Admin]),
+    (Operation::ManageAnyChannel, &[Role::Admin]),
+    (
+        Operation::ModerateAnyChannel,
+        &[Role::Admin, Role::Moderator],
+    ),
+    (Operation::SetRole, &[Role::Admin]),
+    (Operation::ApproveRegistration, &[Role::Admin]),
+    (Operation::ViewTraffic, &[Role::Admin]),
+    (Operation::ViewDeadLetters, &[Role::Admin]),
+    (Operation::ExportState, &[Role::Admin]),
+];
+
+/// True if `role` may perform `op`
+pub fn role_allows(role: Role, op: Operation) -> bool {
+    PERMISSIONS
+        .iter()
+        .any(|(operation, roles)| *operation == op && roles.contains(&role))
+}
+
+/// Roles of the members who aren't plain users, one table per server shared with its channels
+#[derive(Debug, Default)]
+pub struct Roles {
+    roles: RwLock<BTreeMap<String, Role>>,
+}
+
+impl Roles {
+    /// True if `id` may perform `op`
+    pub fn allows(&self, id: &str, op: Operation) -> bool {
+        role_allows(self.role_of(id), op)
+    }
+
+    pub fn role_of(&self, id: &str) -> Role {
+        self.roles
+            .read()
+            .ok()
+            .and_then(|roles| roles.get(id).copied())
+            .unwrap_or_default()
+    }
+
+    /// Remember the role of `id`, e.g. once it logs in
+    pub fn set(&self, id: &str, role: Role) {
+        let Ok(mut roles) = self.roles.write() else {
+            return;
+        };
+        if role == Role::User {
+            roles.remove(id);
+        } else {
+            roles.insert(id.to_owned(), role);
+        }
+    }
+}
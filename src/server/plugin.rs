@@ -0,0 +1,234 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::{config::PluginConfig, guests};
+use crate::packet::Message;
+
+/// What a plugin wants to happen to a message
+pub enum Action {
+    /// Let the message through
+    Continue,
+
+    /// Drop the message, the reason is sent back to the sender
+    Reject(String),
+
+    /// Let the message through and post the reply to the channel afterwards
+    Reply(String),
+}
+
+/// Hooks invoked by the session tasks, every hook does nothing by default
+pub trait Plugin: Send + Sync {
+    /// Name of the plugin shown in logs and rejection notices
+    fn name(&self) -> &str;
+
+    /// A new client has connected from `addr`
+    fn on_connect(&self, _addr: &SocketAddr) {}
+
+    /// The session has logged in as `id`, either as a guest or as a member
+    fn on_login(&self, _id: &str) {}
+
+    /// `msg` is about to be broadcasted to `channel`
+    fn on_message(&self, _channel: &str, _msg: &Message) -> Action {
+        Action::Continue
+    }
+
+    /// `id` has joined `channel`
+    fn on_channel_join(&self, _id: &str, _channel: &str) {}
+
+    /// Notice sent to `id` alone, after it logged in in `channel`
+    fn on_greet(&self, _id: &str, _channel: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Prints every event to the server log
+pub struct Logger;
+
+impl Plugin for Logger {
+    fn name(&self) -> &str {
+        "logger"
+    }
+
+    fn on_connect(&self, addr: &SocketAddr) {
+        println!("[logger] connect: {}", addr);
+    }
+
+    fn on_login(&self, id: &str) {
+        println!("[logger] login: '{}'", id);
+    }
+
+    fn on_message(&self, channel: &str, msg: &Message) -> Action {
+        println!("[logger] #{} {}: {}", channel, msg.id, msg.msg);
+        Action::Continue
+    }
+
+    fn on_channel_join(&self, id: &str, channel: &str) {
+        println!("[logger] join: '{}' -> #{}", id, channel);
+    }
+}
+
+/// Replies to messages starting with one of the triggers
+pub struct AutoResponder {
+    replies: BTreeMap<String, String>,
+}
+
+impl Plugin for AutoResponder {
+    fn name(&self) -> &str {
+        "auto responder"
+    }
+
+    fn on_message(&self, _channel: &str, msg: &Message) -> Action {
+        let first_word = msg.msg.split_whitespace().next().unwrap_or_default();
+        match self.replies.get(first_word) {
+            Some(reply) => Action::Reply(reply.clone()),
+            None => Action::Continue,
+        }
+    }
+}
+
+/// Rejects messages with links in the given channels, every channel if none is given
+pub struct NoLinks {
+    channels: Vec<String>,
+}
+
+impl Plugin for NoLinks {
+    fn name(&self) -> &str {
+        "no links"
+    }
+
+    fn on_message(&self, channel: &str, msg: &Message) -> Action {
+        let applies = self.channels.is_empty() || self.channels.iter().any(|c| c == channel);
+        if applies && (msg.msg.contains("http://") || msg.msg.contains("https://")) {
+            Action::Reject("links are not allowed here".to_owned())
+        } else {
+            Action::Continue
+        }
+    }
+}
+
+/// Greets the guests logging in in `channel` with the next of its tips
+pub struct WelcomeBot {
+    channel: String,
+    greeting: String,
+    tips: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl Plugin for WelcomeBot {
+    fn name(&self) -> &str {
+        "welcome bot"
+    }
+
+    fn on_greet(&self, id: &str, channel: &str) -> Option<String> {
+        if !guests::is_guest(id) || channel != self.channel {
+            return None;
+        }
+        let greeting = self.greeting.replace("{id}", id);
+        if self.tips.is_empty() {
+            return Some(greeting);
+        }
+        let tip = &self.tips[self.next.fetch_add(1, Ordering::Relaxed) % self.tips.len()];
+        Some(format!("{} Tip: {}", greeting, tip))
+    }
+}
+
+/// Plugins registered at startup, hooks are invoked in the order of registration
+#[derive(Default)]
+pub struct PluginHost {
+    plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl PluginHost {
+    /// Host with the built-in plugins listed in `config`
+    pub fn from_config(config: &[PluginConfig]) -> Self {
+        let mut host = Self::default();
+        for plugin in config {
+            host.add(match plugin {
+                PluginConfig::Logger => Box::new(Logger),
+                PluginConfig::AutoResponder { replies } => Box::new(AutoResponder {
+                    replies: replies.clone(),
+                }),
+                PluginConfig::NoLinks { channels } => Box::new(NoLinks {
+                    channels: channels.clone(),
+                }),
+                PluginConfig::WelcomeBot {
+                    channel,
+                    greeting,
+                    tips,
+                } => Box::new(WelcomeBot {
+                    channel: channel.clone(),
+                    greeting: greeting.clone(),
+                    tips: tips.clone(),
+                    next: AtomicUsize::new(0),
+                }),
+            });
+        }
+        host
+    }
+
+    /// Register a plugin after the ones already registered
+    pub fn add(&mut self, plugin: Box<dyn Plugin>) {
+        super::log::info(format_args!("Plugin loaded: {}", plugin.name()));
+        self.plugins.push(plugin);
+    }
+
+    pub fn on_connect(&self, addr: &SocketAddr) {
+        self.plugins.iter().for_each(|p| p.on_connect(addr));
+    }
+
+    pub fn on_login(&self, id: &str) {
+        self.plugins.iter().for_each(|p| p.on_login(id));
+    }
+
+    /// Replies of the plugins, or the reason of the first rejection
+    pub fn on_message(&self, channel: &str, msg: &Message) -> Result<Vec<String>, String> {
+        let mut replies = Vec::new();
+        for plugin in &self.plugins {
+            match plugin.on_message(channel, msg) {
+                Action::Continue => (),
+                Action::Reject(reason) => {
+                    return Err(format!("rejected by {} plugin: {}", plugin.name(), reason))
+                }
+                Action::Reply(reply) => replies.push(reply),
+            }
+        }
+        Ok(replies)
+    }
+
+    pub fn on_channel_join(&self, id: &str, channel: &str) {
+        self.plugins
+            .iter()
+            .for_each(|p| p.on_channel_join(id, channel));
+    }
+
+    /// Notices of the plugins for `id`, who logged in in `channel`
+    pub fn on_greet(&self, id: &str, channel: &str) -> Vec<String> {
+        self.plugins
+            .iter()
+            .filter_map(|p| p.on_greet(id, channel))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn welcome_bot_rotates_its_tips_for_guests() {
+        let host = PluginHost::from_config(&[PluginConfig::WelcomeBot {
+            channel: "public".to_owned(),
+            greeting: "Hi {id}!".to_owned(),
+            tips: vec!["one".to_owned(), "two".to_owned()],
+        }]);
+        assert_eq!(host.on_greet("guest_1", "public"), ["Hi guest_1! Tip: one"]);
+        assert_eq!(host.on_greet("guest_2", "public"), ["Hi guest_2! Tip: two"]);
+        assert_eq!(host.on_greet("guest_3", "public"), ["Hi guest_3! Tip: one"]);
+        // members and the other channels are left alone
+        assert!(host.on_greet("alice", "public").is_empty());
+        assert!(host.on_greet("guest_4", "dev").is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use crate::db::{self, Database};
+use crate::packet::{ErrorCode, PacketError, Presence};
+
+/// Presence of the logged in members, members who aren't here are online
+#[derive(Default)]
+pub struct Presences {
+    states: Mutex<HashMap<String, Presence>>,
+}
+
+impl Presences {
+    pub fn get(&self, id: &str) -> Presence {
+        self.states
+            .lock()
+            .ok()
+            .and_then(|states| states.get(id).copied())
+            .unwrap_or_default()
+    }
+
+    /// Restore the stored presence of `id` once it logs in
+    pub fn on_login(&self, id: &str, db: &Database) -> Presence {
+        if id.starts_with("guest_") {
+            return Presence::Online;
+        }
+        let presence = db::presence::load(id, db).unwrap_or_else(|e| {
+            println!("[!] {}", e);
+            Presence::Online
+        });
+        if let Ok(mut states) = self.states.lock() {
+            states.insert(id.to_owned(), presence);
+        }
+        presence
+    }
+
+    /// `id` has logged out, it doesn't need to be kept in memory
+    pub fn forget(&self, id: &str) {
+        if let Ok(mut states) = self.states.lock() {
+            states.remove(id);
+        }
+    }
+
+    /// Change and store the presence of the member `id`
+    ///
+    /// Away is only kept for the session, the stored presence is restored at the next login.
+    pub fn set(
+        &self,
+        id: &str,
+        presence: Presence,
+        db: &Database,
+    ) -> Result<Presence, PacketError> {
+        if id.is_empty() || id.starts_with("guest_") {
+            return Err(PacketError::new(
+                ErrorCode::PermissionDenied,
+                "only members can change their presence",
+            ));
+        }
+        if presence != Presence::Away {
+            db::presence::save(id, presence, db)
+                .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        }
+        if let Ok(mut states) = self.states.lock() {
+            states.insert(id.to_owned(), presence);
+        }
+        Ok(presence)
+    }
+
+    /// Members hidden from user lists
+    pub fn invisible(&self) -> HashSet<String> {
+        self.states
+            .lock()
+            .map(|states| {
+                states
+                    .iter()
+                    .filter(|(_, p)| **p == Presence::Invisible)
+                    .map(|(id, _)| id.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::packet::PacketType;
+
+/// A logged in session
+struct Session {
+    res_tx: mpsc::Sender<PacketType>,
+
+    /// Ends the session once cancelled
+    kick_token: CancellationToken,
+}
+
+/// Random guest ids tried before giving up, the space of `guest_<u16>` is nearly full by then
+const MAX_GUEST_ID_ATTEMPTS: usize = 1024;
+
+/// Server-wide map of logged in identities to their sessions
+///
+/// Used to deliver packets addressed to a single user regardless of the channel they're in.
+/// Guest ids are handed out here as well, so no two sessions go by the same one whatever their
+/// channels.
+#[derive(Default)]
+pub struct Registry {
+    sessions: HashMap<String, Session>,
+
+    /// Guest ids in use, from their reservation until their session is unregistered
+    guests: HashSet<String>,
+}
+
+impl Registry {
+    /// Register the session of `id`, `res_tx` is the response channel of the session and
+    /// `kick_token` ends it
+    pub fn register(
+        &mut self,
+        id: &str,
+        res_tx: mpsc::Sender<PacketType>,
+        kick_token: CancellationToken,
+    ) {
+        self.sessions
+            .insert(id.to_owned(), Session { res_tx, kick_token });
+    }
+
+    /// Unregister `id` if it's still registered by the session owning `res_tx`, the guest id is
+    /// free to be reserved again
+    pub fn unregister(&mut self, id: &str, res_tx: &mpsc::Sender<PacketType>) {
+        if self
+            .sessions
+            .get(id)
+            .is_some_and(|session| session.res_tx.same_channel(res_tx))
+        {
+            self.sessions.remove(id);
+            self.guests.remove(id);
+        }
+    }
+
+    /// Reserve a guest id nobody else goes by, `None` if none could be found
+    ///
+    /// It stays reserved until the guest's session is unregistered, or `release_guest` if the
+    /// login fails before the session is registered.
+    pub fn reserve_guest(&mut self) -> Option<String> {
+        let mut rng = rand::thread_rng();
+        (0..MAX_GUEST_ID_ATTEMPTS)
+            .map(|_| format!("guest_{}", rng.gen::<u16>()))
+            .find(|id| !self.sessions.contains_key(id) && self.guests.insert(id.clone()))
+    }
+
+    /// Free the guest id `id` that was reserved for a login that failed
+    pub fn release_guest(&mut self, id: &str) {
+        if !self.sessions.contains_key(id) {
+            self.guests.remove(id);
+        }
+    }
+
+    /// Response channel of the session of `id`
+    pub fn get(&self, id: &str) -> Option<mpsc::Sender<PacketType>> {
+        self.sessions.get(id).map(|session| session.res_tx.clone())
+    }
+
+    /// Every logged in identity, sorted
+    pub fn ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.sessions.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// End the session of `id`, false if there's none
+    pub fn kick(&self, id: &str) -> bool {
+        match self.sessions.get(id) {
+            Some(session) => {
+                session.kick_token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guest_ids_are_reserved_until_unregistered() {
+        let mut registry = Registry::default();
+        let (res_tx, _res_rx) = mpsc::channel(1);
+
+        let first = registry.reserve_guest().unwrap();
+        let second = registry.reserve_guest().unwrap();
+        assert_ne!(first, second);
+        registry.register(&first, res_tx.clone(), CancellationToken::new());
+
+        // a failed login frees its reservation, a registered session keeps it
+        registry.release_guest(&second);
+        registry.release_guest(&first);
+        assert!(!registry.guests.contains(&second));
+        assert!(registry.guests.contains(&first));
+
+        registry.unregister(&first, &res_tx);
+        assert!(registry.guests.is_empty());
+    }
+}
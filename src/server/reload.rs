@@ -0,0 +1,86 @@
+//! Applying a changed config file to the running server
+//!
+//! Only the settings in `Config::RELOADABLE` are applied, connections stay as they are. The
+//! other settings are reported and take effect after a restart.
+
+use super::{config::Config, filter::FilterPipeline, log, ServerState};
+
+/// Re-read the config file and apply what can be applied, returns a report of the changes
+///
+/// Nothing is applied if the file can't be read or doesn't validate.
+pub async fn reload(server: &ServerState) -> Result<Vec<String>, String> {
+    let new = super::load_config(&server.options)?;
+    new.validate()?;
+
+    let changed = match server.config.lock() {
+        Ok(current) => current.changed(&new),
+        Err(_) => return Err("the current config is broken".to_owned()),
+    };
+    if changed.is_empty() {
+        return Ok(vec!["nothing has changed".to_owned()]);
+    }
+
+    let mut report = Vec::new();
+    for key in &changed {
+        if Config::RELOADABLE.contains(&key.as_str()) {
+            report.push(format!("'{}' is applied", key));
+        } else {
+            report.push(format!("'{}' takes a restart", key));
+        }
+    }
+    if changed.iter().any(|key| key == "limits") {
+        report.push("packet size and write timeouts apply to new connections".to_owned());
+    }
+
+    if let Ok(mut filters) = server.filters.write() {
+        *filters = FilterPipeline::from_config(&new.filter);
+    }
+    if let Ok(mut limits) = server.limits.write() {
+        *limits = new.limits.clone();
+    }
+    server.channels.lock().await.set_user_limits(
+        new.limits.max_users_per_channel,
+        new.limits.max_guests_per_channel,
+    );
+    log::set_level(new.log_level);
+    server.tarpit.set_config(&new.tarpit);
+
+    // the rest stays as it was started with, so it's reported again until the restart
+    if let Ok(mut current) = server.config.lock() {
+        current.filter = new.filter;
+        current.limits = new.limits;
+        current.log_level = new.log_level;
+        current.tarpit = new.tarpit;
+        current.session_tokens = new.session_tokens;
+        current.registration = new.registration;
+        current.guests = new.guests;
+        current.motd = new.motd;
+    }
+    Ok(report)
+}
+
+/// Reload on every SIGHUP
+#[cfg(unix)]
+pub async fn on_sighup(server: std::sync::Arc<ServerState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+        println!("[!] SIGHUP can't be handled, reload from the console instead");
+        return;
+    };
+    while hangups.recv().await.is_some() {
+        print_report(reload(&server).await);
+    }
+}
+
+/// Print the outcome of a reload to the server log
+pub fn print_report(result: Result<Vec<String>, String>) {
+    match result {
+        Ok(report) => {
+            for line in report {
+                log::info(format_args!("Config reloaded: {}", line));
+            }
+        }
+        Err(e) => println!("[!] Config is not reloaded: {}", e),
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::ServerState;
+use crate::db::{schedule::ScheduledMessage, Database};
+use crate::packet::*;
+
+/// Messages a user can have waiting at once
+const MAX_PENDING_PER_USER: usize = 16;
+
+/// Furthest a message can be scheduled ahead, in seconds
+const MAX_AHEAD: u64 = 30 * 24 * 60 * 60;
+
+/// Interval of checking for due messages
+const TICK: Duration = Duration::from_secs(1);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Scheduled messages waiting for their time, the database keeps them over restarts
+#[derive(Default)]
+pub struct Scheduler {
+    pending: Mutex<Vec<ScheduledMessage>>,
+}
+
+impl Scheduler {
+    /// Scheduler with the messages stored in `db`
+    pub fn load(db: &Database) -> Self {
+        let pending = ScheduledMessage::load_all(db).unwrap_or_else(|e| {
+            println!("[!] Scheduled messages are not restored: {}", e);
+            Vec::new()
+        });
+        Self {
+            pending: Mutex::new(pending),
+        }
+    }
+
+    /// Schedule `msg` of `owner` to be posted to `channel` at `at`
+    pub fn schedule(
+        &self,
+        owner: &str,
+        channel: &str,
+        at: u64,
+        msg: &str,
+        db: &Database,
+    ) -> Result<u64, PacketError> {
+        if owner.is_empty() || owner.starts_with("guest_") {
+            return Err(PacketError::new(
+                ErrorCode::PermissionDenied,
+                "only members can schedule messages",
+            ));
+        }
+        let now = now();
+        if at <= now || at > now + MAX_AHEAD {
+            return Err(PacketError::new(
+                ErrorCode::InvalidArgument,
+                "the time must be in the next 30 days",
+            ));
+        }
+
+        let mut pending = self
+            .pending
+            .lock()
+            .map_err(|_| PacketError::new(ErrorCode::Internal, "scheduler is broken"))?;
+        if pending.iter().filter(|m| m.owner == owner).count() >= MAX_PENDING_PER_USER {
+            return Err(PacketError::new(
+                ErrorCode::Full,
+                "too many scheduled messages",
+            ));
+        }
+
+        let id = ScheduledMessage::insert(owner, channel, at, msg, db)
+            .map_err(|e| PacketError::new(ErrorCode::Unavailable, e))?;
+        pending.push(ScheduledMessage {
+            id,
+            owner: owner.to_owned(),
+            channel: channel.to_owned(),
+            at,
+            msg: msg.to_owned(),
+        });
+        Ok(id)
+    }
+
+    /// Every message waiting
+    pub fn all(&self) -> Vec<ScheduledMessage> {
+        self.pending
+            .lock()
+            .map(|pending| pending.clone())
+            .unwrap_or_default()
+    }
+
+    /// Messages `owner` has waiting
+    pub fn pending_of(&self, owner: &str) -> Vec<ScheduledMessage> {
+        let Ok(pending) = self.pending.lock() else {
+            return Vec::new();
+        };
+        pending
+            .iter()
+            .filter(|m| m.owner == owner)
+            .cloned()
+            .collect()
+    }
+
+    /// Drop the messages of `owner` without posting them, they're deleted from the database
+    pub fn forget_owner(&self, owner: &str, db: &Database) -> Result<(), String> {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.retain(|m| m.owner != owner);
+        }
+        ScheduledMessage::delete_of(owner, db)
+    }
+
+    /// Remove the messages whose time has come
+    fn take_due(&self) -> Vec<ScheduledMessage> {
+        let Ok(mut pending) = self.pending.lock() else {
+            return Vec::new();
+        };
+        let now = now();
+        let (due, waiting) = pending.drain(..).partition(|m| m.at <= now);
+        *pending = waiting;
+        due
+    }
+}
+
+/// Post scheduled messages forever, attributed to their owners
+pub async fn run(server: Arc<ServerState>) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        for scheduled in server.scheduler.take_due() {
+            let id = scheduled.id;
+            let deleted = server
+                .with_db(move |server| ScheduledMessage::delete(id, &server.db))
+                .await;
+            if let Err(e) = deleted {
+                println!("[!] {}", e);
+            }
+
+            let posted = server.channels.lock().await.post_scheduled(
+                &scheduled,
+                |msg| server.filter(&scheduled.channel, msg),
+                |msg| server.plugins.on_message(&scheduled.channel, msg),
+            );
+            let msg = match posted {
+                Ok(msg) => msg,
+                Err(reason) => {
+                    println!(
+                        "[!] Scheduled message {} is dropped, {}",
+                        scheduled.id, reason
+                    );
+                    continue;
+                }
+            };
+            server
+                .bridges
+                .mirror(&scheduled.channel, &msg, &server.dead_letters);
+        }
+    }
+}
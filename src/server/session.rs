@@ -1,17 +1,94 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use mysql::*;
-use rand::prelude::*;
 use tokio::sync::broadcast;
 
-use crate::packet::*;
+use super::{
+    cluster,
+    config::CapacityConfig,
+    history,
+    permissions::{Operation, Roles},
+    transcript,
+};
+use crate::{
+    crypto::auth::Verifier,
+    db::{channel::ChannelRecord, schedule::ScheduledMessage, Database},
+    packet::*,
+};
 
+/// Number of entries of a page unless the request asks for another size
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+
+/// Largest page a request can ask for
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Guests and members a channel takes unless configured otherwise
 pub const NUM_MAX_GUEST: usize = 64;
 pub const NUM_MAX_USER: usize = 128;
 
 /// The default channel you enter when connecting to the server
 pub const DEFAULT_CHANNEL: &str = "public";
 
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Number of the latest messages replayed to clients joining a channel
+pub const NUM_HISTORY_MESSAGES: usize = 50;
+
+/// Number of the latest messages reactions are kept for
+pub const NUM_MAX_REACTION_MESSAGES: u64 = 1024;
+
+/// Number of messages a channel can have pinned at once
+pub const NUM_MAX_PINS: usize = 16;
+
+/// Author of the messages of erased accounts, can't be taken by a member or a guest
+pub const ERASED_ID: &str = "[erased]";
+
+/// Shortest time a channel can keep its messages for, the history is pruned about this often
+pub const MIN_RETENTION: Duration = Duration::from_secs(60);
+
+/// How often the empty user channels are looked for
+pub const GC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum length of an emoji or a shortcode
+const MAX_EMOJI_LEN: usize = 32;
+
+/// Longest nickname in characters
+const MAX_NICKNAME_LEN: usize = 24;
+
+/// Longest topic of a channel, in characters
+const MAX_TOPIC_LEN: usize = 300;
+
+/// Emoji for the supported reaction shortcodes
+const SHORTCODES: &[(&str, &str)] = &[
+    (":+1:", "👍"),
+    (":-1:", "👎"),
+    (":heart:", "❤️"),
+    (":smile:", "😄"),
+    (":laugh:", "😂"),
+    (":tada:", "🎉"),
+    (":eyes:", "👀"),
+    (":fire:", "🔥"),
+];
+
+/// Emoji for `shortcode`, unknown shortcodes and emoji are kept as they are
+fn expand_shortcode(shortcode: &str) -> &str {
+    SHORTCODES
+        .iter()
+        .find(|(code, _)| *code == shortcode)
+        .map_or(shortcode, |(_, emoji)| emoji)
+}
+
 /// Reserved system channels
 pub const SYSTEM_CHANNELS: [&str; 3] = [DEFAULT_CHANNEL, "main", "dev"];
 
@@ -32,6 +109,89 @@ impl State {
     }
 }
 
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Number of users listed as the top speakers of a channel
+const NUM_TOP_SPEAKERS: usize = 3;
+
+/// Activity of a channel since it was created or restored, kept in memory only
+#[derive(Debug)]
+pub struct ChannelStats {
+    /// Unix time in seconds
+    pub created_at: u64,
+
+    /// Largest number of users in the channel at once
+    pub peak_users: usize,
+
+    /// Arrival times of the messages of the last day
+    recent: VecDeque<Instant>,
+
+    /// Number of messages of each user
+    speakers: HashMap<String, u64>,
+}
+
+impl ChannelStats {
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn new() -> Self {
+        Self {
+            created_at: unix_now(),
+            peak_users: 0,
+            recent: VecDeque::new(),
+            speakers: HashMap::new(),
+        }
+    }
+
+    fn on_message(&mut self, id: &str) {
+        let now = Instant::now();
+        self.recent.push_back(now);
+        while self
+            .recent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > Self::DAY)
+        {
+            self.recent.pop_front();
+        }
+        *self.speakers.entry(id.to_owned()).or_default() += 1;
+    }
+
+    /// Number of messages received within `period`
+    fn count_within(&self, period: Duration) -> usize {
+        let now = Instant::now();
+        self.recent
+            .iter()
+            .rev()
+            .take_while(|t| now.duration_since(**t) <= period)
+            .count()
+    }
+
+    /// Users with the most messages, the most talkative first
+    fn top_speakers(&self) -> Vec<(&str, u64)> {
+        let mut speakers: Vec<(&str, u64)> = self
+            .speakers
+            .iter()
+            .map(|(id, count)| (id.as_str(), *count))
+            .collect();
+        speakers.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        speakers.truncate(NUM_TOP_SPEAKERS);
+        speakers
+    }
+}
+
+/// When a post checked by `Channel::check_post` takes effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Posting {
+    /// Broadcast right away, it counts for slow mode
+    Now,
+    /// Scheduled for later, slow mode is up to the time it's posted
+    Scheduled,
+}
+
 /// Individual chat channel
 #[derive(Debug)]
 pub struct Channel {
@@ -40,16 +200,783 @@ pub struct Channel {
 
     /// True if this is one of system channels
     pub is_system: bool,
+
+    /// Creator of the channel, `None` for system channels
+    pub owner: Option<String>,
+
+    /// Archived channels keep their name reserved but can't be joined anymore
+    pub archived: bool,
+
+    /// Users allowed to moderate the channel besides the owner
+    pub moderators: HashSet<String>,
+
+    /// Minimum interval between messages of a user, `None` if slow mode is off
+    pub slow_mode: Option<Duration>,
+
+    /// Time of the last message of each user, tracked only in slow mode
+    pub last_message: HashMap<String, Instant>,
+
+    /// Only moderators can post in announcement channels, everyone else can just read
+    pub announce_only: bool,
+
+    /// Sequence number of the next message
+    pub next_seq: u64,
+
+    /// The latest messages, with their sequence numbers
+    pub history: VecDeque<Message>,
+
+    /// When each message of the history was recorded, oldest first
+    pub recorded_at: VecDeque<Instant>,
+
+    /// What is kept of the messages, see `prune_history`
+    pub history_policy: HistoryPolicy,
+
+    /// Users who reacted with each emoji, per message sequence number
+    pub reactions: BTreeMap<u64, BTreeMap<String, BTreeSet<String>>>,
+
+    /// Messages pinned by the moderators, keyed by sequence number
+    pub pins: BTreeMap<u64, Message>,
+
+    /// Original form of the messages retracted by their senders, kept for the moderators
+    pub retracted: BTreeMap<u64, Message>,
+
+    /// Nicknames the members go by in this channel, by id
+    pub nicknames: BTreeMap<String, String>,
+
+    /// Told to everyone joining the channel, set by the moderators
+    pub topic: Option<String>,
+
+    /// Users the moderators keep out of the channel
+    pub banned: BTreeSet<String>,
+
+    /// Users the moderators keep from posting, until the unix time or until unmuted if `None`
+    pub muted: BTreeMap<String, Option<u64>>,
+
+    /// Password asked of everyone joining but the moderators, set when the channel is created
+    pub password: Option<Verifier>,
+
+    /// Server-wide roles, for the admins and moderators acting on any channel
+    roles: Arc<Roles>,
+
+    pub stats: ChannelStats,
+
+    /// Members and guests the channel takes at most
+    pub max_users: usize,
+    pub max_guests: usize,
+
+    /// Never deleted for being empty, see `Channels::collect_empty`
+    pub gc_exempt: bool,
+
+    /// Since when nobody has been in the channel, from its creation or as last seen by
+    /// `Channels::collect_empty`
+    pub empty_since: Option<Instant>,
+
+    /// Sends the messages to the other nodes of the cluster, `None` if clustering is off
+    pub relay: Option<cluster::Relay>,
+
+    /// Members of the channel logged in on other nodes, kept by `cluster`
+    pub remote_names: BTreeSet<String>,
+
+    /// Appends the messages to the transcript, `None` unless the compliance mode is on
+    pub transcript: Option<transcript::Tap>,
+
+    /// Keeps the recorded messages in the history store, `None` for the `memory` backend
+    pub store: Option<history::Tap>,
 }
 
 impl Channel {
+    /// True if `id` may manage this channel
+    pub fn is_owner(&self, id: &str) -> bool {
+        self.owner.as_deref() == Some(id) || self.roles.allows(id, Operation::ManageAnyChannel)
+    }
+
+    /// True if `id` may moderate this channel
+    pub fn is_moderator(&self, id: &str) -> bool {
+        self.is_owner(id)
+            || self.moderators.contains(id)
+            || self.roles.allows(id, Operation::ModerateAnyChannel)
+    }
+
+    /// Enable slow mode with the interval of `secs` on behalf of `id`, 0 disables it
+    pub fn set_slow_mode(&mut self, id: &str, secs: u64) -> Result<String, String> {
+        if !self.is_moderator(id) {
+            return Err("only moderators can change slow mode".to_owned());
+        }
+        self.last_message.clear();
+        if secs == 0 {
+            self.slow_mode = None;
+            Ok("slow mode is disabled".to_owned())
+        } else {
+            self.slow_mode = Some(Duration::from_secs(secs));
+            Ok(format!("slow mode is enabled: one message per {}s", secs))
+        }
+    }
+
+    /// Assign the next sequence number to `msg` and keep it in the history
+    ///
+    /// Must be called in the same critical section `msg` is broadcasted in, so the history and
+    /// the broadcasting order agree.
+    fn record(&mut self, msg: &mut Message) {
+        msg.seq = Some(self.next_seq);
+        self.next_seq += 1;
+        if !msg.is_system {
+            self.stats.on_message(&msg.id);
+        }
+
+        if self.history_policy == HistoryPolicy::Ephemeral {
+            return;
+        }
+        if let Some(store) = &self.store {
+            store.record(unix_now(), msg);
+        }
+        self.history.push_back(msg.clone());
+        self.recorded_at.push_back(Instant::now());
+        if self.history.len() > NUM_HISTORY_MESSAGES {
+            self.history.pop_front();
+            self.recorded_at.pop_front();
+        }
+    }
+
+    /// Take the messages `stored` with the unix time they were sent at back in the history, the
+    /// sequence numbers go on after them
+    pub fn restore_history(&mut self, stored: Vec<(u64, Message)>) {
+        let (now, now_unix) = (Instant::now(), unix_now());
+        for (at, msg) in stored {
+            let age = Duration::from_secs(now_unix.saturating_sub(at));
+            if let Some(seq) = msg.seq {
+                self.next_seq = self.next_seq.max(seq + 1);
+            }
+            self.history.push_back(msg);
+            self.recorded_at
+                .push_back(now.checked_sub(age).unwrap_or(now));
+        }
+        while self.history.len() > NUM_HISTORY_MESSAGES {
+            self.history.pop_front();
+            self.recorded_at.pop_front();
+        }
+        self.prune_history();
+    }
+
+    /// Drop the messages the history policy doesn't keep anymore, along with their reactions and
+    /// pins, returns the number of messages dropped
+    pub fn prune_history(&mut self) -> usize {
+        let keep = match self.history_policy {
+            HistoryPolicy::Persist => return 0,
+            HistoryPolicy::Ephemeral => 0,
+            HistoryPolicy::Retain(secs) => {
+                let now = Instant::now();
+                let max_age = Duration::from_secs(secs);
+                let expired = self
+                    .recorded_at
+                    .iter()
+                    .take_while(|at| now.duration_since(**at) > max_age)
+                    .count();
+                self.history.len() - expired
+            }
+        };
+        let dropped = self.history.len() - keep;
+        if dropped == 0 {
+            return 0;
+        }
+        self.history.drain(..dropped);
+        self.recorded_at.drain(..dropped);
+
+        // the messages after the history are the ones still kept
+        let oldest = self
+            .history
+            .front()
+            .and_then(|msg| msg.seq)
+            .unwrap_or(self.next_seq);
+        self.reactions = self.reactions.split_off(&oldest);
+        self.pins = self.pins.split_off(&oldest);
+        self.retracted = self.retracted.split_off(&oldest);
+        dropped
+    }
+
+    /// Messages of `id` in the history and the pins, oldest first
+    pub fn messages_of(&self, id: &str) -> Vec<&Message> {
+        let mut messages: Vec<&Message> = self
+            .history
+            .iter()
+            .chain(self.pins.values())
+            .filter(|msg| !msg.is_system && msg.id == id)
+            .collect();
+        messages.sort_by_key(|msg| msg.seq);
+        messages.dedup_by_key(|msg| msg.seq);
+        messages
+    }
+
+    /// First message of the thread the message `seq` is part of, `seq` itself if it's not a reply
+    ///
+    /// A reply to a message gone from the history starts from the message it replies to.
+    pub fn thread_root(&self, seq: u64) -> u64 {
+        self.history
+            .iter()
+            .find(|msg| msg.seq == Some(seq))
+            .and_then(|msg| msg.thread)
+            .unwrap_or(seq)
+    }
+
+    /// Messages of the members after the message `seq` still in the history, the unread ones
+    pub fn unread_after(&self, seq: u64) -> usize {
+        self.history
+            .iter()
+            .filter(|msg| !msg.is_system && msg.seq.is_some_and(|s| s > seq))
+            .count()
+    }
+
+    /// Messages of the thread started by the message `root` still in the history, oldest first
+    pub fn thread(&self, root: u64) -> Vec<&Message> {
+        self.history
+            .iter()
+            .filter(|msg| msg.seq == Some(root) || msg.thread == Some(root))
+            .collect()
+    }
+
+    /// Remove every trace of the member `id`, its messages stay but are attributed to
+    /// `ERASED_ID`, returns the number of messages anonymized
+    ///
+    /// A channel owned by `id` is left to the admins.
+    pub fn erase_user(&mut self, id: &str) -> usize {
+        let mut count = 0;
+        for msg in self.history.iter_mut() {
+            if !msg.is_system && msg.id == id {
+                msg.id = ERASED_ID.to_owned();
+                count += 1;
+            }
+        }
+        // pinned and retracted messages are copies of the ones in the history
+        for msg in self.pins.values_mut().chain(self.retracted.values_mut()) {
+            if !msg.is_system && msg.id == id {
+                msg.id = ERASED_ID.to_owned();
+            }
+        }
+        for users in self
+            .reactions
+            .values_mut()
+            .flat_map(|emojis| emojis.values_mut())
+        {
+            users.remove(id);
+        }
+        self.stats.speakers.remove(id);
+        self.moderators.remove(id);
+        self.nicknames.remove(id);
+        self.banned.remove(id);
+        self.muted.remove(id);
+        self.last_message.remove(id);
+        if self.owner.as_deref() == Some(id) {
+            self.owner = None;
+        }
+        count
+    }
+
+    /// Change what is kept of the messages on behalf of `id`, only the owner can change it
+    pub fn set_history_policy(
+        &mut self,
+        id: &str,
+        policy: HistoryPolicy,
+    ) -> Result<String, String> {
+        if !self.is_owner(id) {
+            return Err("only the owner can change the history policy".to_owned());
+        }
+        if matches!(policy, HistoryPolicy::Retain(secs) if secs < MIN_RETENTION.as_secs()) {
+            return Err(format!(
+                "messages must be kept for at least {}s",
+                MIN_RETENTION.as_secs()
+            ));
+        }
+        self.history_policy = policy;
+        self.prune_history();
+        Ok(match policy {
+            HistoryPolicy::Persist => "recent messages are kept now".to_owned(),
+            HistoryPolicy::Ephemeral => "messages are never stored now".to_owned(),
+            HistoryPolicy::Retain(_) => {
+                format!("messages are {} now", policy.describe().unwrap_or_default())
+            }
+        })
+    }
+
+    /// Record `msg` and broadcast it, the single send point of messages of the channel
+    ///
+    /// Every message, system messages included, gets its sequence number here, so subscribers
+    /// receive the messages in the order of their sequence numbers. The message is serialized here
+    /// once, the subscribers share the frame. Returns the recorded message.
+    pub fn broadcast(&mut self, mut msg: Message) -> Message {
+        if !msg.is_system {
+            msg.display_name = self.nicknames.get(&msg.id).cloned();
+        }
+        let msg = self.broadcast_local(msg);
+        if let Some(relay) = &self.relay {
+            relay.publish(&msg);
+        }
+        msg
+    }
+
+    /// Record `msg` and broadcast it to the subscribers on this node only, e.g. a message relayed
+    /// from another node
+    pub fn broadcast_local(&mut self, mut msg: Message) -> Message {
+        self.record(&mut msg);
+        if let Some(transcript) = &self.transcript {
+            transcript.record(&msg);
+        }
+        _ = self
+            .channel
+            .send(PacketType::Broadcast(Broadcast::new(msg.clone())));
+        msg
+    }
+
+    /// Subscribe to the channel `name` along with the snapshot of its history
+    ///
+    /// Messages are recorded and broadcasted under the same lock the subscription is made in, so
+    /// every message is either in the snapshot or received by the subscription, never both.
+    pub fn subscribe(&self, name: &str) -> (broadcast::Receiver<PacketType>, JoinSnapshot) {
+        let messages: Vec<Message> = self.history.iter().cloned().collect();
+        let reactions = messages
+            .iter()
+            .filter_map(|msg| {
+                let seq = msg.seq?;
+                let counts = self
+                    .reactions
+                    .get(&seq)?
+                    .iter()
+                    .map(|(emoji, users)| (emoji.clone(), users.len()))
+                    .collect();
+                Some((seq, counts))
+            })
+            .collect();
+
+        let snapshot = JoinSnapshot {
+            channel_name: name.to_owned(),
+            messages,
+            reactions,
+            pins: self.pins.values().cloned().collect(),
+        };
+        (self.channel.subscribe(), snapshot)
+    }
+
+    /// Toggle the reaction `emoji` of `id` on the message `seq`
+    ///
+    /// Returns the number of reactions per emoji of the message after the change.
+    pub fn toggle_reaction(
+        &mut self,
+        id: &str,
+        seq: u64,
+        emoji: &str,
+    ) -> Result<BTreeMap<String, usize>, String> {
+        if seq >= self.next_seq || seq + NUM_MAX_REACTION_MESSAGES < self.next_seq {
+            return Err(format!("message #{} not found", seq));
+        }
+        if emoji.is_empty() || emoji.len() > MAX_EMOJI_LEN || emoji.contains(char::is_whitespace) {
+            return Err(format!("invalid emoji: '{}'", emoji));
+        }
+
+        let message = self.reactions.entry(seq).or_default();
+        let users = message
+            .entry(expand_shortcode(emoji).to_owned())
+            .or_default();
+        if !users.remove(id) {
+            users.insert(id.to_owned());
+        }
+        message.retain(|_, users| !users.is_empty());
+        let counts = message
+            .iter()
+            .map(|(emoji, users)| (emoji.clone(), users.len()))
+            .collect();
+
+        // forget reactions of old messages
+        let oldest = self.next_seq.saturating_sub(NUM_MAX_REACTION_MESSAGES);
+        self.reactions = self.reactions.split_off(&oldest);
+        Ok(counts)
+    }
+
+    /// Pin or unpin the message `seq` on behalf of `id`, returns the message
+    ///
+    /// Only messages still in the history can be pinned, the pin keeps a copy of them.
+    pub fn set_pinned(&mut self, id: &str, seq: u64, pinned: bool) -> Result<Message, String> {
+        if !self.is_moderator(id) {
+            return Err("only moderators can pin messages".to_owned());
+        }
+        if !pinned {
+            return self
+                .pins
+                .remove(&seq)
+                .ok_or_else(|| format!("message #{} is not pinned", seq));
+        }
+
+        if self.pins.contains_key(&seq) {
+            return Err(format!("message #{} is already pinned", seq));
+        }
+        if self.pins.len() >= NUM_MAX_PINS {
+            return Err(format!(
+                "at most {} messages can be pinned, unpin one first",
+                NUM_MAX_PINS
+            ));
+        }
+        let msg = self
+            .history
+            .iter()
+            .find(|msg| msg.seq == Some(seq))
+            .ok_or_else(|| format!("message #{} not found in the recent history", seq))?;
+        if msg.is_system {
+            return Err("system messages can't be pinned".to_owned());
+        }
+        self.pins.insert(seq, msg.clone());
+        Ok(msg.clone())
+    }
+
+    /// Take back the message `seq` of `id` sent less than `window` ago
+    ///
+    /// The text is dropped from the history along with the reactions and the pin of the message,
+    /// the original is kept for the moderators.
+    pub fn retract(&mut self, id: &str, seq: u64, window: Duration) -> Result<(), String> {
+        let index = self
+            .history
+            .iter()
+            .position(|msg| msg.seq == Some(seq))
+            .ok_or_else(|| format!("message #{} not found in the recent history", seq))?;
+        let msg = &mut self.history[index];
+        if msg.is_system || msg.id != id {
+            return Err("only your own messages can be retracted".to_owned());
+        }
+        if msg.retracted {
+            return Err(format!("message #{} is already retracted", seq));
+        }
+        if self.recorded_at[index].elapsed() > window {
+            return Err(format!(
+                "messages can only be retracted within {}s of sending",
+                window.as_secs()
+            ));
+        }
+
+        let original = msg.clone();
+        msg.msg.clear();
+        msg.retracted = true;
+        if let Some(store) = &self.store {
+            store.update(msg);
+        }
+        self.retracted.insert(seq, original);
+        self.reactions.remove(&seq);
+        self.pins.remove(&seq);
+
+        // forget the originals of messages gone from the history
+        let oldest = self.history.front().and_then(|msg| msg.seq).unwrap_or(seq);
+        self.retracted = self.retracted.split_off(&oldest);
+        Ok(())
+    }
+
+    /// Turn the channel into an announcement channel on behalf of `id`
+    pub fn set_announce_only(&mut self, id: &str, enabled: bool) -> Result<String, String> {
+        if !self.is_owner(id) {
+            return Err("only the owner can change the announcement mode".to_owned());
+        }
+        self.announce_only = enabled;
+        Ok(if enabled {
+            "the channel is now read-only for everyone but moderators".to_owned()
+        } else {
+            "everyone can post in the channel now".to_owned()
+        })
+    }
+
+    /// Stored form of the channel `name`
+    pub fn to_record(&self, name: &str) -> ChannelRecord {
+        ChannelRecord {
+            name: name.to_owned(),
+            owner: self.owner.clone(),
+            archived: self.archived,
+            announce_only: self.announce_only,
+            slow_mode: self.slow_mode.map(|d| d.as_secs()),
+            moderators: self.moderators.iter().cloned().collect(),
+            system: self.is_system,
+            history_policy: self.history_policy,
+            gc_exempt: self.gc_exempt,
+            nicknames: self.nicknames.clone(),
+            topic: self.topic.clone(),
+            banned: self.banned.iter().cloned().collect(),
+            muted: self.muted.clone(),
+            password: self
+                .password
+                .as_ref()
+                .map(|verifier| serde_json::to_string(verifier).unwrap()),
+        }
+    }
+
+    /// Public settings of the channel `name`
+    pub fn info(&self, name: &str) -> ChannelInfo {
+        ChannelInfo {
+            channel_name: name.to_owned(),
+            announce_only: self.announce_only,
+            posters: self
+                .owner
+                .iter()
+                .chain(self.moderators.iter())
+                .cloned()
+                .collect(),
+            history: self.history_policy,
+            topic: self.topic.clone(),
+        }
+    }
+
+    /// Change the topic on behalf of `id`, `None` clears it, only moderators can change it
+    pub fn set_topic(&mut self, id: &str, topic: Option<String>) -> Result<String, String> {
+        if !self.is_moderator(id) {
+            return Err("only moderators can change the topic".to_owned());
+        }
+        let topic = topic
+            .map(|topic| topic.trim().to_owned())
+            .filter(|topic| !topic.is_empty());
+        if topic
+            .as_ref()
+            .is_some_and(|topic| topic.chars().count() > MAX_TOPIC_LEN)
+        {
+            return Err(format!(
+                "a topic can't be longer than {} characters",
+                MAX_TOPIC_LEN
+            ));
+        }
+        self.topic = topic;
+        Ok(match &self.topic {
+            Some(topic) => format!("the topic is now: {}", topic),
+            None => "the topic is cleared".to_owned(),
+        })
+    }
+
+    /// Keep the channel from being deleted when empty on behalf of `id`, only admins can
+    pub fn set_gc_exempt(&mut self, id: &str, exempt: bool) -> Result<String, String> {
+        if !self.roles.allows(id, Operation::ExemptChannel) {
+            return Err("only admins can keep channels from being deleted".to_owned());
+        }
+        if self.is_system {
+            return Err("system channels are never deleted for being empty".to_owned());
+        }
+        self.gc_exempt = exempt;
+        Ok(if exempt {
+            "the channel is kept even when empty".to_owned()
+        } else {
+            "the channel is deleted once it's been empty for a while".to_owned()
+        })
+    }
+
+    /// Ban `user` from the channel `name` on behalf of `id`, moving them out if they're in
+    ///
+    /// Nobody is banned from the default channel, it's where the banned users go.
+    pub fn ban(&mut self, name: &str, id: &str, user: &str) -> Result<String, String> {
+        if !self.is_moderator(id) {
+            return Err("only moderators can ban users".to_owned());
+        }
+        if name == DEFAULT_CHANNEL {
+            return Err(format!(
+                "nobody is banned from '{}', admins can ban from the server",
+                name
+            ));
+        }
+        if self.is_moderator(user) {
+            return Err(format!("'{}' moderates the channel", user));
+        }
+        if !self.banned.insert(user.to_owned()) {
+            return Err(format!("'{}' is banned already", user));
+        }
+        self.muted.remove(user);
+        _ = self.channel.send(PacketType::ChannelClosed(ChannelClosed {
+            channel_name: name.to_owned(),
+            reason: format!("banned from '{}' by '{}'", name, id),
+            moved_to: DEFAULT_CHANNEL.to_owned(),
+            user: Some(user.to_owned()),
+        }));
+        Ok(format!("'{}' is banned from the channel", user))
+    }
+
+    pub fn unban(&mut self, id: &str, user: &str) -> Result<String, String> {
+        if !self.is_moderator(id) {
+            return Err("only moderators can unban users".to_owned());
+        }
+        if !self.banned.remove(user) {
+            return Err(format!("'{}' is not banned", user));
+        }
+        Ok(format!("'{}' can join the channel again", user))
+    }
+
+    /// Keep `user` from posting on behalf of `id`, for `secs` or until unmuted if `None`
+    pub fn mute(&mut self, id: &str, user: &str, secs: Option<u64>) -> Result<String, String> {
+        if !self.is_moderator(id) {
+            return Err("only moderators can mute users".to_owned());
+        }
+        if self.is_moderator(user) {
+            return Err(format!("'{}' moderates the channel", user));
+        }
+        let secs = secs.filter(|secs| *secs > 0);
+        self.muted
+            .insert(user.to_owned(), secs.map(|secs| unix_now() + secs));
+        Ok(match secs {
+            Some(secs) => format!("'{}' is muted for {}s", user, secs),
+            None => format!("'{}' is muted until unmuted", user),
+        })
+    }
+
+    pub fn unmute(&mut self, id: &str, user: &str) -> Result<String, String> {
+        if !self.is_moderator(id) {
+            return Err("only moderators can unmute users".to_owned());
+        }
+        match self.muted.remove(user) {
+            Some(until) if until.is_none_or(|until| until > unix_now()) => {
+                Ok(format!("'{}' can post again", user))
+            }
+            _ => Err(format!("'{}' is not muted", user)),
+        }
+    }
+
+    /// Check `msg` against the rules of the channel: announcements, bans and mutes, the `filter`
+    /// of the server and slow mode, returns the notice for the sender if it can't be posted
+    ///
+    /// Every post to the channel goes through here, the ones of the sender as well as the
+    /// scheduled ones, once scheduled and again when they're due.
+    pub fn check_post(
+        &mut self,
+        msg: &Message,
+        filter: impl FnOnce(&Message) -> Result<(), String>,
+        posting: Posting,
+    ) -> Result<(), String> {
+        if self.announce_only && !self.is_moderator(&msg.id) {
+            return Err("this channel is read-only, only moderators can post".to_owned());
+        }
+        self.check_sanctions(&msg.id)?;
+        filter(msg)?;
+        if posting == Posting::Now {
+            self.check_slow_mode(&msg.id).map_err(|remaining| {
+                format!(
+                    "slow mode is on, you can send a message in {}s",
+                    remaining.as_millis().div_ceil(1000)
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Why `id` can't post in the channel, a mute that ran out is forgotten
+    pub fn check_sanctions(&mut self, id: &str) -> Result<(), String> {
+        if self.banned.contains(id) {
+            return Err("you are banned from this channel".to_owned());
+        }
+        match self.muted.get(id) {
+            None => Ok(()),
+            Some(None) => Err("you are muted in this channel".to_owned()),
+            Some(Some(until)) => {
+                let now = unix_now();
+                if *until <= now {
+                    self.muted.remove(id);
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "you are muted in this channel for {}s more",
+                        until - now
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Bans and mutes still in force, for the moderators
+    pub fn sanctions(&self) -> serde_json::Value {
+        let now = unix_now();
+        let muted: Vec<serde_json::Value> = self
+            .muted
+            .iter()
+            .filter(|(_, until)| until.is_none_or(|until| until > now))
+            .map(|(id, until)| serde_json::json!({ "id": id, "until": until }))
+            .collect();
+        serde_json::json!({ "banned": self.banned, "muted": muted })
+    }
+
+    /// Make `user` a moderator on behalf of `id`, only the owner can appoint moderators
+    pub fn add_moderator(&mut self, id: &str, user: &str) -> Result<String, String> {
+        if !self.is_owner(id) {
+            return Err("only the owner can appoint moderators".to_owned());
+        }
+        self.moderators.insert(user.to_owned());
+        Ok(format!("'{}' is now a moderator", user))
+    }
+
+    /// Let the member `id` go by `nickname` in this channel, `None` to go by the id again
+    ///
+    /// Nicknames can't be taken from someone else in the channel, as a nickname or as an id.
+    pub fn set_nickname(&mut self, id: &str, nickname: Option<String>) -> Result<String, String> {
+        if id.is_empty() || id.starts_with("guest_") {
+            return Err("only members can have a nickname".to_owned());
+        }
+        let Some(nickname) = nickname else {
+            return Ok(match self.nicknames.remove(id) {
+                Some(_) => "you go by your id again".to_owned(),
+                None => "you have no nickname here".to_owned(),
+            });
+        };
+
+        let nickname = nickname.trim();
+        if nickname.is_empty()
+            || nickname.chars().count() > MAX_NICKNAME_LEN
+            || nickname.chars().any(char::is_control)
+        {
+            return Err(format!(
+                "a nickname has 1 to {} characters, without control characters",
+                MAX_NICKNAME_LEN
+            ));
+        }
+        let taken = |other: &str| other != id && other.eq_ignore_ascii_case(nickname);
+        if nickname.eq_ignore_ascii_case("system")
+            || self
+                .state
+                .names
+                .iter()
+                .chain(self.remote_names.iter())
+                .any(|other| taken(other))
+            || self
+                .nicknames
+                .iter()
+                .any(|(other, nick)| other != id && nick.eq_ignore_ascii_case(nickname))
+        {
+            return Err(format!("'{}' is taken in this channel", nickname));
+        }
+        self.nicknames.insert(id.to_owned(), nickname.to_owned());
+        Ok(format!("you go by '{}' in this channel", nickname))
+    }
+
+    /// Record a message of `id`, returns the remaining cooldown if it's sent too early
+    fn check_slow_mode(&mut self, id: &str) -> Result<(), Duration> {
+        let Some(interval) = self.slow_mode else {
+            return Ok(());
+        };
+        if self.is_moderator(id) {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        if let Some(last) = self.last_message.get(id) {
+            let elapsed = now.duration_since(*last);
+            if elapsed < interval {
+                return Err(interval - elapsed);
+            }
+        }
+        self.last_message.insert(id.to_owned(), now);
+        Ok(())
+    }
+
     pub fn leave_user(&mut self, name: &str) {
+        if !self.state.names.remove(name) {
+            return;
+        }
         if name.starts_with("guest_") {
             self.state.num_guest -= 1;
         } else {
             self.state.num_user -= 1;
         }
-        self.state.names.remove(name);
+        self.last_message.remove(name);
+    }
+
+    /// True if there's no room for `user_name` in the channel
+    pub fn is_full_for(&self, user_name: &str) -> bool {
+        if user_name.starts_with("guest_") {
+            self.num_guest() >= self.max_guests
+        } else {
+            self.num_user() >= self.max_users
+        }
     }
 
     pub fn num_guest(&self) -> usize {
@@ -64,12 +991,29 @@ impl Channel {
         self.state.names.contains(user_name)
     }
 
-    pub fn user_list(&self) -> Vec<String> {
-        self.state
+    /// Sorted page of the users containing `filter` but not in `hidden`, along with the number
+    /// of matching users
+    pub fn user_page(
+        &self,
+        offset: usize,
+        limit: usize,
+        filter: Option<&str>,
+        hidden: &HashSet<String>,
+    ) -> (Vec<String>, usize) {
+        let mut users: Vec<String> = self
+            .state
             .names
             .iter()
-            .map(String::from)
-            .collect::<Vec<String>>()
+            .chain(self.remote_names.iter())
+            .filter(|name| filter.is_none_or(|f| name.contains(f)) && !hidden.contains(*name))
+            .cloned()
+            .collect();
+        users.sort();
+        users.dedup();
+
+        let total = users.len();
+        let page = users.into_iter().skip(offset).take(limit).collect();
+        (page, total)
     }
 
     pub fn add_connection(&mut self, user_name: &str) -> bool {
@@ -78,67 +1022,148 @@ impl Channel {
         } else {
             self.state.num_user += 1;
         }
-        self.state.names.insert(user_name.to_owned())
+        let added = self.state.names.insert(user_name.to_owned());
+        self.stats.peak_users = self.stats.peak_users.max(self.state.names.len());
+        added
     }
 
-    /// Add a new user connection to `self`
-    pub fn connect_user(
-        &mut self,
-        req: &LoginReq,
-        cur_id: &str,
-        pool: Pool,
-    ) -> Result<String, String> {
-        // Account Login
-        if self.num_user() >= NUM_MAX_USER {
-            return Err("too many users".to_owned());
-        }
+    /// Statistics of the channel `name` in the form of a fetch result
+    pub fn stats(&self, name: &str) -> serde_json::Value {
+        let top_speakers: Vec<serde_json::Value> = self
+            .stats
+            .top_speakers()
+            .into_iter()
+            .map(|(id, count)| serde_json::json!({ "id": id, "count": count }))
+            .collect();
+        serde_json::json!({
+            "channel": name,
+            "archived": self.archived,
+            "users": self.state.names.len(),
+            "peak_users": self.stats.peak_users,
+            "last_hour": self.stats.count_within(ChannelStats::HOUR),
+            "last_day": self.stats.count_within(ChannelStats::DAY),
+            "created_at": self.stats.created_at,
+            "top_speakers": top_speakers,
+        })
+    }
 
-        // validation of inputs was done before this packet reached here, but somehow it's broken
-        if req.login_info.id.is_none() || req.login_info.password.is_none() {
-            return Err("broken login packet".to_owned());
+    /// Move everything `old` has in `self` over to `new`
+    fn rename_user(&mut self, old: &str, new: &str) {
+        let last_message = self.last_message.remove(old);
+        self.leave_user(old);
+        self.add_connection(new);
+        if let Some(last) = last_message {
+            self.last_message.insert(new.to_owned(), last);
         }
 
-        let res = req.login_info.login(pool.clone());
-        if res.is_ok() {
-            self.leave_user(cur_id);
-            self.add_connection(req.login_info.id.as_ref().unwrap().as_str());
+        for users in self.reactions.values_mut().flat_map(|r| r.values_mut()) {
+            if users.remove(old) {
+                users.insert(new.to_owned());
+            }
         }
-        res
     }
+}
+
+/// Messages a session sent within the last minute, to limit their rate
+#[derive(Default)]
+pub struct MessageRate {
+    sent: VecDeque<Instant>,
+}
+
+impl MessageRate {
+    const WINDOW: Duration = Duration::from_secs(60);
 
-    /// Add a new guest connection to `self`
-    pub fn connect_guest(&mut self) -> Result<String, String> {
-        if self.num_guest() >= NUM_MAX_GUEST {
-            return Err("too many guests".to_owned());
+    /// Count a message if fewer than `per_minute` were sent in the last minute, 0 is no limit
+    pub fn try_send(&mut self, per_minute: usize) -> bool {
+        if per_minute == 0 {
+            return true;
         }
+        let now = Instant::now();
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= Self::WINDOW)
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= per_minute {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+}
 
-        // Generate a random guest name
-        let mut rng = rand::thread_rng();
-        let guest_id = loop {
-            let random_id = format!("guest_{}", rng.gen::<u16>());
+/// Subscription to the channel a session has switched to
+pub struct Switch {
+    pub sender: broadcast::Sender<PacketType>,
+    pub receiver: broadcast::Receiver<PacketType>,
+    pub snapshot: JoinSnapshot,
+    pub info: ChannelInfo,
+}
 
-            // duplicate check
-            if !self.has_user(&random_id) {
-                break random_id;
-            }
-        };
+/// Identifies a session among the ones logged in as the same id
+pub type SessionKey = u64;
 
-        self.add_connection(guest_id.clone().as_str());
-        Ok(guest_id)
-    }
+/// Key of a new session
+pub fn new_session_key() -> SessionKey {
+    static NEXT_KEY: AtomicU64 = AtomicU64::new(0);
+    NEXT_KEY.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Channel an identity is counted in and the session it's counted for
+#[derive(Debug)]
+struct Membership {
+    channel: String,
+    session: SessionKey,
 }
 
 /// Collection of channels
 #[derive(Debug)]
 pub struct Channels {
     pub channels: HashMap<String, Channel>,
+
+    /// Channel of every identity, changed only by `join` and `leave` so an identity is counted in
+    /// a single channel whatever the order of the logins and the switches
+    members: HashMap<String, Membership>,
+
+    /// Capacity of the broadcast queues of new channels
+    capacity: CapacityConfig,
+
+    /// Members and guests every channel takes at most
+    max_users: usize,
+    max_guests: usize,
+
+    /// Relay of the cluster new channels get, see `set_relay`
+    relay: Option<cluster::Relay>,
+
+    /// Transcript new channels append to, see `set_transcript`
+    transcript: Option<Arc<transcript::Transcript>>,
+
+    /// History store new channels keep their messages in, see `set_history_log`
+    history_log: Option<Arc<history::HistoryLog>>,
+
+    /// Server-wide roles shared with every channel
+    roles: Arc<Roles>,
 }
 
 impl Channels {
-    /// create a new `Channels` with default system channels
-    pub fn with_system_channels() -> Self {
+    /// create a new `Channels` with default system channels and the user channels stored in `db`
+    pub fn with_system_channels(
+        db: &Database,
+        capacity: &CapacityConfig,
+        roles: &Arc<Roles>,
+    ) -> Self {
         let mut channels = Self {
             channels: HashMap::new(),
+            members: HashMap::new(),
+            capacity: capacity.clone(),
+            max_users: NUM_MAX_USER,
+            max_guests: NUM_MAX_GUEST,
+            relay: None,
+            transcript: None,
+            history_log: None,
+            roles: Arc::clone(roles),
         };
 
         // create default system channels
@@ -147,9 +1172,43 @@ impl Channels {
                 .create_channel(sys_ch, true)
                 .expect("failed to create a system channel");
         }
+
+        match ChannelRecord::load_all(db) {
+            Ok(records) => {
+                for record in records {
+                    if !channels.restore_channel(&record) {
+                        println!("[!] Skipped the stored channel '{}'", record.name);
+                    }
+                }
+            }
+            Err(e) => println!("[!] User channels are not restored: {}", e),
+        }
         channels
     }
 
+    /// Recreate a channel from its stored record, false if the name is taken or invalid
+    pub fn restore_channel(&mut self, record: &ChannelRecord) -> bool {
+        let Some(channel) = self.create_channel(&record.name, record.system) else {
+            return false;
+        };
+        channel.owner = record.owner.clone();
+        channel.archived = record.archived;
+        channel.announce_only = record.announce_only;
+        channel.slow_mode = record.slow_mode.map(Duration::from_secs);
+        channel.moderators = record.moderators.iter().cloned().collect();
+        channel.history_policy = record.history_policy;
+        channel.gc_exempt = record.gc_exempt;
+        channel.nicknames = record.nicknames.clone();
+        channel.topic = record.topic.clone();
+        channel.banned = record.banned.iter().cloned().collect();
+        channel.muted = record.muted.clone();
+        channel.password = record
+            .password
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok());
+        true
+    }
+
     /// true if `name` is valid as a channel name
     fn is_valid(name: &str) -> bool {
         const MINIMUM_LEN: usize = 3;
@@ -161,29 +1220,944 @@ impl Channels {
     }
 
     /// create a channel and add it to the list
-    pub fn create_channel(&mut self, name: &str, is_system: bool) -> Option<&Channel> {
+    pub fn create_channel(&mut self, name: &str, is_system: bool) -> Option<&mut Channel> {
         if !Self::is_valid(name) || self.channels.contains_key(name) {
             // The name is either invalid or duplicate
             None
         } else {
-            let (sender, _) = broadcast::channel::<PacketType>(32);
+            let (sender, _) = broadcast::channel::<PacketType>(self.capacity.of(name));
             self.channels.insert(
                 name.to_owned(),
                 Channel {
                     channel: sender,
                     state: State::new(),
                     is_system,
+                    owner: None,
+                    archived: false,
+                    moderators: HashSet::new(),
+                    slow_mode: None,
+                    last_message: HashMap::new(),
+                    announce_only: false,
+                    next_seq: 0,
+                    history: VecDeque::new(),
+                    recorded_at: VecDeque::new(),
+                    history_policy: HistoryPolicy::default(),
+                    reactions: BTreeMap::new(),
+                    pins: BTreeMap::new(),
+                    retracted: BTreeMap::new(),
+                    nicknames: BTreeMap::new(),
+                    topic: None,
+                    banned: BTreeSet::new(),
+                    muted: BTreeMap::new(),
+                    password: None,
+                    roles: Arc::clone(&self.roles),
+                    stats: ChannelStats::new(),
+                    max_users: self.max_users,
+                    max_guests: self.max_guests,
+                    gc_exempt: false,
+                    empty_since: Some(Instant::now()),
+                    relay: self.relay.as_ref().map(|relay| relay.of(name)),
+                    remote_names: BTreeSet::new(),
+                    transcript: self
+                        .transcript
+                        .as_ref()
+                        .map(|transcript| transcript::Tap::new(name, transcript)),
+                    store: self
+                        .history_log
+                        .as_ref()
+                        .map(|log| history::Tap::new(name, log)),
                 },
             );
-            self.channels.get(name)
+            self.channels.get_mut(name)
+        }
+    }
+
+    /// Relay the messages of every channel, the current and the future ones, with `relay`
+    pub fn set_relay(&mut self, relay: cluster::Relay) {
+        for (name, channel) in self.channels.iter_mut() {
+            channel.relay = Some(relay.of(name));
+        }
+        self.relay = Some(relay);
+    }
+
+    /// Append the messages of every channel, the current and the future ones, to `transcript`
+    pub fn set_transcript(&mut self, transcript: &Arc<transcript::Transcript>) {
+        for (name, channel) in self.channels.iter_mut() {
+            channel.transcript = Some(transcript::Tap::new(name, transcript));
+        }
+        self.transcript = Some(Arc::clone(transcript));
+    }
+
+    /// Keep the messages of every channel, the current and the future ones, in `log`
+    ///
+    /// The current channels take their latest stored messages back.
+    pub fn set_history_log(&mut self, log: &Arc<history::HistoryLog>) {
+        for (name, channel) in self.channels.iter_mut() {
+            if channel.history_policy != HistoryPolicy::Ephemeral {
+                channel.restore_history(log.recent(name, NUM_HISTORY_MESSAGES));
+            }
+            channel.store = Some(history::Tap::new(name, log));
+        }
+        self.history_log = Some(Arc::clone(log));
+    }
+
+    pub fn history_log(&self) -> Option<&Arc<history::HistoryLog>> {
+        self.history_log.as_ref()
+    }
+
+    /// Prune the history of every channel, see `Channel::prune_history`
+    ///
+    /// The history store drops what the channels and `history.retention_days` don't keep.
+    pub fn prune_histories(&mut self) -> usize {
+        if let Some(log) = &self.history_log {
+            let now = unix_now();
+            for (name, channel) in &self.channels {
+                if let HistoryPolicy::Retain(secs) = channel.history_policy {
+                    log.prune(Some(name), now.saturating_sub(secs));
+                }
+            }
+            if let Some(secs) = log.retention_secs() {
+                log.prune(None, now.saturating_sub(secs));
+            }
+        }
+        self.channels
+            .values_mut()
+            .map(|channel| channel.prune_history())
+            .sum()
+    }
+
+    /// Delete the user channels nobody has been in for `grace`, returns their names and owners
+    ///
+    /// A channel left empty counts from the first call seeing it empty, a new one from its creation.
+    /// Archived channels and the exempt ones are kept, so are the ones with members on other nodes
+    /// of the cluster.
+    pub fn collect_empty(&mut self, grace: Duration) -> Vec<(String, Option<String>)> {
+        let now = Instant::now();
+        let mut collected = Vec::new();
+        self.channels.retain(|name, channel| {
+            if channel.is_system || channel.archived || channel.gc_exempt {
+                return true;
+            }
+            if !channel.state.names.is_empty() || !channel.remote_names.is_empty() {
+                channel.empty_since = None;
+                return true;
+            }
+            let empty_since = *channel.empty_since.get_or_insert(now);
+            if now.duration_since(empty_since) < grace {
+                return true;
+            }
+            collected.push((name.clone(), channel.owner.clone()));
+            false
+        });
+        if let Some(log) = &self.history_log {
+            for (name, _) in &collected {
+                log.delete_channel(name);
+            }
+        }
+        collected
+    }
+
+    /// Change how many members and guests every channel takes, users already in stay
+    pub fn set_user_limits(&mut self, max_users: usize, max_guests: usize) {
+        self.max_users = max_users;
+        self.max_guests = max_guests;
+        for channel in self.channels.values_mut() {
+            channel.max_users = max_users;
+            channel.max_guests = max_guests;
+        }
+    }
+
+    /// Create a channel owned by `owner`
+    pub fn create_user_channel(
+        &mut self,
+        name: &str,
+        owner: &str,
+        password: Option<&str>,
+    ) -> Result<String, String> {
+        match self.create_channel(name, false) {
+            Some(channel) => {
+                channel.owner = Some(owner.to_owned());
+                channel.password = password.map(Verifier::new);
+                Ok(format!(
+                    "channel '{}' has been created{}",
+                    name,
+                    if password.is_some() {
+                        ", joining it asks for the password"
+                    } else {
+                        ""
+                    }
+                ))
+            }
+            None => Err(format!("invalid or duplicate channel name: '{}'", name)),
+        }
+    }
+
+    /// Create a system channel on behalf of `id`
+    pub fn create_system_channel(&mut self, name: &str, id: &str) -> Result<String, String> {
+        if !self.roles.allows(id, Operation::CreateSystemChannel) {
+            return Err("only admins can create system channels".to_owned());
+        }
+        match self.create_channel(name, true) {
+            Some(_) => Ok(format!("system channel '{}' has been created", name)),
+            None => Err(format!("invalid or duplicate channel name: '{}'", name)),
+        }
+    }
+
+    /// Delete or archive the channel `name` on behalf of `id`
+    ///
+    /// Every subscriber is notified with `ChannelClosed` so their sessions can move back to the
+    /// default channel. The broadcast sender is freed on deletion.
+    pub fn close_channel(&mut self, name: &str, id: &str, archive: bool) -> Result<String, String> {
+        let channel = match self.channels.get_mut(name) {
+            Some(_) if SYSTEM_CHANNELS.contains(&name) => {
+                return Err("built-in channels can't be closed".to_owned())
+            }
+            Some(c) if !c.is_owner(id) => {
+                return Err("only the owner of the channel can close it".to_owned())
+            }
+            Some(c) if c.archived && archive => {
+                return Err(format!("channel '{}' is already archived", name))
+            }
+            Some(c) => c,
+            None => return Err(format!("channel '{}' not found", name)),
+        };
+
+        let reason = if archive {
+            format!("channel '{}' has been archived by '{}'", name, id)
+        } else {
+            format!("channel '{}' has been deleted by '{}'", name, id)
+        };
+        _ = channel
+            .channel
+            .send(PacketType::ChannelClosed(ChannelClosed {
+                channel_name: name.to_owned(),
+                reason: reason.clone(),
+                moved_to: DEFAULT_CHANNEL.to_owned(),
+                user: None,
+            }));
+
+        if archive {
+            channel.archived = true;
+            channel.state = State::new();
+        } else {
+            self.channels.remove(name);
+            if let Some(log) = &self.history_log {
+                log.delete_channel(name);
+            }
+        }
+        Ok(reason)
+    }
+
+    /// Count `id` of `session` in the channel `name`, out of the channel it was counted in
+    ///
+    /// The latest session of an id takes the membership over from an older one.
+    fn join(&mut self, name: &str, id: &str, session: SessionKey) {
+        // sessions that haven't logged in aren't counted anywhere
+        if id.is_empty() {
+            return;
+        }
+        if let Some(previous) = self.members.remove(id) {
+            if let Some(channel) = self.channels.get_mut(&previous.channel) {
+                channel.leave_user(id);
+            }
+        }
+        if let Some(channel) = self.channels.get_mut(name) {
+            channel.add_connection(id);
+        }
+        self.members.insert(
+            id.to_owned(),
+            Membership {
+                channel: name.to_owned(),
+                session,
+            },
+        );
+    }
+
+    /// Stop counting `id` unless another session took it over, returns the channel it left
+    pub fn leave(&mut self, id: &str, session: SessionKey) -> Option<String> {
+        if self.members.get(id)?.session != session {
+            return None;
+        }
+        let membership = self.members.remove(id)?;
+        if let Some(channel) = self.channels.get_mut(&membership.channel) {
+            channel.leave_user(id);
+        }
+        Some(membership.channel)
+    }
+
+    /// Let `new_id` take the place of `cur_id` of `session` once it has logged in, in the channel
+    /// `name` the session is in
+    fn transfer(&mut self, name: &str, cur_id: &str, new_id: &str, session: SessionKey) {
+        self.leave(cur_id, session);
+        self.join(name, new_id, session);
+    }
+
+    /// Let `cur_id` of `session` in the channel `name` in as the guest `guest_id`, reserved
+    /// beforehand with `Registry::reserve_guest`
+    pub fn connect_guest(
+        &mut self,
+        name: &str,
+        cur_id: &str,
+        guest_id: &str,
+        session: SessionKey,
+    ) -> Result<String, PacketError> {
+        let channel = self.existing(name)?;
+        if channel.num_guest() >= channel.max_guests {
+            return Err(PacketError::new(ErrorCode::Full, "too many guests"));
+        }
+        self.transfer(name, cur_id, guest_id, session);
+        Ok(guest_id.to_owned())
+    }
+
+    /// Let `cur_id` of `session` in the channel `name` in as the member `id`, whose credentials
+    /// or session token were checked already
+    pub fn connect_user(
+        &mut self,
+        name: &str,
+        id: &str,
+        cur_id: &str,
+        session: SessionKey,
+    ) -> Result<(), PacketError> {
+        let channel = self.existing(name)?;
+        if channel.num_user() >= channel.max_users {
+            return Err(PacketError::new(ErrorCode::Full, "too many users"));
+        }
+        self.transfer(name, cur_id, id, session);
+        Ok(())
+    }
+
+    /// Check that the guest `guest_id` of `session` may become an account, before registering it
+    pub fn check_upgrade(&self, guest_id: &str, session: SessionKey) -> Result<(), PacketError> {
+        let membership = self
+            .members
+            .get(guest_id)
+            .filter(|m| guest_id.starts_with("guest_") && m.session == session);
+        let Some(channel) = membership.and_then(|m| self.channels.get(&m.channel)) else {
+            return Err(PacketError::new(
+                ErrorCode::PermissionDenied,
+                "only guests can upgrade to an account",
+            ));
+        };
+        if channel.num_user() >= channel.max_users {
+            return Err(PacketError::new(ErrorCode::Full, "too many users"));
+        }
+        Ok(())
+    }
+
+    /// Let the guest `guest_id` of `session` become the account `id` registered for it without
+    /// leaving the channel it's in
+    pub fn upgrade_guest(
+        &mut self,
+        guest_id: &str,
+        id: &str,
+        session: SessionKey,
+    ) -> Result<String, PacketError> {
+        self.check_upgrade(guest_id, session)?;
+        let mut membership = self.members.remove(guest_id).expect("checked above");
+        if let Some(channel) = self.channels.get_mut(&membership.channel) {
+            channel.rename_user(guest_id, id);
+        }
+        membership.session = session;
+        self.members.insert(id.to_owned(), membership);
+        Ok(id.to_owned())
+    }
+
+    fn existing(&self, name: &str) -> Result<&Channel, PacketError> {
+        self.channels.get(name).ok_or_else(|| {
+            PacketError::new(ErrorCode::NotFound, format!("channel '{}' not found", name))
+        })
+    }
+
+    /// Move `id` of `session` from the channel `old` to `new` and subscribe to `new`
+    ///
+    /// Every check is done before anything changes, so on failure `id` stays in `old` as it was.
+    /// `old` doesn't have to exist anymore, e.g. once it has been deleted.
+    pub fn switch_user(
+        &mut self,
+        old: &str,
+        new: &str,
+        id: &str,
+        session: SessionKey,
+        password: Option<&str>,
+    ) -> Result<Switch, PacketError> {
+        let target = match self.channels.get(new) {
+            None => {
+                return Err(PacketError::new(
+                    ErrorCode::NotFound,
+                    "Invalid or not permitted to join the channel",
+                ))
+            }
+            Some(target) => target,
+        };
+        if target.archived {
+            return Err(PacketError::new(
+                ErrorCode::PermissionDenied,
+                format!("channel '{}' is archived", new),
+            ));
+        }
+        if target.banned.contains(id) {
+            return Err(PacketError::new(
+                ErrorCode::PermissionDenied,
+                format!("you are banned from the channel '{}'", new),
+            ));
         }
+        if old == new {
+            return Err(PacketError::new(
+                ErrorCode::InvalidArgument,
+                format!("you are already in the channel '{}'", new),
+            ));
+        }
+        if let Some(verifier) = target
+            .password
+            .as_ref()
+            .filter(|_| !target.is_moderator(id))
+        {
+            match password {
+                Some(password) if verifier.matches(password) => {}
+                Some(_) => {
+                    return Err(PacketError::new(
+                        ErrorCode::WrongCredentials,
+                        format!("wrong password for the channel '{}'", new),
+                    ))
+                }
+                None => {
+                    return Err(PacketError::new(
+                        ErrorCode::WrongCredentials,
+                        format!("the channel '{}' asks for a password", new),
+                    ))
+                }
+            }
+        }
+        if target.is_full_for(id) {
+            return Err(PacketError::new(
+                ErrorCode::Full,
+                format!("channel '{}' is full", new),
+            ));
+        }
+
+        // nothing can fail from here on
+        self.join(new, id, session);
+        let target = self.channels.get_mut(new).expect("checked above");
+        let (receiver, snapshot) = target.subscribe(new);
+        Ok(Switch {
+            sender: target.channel.clone(),
+            receiver,
+            snapshot,
+            info: target.info(new),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Channel> {
+        self.channels.get(name)
     }
 
     pub fn get_mut(&mut self, name: &str) -> Option<&mut Channel> {
         self.channels.get_mut(name)
     }
 
-    pub fn get_channel(&self, name: &str) -> Option<broadcast::Sender<PacketType>> {
-        self.channels.get(name).map(|c| c.channel.clone())
+    /// Post the scheduled message whose time has come, attributed to its owner
+    ///
+    /// The message goes through `Channel::check_post` and the `plugins` as if the owner posted it
+    /// now, the channel may have changed since it was scheduled. The replies of the plugins
+    /// follow the message. Returns why it's dropped otherwise.
+    pub fn post_scheduled(
+        &mut self,
+        scheduled: &ScheduledMessage,
+        filter: impl FnOnce(&Message) -> Result<(), String>,
+        plugins: impl FnOnce(&Message) -> Result<Vec<String>, String>,
+    ) -> Result<Message, String> {
+        let Some(channel) = self
+            .channels
+            .get_mut(&scheduled.channel)
+            .filter(|c| !c.archived)
+        else {
+            return Err(format!("'{}' is gone", scheduled.channel));
+        };
+        let msg = Message {
+            id: scheduled.owner.clone(),
+            msg: format!("{} (scheduled)", scheduled.msg),
+            is_system: false,
+            to: None,
+            seq: None,
+            retracted: false,
+            display_name: None,
+            reply_to: None,
+            thread: None,
+        };
+        channel.check_post(&msg, filter, Posting::Now)?;
+        let replies = plugins(&msg)?;
+        let msg = channel.broadcast(msg);
+        for reply in replies {
+            channel.broadcast(Message::system_notice(&reply));
+        }
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::user::Role;
+
+    fn no_filter(_: &Message) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn no_plugins(_: &Message) -> Result<Vec<String>, String> {
+        Ok(Vec::new())
+    }
+
+    fn channels(names: &[&str]) -> Channels {
+        let mut channels = Channels {
+            channels: HashMap::new(),
+            members: HashMap::new(),
+            capacity: CapacityConfig::default(),
+            max_users: NUM_MAX_USER,
+            max_guests: NUM_MAX_GUEST,
+            relay: None,
+            transcript: None,
+            history_log: None,
+            roles: Arc::default(),
+        };
+        for name in names {
+            channels.create_channel(name, false).unwrap();
+        }
+        channels
+    }
+
+    fn members(channels: &Channels, name: &str) -> (usize, usize, bool) {
+        let channel = channels.get(name).unwrap();
+        (
+            channel.num_user(),
+            channel.num_guest(),
+            channel.has_user("alice"),
+        )
+    }
+
+    #[test]
+    fn empty_channels_are_collected_after_the_grace_period() {
+        let mut channels = channels(&["empty", "busy", "kept", "old"]);
+        channels.get_mut("empty").unwrap().owner = Some("alice".to_owned());
+        channels.get_mut("busy").unwrap().add_connection("bob");
+        channels.get_mut("kept").unwrap().gc_exempt = true;
+        channels.get_mut("old").unwrap().archived = true;
+
+        assert!(channels.collect_empty(Duration::from_secs(60)).is_empty());
+        assert_eq!(
+            channels.collect_empty(Duration::ZERO),
+            vec![("empty".to_owned(), Some("alice".to_owned()))]
+        );
+        assert!(channels.get("empty").is_none());
+        assert!(channels.get("busy").is_some());
+        assert!(channels.get("kept").is_some());
+        assert!(channels.get("old").is_some());
+    }
+
+    #[test]
+    fn joining_restarts_the_grace_period() {
+        let mut channels = channels(&["room"]);
+        assert!(channels.collect_empty(Duration::from_secs(60)).is_empty());
+        assert!(channels.get("room").unwrap().empty_since.is_some());
+
+        channels.get_mut("room").unwrap().add_connection("bob");
+        assert!(channels.collect_empty(Duration::ZERO).is_empty());
+        assert!(channels.get("room").unwrap().empty_since.is_none());
+    }
+
+    #[test]
+    fn members_on_other_nodes_keep_the_channel() {
+        let mut channels = channels(&["room"]);
+        let room = channels.get_mut("room").unwrap();
+        room.remote_names.insert("carol".to_owned());
+
+        assert!(channels.collect_empty(Duration::ZERO).is_empty());
+        assert!(channels.get("room").unwrap().empty_since.is_none());
+
+        channels.get_mut("room").unwrap().remote_names.clear();
+        assert_eq!(
+            channels.collect_empty(Duration::ZERO),
+            vec![("room".to_owned(), None)]
+        );
+    }
+
+    #[test]
+    fn switch_moves_the_user() {
+        let mut channels = channels(&["lobby", "rust"]);
+        channels.join("lobby", "alice", 0);
+
+        let switch = channels
+            .switch_user("lobby", "rust", "alice", 0, None)
+            .unwrap();
+        assert_eq!(switch.info.channel_name, "rust");
+        assert_eq!(switch.snapshot.channel_name, "rust");
+        assert_eq!(members(&channels, "lobby"), (0, 0, false));
+        assert_eq!(members(&channels, "rust"), (1, 0, true));
+    }
+
+    #[test]
+    fn switch_to_unknown_channel_changes_nothing() {
+        let mut channels = channels(&["lobby"]);
+        channels.join("lobby", "alice", 0);
+
+        let err = channels
+            .switch_user("lobby", "nowhere", "alice", 0, None)
+            .err()
+            .unwrap();
+        assert_eq!(err.code, ErrorCode::NotFound);
+        assert_eq!(members(&channels, "lobby"), (1, 0, true));
+    }
+
+    #[test]
+    fn switch_to_archived_channel_changes_nothing() {
+        let mut channels = channels(&["lobby", "old"]);
+        channels.join("lobby", "alice", 0);
+        channels.get_mut("old").unwrap().archived = true;
+
+        let err = channels
+            .switch_user("lobby", "old", "alice", 0, None)
+            .err()
+            .unwrap();
+        assert_eq!(err.code, ErrorCode::PermissionDenied);
+        assert_eq!(members(&channels, "lobby"), (1, 0, true));
+        assert_eq!(members(&channels, "old"), (0, 0, false));
+    }
+
+    #[test]
+    fn switch_to_the_same_channel_keeps_the_user() {
+        let mut channels = channels(&["lobby"]);
+        channels.join("lobby", "alice", 0);
+
+        let err = channels
+            .switch_user("lobby", "lobby", "alice", 0, None)
+            .err()
+            .unwrap();
+        assert_eq!(err.code, ErrorCode::InvalidArgument);
+        assert_eq!(members(&channels, "lobby"), (1, 0, true));
+    }
+
+    #[test]
+    fn switch_to_full_channel_changes_nothing() {
+        let mut channels = channels(&["lobby", "busy"]);
+        channels.join("lobby", "alice", 0);
+        let busy = channels.get_mut("busy").unwrap();
+        for i in 0..NUM_MAX_GUEST {
+            busy.add_connection(&format!("guest_{}", i));
+        }
+
+        let err = channels
+            .switch_user("lobby", "busy", "guest_new", 0, None)
+            .err()
+            .unwrap();
+        assert_eq!(err.code, ErrorCode::Full);
+        assert_eq!(channels.get("busy").unwrap().num_guest(), NUM_MAX_GUEST);
+
+        // members have a limit of their own
+        channels
+            .switch_user("lobby", "busy", "alice", 0, None)
+            .unwrap();
+        assert_eq!(members(&channels, "busy"), (1, NUM_MAX_GUEST, true));
+    }
+
+    #[test]
+    fn switch_from_deleted_channel_joins_the_new_one() {
+        let mut channels = channels(&["lobby", "gone"]);
+        channels.join("gone", "alice", 0);
+        channels.channels.remove("gone");
+
+        channels
+            .switch_user("gone", "lobby", "alice", 0, None)
+            .unwrap();
+        assert_eq!(members(&channels, "lobby"), (1, 0, true));
+    }
+
+    #[test]
+    fn banned_and_muted_users_are_kept_out() {
+        let mut channels = channels(&["lobby", "dev"]);
+        channels.join("dev", "alice", 0);
+        let dev = channels.get_mut("dev").unwrap();
+        dev.owner = Some("mod".to_owned());
+        assert!(dev.ban("dev", "alice", "bob").is_err());
+        assert!(dev.ban("dev", "mod", "mod").is_err());
+        dev.ban("dev", "mod", "alice").unwrap();
+        assert!(dev.ban("dev", "mod", "alice").is_err());
+        assert!(matches!(
+            channels.switch_user("lobby", "dev", "alice", 0, None),
+            Err(PacketError {
+                code: ErrorCode::PermissionDenied,
+                ..
+            })
+        ));
+
+        let dev = channels.get_mut("dev").unwrap();
+        dev.unban("mod", "alice").unwrap();
+        dev.mute("mod", "alice", Some(60)).unwrap();
+        assert!(dev.check_sanctions("alice").unwrap_err().contains("more"));
+        // a mute that ran out is forgotten
+        dev.muted.insert("alice".to_owned(), Some(1));
+        assert!(dev.check_sanctions("alice").is_ok());
+        assert!(dev.unmute("mod", "alice").is_err());
+        assert!(channels
+            .switch_user("lobby", "dev", "alice", 0, None)
+            .is_ok());
+    }
+
+    #[test]
+    fn roles_are_kept_per_server() {
+        let (mut ours, theirs) = (channels(&["lobby"]), channels(&["lobby"]));
+        ours.roles.set("root", Role::Admin);
+        assert!(ours.get("lobby").unwrap().is_owner("root"));
+        assert!(!theirs.get("lobby").unwrap().is_moderator("root"));
+        assert!(ours.create_system_channel("ops", "root").is_ok());
+        ours.roles.set("root", Role::User);
+        assert!(!ours.get("ops").unwrap().is_moderator("root"));
+    }
+
+    #[test]
+    fn protected_channels_ask_for_the_password() {
+        let mut channels = channels(&["lobby"]);
+        channels
+            .create_user_channel("vault", "owner", Some("hashed"))
+            .unwrap();
+        for password in [None, Some("guessed")] {
+            assert!(matches!(
+                channels.switch_user("lobby", "vault", "alice", 0, password),
+                Err(PacketError {
+                    code: ErrorCode::WrongCredentials,
+                    ..
+                })
+            ));
+        }
+        channels
+            .switch_user("lobby", "vault", "alice", 0, Some("hashed"))
+            .unwrap();
+        // the owner doesn't need it, and the password survives a restart
+        channels
+            .switch_user("lobby", "vault", "owner", 1, None)
+            .unwrap();
+        let record = channels.get("vault").unwrap().to_record("vault");
+        let mut restored = self::channels(&["lobby"]);
+        assert!(restored.restore_channel(&record));
+        assert!(restored
+            .switch_user("lobby", "vault", "bob", 2, Some("guessed"))
+            .is_err());
+        assert!(restored
+            .switch_user("lobby", "vault", "bob", 2, Some("hashed"))
+            .is_ok());
+    }
+
+    #[test]
+    fn scheduled_messages_of_muted_owners_are_dropped() {
+        let mut channels = channels(&["dev"]);
+        let scheduled = ScheduledMessage {
+            id: 1,
+            owner: "alice".to_owned(),
+            channel: "dev".to_owned(),
+            at: 0,
+            msg: "queued before the mute".to_owned(),
+        };
+        let dev = channels.get_mut("dev").unwrap();
+        dev.owner = Some("mod".to_owned());
+        dev.mute("mod", "alice", None).unwrap();
+        assert!(channels
+            .post_scheduled(&scheduled, no_filter, no_plugins)
+            .is_err());
+        assert_eq!(channels.get("dev").unwrap().next_seq, 0);
+
+        channels
+            .get_mut("dev")
+            .unwrap()
+            .unmute("mod", "alice")
+            .unwrap();
+        let posted = channels
+            .post_scheduled(&scheduled, no_filter, no_plugins)
+            .unwrap();
+        assert_eq!(posted.msg, "queued before the mute (scheduled)");
+    }
+
+    #[test]
+    fn scheduled_posts_keep_to_announcement_channels() {
+        let mut channels = channels(&["news"]);
+        let news = channels.get_mut("news").unwrap();
+        news.owner = Some("editor".to_owned());
+        news.set_announce_only("editor", true).unwrap();
+        let mut scheduled = ScheduledMessage {
+            id: 1,
+            owner: "alice".to_owned(),
+            channel: "news".to_owned(),
+            at: 0,
+            msg: "breaking".to_owned(),
+        };
+        let err = channels
+            .post_scheduled(&scheduled, no_filter, no_plugins)
+            .unwrap_err();
+        assert!(err.contains("read-only"), "{}", err);
+        assert_eq!(channels.get("news").unwrap().next_seq, 0);
+
+        scheduled.owner = "editor".to_owned();
+        assert!(channels
+            .post_scheduled(&scheduled, no_filter, no_plugins)
+            .is_ok());
+    }
+
+    #[test]
+    fn login_after_goto_leaves_the_channel_the_guest_is_in() {
+        let mut channels = channels(&["lobby", "dev"]);
+        channels.connect_guest("lobby", "", "guest_1", 1).unwrap();
+        channels
+            .switch_user("lobby", "dev", "guest_1", 1, None)
+            .unwrap();
+        assert_eq!(channels.get("lobby").unwrap().num_guest(), 0);
+        assert_eq!(channels.get("dev").unwrap().num_guest(), 1);
+
+        channels.transfer("dev", "guest_1", "alice", 1);
+        assert_eq!(members(&channels, "lobby"), (0, 0, false));
+        assert_eq!(members(&channels, "dev"), (1, 0, true));
+        assert_eq!(channels.members["alice"].channel, "dev");
+        assert!(!channels.members.contains_key("guest_1"));
+    }
+
+    #[test]
+    fn login_again_replaces_the_previous_id() {
+        let mut channels = channels(&["lobby", "dev"]);
+        channels.connect_guest("lobby", "", "guest_1", 1).unwrap();
+        channels
+            .connect_guest("lobby", "guest_1", "guest_2", 1)
+            .unwrap();
+        assert_eq!(channels.get("lobby").unwrap().num_guest(), 1);
+
+        // logged in as a member in another channel after a goto
+        channels
+            .switch_user("lobby", "dev", "guest_2", 1, None)
+            .unwrap();
+        channels.transfer("dev", "guest_2", "alice", 1);
+        channels
+            .switch_user("dev", "lobby", "alice", 1, None)
+            .unwrap();
+        channels.transfer("lobby", "alice", "bob", 1);
+        assert_eq!(members(&channels, "dev"), (0, 0, false));
+        assert_eq!(members(&channels, "lobby"), (1, 0, false));
+        assert_eq!(channels.leave("bob", 1).as_deref(), Some("lobby"));
+        assert_eq!(channels.get("lobby").unwrap().num_user(), 0);
+    }
+
+    #[test]
+    fn newer_session_takes_the_membership_over() {
+        let mut channels = channels(&["lobby", "dev"]);
+        channels.join("lobby", "alice", 1);
+
+        // the same account logs in from another client in another channel
+        channels.transfer("dev", "", "alice", 2);
+        assert_eq!(members(&channels, "lobby"), (0, 0, false));
+        assert_eq!(members(&channels, "dev"), (1, 0, true));
+
+        // the older session closing doesn't take the newer one out
+        assert_eq!(channels.leave("alice", 1), None);
+        channels.transfer("dev", "alice", "guest_1", 1);
+        assert_eq!(members(&channels, "dev"), (1, 1, true));
+        assert_eq!(channels.leave("alice", 2).as_deref(), Some("dev"));
+        assert_eq!(members(&channels, "dev"), (0, 1, false));
+    }
+
+    #[test]
+    fn leaving_twice_keeps_the_counts() {
+        let mut channels = channels(&["lobby"]);
+        let lobby = channels.get_mut("lobby").unwrap();
+        lobby.add_connection("alice");
+        lobby.leave_user("alice");
+        lobby.leave_user("alice");
+        assert_eq!(members(&channels, "lobby"), (0, 0, false));
+    }
+
+    #[test]
+    fn only_recent_own_messages_are_retracted() {
+        let mut channels = channels(&["lobby"]);
+        let lobby = channels.get_mut("lobby").unwrap();
+        for text in ["mine", "theirs"] {
+            let id = if text == "mine" { "alice" } else { "bob" };
+            lobby.record(&mut Message {
+                id: id.to_owned(),
+                msg: text.to_owned(),
+                is_system: false,
+                to: None,
+                seq: None,
+                retracted: false,
+                display_name: None,
+                reply_to: None,
+                thread: None,
+            });
+        }
+        lobby.pins.insert(0, lobby.history[0].clone());
+        let window = Duration::from_secs(60);
+
+        assert!(lobby.retract("alice", 1, window).is_err());
+        assert!(lobby.retract("alice", 7, window).is_err());
+        assert!(lobby.retract("bob", 1, Duration::ZERO).is_err());
+
+        lobby.retract("alice", 0, window).unwrap();
+        assert!(lobby.history[0].retracted && lobby.history[0].msg.is_empty());
+        assert_eq!(lobby.retracted[&0].msg, "mine");
+        assert!(lobby.pins.is_empty());
+        assert!(lobby.retract("alice", 0, window).is_err());
+    }
+
+    #[test]
+    fn replies_of_replies_stay_in_the_thread() {
+        let mut channels = channels(&["lobby"]);
+        let lobby = channels.get_mut("lobby").unwrap();
+        // 0 starts the thread, 2 replies to the reply 1
+        for reply_to in [None, Some(0), Some(1), None] {
+            let mut msg = Message::system_notice("hi");
+            msg.reply_to = reply_to;
+            msg.thread = reply_to.map(|seq| lobby.thread_root(seq));
+            lobby.record(&mut msg);
+        }
+        assert_eq!(lobby.thread_root(2), 0);
+        assert_eq!(lobby.thread_root(3), 3);
+        let thread: Vec<Option<u64>> = lobby.thread(0).iter().map(|msg| msg.seq).collect();
+        assert_eq!(thread, vec![Some(0), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn nicknames_are_unique_in_the_channel() {
+        let mut channels = channels(&["lobby"]);
+        let lobby = channels.get_mut("lobby").unwrap();
+        lobby.add_connection("alice");
+        lobby.add_connection("bob");
+
+        assert!(lobby
+            .set_nickname("guest_1", Some("ghost".to_owned()))
+            .is_err());
+        assert!(lobby.set_nickname("alice", Some(" ".to_owned())).is_err());
+        assert!(lobby
+            .set_nickname("alice", Some("SYSTEM".to_owned()))
+            .is_err());
+        assert!(lobby.set_nickname("alice", Some("Bob".to_owned())).is_err());
+
+        lobby
+            .set_nickname("alice", Some(" Al ".to_owned()))
+            .unwrap();
+        assert_eq!(lobby.nicknames["alice"], "Al");
+        assert!(lobby.set_nickname("bob", Some("al".to_owned())).is_err());
+
+        lobby.set_nickname("alice", None).unwrap();
+        lobby.set_nickname("bob", Some("al".to_owned())).unwrap();
+    }
+
+    #[test]
+    fn only_moderators_set_the_topic() {
+        let mut channels = channels(&["lobby"]);
+        let lobby = channels.get_mut("lobby").unwrap();
+        lobby.owner = Some("alice".to_owned());
+
+        assert!(lobby.set_topic("bob", Some("spam".to_owned())).is_err());
+        assert!(lobby.set_topic("alice", Some("x".repeat(301))).is_err());
+        lobby
+            .set_topic("alice", Some(" Rust only ".to_owned()))
+            .unwrap();
+        assert_eq!(lobby.info("lobby").topic.as_deref(), Some("Rust only"));
+        assert_eq!(lobby.to_record("lobby").topic.as_deref(), Some("Rust only"));
+
+        lobby.set_topic("alice", Some(" ".to_owned())).unwrap();
+        assert_eq!(lobby.topic, None);
     }
 }
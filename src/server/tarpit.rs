@@ -0,0 +1,258 @@
+//! Tarpit for banned and abusive addresses
+//!
+//! Connections from the addresses banned by the config, and from the ones that struck out by
+//! breaking the limits or failing to log in too often, are accepted but never reach a real
+//! channel. A decoy session answers them, slowly, as if they were alone in a channel: logins
+//! succeed, messages are echoed and nobody else ever sees them. Trapped connections are recorded
+//! in the audit log by their address.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, WriteHalf};
+
+use super::{
+    config::TarpitConfig,
+    inbound::{Inbound, PacketBuffer},
+    session, ServerState,
+};
+use crate::crypto::auth::Challenge;
+use crate::packet::*;
+
+/// Longest a trapped client may stay silent before it's let go
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Longest a trapped connection is held at all
+const MAX_HOLD: Duration = Duration::from_secs(60 * 60);
+
+pub struct Tarpit {
+    config: RwLock<TarpitConfig>,
+
+    /// Times of the recent strikes of every address
+    strikes: Mutex<HashMap<IpAddr, Vec<Instant>>>,
+
+    /// Decoy sessions running now
+    held: AtomicUsize,
+}
+
+/// Counts a decoy session for as long as it runs
+struct HeldGuard<'a>(&'a AtomicUsize);
+
+impl Drop for HeldGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Tarpit {
+    pub fn new(config: &TarpitConfig) -> Self {
+        Self {
+            config: RwLock::new(config.clone()),
+            strikes: Mutex::new(HashMap::new()),
+            held: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn set_config(&self, config: &TarpitConfig) {
+        if let Ok(mut current) = self.config.write() {
+            *current = config.clone();
+        }
+    }
+
+    fn config(&self) -> TarpitConfig {
+        self.config.read().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    /// True if connections from `ip` go to the tarpit
+    pub fn traps(&self, ip: IpAddr) -> bool {
+        let config = self.config();
+        if !config.enabled {
+            return false;
+        }
+        if config.banned_ips.contains(&ip) {
+            return true;
+        }
+        let (Some(max_strikes), Ok(strikes)) = (config.max_strikes, self.strikes.lock()) else {
+            return false;
+        };
+        let window = Duration::from_secs(config.strike_window_secs);
+        strikes.get(&ip).is_some_and(|times| {
+            times.iter().filter(|at| at.elapsed() < window).count() >= max_strikes as usize
+        })
+    }
+
+    /// Count a strike of `ip` for `reason`, the strike sending it to the tarpit is audited
+    pub fn strike(&self, server: &ServerState, ip: IpAddr, reason: &str) {
+        let config = self.config();
+        let (true, Some(max_strikes)) = (config.enabled, config.max_strikes) else {
+            return;
+        };
+        let window = Duration::from_secs(config.strike_window_secs);
+        let struck_out = {
+            let Ok(mut strikes) = self.strikes.lock() else {
+                return;
+            };
+            // forget the addresses that behaved for a whole window
+            strikes.retain(|_, times| {
+                times.retain(|at| at.elapsed() < window);
+                !times.is_empty()
+            });
+            let times = strikes.entry(ip).or_default();
+            times.push(Instant::now());
+            times.len() == max_strikes as usize
+        };
+        if struck_out {
+            server.audit.record(
+                &ip.to_string(),
+                "tarpit",
+                &format!(
+                    "trapped after {} strikes, the last: {}",
+                    max_strikes, reason
+                ),
+            );
+        }
+    }
+
+    /// Hold the connection `stream` from `addr` in a decoy session
+    pub async fn hold<S>(&self, stream: S, addr: SocketAddr, server: Arc<ServerState>)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let config = self.config();
+        let ip = addr.ip().to_string();
+        if self.held.fetch_add(1, Ordering::Relaxed) >= config.max_sessions {
+            self.held.fetch_sub(1, Ordering::Relaxed);
+            server
+                .audit
+                .record(&ip, "tarpit", "refused: the tarpit is full");
+            return;
+        }
+        let _held = HeldGuard(&self.held);
+
+        let started = Instant::now();
+        let mut packets = 0;
+        let decoy = decoy_session(stream, &server, &config, &mut packets);
+        _ = tokio::time::timeout(MAX_HOLD, decoy).await;
+        server.audit.record(
+            &ip,
+            "tarpit",
+            &format!(
+                "held for {}s, {} packets went nowhere",
+                started.elapsed().as_secs(),
+                packets
+            ),
+        );
+    }
+}
+
+/// Channel just joined, as empty as the decoy channels always are
+fn empty_snapshot(channel_name: &str) -> Vec<u8> {
+    JoinSnapshot {
+        channel_name: channel_name.to_owned(),
+        messages: Vec::new(),
+        reactions: Default::default(),
+        pins: Vec::new(),
+    }
+    .as_json_bytes()
+}
+
+/// Write `frame` after the delay of the tarpit, give or take half of it
+async fn respond_slowly<S: AsyncWrite>(
+    wr: &mut WriteHalf<S>,
+    delay: Duration,
+    frame: &[u8],
+) -> bool {
+    let jitter = rand::thread_rng().gen_range(0.5..1.5);
+    tokio::time::sleep(delay.mul_f64(jitter)).await;
+    super::send_sized_bytes(wr, frame).await.is_ok()
+}
+
+/// Play a session of a channel nobody else is in, `packets` counts the packets received
+async fn decoy_session<S>(
+    stream: S,
+    server: &ServerState,
+    config: &TarpitConfig,
+    packets: &mut usize,
+) where
+    S: AsyncRead + AsyncWrite,
+{
+    let (mut rd, mut wr) = tokio::io::split(stream);
+    let delay = Duration::from_millis(config.delay_ms);
+    let mut id = format!("guest_{}", rand::thread_rng().gen_range(10000..100000));
+    let mut seq = 0;
+
+    if !respond_slowly(&mut wr, delay, &empty_snapshot(session::DEFAULT_CHANNEL)).await {
+        return;
+    }
+    let max_packet_size = server.limits().max_packet_size;
+    let mut buf = vec![0; max_packet_size];
+    let mut inbound = PacketBuffer::new(max_packet_size);
+    loop {
+        let n = match tokio::time::timeout(IDLE_TIMEOUT, rd.read(&mut buf)).await {
+            Ok(Ok(n)) if n > 0 => n,
+            _ => return,
+        };
+
+        for completed in inbound.push(&buf[0..n]) {
+            let Inbound::Packet(msg_str) = completed else {
+                continue;
+            };
+            *packets += 1;
+            let replies = match PacketType::from_str(&msg_str) {
+                Ok(PacketType::Hello(hello)) => vec![HelloRes::new(hello.version).as_json_bytes()],
+                // every id exists and every proof is right, in the tarpit
+                Ok(PacketType::ChallengeReq(req)) => vec![ChallengeRes {
+                    result: Ok(Challenge::new(&req.id, None, &req.nonce)),
+                }
+                .as_json_bytes()],
+                Ok(PacketType::LoginReq(req)) => {
+                    if let Some(login_id) = req.login_info.id.filter(|_| !req.login_info.guest) {
+                        id = login_id;
+                    }
+                    vec![LoginRes {
+                        result: Ok(id.clone()),
+                        role: Default::default(),
+                        token: None,
+                        last_channel: None,
+                    }
+                    .as_json_bytes()]
+                }
+                Ok(PacketType::GotoReq(req)) => vec![
+                    GotoRes {
+                        result: Ok(req.channel_name.clone()),
+                        topic: None,
+                    }
+                    .as_json_bytes(),
+                    empty_snapshot(&req.channel_name),
+                ],
+                Ok(PacketType::Message(mut msg)) => {
+                    seq += 1;
+                    msg.id = id.clone();
+                    msg.is_system = false;
+                    msg.seq = Some(seq);
+                    vec![msg.as_json_bytes()]
+                }
+                Ok(PacketType::Ping(ping)) => vec![Pong {
+                    timestamp: ping.timestamp,
+                }
+                .as_json_bytes()],
+                Ok(PacketType::Exit(_)) => return,
+                _ => Vec::new(),
+            };
+            for reply in replies {
+                if !respond_slowly(&mut wr, delay, &reply).await {
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Session tokens, letting members reconnect to any server of a deployment without logging in
+//!
+//! A token is the id of the member and its expiry signed with HMAC-SHA256 by the first of the
+//! `session_tokens.keys`. Every server configured with the key accepts it, e.g. the other nodes
+//! behind a load balancer. Tokens are checked against all the keys, so keys can be rotated by
+//! putting the new one first and dropping the old one once its tokens have expired.
+//!
+//! Kicking a member revokes the tokens issued to it so far on the kicking server, bans are checked
+//! in the database on every resume.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64ct::{Base64, Encoding};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use super::config::SessionTokenConfig;
+
+/// What a token says
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Claims {
+    pub id: String,
+
+    /// Seconds since the epoch the token was issued at and is good until
+    pub issued: u64,
+    pub expires: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn mac(key: &str, payload: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("keys of any size");
+    mac.update(payload);
+    mac
+}
+
+/// Token resuming the session of `id`, `None` if no keys are configured
+pub fn issue(config: &SessionTokenConfig, id: &str) -> Option<String> {
+    let key = config.keys.first()?;
+    let claims = Claims {
+        id: id.to_owned(),
+        issued: now(),
+        expires: now().saturating_add(config.lifetime_secs),
+    };
+    let payload = serde_json::to_vec(&claims).ok()?;
+    let signature = mac(key, &payload).finalize().into_bytes();
+    Some(format!(
+        "{}.{}",
+        Base64::encode_string(&payload),
+        Base64::encode_string(&signature)
+    ))
+}
+
+/// What `token` says, if it's signed by one of the keys and not expired
+pub fn verify(config: &SessionTokenConfig, token: &str) -> Result<Claims, String> {
+    let invalid = || "the session token is invalid".to_owned();
+    let (payload, signature) = token.split_once('.').ok_or_else(invalid)?;
+    let payload = Base64::decode_vec(payload).map_err(|_| invalid())?;
+    let signature = Base64::decode_vec(signature).map_err(|_| invalid())?;
+    if !config
+        .keys
+        .iter()
+        .any(|key| mac(key, &payload).verify_slice(&signature).is_ok())
+    {
+        return Err(invalid());
+    }
+    let claims: Claims = serde_json::from_slice(&payload).map_err(|_| invalid())?;
+    if claims.expires <= now() {
+        return Err("the session token has expired, log in again".to_owned());
+    }
+    Ok(claims)
+}
+
+/// Members whose tokens issued before a time are no longer accepted
+#[derive(Default)]
+pub struct Revocations {
+    /// Time of the revocation by the id of the member
+    revoked: Mutex<HashMap<String, u64>>,
+}
+
+impl Revocations {
+    /// Refuse the tokens of `id` issued so far, revocations older than `lifetime_secs` are
+    /// forgotten, their tokens have expired anyway
+    pub fn revoke(&self, id: &str, lifetime_secs: u64) {
+        let now = now();
+        if let Ok(mut revoked) = self.revoked.lock() {
+            revoked.retain(|_, at| at.saturating_add(lifetime_secs) > now);
+            revoked.insert(id.to_owned(), now);
+        }
+    }
+
+    pub fn is_revoked(&self, claims: &Claims) -> bool {
+        self.revoked.lock().is_ok_and(|revoked| {
+            revoked
+                .get(&claims.id)
+                .is_some_and(|at| claims.issued <= *at)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(keys: &[&str]) -> SessionTokenConfig {
+        SessionTokenConfig {
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tokens_survive_key_rotation_only() {
+        assert_eq!(issue(&config(&[]), "alice"), None);
+
+        let token = issue(&config(&["old"]), "alice").unwrap();
+        assert_eq!(verify(&config(&["old"]), &token).unwrap().id, "alice");
+        assert_eq!(
+            verify(&config(&["new", "old"]), &token).unwrap().id,
+            "alice"
+        );
+        assert!(verify(&config(&["new"]), &token).is_err());
+
+        // claims changed without the key
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = serde_json::to_vec(&Claims {
+            id: "admin".to_owned(),
+            issued: 0,
+            expires: u64::MAX,
+        })
+        .unwrap();
+        let forged = format!("{}.{}", Base64::encode_string(&forged), signature);
+        assert!(verify(&config(&["old"]), &forged).is_err());
+
+        let revocations = Revocations::default();
+        let claims = verify(&config(&["old"]), &token).unwrap();
+        revocations.revoke("alice", 60);
+        assert!(revocations.is_revoked(&claims));
+    }
+}
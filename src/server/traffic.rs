@@ -0,0 +1,270 @@
+//! Accounting of the traffic of the connections and the accounts
+//!
+//! Every connection counts the bytes it reads and writes, the packets it receives and the
+//! messages among them. Once it closes, the counts are added to the totals of the account it was
+//! logged in as, guests are counted together, and the connection goes to the audit log. Admins
+//! see the open connections and the busiest accounts with the `traffic` fetch item, the stats carry
+//! the totals of the server, e.g. to spot the bots flooding it and to plan the capacity.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+/// Account the guests and the connections that never logged in are counted as
+const GUESTS: &str = "guests";
+
+/// Accounts listed in the report, the busiest first
+const NUM_REPORTED_ACCOUNTS: usize = 20;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Account the traffic of `id` is counted for
+fn account(id: &str) -> &str {
+    if id.is_empty() || id.starts_with("guest_") {
+        GUESTS
+    } else {
+        id
+    }
+}
+
+#[derive(Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Totals {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub messages_in: u64,
+    pub frames_out: u64,
+}
+
+impl Totals {
+    fn add(&mut self, other: &Totals) {
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.packets_in += other.packets_in;
+        self.messages_in += other.messages_in;
+        self.frames_out += other.frames_out;
+    }
+
+    fn bytes(&self) -> u64 {
+        self.bytes_in + self.bytes_out
+    }
+}
+
+/// Counts of an open connection, shared by the tasks reading and writing it
+pub struct Meter {
+    key: u64,
+    pub addr: SocketAddr,
+
+    /// Id of the session, the account may change while the connection is open
+    id: Arc<Mutex<String>>,
+
+    /// Unix time the connection was opened at
+    opened: u64,
+
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    packets_in: AtomicU64,
+    messages_in: AtomicU64,
+    frames_out: AtomicU64,
+}
+
+impl Meter {
+    pub fn read(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Count a packet received, a chat message if `is_message`
+    pub fn packet(&self, is_message: bool) {
+        self.packets_in.fetch_add(1, Ordering::Relaxed);
+        if is_message {
+            self.messages_in.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a frame of `bytes` written, the size header included
+    pub fn wrote(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.frames_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Seconds the connection has been open for
+    pub fn open_secs(&self) -> u64 {
+        now().saturating_sub(self.opened)
+    }
+
+    pub fn id(&self) -> String {
+        self.id.lock().map(|lock| lock.clone()).unwrap_or_default()
+    }
+
+    pub fn totals(&self) -> Totals {
+        Totals {
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            packets_in: self.packets_in.load(Ordering::Relaxed),
+            messages_in: self.messages_in.load(Ordering::Relaxed),
+            frames_out: self.frames_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Traffic {
+    next_key: AtomicU64,
+    open: Mutex<HashMap<u64, Arc<Meter>>>,
+
+    /// Totals of the closed connections by account
+    accounts: Mutex<HashMap<String, Totals>>,
+}
+
+impl Traffic {
+    /// Start counting the connection from `addr` of the session `id`
+    pub fn open(&self, addr: SocketAddr, id: Arc<Mutex<String>>) -> Arc<Meter> {
+        let meter = Arc::new(Meter {
+            key: self.next_key.fetch_add(1, Ordering::Relaxed),
+            addr,
+            id,
+            opened: now(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            packets_in: AtomicU64::new(0),
+            messages_in: AtomicU64::new(0),
+            frames_out: AtomicU64::new(0),
+        });
+        if let Ok(mut open) = self.open.lock() {
+            open.insert(meter.key, Arc::clone(&meter));
+        }
+        meter
+    }
+
+    /// Add the counts of the closed connection of `meter` to its account
+    pub fn close(&self, meter: &Meter) {
+        if let Ok(mut open) = self.open.lock() {
+            open.remove(&meter.key);
+        }
+        if let Ok(mut accounts) = self.accounts.lock() {
+            let id = meter.id();
+            accounts
+                .entry(account(&id).to_owned())
+                .or_default()
+                .add(&meter.totals());
+        }
+    }
+
+    /// Totals of every account, the open connections included
+    pub fn by_account(&self) -> HashMap<String, Totals> {
+        let mut accounts = self
+            .accounts
+            .lock()
+            .map(|accounts| accounts.clone())
+            .unwrap_or_default();
+        for meter in self.open_meters() {
+            let id = meter.id();
+            accounts
+                .entry(account(&id).to_owned())
+                .or_default()
+                .add(&meter.totals());
+        }
+        accounts
+    }
+
+    /// Totals of the server since the start
+    pub fn totals(&self) -> Totals {
+        let mut totals = Totals::default();
+        for account in self.by_account().values() {
+            totals.add(account);
+        }
+        totals
+    }
+
+    /// Open connections and the busiest accounts, for the admins
+    pub fn report(&self) -> serde_json::Value {
+        let mut meters = self.open_meters();
+        meters.sort_by_key(|meter| std::cmp::Reverse(meter.totals().bytes()));
+        let connections: Vec<serde_json::Value> = meters
+            .iter()
+            .map(|meter| {
+                serde_json::json!({
+                    "id": meter.id(),
+                    "addr": meter.addr.to_string(),
+                    "opened": meter.opened,
+                    "totals": meter.totals(),
+                })
+            })
+            .collect();
+
+        let mut accounts: Vec<(String, Totals)> = self.by_account().into_iter().collect();
+        accounts.sort_by(|a, b| b.1.bytes().cmp(&a.1.bytes()).then(a.0.cmp(&b.0)));
+        let accounts: Vec<serde_json::Value> = accounts
+            .into_iter()
+            .take(NUM_REPORTED_ACCOUNTS)
+            .map(|(id, totals)| serde_json::json!({ "id": id, "totals": totals }))
+            .collect();
+
+        serde_json::json!({
+            "connections": connections,
+            "accounts": accounts,
+            "totals": self.totals(),
+        })
+    }
+
+    fn open_meters(&self) -> Vec<Arc<Meter>> {
+        self.open
+            .lock()
+            .map(|open| open.values().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_add_up_by_account() {
+        let traffic = Traffic::default();
+        let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let alice = Arc::new(Mutex::new(String::new()));
+        let first = traffic.open(addr, Arc::clone(&alice));
+        let second = traffic.open(addr, Arc::new(Mutex::new("guest_1".to_owned())));
+
+        first.read(100);
+        first.packet(false);
+        // logged in, the whole connection counts for the account
+        *alice.lock().unwrap() = "alice".to_owned();
+        first.packet(true);
+        first.wrote(10);
+        second.read(5);
+
+        assert_eq!(traffic.by_account()["alice"].bytes_in, 100);
+        traffic.close(&first);
+        traffic.close(&second);
+
+        let accounts = traffic.by_account();
+        assert_eq!(
+            accounts["alice"],
+            Totals {
+                bytes_in: 100,
+                bytes_out: 10,
+                packets_in: 2,
+                messages_in: 1,
+                frames_out: 1,
+            }
+        );
+        assert_eq!(accounts[GUESTS].bytes_in, 5);
+        assert_eq!(traffic.totals().bytes_in, 105);
+        assert!(traffic.open_meters().is_empty());
+    }
+}
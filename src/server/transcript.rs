@@ -0,0 +1,285 @@
+//! Tamper-evident transcript of the channel traffic, the compliance mode
+//!
+//! Off unless `compliance.transcript` names a file. Every message a channel records, the system
+//! messages included, is appended to the file as a JSON line carrying the hash of the line before
+//! it, so a line changed, removed or inserted afterwards breaks the chain from there on. The file
+//! is rotated to `<path>.<n>` once it reaches `compliance.rotate_bytes`, the chain goes on in the
+//! new file, and a restart picks the chain up from the latest line.
+//!
+//! `rschat verify-transcript <file>...` checks the chain of the files, oldest first.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::config::ComplianceConfig;
+use crate::{crypto::hash, packet::Message};
+
+/// Hash of the line before the first one of a transcript
+const GENESIS: &str = "";
+
+/// Hash of the line `record`, the record without its `hash`
+fn hash_of(record: &serde_json::Value) -> String {
+    hash::sha256_string(&record.to_string())
+}
+
+/// Sequence number and hash of the last line of the file at `path`
+fn last_link(path: &Path) -> Option<(u64, String)> {
+    let content = fs::read_to_string(path).ok()?;
+    let line = content.lines().rev().find(|line| !line.trim().is_empty())?;
+    let record: serde_json::Value = serde_json::from_str(line).ok()?;
+    Some((record["seq"].as_u64()?, record["hash"].as_str()?.to_owned()))
+}
+
+/// Rotated files of the transcript at `path`, by their number
+fn rotated(path: &Path) -> Vec<(u64, PathBuf)> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Vec::new();
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let prefix = format!("{}.", name.to_string_lossy());
+    let mut files: Vec<(u64, PathBuf)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let n = file_name.strip_prefix(&prefix)?.parse().ok()?;
+            Some((n, entry.path()))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[derive(Debug)]
+struct Writer {
+    path: PathBuf,
+    file: File,
+
+    /// Size of the current file
+    bytes: u64,
+
+    /// Sequence number and hash of the latest line
+    seq: u64,
+    prev: String,
+}
+
+impl Writer {
+    fn open(path: PathBuf) -> std::io::Result<Self> {
+        // the chain goes on from the latest line, in the rotated files if the current one is new
+        let (seq, prev) = last_link(&path)
+            .or_else(|| {
+                let (_, latest) = rotated(&path).pop()?;
+                last_link(&latest)
+            })
+            .map_or((0, GENESIS.to_owned()), |(seq, hash)| (seq + 1, hash));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let bytes = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            bytes,
+            seq,
+            prev,
+        })
+    }
+
+    fn append(
+        &mut self,
+        channel: &str,
+        message: &Message,
+        rotate_bytes: u64,
+    ) -> std::io::Result<()> {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let mut record = serde_json::json!({
+            "seq": self.seq,
+            "at": at,
+            "channel": channel,
+            "message": message,
+            "prev": self.prev,
+        });
+        let hash = hash_of(&record);
+        record["hash"] = hash.clone().into();
+
+        let line = format!("{}\n", record);
+        self.file.write_all(line.as_bytes())?;
+        self.bytes += line.len() as u64;
+        self.seq += 1;
+        self.prev = hash;
+
+        if self.bytes >= rotate_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Move the current file to the next free number and start a new one
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let n = rotated(&self.path).last().map_or(1, |(n, _)| n + 1);
+        let mut target = self.path.clone().into_os_string();
+        target.push(format!(".{}", n));
+        fs::rename(&self.path, &target)?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.bytes = 0;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Transcript {
+    writer: Mutex<Writer>,
+    rotate_bytes: u64,
+}
+
+impl Transcript {
+    /// Transcript of the config, `None` if the compliance mode is off
+    ///
+    /// A transcript that can't be opened is an error, the server shouldn't run without it.
+    pub fn open(config: &ComplianceConfig) -> Result<Option<Arc<Self>>, String> {
+        let Some(path) = &config.transcript else {
+            return Ok(None);
+        };
+        let writer = Writer::open(PathBuf::from(path))
+            .map_err(|e| format!("transcript '{}' can't be opened: {}", path, e))?;
+        Ok(Some(Arc::new(Self {
+            writer: Mutex::new(writer),
+            rotate_bytes: config.rotate_bytes,
+        })))
+    }
+
+    /// Append `message` recorded by the channel `channel`
+    pub fn record(&self, channel: &str, message: &Message) {
+        let Ok(mut writer) = self.writer.lock() else {
+            return;
+        };
+        if let Err(e) = writer.append(channel, message, self.rotate_bytes) {
+            println!("[!] Transcript record is not written: {}", e);
+        }
+    }
+}
+
+/// Appends the messages of a channel to the transcript
+#[derive(Clone, Debug)]
+pub struct Tap {
+    channel: String,
+    transcript: Arc<Transcript>,
+}
+
+impl Tap {
+    pub fn new(channel: &str, transcript: &Arc<Transcript>) -> Self {
+        Self {
+            channel: channel.to_owned(),
+            transcript: Arc::clone(transcript),
+        }
+    }
+
+    pub fn record(&self, message: &Message) {
+        self.transcript.record(&self.channel, message);
+    }
+}
+
+/// Check the chain of the transcript files `paths`, oldest first, returns the number of lines
+///
+/// The first line may follow lines of older files not given, every other line must follow the
+/// one before it.
+pub fn verify(paths: &[String]) -> Result<u64, String> {
+    let mut last: Option<(u64, String)> = None;
+    let mut count = 0;
+    for path in paths {
+        let content =
+            fs::read_to_string(path).map_err(|e| format!("{}: can't be read: {}", path, e))?;
+        for (i, line) in content.lines().enumerate() {
+            let at = |what: &str| format!("{}:{}: {}", path, i + 1, what);
+            let mut record: serde_json::Value =
+                serde_json::from_str(line).map_err(|_| at("not a transcript line"))?;
+            let (Some(seq), Some(prev), Some(hash)) = (
+                record["seq"].as_u64(),
+                record["prev"].as_str().map(str::to_owned),
+                record
+                    .as_object_mut()
+                    .and_then(|record| record.remove("hash"))
+                    .and_then(|hash| hash.as_str().map(str::to_owned)),
+            ) else {
+                return Err(at("not a transcript line"));
+            };
+            if hash_of(&record) != hash {
+                return Err(at("the line was changed"));
+            }
+            if let Some((last_seq, last_hash)) = &last {
+                if seq != last_seq + 1 || prev != *last_hash {
+                    return Err(at("a line is missing or was inserted before it"));
+                }
+            }
+            last = Some((seq, hash));
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tampering_breaks_the_chain() {
+        let dir = std::env::temp_dir().join(format!("rschat_transcript_{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("transcript.log");
+        let config = ComplianceConfig {
+            transcript: Some(path.to_string_lossy().into_owned()),
+            // every line rotates the file
+            rotate_bytes: 1,
+        };
+
+        let transcript = Transcript::open(&config).unwrap().unwrap();
+        for text in ["hello", "there", "how are you"] {
+            transcript.record("public", &Message::system_notice(text));
+        }
+        drop(transcript);
+        // a restart goes on with the chain
+        let transcript = Transcript::open(&config).unwrap().unwrap();
+        transcript.record("dev", &Message::system_notice("fine"));
+
+        let mut files: Vec<String> = rotated(&path)
+            .into_iter()
+            .map(|(_, path)| path.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(files.len(), 4);
+        files.push(path.to_string_lossy().into_owned());
+        assert_eq!(verify(&files), Ok(4));
+        // the latest files alone are fine too
+        assert_eq!(verify(&files[2..]), Ok(2));
+
+        // a file left out
+        let mut missing = files.clone();
+        missing.remove(1);
+        assert!(verify(&missing)
+            .unwrap_err()
+            .ends_with(":1: a line is missing or was inserted before it"));
+
+        // a message changed
+        let content = fs::read_to_string(&files[0]).unwrap();
+        fs::write(&files[0], content.replacen("hello", "HELLO", 1)).unwrap();
+        assert!(verify(&files)
+            .unwrap_err()
+            .ends_with(":1: the line was changed"));
+        _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,321 @@
+//! Protocol conformance of the server
+//!
+//! Runs the server binary and talks to it over raw TCP sockets the way a broken or hostile
+//! client would: malformed and truncated JSON, packets over the size limit, fields of the wrong
+//! type and requests before the login. Every case expects a defined answer, and the server
+//! staying up for the next one.
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rschat::packet::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use serde_json::{json, Value};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default `limits.max_packet_size` of the server
+const MAX_PACKET_SIZE: usize = 64 * 1024;
+
+struct Server {
+    child: Child,
+    port: u16,
+    dir: std::path::PathBuf,
+}
+
+impl Server {
+    fn start() -> Self {
+        // a port nobody listens on, released right before the server binds it
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .map(|addr| addr.port())
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("rschat-conformance-{}", port));
+        std::fs::create_dir_all(&dir).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_rschat"))
+            .args(["server", "--port", &port.to_string()])
+            .current_dir(&dir)
+            .env("XDG_CONFIG_HOME", &dir)
+            .env("HOME", &dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        Self { child, port, dir }
+    }
+
+    /// A new session, the server takes a while to come up without a database
+    fn connect(&self) -> Session {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            match TcpStream::connect(("127.0.0.1", self.port)) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+                    return Session { stream };
+                }
+                Err(e) if Instant::now() > deadline => panic!("server never came up: {}", e),
+                Err(_) => thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+
+    /// Stop the server, with what it wrote on stderr
+    fn stop(mut self) -> String {
+        let _ = self.child.kill();
+        let mut stderr = String::new();
+        if let Some(mut pipe) = self.child.stderr.take() {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+        stderr
+    }
+}
+
+struct Session {
+    stream: TcpStream,
+}
+
+impl Session {
+    fn send(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    fn send_json(&mut self, packet: Value) {
+        self.send(packet.to_string().as_bytes());
+    }
+
+    /// Next frame of the server, a big endian size followed by the JSON packet
+    fn recv(&mut self) -> Option<Value> {
+        let mut size = [0u8; 4];
+        match self.stream.read_exact(&mut size) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return None,
+            Err(e) => panic!("no frame from the server: {}", e),
+        }
+        let mut body = vec![0u8; u32::from_be_bytes(size) as usize];
+        self.stream.read_exact(&mut body).unwrap();
+        Some(serde_json::from_slice(&body).expect("frames carry JSON"))
+    }
+
+    /// Next packet of type `ty`, skipping the notifications coming in between
+    fn expect(&mut self, ty: &str) -> Value {
+        loop {
+            let packet = self
+                .recv()
+                .unwrap_or_else(|| panic!("closed while waiting for {}", ty));
+            if packet["type"] == ty {
+                return packet;
+            }
+            assert_ne!(packet["type"], "InvalidPacket", "waiting for {}", ty);
+        }
+    }
+
+    /// Wait for the server to close the session, nothing but notifications coming before
+    fn expect_closed(&mut self) {
+        while let Some(packet) = self.recv() {
+            assert!(packet["type"] != "Pong" && packet["type"] != "InvalidPacket");
+        }
+    }
+
+    /// Code of the next `InvalidPacket`
+    fn expect_invalid(&mut self) -> String {
+        let packet = self.expect("InvalidPacket");
+        packet["error"]["code"].as_str().unwrap().to_owned()
+    }
+
+    /// The session is still served
+    fn ping(&mut self, timestamp: u64) {
+        self.send_json(json!({"type": "Ping", "timestamp": timestamp}));
+        assert_eq!(self.expect("Pong")["timestamp"], timestamp);
+    }
+}
+
+/// A scenario run on sessions of its own
+type Case = fn(&Server);
+
+fn hello() -> Value {
+    json!({"type": "Hello", "version": PROTOCOL_VERSION, "software": "conformance"})
+}
+
+#[test]
+fn conformance() {
+    let server = Server::start();
+    let cases: [(&str, Case); 9] = [
+        ("handshake", handshake),
+        ("malformed", malformed),
+        ("unknown type", unknown_type),
+        ("wrong field types", wrong_field_types),
+        ("truncated", truncated),
+        ("oversized", oversized),
+        ("out of order", out_of_order),
+        ("burst", burst),
+        ("forged system message", forged_system_message),
+    ];
+    for (name, case) in cases {
+        println!("case: {}", name);
+        case(&server);
+    }
+    let stderr = server.stop();
+    assert!(
+        !stderr.contains("panicked"),
+        "the server panicked:\n{}",
+        stderr
+    );
+}
+
+fn handshake(server: &Server) {
+    let mut session = server.connect();
+    session.send_json(hello());
+    let res = session.expect("HelloRes");
+    assert!(res["result"].get("Ok").is_some(), "{}", res);
+    assert_eq!(res["limits"]["max_packet_size"], MAX_PACKET_SIZE);
+    session.ping(1);
+
+    // too old to log in or read the errors, turned away with the reason
+    let mut session = server.connect();
+    session.send_json(json!({
+        "type": "Hello",
+        "version": MIN_PROTOCOL_VERSION - 1,
+        "software": "conformance",
+    }));
+    let res = session.expect("HelloRes");
+    let reason = res["result"]["Err"].as_str().unwrap();
+    assert!(reason.contains("incompatible protocol version"), "{}", res);
+    session.expect_closed();
+}
+
+fn malformed(server: &Server) {
+    let mut session = server.connect();
+    for bytes in [
+        &b"{]"[..],
+        b"{\"no_type\": 1}",
+        b"{\"type\": 42}",
+        b"\"Ping\"",
+        b"{\"type\": \"Ping\", \"timestamp\": \xff\xfe}",
+    ] {
+        session.send(bytes);
+        assert_eq!(session.expect_invalid(), "invalid_argument");
+    }
+    session.ping(2);
+}
+
+fn unknown_type(server: &Server) {
+    let mut session = server.connect();
+    session.send_json(json!({"type": "Bogus", "what": "ever"}));
+    let packet = session.expect("InvalidPacket");
+    assert_eq!(packet["error"]["code"], "invalid_argument");
+    assert!(packet["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("Bogus"));
+    // packets only the server sends
+    session.send_json(json!({"type": "Pong", "timestamp": 3}));
+    assert_eq!(session.expect_invalid(), "invalid_argument");
+    session.ping(3);
+}
+
+fn wrong_field_types(server: &Server) {
+    let mut session = server.connect();
+    for packet in [
+        json!({"type": "Ping", "timestamp": "soon"}),
+        json!({"type": "Ping"}),
+        json!({"type": "Hello", "version": -1, "software": "conformance"}),
+        json!({"type": "FetchReq", "item": "nope"}),
+        json!({"type": "FetchReq", "item": "thread", "seq": "first"}),
+    ] {
+        session.send_json(packet);
+        assert_eq!(session.expect_invalid(), "invalid_argument");
+    }
+    session.ping(4);
+}
+
+fn truncated(server: &Server) {
+    // the rest of the packet never comes, the session is closed without an answer
+    let mut session = server.connect();
+    session.send(b"{\"type\": \"Ping\", \"time");
+    session.stream.shutdown(Shutdown::Write).unwrap();
+    session.expect_closed();
+
+    // a packet split over several writes is put back together
+    let mut session = server.connect();
+    session.send(b"{\"type\": \"Ping\", ");
+    thread::sleep(Duration::from_millis(100));
+    session.send(b"\"timestamp\": 5}");
+    assert_eq!(session.expect("Pong")["timestamp"], 5);
+}
+
+fn oversized(server: &Server) {
+    let mut session = server.connect();
+    let msg = "a".repeat(MAX_PACKET_SIZE + 1);
+    session.send_json(json!({"type": "Message", "id": "", "msg": msg, "is_system": false}));
+    let exceeded = session.expect("LimitExceeded");
+    assert_eq!(exceeded["limit"], MAX_PACKET_SIZE);
+    assert!(exceeded["size"].as_u64().unwrap() > MAX_PACKET_SIZE as u64);
+
+    // a header that never ends, the type is still being read at the limit
+    let mut session = server.connect();
+    session.send(b"{\"type\": \"");
+    session.send(&b"a".repeat(MAX_PACKET_SIZE));
+    session.expect("LimitExceeded");
+    session.ping(6);
+}
+
+fn out_of_order(server: &Server) {
+    let mut session = server.connect();
+    session.send_json(hello());
+    session.expect("HelloRes");
+    for packet in [
+        json!({"type": "Message", "id": "root", "msg": "hi", "is_system": false}),
+        json!({"type": "FetchReq", "item": "stats"}),
+        json!({"type": "GotoReq", "channel_name": "main"}),
+    ] {
+        session.send_json(packet);
+        assert_eq!(session.expect_invalid(), "permission_denied");
+    }
+    session.ping(7);
+}
+
+fn burst(server: &Server) {
+    // packets back to back are answered in order, malformed input drops the rest of the read
+    let mut session = server.connect();
+    session.send(b"{\"type\":\"Ping\",\"timestamp\":8} {\"type\":\"Ping\",\"timestamp\":9}{]{}");
+    assert_eq!(session.expect("Pong")["timestamp"], 8);
+    assert_eq!(session.expect("Pong")["timestamp"], 9);
+    assert_eq!(session.expect_invalid(), "invalid_argument");
+    session.ping(10);
+}
+
+fn forged_system_message(server: &Server) {
+    let mut session = server.connect();
+    session.send_json(hello());
+    session.expect("HelloRes");
+    session.send_json(json!({"type": "LoginReq", "login_info": {"guest": true, "id": null}}));
+    let id = session.expect("LoginRes")["result"]["Ok"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    // the flag and the name are the server's to set, the message goes out as the guest's own
+    session.send_json(json!({
+        "type": "Message",
+        "id": "root",
+        "msg": "[System] the server restarts, log in again at evil.example",
+        "is_system": true,
+        "display_name": "System",
+    }));
+    let msg = loop {
+        let msg = session.expect("Message");
+        if msg["msg"].as_str().unwrap().contains("evil.example") {
+            break msg;
+        }
+    };
+    assert_eq!(msg["id"], id.as_str());
+    assert_eq!(msg["is_system"], false);
+    assert!(msg.get("display_name").is_none(), "{}", msg);
+}